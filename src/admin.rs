@@ -0,0 +1,629 @@
+use crate::config::{AppConfig, Fhir, Kafka};
+use crate::fhir::bootstrap::bootstrap_bundle;
+use crate::fhir::resources::ResourceMap;
+use crate::processor::{create_producer, set_ssl_config};
+use anyhow::{Context, anyhow, bail};
+use chrono::Local;
+use rdkafka::admin::{AdminClient, AdminOptions};
+use rdkafka::client::DefaultClientContext;
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::message::OwnedMessage;
+use rdkafka::producer::FutureRecord;
+use rdkafka::{ClientConfig, Message, Offset, TopicPartitionList};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "usage: adt-to-fhir admin <command>
+
+commands:
+  status                                   broker and topic overview
+  lag                                      consumer lag of the consumer group
+  dlq-list <topic>                         list the records of a dead letter topic
+  dlq-redrive <topic> <partition:offset>.. produce records to the input topic
+  purge <topic>                            delete all records of a (quarantine) topic
+  replay <topic> [speed]                   produce the records to the input topic at their
+                                           original time gaps, scaled by speed (default 1)
+  bootstrap                                produce a bundle of all departments and wards of
+                                           the mapping files to the output topic";
+
+/// Operational commands (`adt-to-fhir admin <command>`)
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    Status,
+    Lag,
+    DlqList {
+        topic: String,
+    },
+    DlqRedrive {
+        topic: String,
+        offsets: Vec<(i32, i64)>,
+    },
+    Purge {
+        topic: String,
+    },
+    Replay {
+        topic: String,
+        speed: f64,
+    },
+    Bootstrap,
+}
+
+impl Command {
+    /// Parses the arguments following `admin`.
+    pub(crate) fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let topic = || {
+            args.get(1)
+                .cloned()
+                .ok_or_else(|| anyhow!("missing topic\n\n{USAGE}"))
+        };
+
+        match args.first().map(String::as_str) {
+            Some("status") => Ok(Command::Status),
+            Some("lag") => Ok(Command::Lag),
+            Some("dlq-list") => Ok(Command::DlqList { topic: topic()? }),
+            Some("dlq-redrive") => {
+                let offsets = args
+                    .iter()
+                    .skip(2)
+                    .map(|a| parse_partition_offset(a))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                if offsets.is_empty() {
+                    bail!("missing partition:offset\n\n{USAGE}");
+                }
+                Ok(Command::DlqRedrive {
+                    topic: topic()?,
+                    offsets,
+                })
+            }
+            Some("bootstrap") => Ok(Command::Bootstrap),
+            Some("purge") => Ok(Command::Purge { topic: topic()? }),
+            Some("replay") => {
+                let speed = match args.get(2) {
+                    Some(speed) => speed
+                        .parse()
+                        .ok()
+                        .filter(|s: &f64| s.is_finite() && *s > 0.0)
+                        .ok_or_else(|| anyhow!("invalid speed: {speed}"))?,
+                    None => 1.0,
+                };
+                Ok(Command::Replay {
+                    topic: topic()?,
+                    speed,
+                })
+            }
+            Some(other) => bail!("unknown command: {other}\n\n{USAGE}"),
+            None => bail!(USAGE),
+        }
+    }
+}
+
+fn parse_partition_offset(value: &str) -> anyhow::Result<(i32, i64)> {
+    let (partition, offset) = value
+        .split_once(':')
+        .ok_or_else(|| anyhow!("invalid partition:offset: {value}"))?;
+
+    Ok((
+        partition
+            .parse()
+            .with_context(|| format!("invalid partition: {value}"))?,
+        offset
+            .parse()
+            .with_context(|| format!("invalid offset: {value}"))?,
+    ))
+}
+
+pub(crate) async fn run(command: Command, app_config: AppConfig) -> anyhow::Result<()> {
+    let config = app_config.kafka;
+    match command {
+        Command::Status => status(&config),
+        Command::Lag => lag(&config),
+        Command::DlqList { topic } => dlq_list(&config, &topic),
+        Command::DlqRedrive { topic, offsets } => dlq_redrive(config, &topic, &offsets).await,
+        Command::Purge { topic } => purge(&config, &topic).await,
+        Command::Replay { topic, speed } => replay(config, &topic, speed).await,
+        Command::Bootstrap => bootstrap(config, &app_config.fhir).await,
+    }
+}
+
+fn status(config: &Kafka) -> anyhow::Result<()> {
+    let consumer = create_consumer(config)?;
+    let metadata = consumer.fetch_metadata(None, TIMEOUT)?;
+
+    println!("brokers:");
+    for broker in metadata.brokers() {
+        println!("  {} {}:{}", broker.id(), broker.host(), broker.port());
+    }
+
+    println!("topics:");
+    let topics = config
+        .topic_lanes()
+        .into_iter()
+        .map(|(t, _)| t)
+        .chain([config.output_topic.as_str()]);
+    for name in topics {
+        match metadata.topics().iter().find(|t| t.name() == name) {
+            Some(topic) => println!("  {name}: {} partition(s)", topic.partitions().len()),
+            None => println!("  {name}: missing"),
+        }
+    }
+    Ok(())
+}
+
+fn lag(config: &Kafka) -> anyhow::Result<()> {
+    let consumer = create_consumer(config)?;
+
+    println!("consumer group: {}", config.consumer_group);
+    for (topic, _) in config.topic_lanes() {
+        let partitions = partitions(&consumer, topic)?;
+        let mut tpl = TopicPartitionList::new();
+        for partition in &partitions {
+            tpl.add_partition(topic, *partition);
+        }
+        let committed = consumer.committed_offsets(tpl, TIMEOUT)?;
+
+        let mut total = 0;
+        for partition in partitions {
+            let (low, high) = consumer.fetch_watermarks(topic, partition, TIMEOUT)?;
+            let offset = match committed
+                .find_partition(topic, partition)
+                .map(|e| e.offset())
+            {
+                Some(Offset::Offset(o)) => o,
+                // nothing committed yet
+                _ => low,
+            };
+            let lag = (high - offset).max(0);
+            total += lag;
+            println!("  {topic}[{partition}]: committed {offset}, end {high}, lag {lag}");
+        }
+        println!("  {topic}: total lag {total}");
+    }
+    Ok(())
+}
+
+fn dlq_list(config: &Kafka, topic: &str) -> anyhow::Result<()> {
+    let consumer = create_consumer(config)?;
+
+    for partition in partitions(&consumer, topic)? {
+        let (low, high) = consumer.fetch_watermarks(topic, partition, TIMEOUT)?;
+        if low >= high {
+            continue;
+        }
+        let mut tpl = TopicPartitionList::new();
+        tpl.add_partition_offset(topic, partition, Offset::Offset(low))?;
+        consumer.assign(&tpl)?;
+
+        // the partition is read once, up to its end offset
+        while let Some(m) = consumer.poll(TIMEOUT) {
+            let m = m?;
+            println!(
+                "{partition}:{} key={} timestamp={} size={}",
+                m.offset(),
+                m.key_view::<str>().and_then(Result::ok).unwrap_or_default(),
+                m.timestamp().to_millis().unwrap_or_default(),
+                m.payload().map(<[u8]>::len).unwrap_or_default()
+            );
+            if m.offset() >= high - 1 {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn dlq_redrive(config: Kafka, topic: &str, offsets: &[(i32, i64)]) -> anyhow::Result<()> {
+    let consumer = create_consumer(&config)?;
+    let input_topic = config.input_topic.clone();
+    let producer = create_producer(config);
+
+    for (partition, offset) in offsets {
+        let m = read_record(&consumer, topic, *partition, *offset)?
+            .ok_or_else(|| anyhow!("record {partition}:{offset} not found in {topic}"))?;
+
+        producer
+            .send(forward_record(&m, &input_topic), TIMEOUT)
+            .await
+            .map_err(|(e, _)| e)?;
+        println!("{partition}:{offset} re-driven to {input_topic}");
+    }
+    Ok(())
+}
+
+async fn purge(config: &Kafka, topic: &str) -> anyhow::Result<()> {
+    check_purge(config, topic)?;
+    let consumer = create_consumer(config)?;
+    let mut tpl = TopicPartitionList::new();
+    for partition in partitions(&consumer, topic)? {
+        tpl.add_partition_offset(topic, partition, Offset::End)?;
+    }
+
+    let admin: AdminClient<DefaultClientContext> = client_config(config).create()?;
+    let result = admin
+        .delete_records(&tpl, &AdminOptions::new().operation_timeout(Some(TIMEOUT)))
+        .await?;
+    for e in result.elements() {
+        e.error()?;
+        println!("{topic}[{}]: purged", e.partition());
+    }
+    Ok(())
+}
+
+/// Topics consumed or produced by the processor are never purged.
+fn check_purge(config: &Kafka, topic: &str) -> anyhow::Result<()> {
+    if config.topic_lanes().iter().any(|(t, _)| *t == topic) || config.output_topic == topic {
+        bail!("can't purge {topic}, it is consumed or produced by the processor");
+    }
+    Ok(())
+}
+
+/// Produces all records of the topic to the input topic, e.g. for load tests. The records of
+/// the partitions are merged by timestamp and the time gaps are kept, scaled by `speed`.
+async fn replay(config: Kafka, topic: &str, speed: f64) -> anyhow::Result<()> {
+    let input_topic = config.input_topic.clone();
+    if topic == input_topic {
+        bail!("can't replay the input topic {topic} to itself");
+    }
+    let consumer = create_consumer(&config)?;
+    let producer = create_producer(config);
+
+    let partitions = partitions(&consumer, topic)?;
+    let mut merge = TimestampMerge::new(&partitions);
+    let mut ends = HashMap::new();
+    let mut tpl = TopicPartitionList::new();
+    for partition in partitions {
+        let (low, high) = consumer.fetch_watermarks(topic, partition, TIMEOUT)?;
+        if low < high {
+            tpl.add_partition_offset(topic, partition, Offset::Offset(low))?;
+            ends.insert(partition, high);
+        } else {
+            merge.finish(partition);
+        }
+    }
+    consumer.assign(&tpl)?;
+
+    let start = Instant::now();
+    let mut first_timestamp = None;
+    let mut paused = HashSet::new();
+    let mut count = 0;
+    while !merge.is_done() {
+        while let Some(m) = merge.pop() {
+            if merge.buffered(m.partition()) == 0 && paused.remove(&m.partition()) {
+                consumer.resume(&partition_list(topic, m.partition()))?;
+            }
+            // the first record has the earliest timestamp of all partitions
+            if let Some(timestamp) = m.timestamp().to_millis() {
+                let first = *first_timestamp.get_or_insert(timestamp);
+                let due = replay_offset(first, timestamp, speed);
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    tokio::time::sleep(wait).await;
+                }
+            }
+
+            producer
+                .send(forward_record(&m, &input_topic), TIMEOUT)
+                .await
+                .map_err(|(e, _)| e)?;
+            count += 1;
+        }
+
+        match consumer.poll(TIMEOUT) {
+            Some(m) => {
+                let m = m?.detach();
+                let partition = m.partition();
+                if ends.get(&partition).is_none_or(|end| m.offset() >= end - 1) {
+                    merge.finish(partition);
+                }
+                if merge.push(m) >= REPLAY_BUFFER && paused.insert(partition) {
+                    consumer.pause(&partition_list(topic, partition))?;
+                }
+            }
+            // no more records, e.g. compacted offsets at the end of a partition
+            None => merge.finish_pending(),
+        }
+    }
+    println!("{count} record(s) of {topic} replayed to {input_topic}");
+    Ok(())
+}
+
+/// Records buffered per partition, before the partition is paused.
+const REPLAY_BUFFER: usize = 500;
+
+/// Merges the records of the partitions by their timestamp. A record is only released, if
+/// every unfinished partition has a buffered record to compare with.
+struct TimestampMerge {
+    buffers: BTreeMap<i32, VecDeque<OwnedMessage>>,
+    finished: HashSet<i32>,
+}
+
+impl TimestampMerge {
+    fn new(partitions: &[i32]) -> Self {
+        TimestampMerge {
+            buffers: partitions.iter().map(|p| (*p, VecDeque::new())).collect(),
+            finished: HashSet::new(),
+        }
+    }
+
+    /// Buffers the record, returns the buffered records of its partition.
+    fn push(&mut self, m: OwnedMessage) -> usize {
+        let buffer = self.buffers.entry(m.partition()).or_default();
+        buffer.push_back(m);
+        buffer.len()
+    }
+
+    /// No more records are expected from the partition.
+    fn finish(&mut self, partition: i32) {
+        self.finished.insert(partition);
+    }
+
+    /// Finishes the partitions without buffered records.
+    fn finish_pending(&mut self) {
+        for (partition, buffer) in &self.buffers {
+            if buffer.is_empty() {
+                self.finished.insert(*partition);
+            }
+        }
+    }
+
+    fn buffered(&self, partition: i32) -> usize {
+        self.buffers.get(&partition).map_or(0, VecDeque::len)
+    }
+
+    fn is_done(&self) -> bool {
+        self.buffers
+            .iter()
+            .all(|(p, b)| b.is_empty() && self.finished.contains(p))
+    }
+
+    /// The record with the earliest timestamp, [`None`] if an unfinished partition has no
+    /// buffered record.
+    fn pop(&mut self) -> Option<OwnedMessage> {
+        if self
+            .buffers
+            .iter()
+            .any(|(p, b)| b.is_empty() && !self.finished.contains(p))
+        {
+            return None;
+        }
+        let (_, partition) = self
+            .buffers
+            .iter()
+            .filter_map(|(p, b)| {
+                b.front()
+                    .map(|m| (m.timestamp().to_millis().unwrap_or(i64::MIN), *p))
+            })
+            .min()?;
+
+        self.buffers.get_mut(&partition)?.pop_front()
+    }
+}
+
+fn partition_list(topic: &str, partition: i32) -> TopicPartitionList {
+    let mut tpl = TopicPartitionList::new();
+    tpl.add_partition(topic, partition);
+    tpl
+}
+
+async fn bootstrap(config: Kafka, fhir: &Fhir) -> anyhow::Result<()> {
+    let resources = ResourceMap::new(fhir)?;
+    let bundle = bootstrap_bundle(fhir, &resources, Local::now().date_naive())?;
+    let output_topic = config.output_topic.clone();
+    let producer = create_producer(config);
+
+    producer
+        .send(
+            FutureRecord::to(&output_topic)
+                .key("bootstrap")
+                .payload(&bundle),
+            TIMEOUT,
+        )
+        .await
+        .map_err(|(e, _)| e)?;
+    println!(
+        "{} department(s) and {} ward(s) produced to {output_topic}",
+        resources.department_map.len(),
+        resources.ward_map.len()
+    );
+    Ok(())
+}
+
+/// Record to produce a consumed record with its key, payload and headers to another topic.
+fn forward_record<'a>(m: &'a OwnedMessage, topic: &'a str) -> FutureRecord<'a, [u8], [u8]> {
+    let mut record = FutureRecord::<[u8], [u8]>::to(topic);
+    if let Some(key) = m.key() {
+        record = record.key(key);
+    }
+    if let Some(payload) = m.payload() {
+        record = record.payload(payload);
+    }
+    if let Some(headers) = m.headers() {
+        record = record.headers(headers.clone());
+    }
+    record
+}
+
+/// Time of a record since the start of the replay, by its timestamp and the speed factor.
+fn replay_offset(first_timestamp: i64, timestamp: i64, speed: f64) -> Duration {
+    let gap = (timestamp - first_timestamp).max(0) as f64 / 1000.0;
+    Duration::from_secs_f64(gap / speed)
+}
+
+fn partitions(consumer: &BaseConsumer, topic: &str) -> anyhow::Result<Vec<i32>> {
+    let metadata = consumer.fetch_metadata(Some(topic), TIMEOUT)?;
+    let topic = metadata
+        .topics()
+        .iter()
+        .find(|t| t.name() == topic)
+        .ok_or_else(|| anyhow!("topic {topic} not found"))?;
+
+    Ok(topic.partitions().iter().map(|p| p.id()).collect())
+}
+
+/// Reads a single record by offset, [`None`] if the offset doesn't exist (e.g. compacted).
+fn read_record(
+    consumer: &BaseConsumer,
+    topic: &str,
+    partition: i32,
+    offset: i64,
+) -> anyhow::Result<Option<OwnedMessage>> {
+    let mut tpl = TopicPartitionList::new();
+    tpl.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+    consumer.assign(&tpl)?;
+
+    match consumer.poll(TIMEOUT) {
+        Some(m) => {
+            let m = m?;
+            Ok((m.offset() == offset).then(|| m.detach()))
+        }
+        None => Ok(None),
+    }
+}
+
+fn create_consumer(config: &Kafka) -> anyhow::Result<BaseConsumer> {
+    let mut c = client_config(config);
+    // offsets of the consumer group are never committed
+    c.set("group.id", config.consumer_group.as_str())
+        .set("enable.auto.commit", "false")
+        .set("enable.partition.eof", "false");
+
+    Ok(c.create()?)
+}
+
+fn client_config(config: &Kafka) -> ClientConfig {
+    let mut c = ClientConfig::new();
+    c.set("bootstrap.servers", config.brokers.as_str())
+        .set("security.protocol", config.security_protocol.as_str());
+
+    set_ssl_config(c, config.ssl.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::admin::{Command, TimestampMerge, check_purge, replay_offset};
+    use crate::config::{Kafka, Lane};
+    use rdkafka::message::OwnedMessage;
+    use rdkafka::{Message, Timestamp};
+    use std::time::Duration;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_command() {
+        assert_eq!(Command::parse(&args(&["lag"])).unwrap(), Command::Lag);
+        assert_eq!(
+            Command::parse(&args(&["dlq-redrive", "adt-dlq", "0:12", "1:3"])).unwrap(),
+            Command::DlqRedrive {
+                topic: "adt-dlq".to_string(),
+                offsets: vec![(0, 12), (1, 3)],
+            }
+        );
+        assert_eq!(
+            Command::parse(&args(&["purge", "adt-quarantine"])).unwrap(),
+            Command::Purge {
+                topic: "adt-quarantine".to_string()
+            }
+        );
+        assert_eq!(
+            Command::parse(&args(&["replay", "adt-prod", "10"])).unwrap(),
+            Command::Replay {
+                topic: "adt-prod".to_string(),
+                speed: 10.0,
+            }
+        );
+        assert_eq!(
+            Command::parse(&args(&["replay", "adt-prod"])).unwrap(),
+            Command::Replay {
+                topic: "adt-prod".to_string(),
+                speed: 1.0,
+            }
+        );
+        assert_eq!(
+            Command::parse(&args(&["bootstrap"])).unwrap(),
+            Command::Bootstrap
+        );
+    }
+
+    #[test]
+    fn parse_invalid_command() {
+        assert!(Command::parse(&[]).is_err());
+        assert!(Command::parse(&args(&["drain"])).is_err());
+        assert!(Command::parse(&args(&["dlq-list"])).is_err());
+        assert!(Command::parse(&args(&["dlq-redrive", "adt-dlq"])).is_err());
+        assert!(Command::parse(&args(&["dlq-redrive", "adt-dlq", "12"])).is_err());
+        assert!(Command::parse(&args(&["replay"])).is_err());
+        assert!(Command::parse(&args(&["replay", "adt-prod", "0"])).is_err());
+        assert!(Command::parse(&args(&["replay", "adt-prod", "fast"])).is_err());
+    }
+
+    #[test]
+    fn purge_processor_topics() {
+        let config = Kafka {
+            input_topic: "adt".to_string(),
+            output_topic: "fhir".to_string(),
+            lanes: vec![Lane {
+                topic: "adt-priority".to_string(),
+                weight: 1,
+            }],
+            ..Default::default()
+        };
+
+        assert!(check_purge(&config, "adt-quarantine").is_ok());
+        assert!(check_purge(&config, "adt").is_err());
+        assert!(check_purge(&config, "adt-priority").is_err());
+        assert!(check_purge(&config, "fhir").is_err());
+    }
+
+    fn record(partition: i32, offset: i64, timestamp: i64) -> OwnedMessage {
+        OwnedMessage::new(
+            None,
+            None,
+            "adt-prod".to_string(),
+            Timestamp::CreateTime(timestamp),
+            partition,
+            offset,
+            None,
+        )
+    }
+
+    #[test]
+    fn replay_merges_partitions_by_timestamp() {
+        let mut merge = TimestampMerge::new(&[0, 1]);
+        merge.push(record(0, 0, 3_000));
+        // partition 1 has no record yet
+        assert!(merge.pop().is_none());
+
+        merge.push(record(1, 0, 1_000));
+        merge.push(record(1, 1, 4_000));
+        merge.finish(1);
+        assert_eq!(
+            merge.pop().map(|m| m.timestamp()),
+            Some(Timestamp::CreateTime(1_000))
+        );
+        assert_eq!(
+            merge.pop().map(|m| m.timestamp()),
+            Some(Timestamp::CreateTime(3_000))
+        );
+        // partition 0 is unfinished
+        assert!(merge.pop().is_none());
+
+        merge.finish_pending();
+        assert_eq!(
+            merge.pop().map(|m| m.timestamp()),
+            Some(Timestamp::CreateTime(4_000))
+        );
+        assert!(merge.is_done());
+    }
+
+    #[test]
+    fn replay_time_gaps() {
+        assert_eq!(replay_offset(1_000, 1_000, 1.0), Duration::ZERO);
+        assert_eq!(replay_offset(1_000, 61_000, 1.0), Duration::from_secs(60));
+        assert_eq!(replay_offset(1_000, 61_000, 10.0), Duration::from_secs(6));
+        assert_eq!(replay_offset(1_000, 61_000, 0.5), Duration::from_secs(120));
+        // out of order records are produced immediately
+        assert_eq!(replay_offset(61_000, 1_000, 1.0), Duration::ZERO);
+    }
+}
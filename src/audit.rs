@@ -0,0 +1,166 @@
+use crate::config::AuditConfig;
+use anyhow::anyhow;
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Append-only audit log of the entries written per patient, for data protection audits.
+///
+/// Patient identifiers are logged as salted hash only.
+pub(crate) struct AuditLog {
+    file: Option<Mutex<File>>,
+    pub(crate) topic: Option<String>,
+    salt: String,
+}
+
+/// Audit record of a produced bundle entry.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AuditRecord {
+    pub(crate) timestamp: String,
+    /// hashed patient identifier (PID-2)
+    pub(crate) patient: String,
+    pub(crate) resource_type: String,
+    pub(crate) method: String,
+    /// conditional request url
+    pub(crate) url: String,
+}
+
+impl AuditLog {
+    pub(crate) fn new(config: AuditConfig) -> anyhow::Result<Self> {
+        if config.file.is_none() && config.topic.is_none() {
+            return Err(anyhow!("audit requires a file or a topic"));
+        }
+        // unsalted hashes of the (numeric) patient identifiers are easily reversed
+        if config.salt.trim().is_empty() {
+            return Err(anyhow!("audit requires a salt"));
+        }
+        let file = match config.file {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+
+        Ok(AuditLog {
+            file,
+            topic: config.topic,
+            salt: config.salt,
+        })
+    }
+
+    /// Audit records of all entries of the bundle.
+    pub(crate) fn records(&self, patient_id: &str, bundle: &str) -> Vec<AuditRecord> {
+        let Ok(bundle) = serde_json::from_str::<Value>(bundle) else {
+            return vec![];
+        };
+        let timestamp = Utc::now().to_rfc3339();
+        let patient = self.hash(patient_id);
+
+        bundle
+            .get("entry")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.get("request"))
+            .filter_map(|request| {
+                let url = request.get("url")?.as_str()?;
+                Some(AuditRecord {
+                    timestamp: timestamp.clone(),
+                    patient: patient.clone(),
+                    // the request url starts with the resource type, also for patch and delete
+                    resource_type: url.split(['?', '/']).next()?.to_string(),
+                    method: request.get("method")?.as_str()?.to_string(),
+                    url: url.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Appends the records to the audit file, one JSON object per line.
+    pub(crate) fn append(&self, records: &[AuditRecord]) -> anyhow::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let mut file = file
+            .lock()
+            .map_err(|_| anyhow!("audit file lock poisoned"))?;
+        for record in records {
+            writeln!(file, "{}", serde_json::to_string(record)?)?;
+        }
+        file.flush()?;
+
+        Ok(())
+    }
+
+    fn hash(&self, patient_id: &str) -> String {
+        let namespace = Uuid::new_v5(&Uuid::NAMESPACE_OID, self.salt.as_bytes());
+        Uuid::new_v5(&namespace, patient_id.as_bytes()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fhir::mapper::FhirMapper;
+    use crate::test_utils::tests::{get_test_config, read_test_resource};
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn audit_records_of_bundle() {
+        let path = env::temp_dir().join(format!("adt-to-fhir-audit-{}.log", process::id()));
+        let _ = fs::remove_file(&path);
+        let audit = AuditLog::new(AuditConfig {
+            file: Some(path.display().to_string()),
+            topic: None,
+            salt: "secret".to_string(),
+        })
+        .unwrap();
+        let bundle = FhirMapper::new(get_test_config())
+            .unwrap()
+            .map(&read_test_resource("a01_test.hl7"))
+            .unwrap()
+            .unwrap();
+
+        let records = audit.records("9999999", &bundle);
+
+        assert!(records.iter().any(|r| r.resource_type == "Patient"));
+        assert!(records.iter().all(|r| r.patient != "9999999"));
+        assert!(records.iter().all(|r| r.patient == records[0].patient));
+
+        audit.append(&records).unwrap();
+        let lines = fs::read_to_string(&path).unwrap();
+        assert_eq!(lines.lines().count(), records.len());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn audit_requires_sink() {
+        assert!(
+            AuditLog::new(AuditConfig {
+                file: None,
+                topic: None,
+                salt: "secret".to_string(),
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn audit_requires_salt() {
+        assert!(
+            AuditLog::new(AuditConfig {
+                file: None,
+                topic: Some("audit".to_string()),
+                salt: " ".to_string(),
+            })
+            .is_err()
+        );
+    }
+}
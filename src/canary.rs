@@ -0,0 +1,141 @@
+use crate::config::CanaryConfig;
+use crate::fhir::mapper::FhirMapper;
+use crate::metrics::canary_count;
+use anyhow::anyhow;
+use log::{debug, warn};
+use opentelemetry::KeyValue;
+use serde_json::Value;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Shadow mapping of a share of the records with the canary mapping config, to validate a new
+/// mapping in production. The results are produced to the shadow topic and compared with the
+/// stable mapping.
+///
+/// __note:__ the canary maps the unprocessed record with all of its own mapping settings, the
+/// filter and rule stages of the stable pipeline don't apply
+pub(crate) struct Canary {
+    mapper: FhirMapper,
+    percentage: u8,
+    pub(crate) topic: String,
+}
+
+/// Result of the canary mapping compared to the stable one.
+#[derive(Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    Different,
+    Error,
+}
+
+impl Comparison {
+    fn name(&self) -> &'static str {
+        match self {
+            Comparison::Equal => "equal",
+            Comparison::Different => "different",
+            Comparison::Error => "error",
+        }
+    }
+}
+
+impl Canary {
+    pub(crate) fn new(config: CanaryConfig) -> anyhow::Result<Self> {
+        if config.percentage > 100 {
+            return Err(anyhow!("canary.percentage must be between 0 and 100"));
+        }
+
+        Ok(Canary {
+            mapper: FhirMapper::new(config.fhir)?,
+            percentage: config.percentage,
+            topic: config.topic,
+        })
+    }
+
+    /// Records are sampled by key, so all messages of a key take the same path.
+    pub(crate) fn is_sampled(&self, key: &str) -> bool {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() % 100 < self.percentage as u64
+    }
+
+    /// Maps the payload with the canary mapping and compares the result with the stable bundle.
+    pub(crate) fn process(&self, key: &str, payload: &str, stable: &str) -> Option<String> {
+        let (comparison, result) = match self.mapper.map(payload) {
+            Ok(Some(bundle)) => (compare(stable, &bundle), Some(bundle)),
+            Ok(None) => (Comparison::Different, None),
+            Err(e) => {
+                warn!("Canary mapping failed for [key={key}]: {e}");
+                (Comparison::Error, None)
+            }
+        };
+        debug!("Canary mapping of [key={key}]: {}", comparison.name());
+        canary_count().add(1, &[KeyValue::new("result", comparison.name())]);
+
+        result
+    }
+}
+
+/// Compares the bundles, ignoring the bundle's `meta.lastUpdated`.
+fn compare(stable: &str, canary: &str) -> Comparison {
+    let parse = |bundle: &str| {
+        serde_json::from_str::<Value>(bundle).map(|mut v| {
+            if let Some(meta) = v.get_mut("meta").and_then(Value::as_object_mut) {
+                meta.remove("lastUpdated");
+            }
+            v
+        })
+    };
+
+    match (parse(stable), parse(canary)) {
+        (Ok(s), Ok(c)) if s == c => Comparison::Equal,
+        (Ok(_), Ok(_)) => Comparison::Different,
+        _ => Comparison::Error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tests::{get_test_config, read_test_resource};
+
+    fn canary(percentage: u8) -> Canary {
+        Canary::new(CanaryConfig {
+            percentage,
+            topic: "canary".to_string(),
+            fhir: get_test_config(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn canary_sampling() {
+        assert!(!canary(0).is_sampled("test"));
+        assert!(canary(100).is_sampled("test"));
+        assert!(
+            Canary::new(CanaryConfig {
+                percentage: 101,
+                topic: "canary".to_string(),
+                fhir: get_test_config(),
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn canary_compares_bundles() {
+        let canary = canary(100);
+        let payload = read_test_resource("a01_test.hl7");
+        let stable = FhirMapper::new(get_test_config())
+            .unwrap()
+            .map(&payload)
+            .unwrap()
+            .unwrap();
+
+        let result = canary.process("test", &payload, &stable).unwrap();
+
+        assert_eq!(compare(&stable, &result), Comparison::Equal);
+        assert_eq!(
+            compare(&stable, &result.replace("Patient", "Person")),
+            Comparison::Different
+        );
+    }
+}
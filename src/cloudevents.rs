@@ -0,0 +1,118 @@
+use crate::hl7::parser::MessageInfo;
+use chrono::DateTime;
+use serde_json::{Value, json};
+
+/// Content type of structured mode CloudEvents (Kafka `content-type` header).
+pub(crate) const CONTENT_TYPE: &str = "application/cloudevents+json; charset=UTF-8";
+
+const SPEC_VERSION: &str = "1.0";
+const DATA_CONTENT_TYPE: &str = "application/fhir+json";
+/// source, if the sending facility (MSH-4) is missing
+const DEFAULT_SOURCE: &str = "adt-to-fhir";
+
+/// CloudEvents v1.0 context attributes, taken from the header of the source message.
+#[derive(Debug, PartialEq)]
+pub(crate) struct EventAttributes {
+    /// message control id (MSH-10), the record key as fallback
+    id: String,
+    /// sending facility (MSH-4)
+    source: String,
+    /// e.g. `adt.A01` (MSH-9.2)
+    r#type: String,
+    /// message creation time (MSH-7)
+    time: Option<String>,
+}
+
+impl EventAttributes {
+    pub(crate) fn new(info: &MessageInfo, key: &str) -> Self {
+        EventAttributes {
+            id: info.control_id.as_deref().unwrap_or(key).to_string(),
+            source: info
+                .sending_facility
+                .as_deref()
+                .unwrap_or(DEFAULT_SOURCE)
+                .to_string(),
+            r#type: match &info.trigger_event {
+                Some(event) => format!("adt.{event}"),
+                None => "adt".to_string(),
+            },
+            time: info
+                .created
+                .and_then(DateTime::from_timestamp_millis)
+                .map(|t| t.to_rfc3339()),
+        }
+    }
+
+    /// Wraps the bundle in a structured mode JSON envelope.
+    pub(crate) fn wrap(&self, bundle: &str) -> serde_json::Result<String> {
+        let mut event = json!({
+            "specversion": SPEC_VERSION,
+            "id": self.id,
+            "source": self.source,
+            "type": self.r#type,
+            "datacontenttype": DATA_CONTENT_TYPE,
+            "data": serde_json::from_str::<Value>(bundle)?,
+        });
+        if let Some(time) = &self.time {
+            event["time"] = json!(time);
+        }
+
+        serde_json::to_string(&event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cloudevents::EventAttributes;
+    use crate::hl7::parser::MessageInfo;
+    use hl7_parser::Message;
+    use serde_json::Value;
+
+    #[test]
+    fn wrap_bundle() {
+        let raw = "MSH|^~\\&|ORBIS|KH|RECAPP|ORBIS|20211122103015||ADT^A01|62293727|P|2.5";
+        let info = MessageInfo::new(&Message::parse_with_lenient_newlines(raw, true).unwrap());
+
+        let event = EventAttributes::new(&info, "key")
+            .wrap(r#"{"resourceType":"Bundle"}"#)
+            .unwrap();
+        let event: Value = serde_json::from_str(&event).unwrap();
+
+        assert_eq!(event["specversion"], "1.0");
+        assert_eq!(event["id"], "62293727");
+        assert_eq!(event["source"], "KH");
+        assert_eq!(event["type"], "adt.A01");
+        assert_eq!(event["time"], "2021-11-22T09:30:15+00:00");
+        assert_eq!(event["datacontenttype"], "application/fhir+json");
+        assert_eq!(event["data"]["resourceType"], "Bundle");
+    }
+
+    #[test]
+    fn custom_separators() {
+        // encoding characters of MSH-1 and MSH-2
+        let raw =
+            "MSH#@~\\&#ORBIS#KH@UKM#RECAPP#ORBIS#20211122103015##ADT@A01@ADT_A01#62293727#P#2.5";
+        let info = MessageInfo::new(&Message::parse_with_lenient_newlines(raw, true).unwrap());
+
+        let attributes = EventAttributes::new(&info, "key");
+
+        assert_eq!(attributes.id, "62293727");
+        assert_eq!(attributes.source, "KH");
+        assert_eq!(attributes.r#type, "adt.A01");
+    }
+
+    #[test]
+    fn attributes_fallback() {
+        let attributes = EventAttributes::new(&MessageInfo::default(), "key");
+
+        assert_eq!(
+            attributes,
+            EventAttributes {
+                id: "key".to_string(),
+                source: "adt-to-fhir".to_string(),
+                r#type: "adt".to_string(),
+                time: None,
+            }
+        );
+    }
+}
@@ -1,12 +1,19 @@
+use crate::hl7::parser::MessageType;
 use anyhow::anyhow;
 use config::{Config, Environment, File};
+use fhir_model::r4b::codes::EncounterStatus;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
 use validator::Validate;
 
 #[derive(Default, Debug, Deserialize, Clone)]
 pub(crate) struct App {
     pub(crate) log_level: String,
     pub(crate) telemetry_endpoint: String,
+    /// marker file, which exists while the processor is ready
+    #[serde(default)]
+    pub(crate) readiness_file: Option<String>,
 }
 
 #[derive(Default, Deserialize, Clone, Debug, Validate)]
@@ -20,6 +27,90 @@ pub(crate) struct Kafka {
     pub(crate) offset_reset: String,
     #[validate(range(min = 1, max = 20))]
     pub(crate) num_partitions: i32,
+    /// failed attempts to produce a record, before consumption is paused
+    #[serde(default = "Kafka::produce_retries")]
+    #[validate(range(min = 1))]
+    pub(crate) produce_retries: u32,
+    /// priority lanes, which are consumed in addition to the input topic
+    #[serde(default)]
+    #[validate(nested)]
+    pub(crate) lanes: Vec<Lane>,
+    /// maximum age of consumed records in days (record timestamp), unlimited if missing
+    #[serde(default)]
+    pub(crate) max_message_age: Option<u32>,
+    /// handling of records exceeding `max_message_age`
+    #[serde(default)]
+    pub(crate) max_message_age_action: MessageAgeAction,
+    /// confirms, that the first consumed record may exceed `max_message_age`
+    #[serde(default)]
+    pub(crate) confirm_replay: bool,
+    /// format of the produced records
+    #[serde(default)]
+    pub(crate) output_format: OutputFormat,
+    /// timestamp of the produced records
+    #[serde(default)]
+    pub(crate) output_timestamp: OutputTimestamp,
+    /// topic of the records left to manual review (e.g. A47), unchanged with their headers
+    #[serde(default)]
+    pub(crate) review_topic: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputTimestamp {
+    /// timestamp of the consumed record
+    #[default]
+    Input,
+    /// time of producing the record
+    Now,
+    /// event time (EVN-2), the timestamp of the consumed record if missing
+    Event,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// the FHIR bundle
+    #[default]
+    Bundle,
+    /// the FHIR bundle wrapped in a CloudEvents (v1.0) structured mode envelope
+    CloudEvents,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageAgeAction {
+    /// skip the record (offset is stored)
+    Skip,
+    /// log a warning and process the record
+    #[default]
+    Warn,
+    /// process the record
+    Process,
+}
+
+impl Kafka {
+    /// Topics to consume and their scheduling weight. Priority lanes come first,
+    /// the input topic has a weight of 1.
+    pub(crate) fn topic_lanes(&self) -> Vec<(&str, u32)> {
+        self.lanes
+            .iter()
+            .map(|l| (l.topic.as_str(), l.weight))
+            .chain([(self.input_topic.as_str(), 1)])
+            .collect()
+    }
+
+    fn produce_retries() -> u32 {
+        3
+    }
+}
+
+#[derive(Default, Deserialize, Clone, Debug, Validate)]
+pub(crate) struct Lane {
+    pub(crate) topic: String,
+    /// records in a row taken from this lane, before the next lane is served
+    #[validate(range(min = 1))]
+    pub(crate) weight: u32,
 }
 
 #[derive(Deserialize, Clone)]
@@ -31,9 +122,216 @@ pub(crate) struct Fhir {
     pub(crate) fall: FallConfig,
     pub(crate) location: LocationConfig,
     pub(crate) meta_source: String,
-    pub(crate) condition: SystemConfig,
+    pub(crate) condition: ConditionConfig,
+    #[serde(default)]
+    pub(crate) procedure: ProcedureConfig,
+    #[serde(default)]
+    pub(crate) coverage: CoverageConfig,
+    /// physicians (PV1-7, PV1-8, PV1-17, ROL), not mapped if missing
+    #[serde(default)]
+    pub(crate) practitioner: Option<PractitionerConfig>,
+    /// billing cases (_Abrechnungsfall_, PID-18), not mapped if missing
+    #[serde(default)]
+    pub(crate) account: Option<AccountConfig>,
     pub(crate) observation: ObservationConfig,
     pub(crate) organization: OrganizationConfig,
+    /// attach a redacted segment sample to warnings about unknown code values
+    #[serde(default)]
+    pub(crate) unknown_code_samples: bool,
+    #[serde(default)]
+    pub(crate) vip: VipConfig,
+    /// canonical base url of the target FHIR store
+    #[serde(default)]
+    pub(crate) base_url: Option<String>,
+    /// emit absolute references (prefixed with `base_url`) for `serviceProvider` and `location`
+    #[serde(default)]
+    pub(crate) absolute_references: bool,
+    /// preferred source of the message type, the other one is the fallback
+    #[serde(default)]
+    pub(crate) message_type_source: MessageTypeSource,
+    /// target FHIR server, sets the defaults of `bundle_type`, `identifier_escaping` and
+    /// `reference_resolution`
+    #[serde(default)]
+    pub(crate) target_preset: Option<TargetPreset>,
+    #[serde(default)]
+    pub(crate) bundle_type: Option<BundleKind>,
+    /// escaping of identifier values in conditional references and request urls
+    #[serde(default)]
+    pub(crate) identifier_escaping: Option<IdentifierEscaping>,
+    #[serde(default)]
+    pub(crate) reference_resolution: Option<ReferenceResolution>,
+    /// resource types in the order of the bundle entries, defaults to the dependency order
+    #[serde(default)]
+    pub(crate) entry_order: Vec<String>,
+    /// identifier of the conditional request urls and references
+    #[serde(default)]
+    pub(crate) identifier_selection: IdentifierSelection,
+    /// protected (e.g. psychiatric) units, whose diagnoses and exact locations are suppressed
+    #[serde(default)]
+    pub(crate) protected_units: ProtectedUnitsConfig,
+    /// namespace of the UUIDv5 `fullUrl`s, derived from `facility_id` if missing
+    #[serde(default)]
+    pub(crate) full_url_namespace: Option<Uuid>,
+    /// resources of this deployment, to scale demographic and encounter processing independently
+    #[serde(default)]
+    pub(crate) role: Role,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// all resources
+    #[default]
+    Full,
+    /// `Patient`, `RelatedPerson` and the managing organization only
+    Demographics,
+    /// all resources except the demographics, patients are referenced by conditional create
+    Encounters,
+}
+
+impl Fhir {
+    /// Configured namespace of the `fullUrl`s, or the UUIDv5 of the `facility_id`.
+    pub(crate) fn full_url_namespace(&self) -> Uuid {
+        self.full_url_namespace
+            .unwrap_or_else(|| Uuid::new_v5(&Uuid::NAMESPACE_DNS, self.facility_id.as_bytes()))
+    }
+
+    /// Configured bundle type, or the one of the target preset.
+    pub(crate) fn bundle_type(&self) -> BundleKind {
+        self.bundle_type
+            .or(self.target_preset.map(TargetPreset::bundle_kind))
+            .unwrap_or_default()
+    }
+
+    /// Configured identifier escaping, or the one of the target preset.
+    pub(crate) fn identifier_escaping(&self) -> IdentifierEscaping {
+        self.identifier_escaping
+            .or(self.target_preset.map(TargetPreset::identifier_escaping))
+            .unwrap_or_default()
+    }
+
+    /// Configured reference resolution (conditional reference style), or the one of the target
+    /// preset.
+    pub(crate) fn reference_resolution(&self) -> ReferenceResolution {
+        self.reference_resolution
+            .or(self.target_preset.map(TargetPreset::reference_resolution))
+            .unwrap_or_default()
+    }
+}
+
+/// Settings for well-known FHIR servers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetPreset {
+    Hapi,
+    Blaze,
+    Firely,
+}
+
+impl TargetPreset {
+    pub(crate) fn bundle_kind(self) -> BundleKind {
+        match self {
+            // the entries reference each other conditionally, which is resolved in transactions
+            // only, and all of them support transactions
+            TargetPreset::Hapi | TargetPreset::Blaze | TargetPreset::Firely => {
+                BundleKind::Transaction
+            }
+        }
+    }
+
+    pub(crate) fn identifier_escaping(self) -> IdentifierEscaping {
+        match self {
+            // HAPI decodes the url, but splits token values at unescaped '|' and ','
+            TargetPreset::Hapi => IdentifierEscaping::Search,
+            // Blaze rejects request urls with reserved characters (e.g. spaces)
+            TargetPreset::Blaze | TargetPreset::Firely => IdentifierEscaping::Url,
+        }
+    }
+
+    pub(crate) fn reference_resolution(self) -> ReferenceResolution {
+        match self {
+            // Blaze rejects conditional references to resources, which don't exist yet
+            TargetPreset::Blaze => ReferenceResolution::Inline,
+            TargetPreset::Hapi | TargetPreset::Firely => ReferenceResolution::Conditional,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleKind {
+    #[default]
+    Transaction,
+    Batch,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReferenceResolution {
+    /// conditional references (`Type?identifier=system|value`), resolved by the server
+    #[default]
+    Conditional,
+    /// references point to bundle entries, missing targets are added as conditionally
+    /// created stubs
+    Inline,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentifierEscaping {
+    /// values are used as is
+    #[default]
+    None,
+    /// FHIR search escaping of `\`, `|`, `,` and `$`
+    Search,
+    /// percent-encoding of all but unreserved characters
+    Url,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct IdentifierSelection {
+    /// identifier use, if none of the prioritized systems is present
+    #[serde(default, rename = "use")]
+    pub(crate) r#use: SelectedIdentifierUse,
+    /// identifier systems in order of priority
+    #[serde(default)]
+    pub(crate) system_priority: Vec<String>,
+}
+
+impl IdentifierSelection {
+    /// Whether the selection differs from the default, the first usual identifier.
+    pub(crate) fn is_configured(&self) -> bool {
+        !self.system_priority.is_empty() || self.r#use != SelectedIdentifierUse::Usual
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectedIdentifierUse {
+    #[default]
+    Usual,
+    Official,
+    Secondary,
+}
+
+impl SelectedIdentifierUse {
+    pub(crate) fn code(self) -> &'static str {
+        match self {
+            SelectedIdentifierUse::Usual => "usual",
+            SelectedIdentifierUse::Official => "official",
+            SelectedIdentifierUse::Secondary => "secondary",
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageTypeSource {
+    /// EVN-1
+    #[default]
+    Evn,
+    /// MSH-9.2
+    Msh,
 }
 
 #[derive(Default, Debug, Deserialize, Clone)]
@@ -41,16 +339,621 @@ pub(crate) struct PatientConfig {
     pub(crate) profile: String,
     pub(crate) system: String,
     pub(crate) other_insurance_system: String,
+    #[serde(default)]
+    pub(crate) delete_person_information: PatientDeletionMode,
+    /// test patients, which are not mapped
+    #[serde(default)]
+    pub(crate) exclude: PatientExclusionConfig,
+    /// next of kin (NK1)
+    #[serde(default)]
+    pub(crate) contact: ContactConfig,
+    /// values of elements, which are missing in the message
+    #[serde(default)]
+    pub(crate) defaults: PatientDefaults,
+    /// message types (e.g. movement-only events A02, A03), which don't map the `Patient`
+    #[serde(default)]
+    pub(crate) skip_message_types: Vec<MessageType>,
+    /// additional identifiers of the repeating PID-3 by identifier type code (PID-3.5)
+    #[serde(default)]
+    pub(crate) identifier_types: Vec<IdentifierTypeConfig>,
+    /// local language codes (PID-15), translated to BCP-47 language tags
+    #[serde(default)]
+    pub(crate) languages: Vec<LanguageConfig>,
+    /// local marital status codes (PID-16), translated to v3-MaritalStatus codes
+    #[serde(default)]
+    pub(crate) marital_statuses: Vec<MaritalStatusConfig>,
+    /// normalization of name parts (PID-5, PID-6, NK1-2)
+    #[serde(default)]
+    pub(crate) name_normalization: NameNormalizationConfig,
+    /// religion (PID-17) as `patient-religion` extension, not mapped if missing
+    #[serde(default)]
+    pub(crate) religion: Option<ReligionConfig>,
+    /// guarantor employer (GT1-16, GT1-17), not mapped if missing
+    #[serde(default)]
+    pub(crate) employer: Option<EmployerConfig>,
+    /// lookup key of the Gemeindeschlüssel of the addresses (`Gemeindeschluessel.json`), not
+    /// mapped if missing
+    #[serde(default)]
+    pub(crate) municipality_key: Option<MunicipalityKey>,
+    /// evidence of the death of the patient (PID-29, PID-30)
+    #[serde(default)]
+    pub(crate) deceased_rule: DeceasedRule,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DeceasedRule {
+    /// the date of death (PID-29) alone implies deceased, the indicator (PID-30) is used
+    /// without date
+    #[default]
+    Date,
+    /// deceased requires the indicator (PID-30 = Y), the date of death is added if present
+    Indicator,
+}
+
+/// Address component to look up the amtlicher Gemeindeschlüssel by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MunicipalityKey {
+    /// postal code (XAD-5)
+    PostalCode,
+    /// county code of the HIS (XAD-9)
+    CountyCode,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct EmployerConfig {
+    #[serde(default)]
+    pub(crate) target: EmployerTarget,
+    /// extension url (target `extension`)
+    #[serde(default)]
+    pub(crate) url: String,
+    /// `RelatedPerson` identifier system (target `related_person`)
+    #[serde(default)]
+    pub(crate) system: String,
+}
+
+impl PatientConfig {
+    /// Checks that only message types, which create or update the `Patient`, are skipped.
+    /// Deletions and merges (e.g. A29, A40) are always mapped.
+    pub(crate) fn check_skip_message_types(&self) -> anyhow::Result<()> {
+        match self.skip_message_types.iter().find(|t| {
+            !matches!(
+                t,
+                MessageType::A01
+                    | MessageType::A02
+                    | MessageType::A03
+                    | MessageType::A04
+                    | MessageType::A05
+                    | MessageType::A06
+                    | MessageType::A07
+                    | MessageType::A08
+                    | MessageType::A31
+            )
+        }) {
+            Some(t) => Err(anyhow!(
+                "fhir.person.skip_message_types: {t} can't be skipped"
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl EmployerConfig {
+    /// Checks that the configured target has its extension url or identifier system.
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        match self.target {
+            EmployerTarget::Extension if self.url.is_empty() => Err(anyhow!(
+                "fhir.person.employer.url is required for the target 'extension'"
+            )),
+            EmployerTarget::RelatedPerson if self.system.is_empty() => Err(anyhow!(
+                "fhir.person.employer.system is required for the target 'related_person'"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmployerTarget {
+    /// complex `Patient` extension with `name` and `address`
+    #[default]
+    Extension,
+    /// `RelatedPerson` with the relationship `E` (Employer, HL7 table 0131)
+    RelatedPerson,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct ReligionConfig {
+    /// local religion codes, translated to v3-ReligiousAffiliation codes
+    #[serde(default)]
+    pub(crate) codes: Vec<ReligionCodeConfig>,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct ReligionCodeConfig {
+    /// local religion code
+    pub(crate) code: String,
+    /// v3-ReligiousAffiliation code, e.g. 1041 (Roman Catholic Church)
+    pub(crate) religion: String,
+    pub(crate) display: Option<String>,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct NameNormalizationConfig {
+    /// decode HL7 escape sequences, e.g. `\XFC\` or `\XC3BC\` for `ü`
+    #[serde(default)]
+    pub(crate) unescape: bool,
+    /// restore UTF-8 names, which were decoded as Latin-1 upstream, e.g. `MÃ¼ller`
+    #[serde(default)]
+    pub(crate) repair_encoding: bool,
+    /// Unicode NFC normalization, e.g. of combining diacritical marks
+    #[serde(default)]
+    pub(crate) nfc: bool,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct MaritalStatusConfig {
+    /// local marital status code
+    pub(crate) code: String,
+    /// v3-MaritalStatus code, e.g. M or W
+    pub(crate) marital_status: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct LanguageConfig {
+    /// local language code
+    pub(crate) code: String,
+    /// BCP-47 language tag, e.g. de or en-GB
+    pub(crate) language: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct IdentifierTypeConfig {
+    /// HL7 identifier type code (PID-3.5), e.g. PI, GKV or PKV
+    pub(crate) code: String,
+    /// identifier system
+    pub(crate) system: String,
+    /// `Identifier.type` code system
+    pub(crate) type_system: String,
+    /// `Identifier.type` code, e.g. KVZ10 or PKV (identifier-type-de-basis)
+    pub(crate) type_code: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct PatientDefaults {
+    /// `Patient.gender`, if PID-8 is missing
+    #[serde(default)]
+    pub(crate) gender: Option<DefaultGender>,
+    /// `Patient.address.country`, if the address has no country
+    #[serde(default)]
+    pub(crate) country: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultGender {
+    Male,
+    Female,
+    Other,
+    Unknown,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct ContactConfig {
+    /// emit `RelatedPerson` resources in addition to `Patient.contact`
+    #[serde(default)]
+    pub(crate) related_person: bool,
+    /// `RelatedPerson` identifier system
+    #[serde(default)]
+    pub(crate) system: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct PatientExclusionConfig {
+    /// regular expressions, matched against the patient name (PID-5)
+    #[serde(default)]
+    pub(crate) names: Vec<String>,
+    /// patient identifier ranges (PID-3.1)
+    #[serde(default)]
+    pub(crate) id_ranges: Vec<IdRange>,
+}
+
+/// inclusive range of numeric identifiers
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct IdRange {
+    pub(crate) from: u64,
+    pub(crate) to: u64,
+}
+
+/// handling of deleted person information (A29)
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatientDeletionMode {
+    /// delete the patient
+    #[default]
+    Delete,
+    /// set the patient inactive
+    Deactivate,
 }
 
 #[derive(Default, Debug, Deserialize, Clone)]
 pub(crate) struct FallConfig {
     pub(crate) profile: String,
     pub(crate) system: String,
-    pub(crate) einrichtungskontakt: SystemConfig,
-    pub(crate) abteilungskontakt: SystemConfig,
-    pub(crate) versorgungsstellenkontakt: SystemConfig,
+    pub(crate) einrichtungskontakt: KontaktebeneConfig,
+    pub(crate) abteilungskontakt: KontaktebeneConfig,
+    pub(crate) versorgungsstellenkontakt: KontaktebeneConfig,
+    #[serde(default)]
+    pub(crate) delete_stale_visit_number: bool,
+    #[serde(default)]
+    pub(crate) cancel_admit: CancelAdmitMode,
+    #[serde(default)]
+    pub(crate) cancel_pending_admit: CancelPendingAdmitMode,
+    /// class and Kontaktart by patient type (PV1-18), PV1-2 is the fallback
+    #[serde(default)]
+    pub(crate) patient_types: Vec<PatientTypeConfig>,
+    /// site specific case category (e.g. ORBIS Fallart) as extension
+    #[serde(default)]
+    pub(crate) case_category: Option<CaseCategoryConfig>,
+    /// financial class (PV1-20) as extension
+    #[serde(default)]
+    pub(crate) financial_class: Option<FinancialClassConfig>,
+    /// Wahlleistungen (e.g. Chefarztbehandlung, Einbettzimmer) as extension
+    #[serde(default)]
+    pub(crate) wahlleistung: Option<WahlleistungConfig>,
+    /// Aufnahmeanlass (`Encounter.hospitalization.admitSource`)
+    #[serde(default)]
+    pub(crate) admit_source: AdmitSourceConfig,
+    /// message locations of the Aufnahmegrund (§301), e.g. a Z-segment field
+    #[serde(default)]
+    pub(crate) aufnahmegrund: AufnahmegrundConfig,
+    /// site specific discharge dispositions (PV1-36), translated to Entlassungsgrund codes
+    #[serde(default)]
+    pub(crate) discharge_reasons: Vec<DischargeReasonConfig>,
+    /// local admission type codes (PV2-25, PV1-4.1), translated to `Encounter.priority`
+    #[serde(default)]
+    pub(crate) priorities: Vec<PriorityConfig>,
+    /// unit of `Encounter.length`
+    #[serde(default)]
+    pub(crate) length_unit: LengthUnit,
+    /// sources of the `serviceType` in order of priority, defaults to `pv1_3`, `pv1_39`
+    #[serde(default)]
+    pub(crate) service_type_sources: Vec<ServiceTypeSource>,
+    /// identifier system of the `Encounter.account` reference (PID-18), defaults to
+    /// `fhir.account.system`
+    #[serde(default)]
+    pub(crate) account_system: Option<String>,
+    /// `Encounter.status` by message type (and class), checked in order. Derived from the
+    /// period if no transition matches
+    #[serde(default)]
+    pub(crate) status_transitions: Vec<StatusTransitionConfig>,
+}
+
+/// Transition of the `Encounter.status` by an event.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct StatusTransitionConfig {
+    /// message types, e.g. A03
+    pub(crate) message_types: Vec<MessageType>,
+    /// encounter class codes (e.g. AMB), all classes if empty
+    #[serde(default)]
+    pub(crate) classes: Vec<String>,
+    /// `Encounter.status` code, e.g. in-progress
+    pub(crate) status: EncounterStatus,
+}
+
+impl FallConfig {
+    /// Configured sources of the `serviceType`, or the default ones.
+    pub(crate) fn service_type_sources(&self) -> &[ServiceTypeSource] {
+        if self.service_type_sources.is_empty() {
+            &[ServiceTypeSource::Location, ServiceTypeSource::Pv1_39]
+        } else {
+            &self.service_type_sources
+        }
+    }
+
+    /// Configured identifier system of the `Encounter.account`, or the one of the mapped
+    /// `Account` resources. Not mapped if [`None`].
+    pub(crate) fn account_system<'a>(
+        &'a self,
+        account: Option<&'a AccountConfig>,
+    ) -> Option<&'a str> {
+        self.account_system
+            .as_deref()
+            .or(account.map(|a| a.system.as_str()))
+    }
+
+    /// Checks that the enabled Kontaktebenen have an identifier system.
+    pub(crate) fn check_kontaktebenen(&self) -> anyhow::Result<()> {
+        if !self.einrichtungskontakt.enabled {
+            return Err(anyhow!("fhir.fall.einrichtungskontakt can't be disabled"));
+        }
+        for (name, level) in [
+            ("einrichtungskontakt", &self.einrichtungskontakt),
+            ("abteilungskontakt", &self.abteilungskontakt),
+            ("versorgungsstellenkontakt", &self.versorgungsstellenkontakt),
+        ] {
+            if level.enabled && level.system.is_empty() {
+                return Err(anyhow!("fhir.fall.{name}.system is required"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Kontaktebene of the `Encounter` (Einrichtungs-, Abteilungs- or Versorgungsstellenkontakt).
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct KontaktebeneConfig {
+    /// identifier system
+    #[serde(default)]
+    pub(crate) system: String,
+    /// profile, defaults to `fhir.fall.profile`
+    #[serde(default)]
+    pub(crate) profile: Option<String>,
+    /// map the Kontaktebene, the Einrichtungskontakt is always mapped
+    #[serde(default = "KontaktebeneConfig::enabled")]
+    pub(crate) enabled: bool,
+}
+
+impl KontaktebeneConfig {
+    fn enabled() -> bool {
+        true
+    }
+}
+
+impl Default for KontaktebeneConfig {
+    fn default() -> Self {
+        KontaktebeneConfig {
+            system: String::new(),
+            profile: None,
+            enabled: true,
+        }
+    }
+}
+
+/// Source field of the `serviceType` (Fachabteilungsschlüssel).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceTypeSource {
+    /// department of the assigned patient location (PV1-3), mapped by `InfoByAbteilungskuerzel.json`
+    #[serde(rename = "pv1_3")]
+    Location,
+    /// Fachabteilungsschlüssel (PV1-39)
+    #[serde(rename = "pv1_39")]
+    Pv1_39,
+    /// hospital service (PV1-10)
+    #[serde(rename = "pv1_10")]
+    Pv1_10,
+    /// responsible medical department (ZBE-7.10)
+    #[serde(rename = "zbe_7")]
+    Zbe7,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthUnit {
+    #[default]
+    Days,
+    Hours,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct PriorityConfig {
+    /// local code (PV2-25 or PV1-4.1)
+    pub(crate) code: String,
+    /// v3-ActPriority code, e.g. EM, UR, EL
+    pub(crate) priority: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct DischargeReasonConfig {
+    /// site specific code (PV1-36)
+    pub(crate) code: String,
+    /// Entlassungsgrund (§301), 1. und 2. Stelle and optionally the 3. Stelle, e.g. 01 or 019
+    pub(crate) entlassgrund: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct AufnahmegrundConfig {
+    /// 1. und 2. Stelle, defaults to PV2.3.1
+    #[serde(default)]
+    pub(crate) erste_und_zweite: Option<String>,
+    /// 3. und 4. Stelle, defaults to PV1.4[2].1
+    #[serde(default)]
+    pub(crate) dritte_und_vierte: Option<String>,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct AdmitSourceConfig {
+    /// message location of the Aufnahmeanlass, e.g. PV1.14.1, defaults to PV1.4.1
+    #[serde(default)]
+    pub(crate) field: Option<String>,
+    /// site specific codes, translated to Aufnahmeanlass codes
+    #[serde(default)]
+    pub(crate) codes: Vec<AdmitSourceCodeConfig>,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct AdmitSourceCodeConfig {
+    /// site specific code
+    pub(crate) code: String,
+    /// Aufnahmeanlass code (dgkev/Aufnahmeanlass), e.g. E, N
+    pub(crate) aufnahmeanlass: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct CaseCategoryConfig {
+    /// message location of the case category, e.g. PV1.4.1 or ZBE.6
+    pub(crate) field: String,
+    /// extension url
+    pub(crate) url: String,
+    /// local code system
+    pub(crate) system: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct FinancialClassConfig {
+    /// extension url
+    pub(crate) url: String,
+    /// local code system
+    pub(crate) system: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct WahlleistungConfig {
+    /// message locations of the Wahlleistungen, e.g. PV2.11.1 or ZWL.1
+    pub(crate) fields: Vec<String>,
+    /// site specific codes, translated to Wahlleistung codes
+    pub(crate) codes: Vec<WahlleistungCodeConfig>,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct WahlleistungCodeConfig {
+    /// site specific code
+    pub(crate) code: String,
+    /// Wahlleistung code (http://fhir.de/CodeSystem/Wahlleistung)
+    pub(crate) wahlleistung: String,
+    pub(crate) display: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct PatientTypeConfig {
+    /// patient type (PV1-18)
+    pub(crate) code: String,
+    /// encounter class (v3-ActCode), e.g. IMP, AMB, SS
+    pub(crate) class: String,
+    /// Kontaktart (kontaktart-de), e.g. teilstationaer
+    pub(crate) kontaktart: Option<String>,
+}
+
+/// handling of cancelled admissions (A11)
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CancelAdmitMode {
+    /// delete the encounters
+    #[default]
+    Delete,
+    /// set the encounter status to 'entered-in-error'
+    EnteredInError,
+}
+
+/// handling of cancelled pending admissions (A27)
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CancelPendingAdmitMode {
+    /// delete the planned encounters
+    #[default]
+    Delete,
+    /// set the encounter status to 'cancelled'
+    Cancelled,
+}
+
+/// units, whose diagnoses and room/bed locations are not mapped
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct ProtectedUnitsConfig {
+    /// department codes (FAB)
+    #[serde(default)]
+    pub(crate) departments: Vec<String>,
+    /// ward codes (PV1-3.1)
+    #[serde(default)]
+    pub(crate) wards: Vec<String>,
+}
+
+/// security label for patients flagged as VIP in PV1-16
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct VipConfig {
+    pub(crate) enabled: bool,
+    /// PV1-16 values, which flag a VIP patient
+    pub(crate) indicators: Vec<String>,
+    pub(crate) system: String,
+    pub(crate) code: String,
+    /// url of an additional `Encounter` extension (boolean) for VIP patients
+    #[serde(default)]
+    pub(crate) extension: Option<String>,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct ConditionConfig {
+    pub(crate) system: String,
+    #[serde(default)]
+    pub(crate) profile: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct ProcedureConfig {
+    pub(crate) system: String,
+    pub(crate) profile: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct CoverageConfig {
+    pub(crate) system: String,
+    pub(crate) profile_gkv: String,
+    pub(crate) profile_pkv: String,
+    /// payer kind (IN1-9.5) to Versicherungsart, coverages of unknown kinds are not mapped
+    /// (except for a KVID-10 insurance number, which implies GKV)
+    #[serde(default = "CoverageConfig::kinds")]
+    pub(crate) kinds: HashMap<String, Versicherungsart>,
+}
+
+impl CoverageConfig {
+    pub(crate) fn kinds() -> HashMap<String, Versicherungsart> {
+        HashMap::from([
+            ("1".to_string(), Versicherungsart::Gkv),
+            ("8".to_string(), Versicherungsart::Pkv),
+        ])
+    }
+}
+
+/// Versicherungsart (`http://fhir.de/CodeSystem/versicherungsart-de-basis`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub(crate) enum Versicherungsart {
+    /// gesetzliche Krankenversicherung
+    Gkv,
+    /// private Krankenversicherung
+    Pkv,
+    /// Berufsgenossenschaft
+    Bg,
+    /// Selbstzahler
+    Sel,
+    /// Sozialamt
+    Soz,
+    /// gesetzliche Pflegeversicherung
+    Gpv,
+    /// private Pflegeversicherung
+    Ppv,
+    /// Beihilfe
+    Bei,
+}
+
+impl Versicherungsart {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Versicherungsart::Gkv => "GKV",
+            Versicherungsart::Pkv => "PKV",
+            Versicherungsart::Bg => "BG",
+            Versicherungsart::Sel => "SEL",
+            Versicherungsart::Soz => "SOZ",
+            Versicherungsart::Gpv => "GPV",
+            Versicherungsart::Ppv => "PPV",
+            Versicherungsart::Bei => "BEI",
+        }
+    }
 }
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct PractitionerConfig {
+    pub(crate) system: String,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct AccountConfig {
+    pub(crate) system: String,
+}
+
 #[derive(Default, Debug, Deserialize, Clone)]
 pub(crate) struct LocationConfig {
     pub(crate) system_ward: String,
@@ -65,12 +968,45 @@ pub(crate) struct ObservationConfig {
     pub(crate) profile_weight: String,
     pub(crate) profile_vital_status: String,
     pub(crate) profile_height: String,
+    /// OBX-3 translation table, OBX segments of other codes are skipped
+    #[serde(default)]
+    pub(crate) obx: Vec<ObxCodeConfig>,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct ObxCodeConfig {
+    /// observation identifier (OBX-3.1)
+    pub(crate) code: String,
+    pub(crate) loinc: String,
+    pub(crate) display: String,
+    /// UCUM unit, if OBX-6 is empty
+    pub(crate) unit: String,
+    pub(crate) profile: String,
 }
 
 #[derive(Default, Debug, Deserialize, Clone)]
 pub(crate) struct OrganizationConfig {
     pub(crate) department: SystemConfig,
     pub(crate) ward: SystemConfig,
+    /// managing organization of the patients (`Patient.managingOrganization`), not mapped if
+    /// missing
+    #[serde(default)]
+    pub(crate) managing: Option<ManagingOrganizationConfig>,
+}
+
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct ManagingOrganizationConfig {
+    /// identifier system of the organization
+    pub(crate) system: String,
+    /// identifier value of the organization
+    pub(crate) value: String,
+    /// name of the `Organization` resource
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+    /// add the `Organization` resource (conditional create) to bundles with a patient,
+    /// otherwise it's referenced only
+    #[serde(default)]
+    pub(crate) create: bool,
 }
 
 #[derive(Default, Debug, Deserialize, Clone)]
@@ -86,11 +1022,205 @@ pub(crate) struct Ssl {
     pub(crate) key_password: Option<String>,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct PipelineConfig {
+    /// processing stages in order of execution
+    pub(crate) stages: Vec<StageKind>,
+    /// site specific skip rules of the `rules` stage
+    #[serde(default)]
+    pub(crate) rules: Vec<RuleConfig>,
+    /// maximum lengths of string elements of the `limits` stage
+    #[serde(default)]
+    pub(crate) limits: Vec<LengthLimitConfig>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            stages: vec![StageKind::Filter, StageKind::Map],
+            rules: vec![],
+            limits: vec![],
+        }
+    }
+}
+
+/// Maximum length of a string element of the mapped resources.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct LengthLimitConfig {
+    /// element path starting with the resource type, e.g. Patient.name.family
+    pub(crate) path: String,
+    /// maximum number of characters
+    pub(crate) max_length: usize,
+    #[serde(default)]
+    pub(crate) policy: LengthPolicy,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LengthPolicy {
+    /// shorten the value to the maximum length
+    #[default]
+    Truncate,
+    /// remove the entry of the resource from the bundle
+    Reject,
+}
+
+/// Rule, which applies if all conditions match the message.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct RuleConfig {
+    pub(crate) conditions: Vec<RuleConditionConfig>,
+    pub(crate) action: RuleAction,
+    /// resource types removed from the bundle by the `drop` action, e.g. Encounter
+    #[serde(default)]
+    pub(crate) resource_types: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct RuleConditionConfig {
+    /// message location, e.g. PV1.2
+    pub(crate) field: String,
+    pub(crate) operator: Operator,
+    #[serde(default)]
+    pub(crate) values: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+    /// value is one of `values`
+    #[serde(alias = "eq")]
+    In,
+    /// value is missing or none of `values`
+    #[serde(alias = "ne")]
+    NotIn,
+    /// value is present
+    Present,
+    /// value is missing
+    Missing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// skip the record
+    Skip,
+    /// remove the entries of `resource_types` from the bundle
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageKind {
+    /// skip tombstone records
+    Filter,
+    /// apply the skip rules (`pipeline.rules`), before `map`
+    Rules,
+    /// map HL7 to FHIR
+    Map,
+    /// apply the length limits (`pipeline.limits`), after `map`
+    Limits,
+}
+
 #[derive(Deserialize, Clone)]
 pub(crate) struct AppConfig {
     pub(crate) app: App,
     pub(crate) kafka: Kafka,
     pub(crate) fhir: Fhir,
+    #[serde(default)]
+    pub(crate) pipeline: PipelineConfig,
+    #[serde(default)]
+    pub(crate) canary: Option<CanaryConfig>,
+    #[serde(default)]
+    pub(crate) audit: Option<AuditConfig>,
+    #[serde(default)]
+    pub(crate) sequence_check: Option<SequenceCheckConfig>,
+    #[serde(default)]
+    pub(crate) demographics: Option<DemographicsConfig>,
+    #[serde(default)]
+    pub(crate) quality: Option<QualityConfig>,
+}
+
+/// Data quality scorecards per sending facility, produced to an ops topic.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct QualityConfig {
+    /// scorecard topic
+    pub(crate) topic: String,
+    /// interval of the scorecards in seconds
+    #[serde(default = "QualityConfig::interval_secs")]
+    pub(crate) interval_secs: u64,
+    /// fields of the missing field rates, e.g. PID.7
+    #[serde(default = "QualityConfig::fields")]
+    pub(crate) fields: Vec<String>,
+}
+
+impl QualityConfig {
+    fn interval_secs() -> u64 {
+        3600
+    }
+
+    fn fields() -> Vec<String> {
+        [
+            "PID.3", "PID.5", "PID.7", "PID.8", "PID.11", "PV1.2", "PV1.3", "PV1.19",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+}
+
+/// Latest demographics per patient, produced to a compacted topic.
+#[derive(Default, Debug, Deserialize, Clone)]
+pub(crate) struct DemographicsConfig {
+    /// compacted topic of the `Patient` resources, keyed by patient id (PID-2)
+    pub(crate) topic: String,
+}
+
+/// Check of the ADT event sequence per visit number.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct SequenceCheckConfig {
+    /// warnings topic
+    pub(crate) topic: String,
+    /// seconds a visit without events is kept
+    #[serde(default = "SequenceCheckConfig::visit_ttl_secs")]
+    pub(crate) visit_ttl_secs: u64,
+    /// seconds a discharged visit is kept, to detect transfers after the discharge
+    #[serde(default = "SequenceCheckConfig::discharged_ttl_secs")]
+    pub(crate) discharged_ttl_secs: u64,
+}
+
+impl SequenceCheckConfig {
+    fn visit_ttl_secs() -> u64 {
+        // 90 days
+        7_776_000
+    }
+
+    fn discharged_ttl_secs() -> u64 {
+        // 7 days
+        604_800
+    }
+}
+
+/// Audit log of the produced entries per patient.
+#[derive(Debug, Deserialize, Clone)]
+pub(crate) struct AuditConfig {
+    /// append-only audit file
+    #[serde(default)]
+    pub(crate) file: Option<String>,
+    /// audit topic
+    #[serde(default)]
+    pub(crate) topic: Option<String>,
+    /// salt of the patient identifier hash, required
+    pub(crate) salt: String,
+}
+
+/// Shadow mapping of a share of the records with a different mapping config.
+#[derive(Deserialize, Clone)]
+pub(crate) struct CanaryConfig {
+    /// share of the records in percent, sampled by record key
+    pub(crate) percentage: u8,
+    /// shadow topic for the canary bundles
+    pub(crate) topic: String,
+    /// mapping config of the canary
+    pub(crate) fhir: Fhir,
 }
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -122,8 +1252,10 @@ impl AppConfig {
 
 #[cfg(test)]
 mod tests {
-    use crate::config::AppConfig;
+    use crate::config::{AppConfig, EmployerConfig, EmployerTarget, StatusTransitionConfig};
+    use crate::hl7::parser::MessageType;
     use config::Environment;
+    use serde_json::json;
     use std::collections::HashMap;
 
     #[test]
@@ -136,6 +1268,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn kontaktebenen_require_system() {
+        let mut fall = AppConfig::new().unwrap().fhir.fall;
+        assert!(fall.check_kontaktebenen().is_ok());
+
+        fall.versorgungsstellenkontakt.system = String::new();
+        assert!(fall.check_kontaktebenen().is_err());
+
+        // not required, if disabled
+        fall.versorgungsstellenkontakt.enabled = false;
+        assert!(fall.check_kontaktebenen().is_ok());
+
+        fall.einrichtungskontakt.enabled = false;
+        assert!(fall.check_kontaktebenen().is_err());
+    }
+
+    #[test]
+    fn skip_only_patient_updates() {
+        let mut person = AppConfig::new().unwrap().fhir.person;
+        person.skip_message_types = vec![MessageType::A02, MessageType::A03];
+        assert!(person.check_skip_message_types().is_ok());
+
+        person.skip_message_types.push(MessageType::A40);
+        assert!(person.check_skip_message_types().is_err());
+    }
+
+    #[test]
+    fn employer_requires_url_or_system() {
+        let mut employer = EmployerConfig::default();
+        assert!(employer.check().is_err());
+
+        employer.url = "https://example.org/employer".to_string();
+        assert!(employer.check().is_ok());
+
+        employer.target = EmployerTarget::RelatedPerson;
+        assert!(employer.check().is_err());
+
+        employer.system = "https://example.org/employer-id".to_string();
+        assert!(employer.check().is_ok());
+    }
+
+    #[test]
+    fn status_transitions_validate_values() {
+        let parse = |value| serde_json::from_value::<StatusTransitionConfig>(value);
+
+        assert!(parse(json!({"message_types": ["A03"], "status": "finished"})).is_ok());
+        // unknown message type or status
+        assert!(parse(json!({"message_types": ["A3"], "status": "finished"})).is_err());
+        assert!(parse(json!({"message_types": ["A03"], "status": "done"})).is_err());
+    }
+
     #[test]
     fn invalid_config_fails() {
         // override validated property with invalid data
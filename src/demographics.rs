@@ -0,0 +1,168 @@
+use crate::config::DemographicsConfig;
+use crate::hl7::parser::MessageInfo;
+use serde_json::Value;
+
+/// Latest demographics per patient: the `Patient` resource of the produced bundles, keyed by the
+/// patient id (PID-2), for a compacted topic.
+pub(crate) struct Demographics {
+    pub(crate) topic: String,
+}
+
+/// Update of the latest demographics of a patient.
+#[derive(Debug, PartialEq)]
+pub(crate) enum PatientUpdate {
+    /// current `Patient` resource
+    Upsert(String),
+    /// deleted patient, produced as tombstone
+    Delete,
+}
+
+impl Demographics {
+    pub(crate) fn new(config: DemographicsConfig) -> Self {
+        Demographics {
+            topic: config.topic,
+        }
+    }
+
+    /// Updates of the patients of the bundle, keyed by patient id: the `Patient` entry of the
+    /// current patient (PID-2) and a tombstone of the old patient of merges (MRG-1).
+    ///
+    /// Only updates (`PUT`) of the patient are produced. Conditional creates (`POST`, e.g. A02 or
+    /// A03) only contain minimal patient data and don't change an existing patient, patches (e.g.
+    /// A31 updates) don't contain the whole resource.
+    pub(crate) fn updates(&self, info: &MessageInfo, bundle: &str) -> Vec<(String, PatientUpdate)> {
+        let Ok(bundle) = serde_json::from_str::<Value>(bundle) else {
+            return vec![];
+        };
+        let patient_entries: Vec<(&str, &Value)> = bundle
+            .get("entry")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| {
+                // the request url starts with the resource type, also for patch and delete
+                let request = e.get("request")?;
+                let url = request.get("url")?.as_str()?;
+                (url.split(['?', '/']).next() == Some("Patient"))
+                    .then_some((request.get("method")?.as_str()?, e))
+            })
+            .collect();
+
+        let current = info.patient_id.as_ref().and_then(|patient| {
+            patient_entries.iter().find_map(|&(method, e)| {
+                let update = match method {
+                    "DELETE" => PatientUpdate::Delete,
+                    "PUT" => PatientUpdate::Upsert(
+                        e.get("resource")
+                            .filter(|r| {
+                                r.get("resourceType").and_then(Value::as_str) == Some("Patient")
+                            })?
+                            .to_string(),
+                    ),
+                    _ => return None,
+                };
+                Some((patient.clone(), update))
+            })
+        });
+        // the old patient is linked to the current one (replaced-by)
+        let merged = info
+            .prior_patient_id
+            .as_ref()
+            .filter(|_| {
+                patient_entries.iter().any(|&(method, e)| {
+                    method == "PATCH" && e.get("resource").is_some_and(is_replaced)
+                })
+            })
+            .map(|patient| (patient.clone(), PatientUpdate::Delete));
+
+        current.into_iter().chain(merged).collect()
+    }
+}
+
+/// Whether the patch adds a `replaced-by` link.
+fn is_replaced(patch: &Value) -> bool {
+    match patch {
+        Value::Object(object) => object
+            .iter()
+            .any(|(name, v)| (name == "valueCode" && v == "replaced-by") || is_replaced(v)),
+        Value::Array(items) => items.iter().any(is_replaced),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fhir::mapper::FhirMapper;
+    use crate::test_utils::tests::{get_test_config, read_test_resource};
+
+    fn demographics() -> Demographics {
+        Demographics::new(DemographicsConfig {
+            topic: "patient-demographics".to_string(),
+        })
+    }
+
+    fn info(patient_id: &str, prior_patient_id: Option<&str>) -> MessageInfo {
+        MessageInfo {
+            patient_id: Some(patient_id.to_string()),
+            prior_patient_id: prior_patient_id.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn patient_of_bundle() {
+        let bundle = FhirMapper::new(get_test_config())
+            .unwrap()
+            .map(&read_test_resource("a01_test.hl7"))
+            .unwrap()
+            .unwrap();
+
+        let updates = demographics().updates(&info("1499653", None), &bundle);
+
+        let [(key, PatientUpdate::Upsert(patient))] = updates.as_slice() else {
+            panic!("expected patient");
+        };
+        let patient: Value = serde_json::from_str(patient).unwrap();
+        assert_eq!(key, "1499653");
+        assert_eq!(patient["resourceType"], "Patient");
+    }
+
+    #[test]
+    fn deleted_and_missing_patient() {
+        let deleted = r#"{"resourceType":"Bundle","entry":[
+            {"request":{"method":"DELETE","url":"Patient?identifier=a|1"}}]}"#;
+        assert_eq!(
+            demographics().updates(&info("1", None), deleted),
+            vec![("1".to_string(), PatientUpdate::Delete)]
+        );
+
+        let encounter_only = r#"{"resourceType":"Bundle","entry":[
+            {"resource":{"resourceType":"Encounter"},"request":{"method":"PUT","url":"Encounter?identifier=b|2"}}]}"#;
+        assert_eq!(
+            demographics().updates(&info("2", None), encounter_only),
+            vec![]
+        );
+
+        // conditional create with minimal patient data
+        let created = r#"{"resourceType":"Bundle","entry":[
+            {"resource":{"resourceType":"Patient"},"request":{"method":"POST","url":"Patient","ifNoneExist":"identifier=a|3"}}]}"#;
+        assert_eq!(demographics().updates(&info("3", None), created), vec![]);
+    }
+
+    #[test]
+    fn merged_patient() {
+        let merge = r#"{"resourceType":"Bundle","entry":[
+            {"resource":{"resourceType":"Parameters","parameter":[{"name":"operation","part":[
+                {"name":"type","valueCode":"add"},{"name":"path","valueString":"Patient"},
+                {"name":"name","valueString":"link"},
+                {"name":"value","part":[{"name":"other","valueReference":{"reference":"Patient?identifier=a|2"}},
+                    {"name":"type","valueCode":"replaced-by"}]}]}]},
+             "request":{"method":"PATCH","url":"Patient?identifier=a|1"}}]}"#;
+
+        assert_eq!(
+            demographics().updates(&info("2", Some("1")), merge),
+            vec![("1".to_string(), PatientUpdate::Delete)]
+        );
+    }
+}
@@ -10,6 +10,10 @@ pub(crate) enum ProcessingError {
     Kafka(#[from] KafkaError),
     #[error(transparent)]
     Mapping(#[from] MappingError),
+    #[error("replay not confirmed: {0}")]
+    Replay(String),
+    #[error("audit failed: {0}")]
+    Audit(String),
 }
 
 #[derive(Debug, Error)]
@@ -24,6 +28,11 @@ pub(crate) enum MappingError {
     MissingResourceError { resource: String, value: String },
     #[error(transparent)]
     Hl7ParseError(#[from] hl7_parser::parser::ParseError),
+    #[error("mapping panicked: {0}")]
+    Panic(String),
+    /// the message can't be mapped safely and is left to manual review
+    #[error("manual review required: {0}")]
+    ManualReview(String),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -36,6 +45,8 @@ impl MappingError {
             MappingError::FormattingError(_) => "FormattingError",
             MappingError::MissingResourceError { .. } => "MissingResourceError",
             MappingError::Hl7ParseError(_) => "Hl7ParseError",
+            MappingError::Panic(_) => "Panic",
+            MappingError::ManualReview(_) => "ManualReview",
             MappingError::Other(_) => "Other",
         }
     }
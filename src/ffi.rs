@@ -0,0 +1,146 @@
+use crate::config::Fhir;
+use crate::fhir::mapper::FhirMapper;
+use crate::fhir::resources::ResourceMap;
+use crate::fhir::state::LocationHistory;
+use serde_json::json;
+use std::ffi::{CStr, CString, c_char};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+/// Mapping files, relative to the working directory of the host process.
+const MAPPING_DIR: &str = "resources/mapping";
+
+/// Mapper of the last config, so the mapping files are only loaded on config changes.
+static MAPPER: LazyLock<Mutex<Option<(String, FhirMapper)>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Maps an HL7 v2 message to a FHIR bundle, like the service with the same `fhir` config.
+///
+/// `json_config` is the `fhir` section of `app.yaml` as JSON, the mapping files are read from
+/// `resources/mapping` of the working directory. Returns the serialized bundle, null if the
+/// message isn't mapped (e.g. skipped message types) or an `OperationOutcome` on errors (also on
+/// panics). The result must be released with [`free_string`].
+///
+/// Each message is mapped on its own: unlike the service, no location history of earlier
+/// messages is kept (e.g. for cancelled transfers).
+///
+/// # Safety
+///
+/// `json_config` and `hl7` must be valid pointers to nul-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn map_adt_to_fhir(
+    json_config: *const c_char,
+    hl7: *const c_char,
+) -> *mut c_char {
+    if json_config.is_null() || hl7.is_null() {
+        return into_raw(operation_outcome("config and message are required"));
+    }
+    // SAFETY: non-null and nul-terminated by the contract of the caller
+    let (config, hl7) = unsafe { (CStr::from_ptr(json_config), CStr::from_ptr(hl7)) };
+
+    // unwinding into the host process is undefined behavior
+    match panic::catch_unwind(AssertUnwindSafe(|| map(config, hl7))) {
+        Ok(Ok(Some(bundle))) => into_raw(bundle),
+        Ok(Ok(None)) => std::ptr::null_mut(),
+        Ok(Err(e)) => into_raw(operation_outcome(&format!("{e:#}"))),
+        Err(_) => into_raw(operation_outcome("mapping panicked")),
+    }
+}
+
+/// Releases a result of [`map_adt_to_fhir`].
+///
+/// # Safety
+///
+/// `value` must be null or a result of [`map_adt_to_fhir`], which isn't released yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn free_string(value: *mut c_char) {
+    if !value.is_null() {
+        // SAFETY: allocated by `CString::into_raw` by the contract of the caller
+        drop(unsafe { CString::from_raw(value) });
+    }
+}
+
+fn map(config: &CStr, hl7: &CStr) -> anyhow::Result<Option<String>> {
+    let config = config.to_str()?;
+    let hl7 = hl7.to_str()?;
+
+    let mut mapper = MAPPER.lock().unwrap_or_else(PoisonError::into_inner);
+    if mapper.as_ref().is_none_or(|(c, _)| c != config) {
+        let fhir: Fhir = serde_json::from_str(config)?;
+        let resources = ResourceMap::from_dir(Path::new(MAPPING_DIR), &fhir)?;
+        *mapper = Some((
+            config.to_string(),
+            FhirMapper::with_resources(fhir, resources)?,
+        ));
+    }
+    let (_, mapper) = mapper.as_mut().expect("mapper initialized");
+    // the result only depends on the message
+    mapper.history = LocationHistory::new(mapper.config.identifier_selection.clone());
+
+    Ok(mapper.map(hl7)?)
+}
+
+fn operation_outcome(diagnostics: &str) -> String {
+    json!({
+        "resourceType": "OperationOutcome",
+        "issue": [{
+            "severity": "error",
+            "code": "exception",
+            "diagnostics": diagnostics
+        }]
+    })
+    .to_string()
+}
+
+fn into_raw(value: String) -> *mut c_char {
+    // JSON never contains nul bytes, they are escaped
+    CString::new(value).expect("nul byte in JSON").into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tests::read_test_resource;
+    use config::{Config, File};
+    use serde_json::Value;
+
+    fn fhir_config() -> CString {
+        let fhir: Value = Config::builder()
+            .add_source(File::with_name("app.yaml"))
+            .build()
+            .unwrap()
+            .get("fhir")
+            .unwrap();
+        CString::new(fhir.to_string()).unwrap()
+    }
+
+    fn call(config: &CStr, hl7: &str) -> Option<Value> {
+        let hl7 = CString::new(hl7).unwrap();
+        unsafe {
+            let result = map_adt_to_fhir(config.as_ptr(), hl7.as_ptr());
+            if result.is_null() {
+                return None;
+            }
+            let value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+            free_string(result);
+            Some(value)
+        }
+    }
+
+    #[test]
+    fn map_message() {
+        let bundle = call(&fhir_config(), &read_test_resource("a01_test.hl7")).unwrap();
+
+        assert_eq!(bundle["resourceType"], "Bundle");
+        assert!(!bundle["entry"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_input() {
+        let outcome = call(c"{}", &read_test_resource("a01_test.hl7")).unwrap();
+        assert_eq!(outcome["resourceType"], "OperationOutcome");
+
+        let outcome = call(&fhir_config(), "no hl7").unwrap();
+        assert_eq!(outcome["resourceType"], "OperationOutcome");
+    }
+}
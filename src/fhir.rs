@@ -1,8 +1,20 @@
+pub(crate) mod account;
+pub(crate) mod bootstrap;
+pub(crate) mod condition;
+pub(crate) mod coverage;
 pub(crate) mod encounter;
+pub(crate) mod exclusion;
 pub(crate) mod location;
 pub(crate) mod mapper;
+mod names;
 pub mod observation;
 pub mod organization;
 pub(crate) mod patient;
+pub(crate) mod practitioner;
+pub(crate) mod procedure;
+mod protection;
+pub(crate) mod references;
 pub(crate) mod resources;
+mod role;
+pub(crate) mod state;
 mod terminology;
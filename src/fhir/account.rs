@@ -0,0 +1,108 @@
+use crate::config::Fhir;
+use crate::error::MappingError;
+use crate::fhir::mapper::{EntryRequestType, MappingContext, bundle_entry, get_cc_with_one_code};
+use crate::hl7::parser::{MessageType, PID_18_1, query};
+use fhir_model::r4b::codes::{AccountStatus, IdentifierUse};
+use fhir_model::r4b::resources::{Account, BundleEntry};
+use fhir_model::r4b::types::{Identifier, Meta};
+
+/// Maps the patient account number (PID-18) to an `Account` (_Abrechnungsfall_) of the patient,
+/// so billing cases can be tracked separately from the encounters.
+pub(crate) fn map(ctx: &MappingContext, config: &Fhir) -> Result<Vec<BundleEntry>, MappingError> {
+    let Some(account_config) = &config.account else {
+        return Ok(vec![]);
+    };
+
+    match ctx.message_type() {
+        // same messages, which map the patient
+        MessageType::A01
+        | MessageType::A02
+        | MessageType::A03
+        | MessageType::A04
+        | MessageType::A05
+        | MessageType::A06
+        | MessageType::A07
+        | MessageType::A08
+        | MessageType::A31 => {
+            let Some(account_number) = query(ctx.msg, PID_18_1) else {
+                return Ok(vec![]);
+            };
+
+            let account = Account::builder()
+                .meta(
+                    Meta::builder()
+                        .source(config.meta_source.to_string())
+                        .build()?,
+                )
+                .identifier(vec![Some(
+                    Identifier::builder()
+                        .r#use(IdentifierUse::Usual)
+                        .system(account_config.system.clone())
+                        .value(account_number.to_string())
+                        .r#type(get_cc_with_one_code(
+                            "AN".to_string(),
+                            "http://terminology.hl7.org/CodeSystem/v2-0203".to_string(),
+                        )?)
+                        .build()?,
+                )])
+                .status(AccountStatus::Active)
+                .subject(vec![Some(ctx.subject_ref(&config.person.system)?)])
+                .build()?;
+
+            Ok(vec![bundle_entry(
+                account,
+                EntryRequestType::UpdateAsCreate,
+                config,
+            )?])
+        }
+        _ => Ok(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AccountConfig;
+    use crate::test_utils::tests::get_test_config;
+    use fhir_model::r4b::resources::Resource;
+    use hl7_parser::Message;
+
+    const MSG: &str = "MSH|^~\\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A08^ADT_A01|65298857|P|2.5\r\
+        EVN|A08|202208200651\r\
+        PID|1|1212121|1212121|21600000|Sokolovski^Malina||19820101|F||||||||||87654321";
+
+    fn map_msg(config: &Fhir) -> Vec<BundleEntry> {
+        let msg = Message::parse_with_lenient_newlines(MSG, true).unwrap();
+
+        map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            config,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn map_account() {
+        let mut config = get_test_config();
+        assert!(map_msg(&config).is_empty());
+
+        config.account = Some(AccountConfig {
+            system: "https://fhir.diz.uni-marburg.de/sid/account-id".to_string(),
+        });
+        let entries = map_msg(&config);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].request.as_ref().unwrap().url,
+            "Account?identifier=https://fhir.diz.uni-marburg.de/sid/account-id|87654321"
+        );
+        let Some(Resource::Account(account)) = &entries[0].resource else {
+            panic!("expected account");
+        };
+        assert_eq!(account.status, AccountStatus::Active);
+        assert_eq!(
+            account.subject[0].as_ref().unwrap().reference.as_deref(),
+            Some("Patient?identifier=https://fhir.diz.uni-marburg.de/sid/patient-id|1212121")
+        );
+    }
+}
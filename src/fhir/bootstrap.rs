@@ -0,0 +1,106 @@
+use crate::config::Fhir;
+use crate::error::MappingError;
+use crate::fhir::location::ward_location;
+use crate::fhir::mapper::{EntryRequestType, bundle_entry};
+use crate::fhir::organization::department_org;
+use crate::fhir::resources::{ResourceMap, is_valid_date};
+use chrono::NaiveDate;
+use fhir_model::Instant;
+use fhir_model::r4b::codes::BundleType;
+use fhir_model::r4b::resources::Bundle;
+use fhir_model::r4b::types::Meta;
+use fhir_model::time::OffsetDateTime;
+
+/// Transaction bundle of all department organizations (`InfoByAbteilungskuerzel.json`) and ward
+/// locations (`InfoStation.json`) of the mapping files, so conditional references of later
+/// messages always resolve.
+///
+/// The resources are created conditionally, existing resources (e.g. with the managing
+/// organization of a ward) are kept. Wards are typed as intensive care unit, if they are valid
+/// ICUs at `today`.
+pub(crate) fn bootstrap_bundle(
+    config: &Fhir,
+    resources: &ResourceMap,
+    today: NaiveDate,
+) -> Result<String, MappingError> {
+    let mut departments: Vec<_> = resources.department_map.keys().collect();
+    departments.sort();
+    let mut wards: Vec<_> = resources.ward_map.iter().collect();
+    wards.sort_by_key(|(id, _)| *id);
+
+    let mut entries = vec![];
+    for fab in departments {
+        entries.push(Some(bundle_entry(
+            department_org(fab, config, resources)?,
+            EntryRequestType::ConditionalCreate,
+            config,
+        )?));
+    }
+    for (ward_id, ward) in wards {
+        let icu = ward.is_icu && ward.valid_period.iter().any(|p| is_valid_date(p, &today));
+        let mut location = ward_location(ward_id, icu, config)?;
+        location.name = Some(ward.display.to_string());
+        entries.push(Some(bundle_entry(
+            location,
+            EntryRequestType::ConditionalCreate,
+            config,
+        )?));
+    }
+
+    let bundle = Bundle::builder()
+        .r#type(BundleType::Transaction)
+        .entry(entries)
+        .meta(
+            Meta::builder()
+                .last_updated(Instant(OffsetDateTime::now_utc()))
+                .build()?,
+        )
+        .build()?;
+
+    Ok(serde_json::to_string(&bundle).expect("failed to serialize bootstrap bundle"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tests::{get_dummy_resources, get_test_config};
+    use fhir_model::r4b::codes::HTTPVerb;
+    use fhir_model::r4b::resources::Resource;
+
+    #[test]
+    fn bootstrap_departments_and_wards() {
+        let resources = get_dummy_resources();
+        let today = NaiveDate::from_ymd_opt(2002, 6, 1).unwrap();
+
+        let bundle = bootstrap_bundle(&get_test_config(), &resources, today).unwrap();
+        let bundle: Bundle = serde_json::from_str(&bundle).unwrap();
+
+        assert_eq!(bundle.r#type, BundleType::Transaction);
+        let entries: Vec<_> = bundle.entry.into_iter().flatten().collect();
+        assert_eq!(
+            entries.len(),
+            resources.department_map.len() + resources.ward_map.len()
+        );
+        assert!(
+            entries
+                .iter()
+                .all(|e| e.request.as_ref().unwrap().method == HTTPVerb::Post)
+        );
+
+        let icu_wards: Vec<_> = entries
+            .iter()
+            .filter_map(|e| match &e.resource {
+                Some(Resource::Location(l)) if !l.r#type.is_empty() => l.name.as_deref(),
+                _ => None,
+            })
+            .collect();
+        // ANA is no ICU in 2002
+        assert_eq!(icu_wards, vec!["IDIST1I"]);
+
+        let pneumology = entries.iter().find_map(|e| match &e.resource {
+            Some(Resource::Organization(o)) if o.name.as_deref() == Some("Pneumologie") => Some(o),
+            _ => None,
+        });
+        assert!(pneumology.is_some());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::config::Fhir;
+use crate::error::MappingError;
+use crate::fhir::encounter::{einrichtungskontakt_ref, map_bar_identifier};
+use crate::fhir::mapper::{
+    EntryRequestType, MappingContext, build_usual_identifier, bundle_entry, get_meta_with_profile,
+    parse_datetime,
+};
+use crate::hl7::parser::{MessageType, segment_value};
+use fhir_model::r4b::resources::{BundleEntry, Condition};
+use fhir_model::r4b::types::{CodeableConcept, Coding, Extension, ExtensionValue};
+use hl7_parser::message::Segment;
+
+const ICD_10_GM_SYSTEM: &str = "http://fhir.de/CodeSystem/bfarm/icd-10-gm";
+const MEHRFACHCODIERUNG_URL: &str =
+    "http://fhir.de/StructureDefinition/icd-10-gm-mehrfachcodierungs-kennzeichen";
+const MEHRFACHCODIERUNG_SYSTEM: &str =
+    "http://fhir.de/CodeSystem/icd-10-gm-mehrfachcodierungs-kennzeichen";
+
+/// Maps the diagnoses (DG1) to `Condition` resources, which are referenced by
+/// `Encounter.diagnosis` of the 'Einrichtungskontakt'.
+pub(crate) fn map(ctx: &MappingContext, config: &Fhir) -> Result<Vec<BundleEntry>, MappingError> {
+    let mut result = vec![];
+
+    match ctx.message_type() {
+        // same messages, which map the 'Einrichtungskontakt'
+        MessageType::A01
+        | MessageType::A02
+        | MessageType::A03
+        | MessageType::A04
+        | MessageType::A05
+        | MessageType::A06
+        | MessageType::A07
+        | MessageType::A08
+        | MessageType::A13
+        | MessageType::A50 => {
+            for dg1 in ctx.msg.segments().filter(|seg| seg.name.eq("DG1")) {
+                if let Some(condition) = map_condition(ctx, dg1, config)? {
+                    result.push(bundle_entry(
+                        condition,
+                        EntryRequestType::UpdateAsCreate,
+                        config,
+                    )?);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(result)
+}
+
+fn map_condition(
+    ctx: &MappingContext,
+    dg1: &Segment,
+    config: &Fhir,
+) -> Result<Option<Condition>, MappingError> {
+    // same preconditions as the reference at Encounter.diagnosis
+    let (Some(condition_id), Some(priority), Some(code)) = (
+        dg1.field(20).filter(|f| !f.is_empty()),
+        dg1.field(15).filter(|f| !f.is_empty()),
+        segment_value(dg1, 3, 1, 1),
+    ) else {
+        return Ok(None);
+    };
+    if dg1.field(6).is_none_or(|f| f.is_empty()) {
+        return Ok(None);
+    }
+
+    let mut condition = Condition::builder()
+        .meta(get_meta_with_profile(&config.condition.profile, config)?)
+        .identifier(vec![Some(build_usual_identifier(
+            vec![map_bar_identifier(condition_id, priority)?.as_str()],
+            config.condition.system.clone(),
+        )?)])
+        .code(
+            CodeableConcept::builder()
+                .coding(vec![Some(map_icd_coding(
+                    code,
+                    segment_value(dg1, 3, 1, 2),
+                    segment_value(dg1, 3, 1, 3),
+                )?)])
+                .build()?,
+        )
+        .subject(ctx.subject_ref(&config.person.system)?)
+        .encounter(einrichtungskontakt_ref(ctx, config)?)
+        .build()?;
+
+    // diagnosis date (YYYYMMDDHHMM[SS]), placeholders (e.g. 0000000000000) are skipped
+    condition.recorded_date = segment_value(dg1, 5, 1, 1)
+        .and_then(|d| d.get(..12))
+        .and_then(|d| parse_datetime(d).ok());
+
+    Ok(Some(condition))
+}
+
+/// ICD-10-GM coding, the version is taken from the coding system (e.g. `icd10gm2022`).
+///
+/// Multiple coding markers (†, *, !) are mapped to the extension, local suffixes
+/// (e.g. `H91.2 LG`) are dropped.
+fn map_icd_coding(
+    code: &str,
+    display: Option<&str>,
+    coding_system: Option<&str>,
+) -> Result<Coding, MappingError> {
+    let code = code.split_whitespace().next().unwrap_or(code);
+    let marker = code.chars().last().filter(|c| matches!(c, '†' | '*' | '!'));
+    let code = code.trim_end_matches(['†', '*', '!']);
+
+    let mut coding = Coding::builder()
+        .system(ICD_10_GM_SYSTEM.to_string())
+        .code(code.to_string())
+        .build()?;
+    coding.display = display.map(String::from);
+    coding.version = coding_system
+        .and_then(|s| s.strip_prefix("icd10gm"))
+        .filter(|v| !v.is_empty())
+        .map(String::from);
+
+    if let Some(marker) = marker {
+        coding.extension = vec![
+            Extension::builder()
+                .url(MEHRFACHCODIERUNG_URL.to_string())
+                .value(ExtensionValue::Coding(
+                    Coding::builder()
+                        .system(MEHRFACHCODIERUNG_SYSTEM.to_string())
+                        .code(marker.to_string())
+                        .build()?,
+                ))
+                .build()?,
+        ];
+    }
+
+    Ok(coding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tests::{get_test_config, read_test_resource};
+    use fhir_model::r4b::resources::Resource;
+    use hl7_parser::Message;
+
+    #[test]
+    fn map_dg1_conditions() {
+        let hl7 = read_test_resource("a01_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let config = get_test_config();
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(result.len(), 3);
+        let conditions: Vec<Condition> = result
+            .into_iter()
+            .map(|e| match e.resource {
+                Some(Resource::Condition(c)) => c,
+                _ => panic!("expected condition"),
+            })
+            .collect();
+
+        let first = conditions[0].code.as_ref().unwrap().coding[0]
+            .as_ref()
+            .unwrap();
+        assert_eq!(first.code.as_deref(), Some("K42.9"));
+        assert_eq!(first.version.as_deref(), Some("2022"));
+        assert!(first.extension.is_empty());
+        assert_eq!(
+            conditions[0].identifier[0]
+                .as_ref()
+                .unwrap()
+                .value
+                .as_deref(),
+            Some("12345677-1.1")
+        );
+
+        // secondary code
+        let third = conditions[2].code.as_ref().unwrap().coding[0]
+            .as_ref()
+            .unwrap();
+        assert_eq!(third.code.as_deref(), Some("U99.0"));
+        assert_eq!(
+            third.extension[0].value,
+            Some(ExtensionValue::Coding(
+                Coding::builder()
+                    .system(MEHRFACHCODIERUNG_SYSTEM.to_string())
+                    .code("!".to_string())
+                    .build()
+                    .unwrap()
+            ))
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use crate::config::{Fhir, Versicherungsart};
+use crate::error::MappingError;
+use crate::fhir::mapper::{
+    EntryRequestType, MappingContext, build_usual_identifier, bundle_entry, get_cc_with_one_code,
+    get_meta_with_profile, resource_ref,
+};
+use crate::fhir::patient::{get_identifier_period, is_valid_gkv10};
+use crate::hl7::parser::{MessageType, repeat_subcomponents, segment_value};
+use fhir_model::r4b::codes::FinancialResourceStatusCodes;
+use fhir_model::r4b::resources::{BundleEntry, Coverage, Organization, ResourceType};
+use fhir_model::r4b::types::{Coding, Extension, ExtensionValue, Meta, Reference};
+use hl7_parser::message::Segment;
+use log::warn;
+
+const IKNR_SYSTEM: &str = "http://fhir.de/sid/arge-ik/iknr";
+const VERSICHERUNGSART_SYSTEM: &str = "http://fhir.de/CodeSystem/versicherungsart-de-basis";
+const VERSICHERTENART_URL: &str = "http://fhir.de/StructureDefinition/gkv/versichertenart";
+const VERSICHERTENSTATUS_SYSTEM: &str =
+    "https://fhir.kbv.de/CodeSystem/KBV_CS_SFHIR_KBV_VERSICHERTENSTATUS";
+
+/// Maps the insurance data (IN1, IN2) to `Coverage` resources and their payer `Organization`.
+pub(crate) fn map(ctx: &MappingContext, config: &Fhir) -> Result<Vec<BundleEntry>, MappingError> {
+    let mut result = vec![];
+
+    match ctx.message_type() {
+        // same messages, which map the patient
+        MessageType::A01
+        | MessageType::A02
+        | MessageType::A03
+        | MessageType::A04
+        | MessageType::A05
+        | MessageType::A06
+        | MessageType::A07
+        | MessageType::A08
+        | MessageType::A31 => {
+            let mut segments = ctx.msg.segments().peekable();
+            while let Some(in1) = segments.next() {
+                if in1.name != "IN1" {
+                    continue;
+                }
+                // the additional insurance data follows its IN1 segment
+                let in2 = segments.next_if(|seg| seg.name == "IN2");
+
+                let entries = [
+                    map_payer(in1, config)?
+                        .map(|p| bundle_entry(p, EntryRequestType::ConditionalCreate, config))
+                        .transpose()?,
+                    map_coverage(ctx, in1, in2, config)?
+                        .map(|c| bundle_entry(c, EntryRequestType::UpdateAsCreate, config))
+                        .transpose()?,
+                ];
+                // the same payer (or insurance) may be listed repeatedly
+                for entry in entries.into_iter().flatten() {
+                    if !result
+                        .iter()
+                        .any(|e: &BundleEntry| e.full_url == entry.full_url)
+                    {
+                        result.push(entry);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(result)
+}
+
+/// Institutionskennzeichen (IN1-3.1)
+fn map_ik<'a>(in1: &Segment<'a>) -> Option<&'a str> {
+    segment_value(in1, 3, 1, 1).filter(|ik| ik.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn map_payer(in1: &Segment, config: &Fhir) -> Result<Option<Organization>, MappingError> {
+    let Some(ik) = map_ik(in1) else {
+        return Ok(None);
+    };
+
+    let mut payer = Organization::builder()
+        .meta(
+            Meta::builder()
+                .source(config.meta_source.to_string())
+                .build()?,
+        )
+        .identifier(vec![Some(build_usual_identifier(
+            vec![ik],
+            IKNR_SYSTEM.to_string(),
+        )?)])
+        .active(true)
+        .build()?;
+    payer.name = segment_value(in1, 4, 1, 1).map(String::from);
+
+    Ok(Some(payer))
+}
+
+fn map_coverage(
+    ctx: &MappingContext,
+    in1: &Segment,
+    in2: Option<&Segment>,
+    config: &Fhir,
+) -> Result<Option<Coverage>, MappingError> {
+    // Versichertennummer
+    let Some(insurance_number) = segment_value(in1, 36, 1, 1) else {
+        return Ok(None);
+    };
+    let Some(kind) = map_kind(in1, insurance_number, config) else {
+        warn!(
+            "unknown payer kind {:?} in IN1-9.5, coverage not mapped",
+            payer_kind(in1)
+        );
+        return Ok(None);
+    };
+
+    let profile = match kind {
+        Versicherungsart::Gkv => config.coverage.profile_gkv.as_str(),
+        Versicherungsart::Pkv => config.coverage.profile_pkv.as_str(),
+        _ => "",
+    };
+
+    let payor = match map_ik(in1) {
+        Some(ik) => resource_ref(&ResourceType::Organization, ik, IKNR_SYSTEM, config)?,
+        None => {
+            let mut payor = Reference::builder().build()?;
+            payor.display = segment_value(in1, 4, 1, 1).map(String::from);
+            payor
+        }
+    };
+    // the beneficiary requires the patient id
+    let beneficiary = ctx.subject_ref(&config.person.system)?;
+    let identifier_value = [
+        ctx.patient_id(),
+        Some(insurance_number),
+        segment_value(in1, 3, 1, 1),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let mut coverage = Coverage::builder()
+        .meta(get_meta_with_profile(profile, config)?)
+        .identifier(vec![Some(build_usual_identifier(
+            identifier_value,
+            config.coverage.system.clone(),
+        )?)])
+        .status(FinancialResourceStatusCodes::Active)
+        .r#type(get_cc_with_one_code(
+            kind.code().to_string(),
+            VERSICHERUNGSART_SYSTEM.to_string(),
+        )?)
+        .beneficiary(beneficiary)
+        .payor(vec![Some(payor)])
+        .build()?;
+    coverage.subscriber_id = Some(insurance_number.to_string());
+    coverage.period = get_identifier_period(in1)?;
+    if kind == Versicherungsart::Gkv
+        && let Some(status) = in2.and_then(map_insured_status)
+    {
+        coverage.extension = vec![
+            Extension::builder()
+                .url(VERSICHERTENART_URL.to_string())
+                .value(ExtensionValue::Coding(
+                    Coding::builder()
+                        .system(VERSICHERTENSTATUS_SYSTEM.to_string())
+                        .code(status.to_string())
+                        .build()?,
+                ))
+                .build()?,
+        ];
+    }
+
+    Ok(Some(coverage))
+}
+
+/// Payer kind (IN1-9.5.1)
+fn payer_kind<'a>(in1: &Segment<'a>) -> Option<&'a str> {
+    in1.field(9)
+        .and_then(|f| f.repeat(1))
+        .and_then(|r| repeat_subcomponents(r, 5))
+        .and_then(|k| k.first().copied())
+}
+
+/// Versicherungsart by the configured payer kinds, a KVID-10 insurance number implies GKV
+fn map_kind(in1: &Segment, insurance_number: &str, config: &Fhir) -> Option<Versicherungsart> {
+    payer_kind(in1)
+        .and_then(|k| config.coverage.kinds.get(k).copied())
+        .or_else(|| is_valid_gkv10(insurance_number).then_some(Versicherungsart::Gkv))
+}
+
+/// Versichertenart of statutory insurances by the insured's status (IN2-3.1): Mitglied,
+/// Familienversicherter or Rentner
+fn map_insured_status(in2: &Segment) -> Option<&'static str> {
+    match segment_value(in2, 3, 1, 1)? {
+        "M" => Some("1"),
+        "F" => Some("3"),
+        "R" => Some("5"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tests::{get_test_config, read_test_resource};
+    use fhir_model::r4b::resources::Resource;
+    use hl7_parser::Message;
+
+    fn map_file(file: &str) -> Vec<Resource> {
+        let hl7 = read_test_resource(file);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+
+        map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+        )
+        .unwrap()
+        .into_iter()
+        .filter_map(|e| e.resource)
+        .collect()
+    }
+
+    #[test]
+    fn map_private_coverage() {
+        let resources = map_file("a04_test.hl7");
+
+        assert_eq!(resources.len(), 2);
+        let Resource::Organization(payer) = &resources[0] else {
+            panic!("expected payer organization");
+        };
+        assert_eq!(
+            payer.identifier[0].as_ref().unwrap().value.as_deref(),
+            Some("99999999")
+        );
+
+        let Resource::Coverage(coverage) = &resources[1] else {
+            panic!("expected coverage");
+        };
+        assert_eq!(coverage.subscriber_id.as_deref(), Some("01010101"));
+        assert_eq!(
+            coverage.r#type.as_ref().unwrap().coding[0]
+                .as_ref()
+                .unwrap()
+                .code
+                .as_deref(),
+            Some("PKV")
+        );
+        assert_eq!(
+            coverage.payor[0].as_ref().unwrap().reference.as_deref(),
+            Some("Organization?identifier=http://fhir.de/sid/arge-ik/iknr|99999999")
+        );
+    }
+
+    #[test]
+    fn map_statutory_coverage() {
+        let coverages: Vec<Coverage> = map_file("a04_test2.hl7")
+            .into_iter()
+            .filter_map(|r| match r {
+                Resource::Coverage(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(coverages.len(), 2);
+        assert!(coverages.iter().all(|c| {
+            c.r#type.as_ref().unwrap().coding[0]
+                .as_ref()
+                .unwrap()
+                .code
+                .as_deref()
+                == Some("GKV")
+        }));
+        // no IK, payer by name only
+        assert_eq!(
+            coverages[1].payor[0].as_ref().unwrap().display.as_deref(),
+            Some("AOK - Die Gesundheitskasse in Hessen-")
+        );
+        // Versichertenart of the following IN2 segment (Rentner)
+        assert!(coverages[0].extension.is_empty());
+        let Some(ExtensionValue::Coding(status)) = &coverages[1].extension[0].value else {
+            panic!("expected versichertenart coding");
+        };
+        assert_eq!(status.code.as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn unknown_payer_kind_not_mapped() {
+        // no payer kind (IN1-9) and not a KVID-10
+        let resources = map_file("a05_ns_test.hl7");
+
+        assert_eq!(resources.len(), 1);
+        assert!(matches!(resources[0], Resource::Organization(_)));
+    }
+}
@@ -1,4 +1,9 @@
-use crate::config::Fhir;
+use crate::config::{
+    AdmitSourceConfig, AufnahmegrundConfig, CancelAdmitMode, CancelPendingAdmitMode,
+    CaseCategoryConfig, DischargeReasonConfig, Fhir, FinancialClassConfig, KontaktebeneConfig,
+    LengthUnit, PatientTypeConfig, PriorityConfig, ServiceTypeSource, StatusTransitionConfig,
+    WahlleistungConfig,
+};
 use crate::error::MessageAccessError::MissingMessageValue;
 use crate::error::{MappingError, MessageAccessError, ParsingError};
 use crate::fhir::encounter::EncounterType::{Fachabteilungskontakt, Versorgungsstellenkontakt};
@@ -6,29 +11,36 @@ use crate::fhir::location::{
     map_bed_location, map_room_location, map_ward_location, to_encounter_location,
 };
 use crate::fhir::mapper::{
-    EntryRequestType, bundle_entry, get_cc_with_one_code, is_begleitperson, is_inpatient_location,
-    is_ward_valid_icu, map_visit_number, parse_datetime, parse_fab, resource_ref, subject_ref,
+    EntryRequestType, MappingContext, bundle_entry, canonical_ref, conditional_patch_bundle_entry,
+    delete_bundle_entry, get_cc_with_one_code, is_begleitperson, is_inpatient_location,
+    is_ward_valid_icu, map_vip, parse_datetime, parse_fab, patch_bundle_entry, replace_patch,
+    resource_ref, select_identifier, upsert_reference,
 };
+use crate::fhir::practitioner::map_participants;
+use crate::fhir::protection;
 use crate::fhir::resources::ResourceMap;
+use crate::fhir::state::{LocationHistory, LocationUpdate};
 use crate::fhir::terminology::{
     AufnahmeGrundStelle, EntlassgrundStelle, diagnose_role_coding, kontakt_diagnose_procedures,
 };
 use crate::hl7::parser::{
-    MessageType, PID_21_1, PV1_2, PV1_3_1, PV1_3_2, PV1_3_3, PV1_4__2_1, PV1_4_1, PV1_36_1,
-    PV1_39_1, PV1_40_1, PV1_44, PV1_45, PV2_3_1, ZBE_1_1, ZBE_2, ZBE_3, check_is_numeric_ascii,
-    get_message_key, message_type, query,
+    MRG_5_1, MessageType, PID_18_1, PID_21_1, PV1_2, PV1_3_1, PV1_3_2, PV1_3_3, PV1_4__2_1,
+    PV1_4_1, PV1_10_1, PV1_13, PV1_18, PV1_20_1, PV1_36_1, PV1_39_1, PV1_40_1, PV1_44, PV1_45,
+    PV2_3_1, PV2_8, PV2_9, PV2_25, ZBE_1_1, ZBE_2, ZBE_3, ZBE_4, ZBE_7_10, check_is_numeric_ascii,
+    get_message_key, query,
 };
 use EncounterType::Einrichtungskontakt;
 use anyhow::anyhow;
-use fhir_model::DateTime;
 use fhir_model::r4b::codes::{EncounterLocationStatus, EncounterStatus, IdentifierUse};
 use fhir_model::r4b::resources::{
     BundleEntry, Encounter, EncounterBuilder, EncounterDiagnosis, EncounterHospitalization,
-    EncounterLocation, ResourceType,
+    EncounterLocation, ParametersParameterValue, ResourceType,
 };
 use fhir_model::r4b::types::{
-    CodeableConcept, Coding, Extension, ExtensionValue, Identifier, Meta, Period, Reference,
+    CodeableConcept, Coding, Duration, Extension, ExtensionValue, Identifier, Meta, Period,
+    Reference,
 };
+use fhir_model::{DateTime, Instant};
 use hl7_parser::Message;
 use hl7_parser::message::Field;
 use log::{Level, log};
@@ -68,18 +80,36 @@ impl From<&EncounterType> for Coding {
 }
 
 pub(super) fn map(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     resources: &ResourceMap,
+    history: &LocationHistory,
+    updates: &mut Vec<LocationUpdate>,
 ) -> Result<Vec<BundleEntry>, MappingError> {
+    let msg = ctx.msg;
     let mut result: Vec<BundleEntry> = vec![];
 
-    if should_msg_be_skipped(msg)? {
+    match ctx.message_type() {
+        // A47 carries no visit data, the encounters of the former patient can't be listed
+        MessageType::A47 => {
+            return Err(MappingError::ManualReview(
+                "A47 reassigns all encounters of the former patient (MRG-1), which are unknown"
+                    .to_string(),
+            ));
+        }
+        // swap visit number at the existing encounters
+        MessageType::A50 if !config.fall.delete_stale_visit_number => {
+            result.append(&mut map_visit_number_update(ctx, config, history, updates)?);
+            return Ok(result);
+        }
+        _ => {}
+    }
+
+    if should_msg_be_skipped(ctx)? {
         return Ok(result);
     }
 
-    let msg_type = message_type(msg);
-    let message_type = msg_type.map_err(MessageAccessError::MessageTypeError)?;
+    let message_type = ctx.message_type();
 
     match message_type {
         MessageType::A01
@@ -90,8 +120,9 @@ pub(super) fn map(
         | MessageType::A06
         | MessageType::A07
         | MessageType::A08
-        | MessageType::A13 => {
-            let enc_admit = map_einrichtungskontakt(msg, config, resources)?;
+        | MessageType::A13
+        | MessageType::A50 => {
+            let enc_admit = map_einrichtungskontakt(ctx, config, resources)?;
 
             let mut lvl_1_request_type = EntryRequestType::UpdateAsCreate;
             if message_type == MessageType::A04 {
@@ -103,7 +134,22 @@ pub(super) fn map(
 
             result.push(bundle_entry(enc_admit, lvl_1_request_type, config)?);
 
-            if let Some(enc_dep) = map_abteilungskontakt(msg, config, resources)? {
+            if is_movement_deleted(msg) {
+                // the movement (ZBE-1) was removed at the source system
+                for level in
+                    enabled_levels(config, [Fachabteilungskontakt, Versorgungsstellenkontakt])
+                {
+                    let enc = base_encounter(ctx, config, resources, &level)?.build()?;
+                    result.push(bundle_entry(enc, EntryRequestType::Delete, config)?);
+                }
+                updates.push(LocationUpdate::Remove(
+                    ctx.require_visit_number()?.to_string(),
+                    map_selected_identifier(&Versorgungsstellenkontakt, config, ctx)?,
+                ));
+                return Ok(result);
+            }
+
+            if let Some(enc_dep) = map_abteilungskontakt(ctx, config, resources)? {
                 result.push(bundle_entry(
                     enc_dep,
                     EntryRequestType::UpdateAsCreate,
@@ -111,50 +157,102 @@ pub(super) fn map(
                 )?);
             }
 
-            if let Some(care_site_enc) = map_versorgungsstellenkontakt(msg, config, resources)? {
+            if let Some(mut care_site_enc) = map_versorgungsstellenkontakt(ctx, config, resources)?
+            {
+                // the history is restored by A12, it must not keep exact locations of protected
+                // units
+                if protection::is_protected(msg, &config.protected_units) {
+                    protection::protect_encounter(&mut care_site_enc);
+                }
+                updates.push(LocationUpdate::Record(
+                    ctx.require_visit_number()?.to_string(),
+                    care_site_enc.clone(),
+                ));
                 result.push(bundle_entry(
                     care_site_enc,
                     EntryRequestType::UpdateAsCreate,
                     config,
                 )?);
             }
+
+            if message_type == MessageType::A50 {
+                // encounter was recreated with the new visit number, remove the stale one
+                result.push(delete_bundle_entry(
+                    &ResourceType::Encounter,
+                    &stale_visit_identifier(msg, &config.fall.einrichtungskontakt.system)?,
+                    config,
+                )?);
+            }
+            if message_type == MessageType::A03 {
+                updates.push(LocationUpdate::Clear(
+                    ctx.require_visit_number()?.to_string(),
+                ));
+            }
+            if matches!(message_type, MessageType::A06 | MessageType::A07) {
+                result.append(&mut map_class_change(ctx, config, history, updates)?);
+            }
+            Ok(result)
+        }
+        // pending admission creates the planned 'Einrichtungskontakt' only
+        MessageType::A14 => {
+            let mut enc_planned =
+                base_encounter(ctx, config, resources, &Einrichtungskontakt)?.build()?;
+            enc_planned.status = EncounterStatus::Planned;
+            enc_planned.extension.append(&mut map_planned_dates(msg)?);
+
+            // don't overwrite the encounter, if the admission was already processed
+            result.push(bundle_entry(
+                enc_planned,
+                EntryRequestType::ConditionalCreate,
+                config,
+            )?);
             Ok(result)
         }
         // create only basic encounter data for delete
-        MessageType::A11 | MessageType::A27 | MessageType::A12 | MessageType::A38 => {
+        // cancelled pending admission removes the planned encounters (A14, A05)
+        MessageType::A27 => {
+            let mut levels = vec![Einrichtungskontakt];
+            // pending admissions without movement (A14) have no lower levels
+            if query(msg, ZBE_1_1).is_some() {
+                levels.extend([Fachabteilungskontakt, Versorgungsstellenkontakt]);
+            }
+
+            for level in enabled_levels(config, levels) {
+                let enc = base_encounter(ctx, config, resources, &level)?.build()?;
+                result.push(cancel_pending_admit_entry(enc, config)?);
+            }
+            updates.push(LocationUpdate::Clear(
+                ctx.require_visit_number()?.to_string(),
+            ));
+
+            Ok(result)
+        }
+        MessageType::A11 | MessageType::A12 | MessageType::A38 => {
+            let mut levels = vec![Fachabteilungskontakt, Versorgungsstellenkontakt];
             // A12 deletes only  Fachabteilungskontakt & Versorgungsstellenkontakt
-            if message_type == MessageType::A11
-                || message_type == MessageType::A27
-                || message_type == MessageType::A38
-            {
-                let enc_admit =
-                    base_encounter(msg, config, resources, &Einrichtungskontakt)?.build()?;
-                result.push(bundle_entry(enc_admit, EntryRequestType::Delete, config)?)
+            if message_type != MessageType::A12 {
+                levels.insert(0, Einrichtungskontakt);
             }
 
-            result.push(bundle_entry(
-                base_encounter(
-                    msg,
-                    config,
-                    resources,
-                    &EncounterType::Fachabteilungskontakt,
-                )?
-                .build()?,
-                EntryRequestType::Delete,
-                config,
-            )?);
+            for level in enabled_levels(config, levels) {
+                let enc = base_encounter(ctx, config, resources, &level)?.build()?;
+                result.push(match message_type {
+                    MessageType::A11 => cancel_admit_entry(enc, config)?,
+                    _ => bundle_entry(enc, EntryRequestType::Delete, config)?,
+                });
+            }
 
-            result.push(bundle_entry(
-                base_encounter(
-                    msg,
-                    config,
-                    resources,
-                    &EncounterType::Versorgungsstellenkontakt,
-                )?
-                .build()?,
-                EntryRequestType::Delete,
-                config,
-            )?);
+            match message_type {
+                MessageType::A11 => updates.push(LocationUpdate::Clear(
+                    ctx.require_visit_number()?.to_string(),
+                )),
+                MessageType::A12 => {
+                    if let Some(reverted) = map_transfer_reversal(ctx, config, history, updates)? {
+                        result.push(reverted);
+                    }
+                }
+                _ => {}
+            }
 
             Ok(result)
         }
@@ -163,7 +261,226 @@ pub(super) fn map(
     }
 }
 
-fn should_msg_be_skipped(msg: &Message) -> Result<bool, ParsingError> {
+/// Movement action (ZBE-4) 'DELETE' removes the movement identified by ZBE-1.
+fn is_movement_deleted(msg: &Message) -> bool {
+    query(msg, ZBE_1_1).is_some() && query(msg, ZBE_4) == Some("DELETE")
+}
+
+/// Corrects the class of the encounters of the visit (A06, A07), since the mapped encounters
+/// only cover the current movement.
+///
+/// The former movements are taken from the [`LocationHistory`] and each encounter level is patched
+/// by its level identifier, which matches a single encounter. Movements, which are not recorded
+/// (e.g. after a restart), keep their class.
+fn map_class_change(
+    ctx: &MappingContext,
+    config: &Fhir,
+    history: &LocationHistory,
+    updates: &mut Vec<LocationUpdate>,
+) -> Result<Vec<BundleEntry>, MappingError> {
+    // 'nachstationär' keeps the inpatient class at the 'Einrichtungskontakt'
+    if query(ctx.msg, PV1_2) == Some("NS") {
+        return Ok(vec![]);
+    }
+
+    let visit_number = ctx.require_visit_number()?;
+    let class = map_encounter_class(ctx.msg, config)?;
+    let patch = replace_patch(
+        "Encounter.class".to_string(),
+        ParametersParameterValue::Coding(class.clone()),
+    )?;
+    updates.push(LocationUpdate::Class(visit_number.to_string(), class));
+
+    movement_urls(
+        &history.movements(visit_number),
+        query(ctx.msg, ZBE_1_1),
+        config,
+    )?
+    .into_iter()
+    .map(|url| conditional_patch_bundle_entry(patch.clone(), url, config))
+    .collect()
+}
+
+/// Conditional urls of the 'Abteilungskontakt' and 'Versorgungsstellenkontakt' of the recorded
+/// movements by their level identifier (ZBE-1.1). The current movement is skipped.
+fn movement_urls(
+    movements: &[Encounter],
+    current: Option<&str>,
+    config: &Fhir,
+) -> Result<Vec<String>, MappingError> {
+    let mut urls: Vec<String> = vec![];
+
+    for movement in movements {
+        let Some(id) = movement
+            .identifier
+            .iter()
+            .flatten()
+            .find(|i| i.system.as_ref() == Some(&config.fall.versorgungsstellenkontakt.system))
+            .and_then(|i| i.value.as_deref())
+            .filter(|id| Some(*id) != current)
+        else {
+            continue;
+        };
+
+        for level in enabled_levels(config, [Fachabteilungskontakt, Versorgungsstellenkontakt]) {
+            let identifier = Identifier::builder()
+                .system(kontaktebene(config, &level).system.clone())
+                .value(id.to_string())
+                .build()?;
+            let url = upsert_reference(&ResourceType::Encounter, &identifier, config)?;
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+
+    Ok(urls)
+}
+
+/// Reverts a cancelled transfer (A12) by restoring the 'Versorgungsstellenkontakt' of the
+/// movement before, as recorded in the [`LocationHistory`].
+fn map_transfer_reversal(
+    ctx: &MappingContext,
+    config: &Fhir,
+    history: &LocationHistory,
+    updates: &mut Vec<LocationUpdate>,
+) -> Result<Option<BundleEntry>, MappingError> {
+    let visit_number = ctx.require_visit_number()?;
+    let cancelled = map_selected_identifier(&Versorgungsstellenkontakt, config, ctx)?;
+    let previous = history.previous(visit_number, &cancelled);
+    updates.push(LocationUpdate::Remove(visit_number.to_string(), cancelled));
+
+    match previous {
+        Some(mut previous) => {
+            previous.status = EncounterStatus::InProgress;
+            if let Some(period) = previous.period.as_mut() {
+                period.end = None;
+            }
+            previous
+                .location
+                .iter_mut()
+                .flatten()
+                .for_each(|l| l.status = Some(EncounterLocationStatus::Active));
+
+            Ok(Some(bundle_entry(
+                previous,
+                EntryRequestType::UpdateAsCreate,
+                config,
+            )?))
+        }
+        None => {
+            log!(
+                Level::Debug,
+                "No previous location known for visit number {visit_number} at msg-id '{}'",
+                get_message_key(ctx.msg)?
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Cancels the encounter of a cancelled admission, either by deleting it or by
+/// setting its status to 'entered-in-error' (see [`CancelAdmitMode`]).
+fn cancel_admit_entry(enc: Encounter, config: &Fhir) -> Result<BundleEntry, MappingError> {
+    match config.fall.cancel_admit {
+        CancelAdmitMode::Delete => bundle_entry(enc, EntryRequestType::Delete, config),
+        CancelAdmitMode::EnteredInError => status_patch_entry(enc, "entered-in-error", config),
+    }
+}
+
+/// Cancels the planned encounter of a cancelled pending admission, either by deleting it or
+/// by setting its status to 'cancelled' (see [`CancelPendingAdmitMode`]).
+fn cancel_pending_admit_entry(enc: Encounter, config: &Fhir) -> Result<BundleEntry, MappingError> {
+    match config.fall.cancel_pending_admit {
+        CancelPendingAdmitMode::Delete => bundle_entry(enc, EntryRequestType::Delete, config),
+        CancelPendingAdmitMode::Cancelled => status_patch_entry(enc, "cancelled", config),
+    }
+}
+
+fn status_patch_entry(
+    enc: Encounter,
+    status: &str,
+    config: &Fhir,
+) -> Result<BundleEntry, MappingError> {
+    let identifier = select_identifier(&enc.identifier, config)?;
+
+    patch_bundle_entry(
+        replace_patch(
+            "Encounter.status".to_string(),
+            ParametersParameterValue::Code(status.to_string()),
+        )?,
+        &ResourceType::Encounter,
+        identifier,
+        config,
+    )
+}
+
+/// Swaps the prior visit number (MRG-5) with the current one (PV1-19) at the existing encounters.
+///
+/// The 'Einrichtungskontakt' is patched by its usual identifier with the prior visit number. The
+/// official identifier with the visit number is present at every encounter level, so the lower
+/// levels of the movements recorded in the [`LocationHistory`] are patched by their level
+/// identifiers. Movements, which are not recorded (e.g. after a restart), keep the prior one.
+fn map_visit_number_update(
+    ctx: &MappingContext,
+    config: &Fhir,
+    history: &LocationHistory,
+    updates: &mut Vec<LocationUpdate>,
+) -> Result<Vec<BundleEntry>, MappingError> {
+    let visit_number = ctx.require_visit_number()?;
+    let stale = stale_visit_identifier(ctx.msg, &config.fall.einrichtungskontakt.system)?;
+    let stale_number = stale.value.as_deref().unwrap_or_default();
+    let replace = |system: &str| {
+        replace_patch(
+            format!("Encounter.identifier.where(system='{system}').value"),
+            ParametersParameterValue::String(visit_number.to_string()),
+        )
+    };
+
+    let mut patch = replace(&config.fall.einrichtungskontakt.system)?;
+    patch
+        .parameter
+        .extend(replace(&config.fall.system)?.parameter);
+    let mut result = vec![conditional_patch_bundle_entry(
+        patch,
+        upsert_reference(&ResourceType::Encounter, &stale, config)?,
+        config,
+    )?];
+
+    let movements = history.movements(stale_number);
+    let patch = replace(&config.fall.system)?;
+    for url in movement_urls(&movements, None, config)? {
+        result.push(conditional_patch_bundle_entry(patch.clone(), url, config)?);
+    }
+
+    // the movements are recorded with the current visit number
+    updates.push(LocationUpdate::Clear(stale_number.to_string()));
+    updates.extend(movements.into_iter().map(|mut movement| {
+        movement
+            .identifier
+            .iter_mut()
+            .flatten()
+            .filter(|i| i.system.as_ref() == Some(&config.fall.system))
+            .for_each(|i| i.value = Some(visit_number.to_string()));
+        LocationUpdate::Record(visit_number.to_string(), movement)
+    }));
+
+    Ok(result)
+}
+
+fn stale_visit_identifier(msg: &Message, system: &str) -> Result<Identifier, MappingError> {
+    Ok(Identifier::builder()
+        .system(system.to_string())
+        .value(
+            query(msg, MRG_5_1)
+                .map(String::from)
+                .ok_or(MissingMessageValue("MRG.5.1".to_string()))?,
+        )
+        .build()?)
+}
+
+fn should_msg_be_skipped(ctx: &MappingContext) -> Result<bool, ParsingError> {
+    let msg = ctx.msg;
     if is_begleitperson(msg).is_ok_and(|v| v) {
         log!(
             Level::Debug,
@@ -172,7 +489,10 @@ fn should_msg_be_skipped(msg: &Message) -> Result<bool, ParsingError> {
         );
         return Ok(true);
     }
-    if query(msg, PV1_44).is_none() {
+    // pending admission has no admit date yet
+    if query(msg, PV1_44).is_none()
+        && !matches!(ctx.message_type(), MessageType::A14 | MessageType::A27)
+    {
         log!(
             Level::Debug,
             "skipping message with id {}, since encounter has no stat date ",
@@ -184,40 +504,60 @@ fn should_msg_be_skipped(msg: &Message) -> Result<bool, ParsingError> {
 }
 
 fn map_einrichtungskontakt(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     resources: &ResourceMap,
 ) -> Result<Encounter, MappingError> {
+    let msg = ctx.msg;
     // base encounter
-    let mut enc = base_encounter(msg, config, resources, &Einrichtungskontakt)?
+    let mut enc = base_encounter(ctx, config, resources, &Einrichtungskontakt)?
         // serviceProvider -> Hospital
-        .service_provider(
-            Reference::builder()
-                .reference(format!(
-                    "Organization?identifier=http://fhir.de/sid/arge-ik/iknr|{}",
-                    config.facility_id
-                ))
-                .build()?,
-        )
+        .service_provider(canonical_ref(
+            &ResourceType::Organization,
+            &config.facility_id,
+            "http://fhir.de/sid/arge-ik/iknr",
+            config,
+        )?)
         .build()?;
 
     // hospitalization admit source & discharge disposition (Entlassgrund)
-    enc.hospitalization = map_hospitalization(msg)?;
+    enc.hospitalization = map_hospitalization(ctx, config)?;
+
+    enc.priority = map_priority(msg, config)?;
 
     // Aufnahmegrund
-    if let Some(aufnahmegrund) = map_aufnahmegrund(msg)? {
-        enc.extension = vec![
+    if let Some(aufnahmegrund) = map_aufnahmegrund(msg, config)? {
+        enc.extension.push(
             Extension::builder()
                 .url("http://fhir.de/StructureDefinition/Aufnahmegrund".to_string())
                 .extension(aufnahmegrund)
                 .build()?,
-        ];
+        );
+    }
+
+    if let Some(case_category) = map_case_category(msg, config)? {
+        enc.extension.push(case_category);
+    }
+
+    if let Some(financial_class) = map_financial_class(msg, config)? {
+        enc.extension.push(financial_class);
+    }
+
+    enc.extension.append(&mut map_wahlleistungen(msg, config)?);
+
+    // pre-admission
+    if ctx.message_type() == MessageType::A05 {
+        enc.extension.append(&mut map_planned_dates(msg)?);
     }
 
     enc.diagnosis = map_conditions(msg, config)?;
 
+    enc.participant = map_participants(msg, config)?;
+
     enc.part_of = map_mothers_encounter(msg, config)?;
 
+    enc.account = map_account(msg, config)?.into_iter().map(Some).collect();
+
     if let Some(bed_status) = query(msg, PV1_2)
         && bed_status == "NS"
     {
@@ -233,25 +573,222 @@ fn map_einrichtungskontakt(
     Ok(enc)
 }
 
+/// Priority (v3-ActPriority) of the admission by the visit priority code (PV2-25) or the
+/// admission type (PV1-4.1), local codes are translated by the configured code table
+/// (see [`PriorityConfig`]).
+fn map_priority(msg: &Message, config: &Fhir) -> Result<Option<CodeableConcept>, MappingError> {
+    let translate = |code: &str| {
+        config
+            .fall
+            .priorities
+            .iter()
+            .find(|p| p.code == code)
+            .map(|p| p.priority.as_str())
+    };
+
+    let priority = query(msg, PV2_25)
+        .and_then(|code| {
+            translate(code).or(match code {
+                "1" => Some("EM"),
+                "2" => Some("UR"),
+                "3" => Some("EL"),
+                _ => None,
+            })
+        })
+        .or_else(|| query(msg, PV1_4_1).and_then(translate));
+
+    let Some(priority) = priority else {
+        return Ok(None);
+    };
+    let display = match priority {
+        "A" => Some("ASAP"),
+        "EL" => Some("elective"),
+        "EM" => Some("emergency"),
+        "P" => Some("preoperative"),
+        "R" => Some("routine"),
+        "S" => Some("stat"),
+        "UR" => Some("urgent"),
+        _ => None,
+    };
+
+    let mut coding = Coding::builder()
+        .system("http://terminology.hl7.org/CodeSystem/v3-ActPriority".to_string())
+        .code(priority.to_string())
+        .build()?;
+    coding.display = display.map(str::to_string);
+
+    Ok(Some(
+        CodeableConcept::builder()
+            .coding(vec![Some(coding)])
+            .build()?,
+    ))
+}
+
+/// Expected admit (PV2-8) and discharge (PV2-9) date of a pre-admission as
+/// `plannedStartDate` / `plannedEndDate` extensions.
+fn map_planned_dates(msg: &Message) -> Result<Vec<Extension>, MappingError> {
+    [
+        (
+            PV2_8,
+            "http://hl7.org/fhir/5.0/StructureDefinition/extension-Encounter.plannedStartDate",
+        ),
+        (
+            PV2_9,
+            "http://hl7.org/fhir/5.0/StructureDefinition/extension-Encounter.plannedEndDate",
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(field, url)| query(msg, field).map(|value| (value, url)))
+    .map(|(value, url)| -> Result<Extension, MappingError> {
+        Ok(Extension::builder()
+            .url(url.to_string())
+            .value(ExtensionValue::DateTime(parse_datetime(value)?))
+            .build()?)
+    })
+    .collect()
+}
+
+/// Site specific case category (see [`CaseCategoryConfig`]).
+fn map_case_category(msg: &Message, config: &Fhir) -> Result<Option<Extension>, MappingError> {
+    let Some(case_category) = &config.fall.case_category else {
+        return Ok(None);
+    };
+
+    map_local_code_extension(
+        msg,
+        &case_category.field,
+        &case_category.url,
+        &case_category.system,
+    )
+}
+
+/// Extension with the local code at the message location as coding, if present.
+fn map_local_code_extension(
+    msg: &Message,
+    field: &str,
+    url: &str,
+    system: &str,
+) -> Result<Option<Extension>, MappingError> {
+    query(msg, field)
+        .map(|code| -> Result<Extension, MappingError> {
+            Ok(Extension::builder()
+                .url(url.to_string())
+                .value(ExtensionValue::Coding(
+                    Coding::builder()
+                        .system(system.to_string())
+                        .code(code.to_string())
+                        .build()?,
+                ))
+                .build()?)
+        })
+        .transpose()
+}
+
+/// Wahlleistungen as `Wahlleistung` extensions (see [`WahlleistungConfig`]). Site specific
+/// codes without translation are skipped.
+fn map_wahlleistungen(msg: &Message, config: &Fhir) -> Result<Vec<Extension>, MappingError> {
+    let Some(wahlleistung) = &config.fall.wahlleistung else {
+        return Ok(vec![]);
+    };
+
+    wahlleistung
+        .fields
+        .iter()
+        .filter_map(|field| query(msg, field))
+        .filter_map(|value| {
+            let code = wahlleistung.codes.iter().find(|c| c.code == value);
+            if code.is_none() {
+                log!(Level::Debug, "No Wahlleistung for code {value}");
+            }
+            code
+        })
+        .map(|code| -> Result<Extension, MappingError> {
+            Ok(Extension::builder()
+                .url("http://fhir.de/StructureDefinition/Wahlleistung".to_string())
+                .value(ExtensionValue::Coding(
+                    Coding::builder()
+                        .system("http://fhir.de/CodeSystem/Wahlleistung".to_string())
+                        .code(code.wahlleistung.clone())
+                        .display(code.display.clone())
+                        .build()?,
+                ))
+                .build()?)
+        })
+        .collect()
+}
+
+/// Financial class (PV1-20), e.g. DRG, PEPP or ambulant billing (see [`FinancialClassConfig`]).
+fn map_financial_class(msg: &Message, config: &Fhir) -> Result<Option<Extension>, MappingError> {
+    let Some(financial_class) = &config.fall.financial_class else {
+        return Ok(None);
+    };
+
+    map_local_code_extension(msg, PV1_20_1, &financial_class.url, &financial_class.system)
+}
+
+/// Conditional reference to the billing case (`Account`) of the patient account number (PID-18).
+fn map_account(msg: &Message, config: &Fhir) -> Result<Option<Reference>, MappingError> {
+    let Some(system) = config.fall.account_system(config.account.as_ref()) else {
+        return Ok(None);
+    };
+
+    query(msg, PID_18_1)
+        .map(|account_number| resource_ref(&ResourceType::Account, account_number, system, config))
+        .transpose()
+}
+
 fn map_mothers_encounter(msg: &Message, config: &Fhir) -> Result<Option<Reference>, MappingError> {
-    let mothers_enc_number = query(msg, PID_21_1);
-    match mothers_enc_number {
-        Some(mothers_enc_number) => Ok(Some(resource_ref(
-            &ResourceType::Encounter,
-            mothers_enc_number,
-            config.fall.einrichtungskontakt.system.as_str(),
-        )?)),
-        None => Ok(None),
-    }
+    let Some(mothers_enc_number) = query(msg, PID_21_1) else {
+        return Ok(None);
+    };
+    // identifiers of the mother's 'Einrichtungskontakt'
+    let identifiers = vec![
+        Some(
+            Identifier::builder()
+                .system(config.fall.einrichtungskontakt.system.clone())
+                .value(mothers_enc_number.to_string())
+                .r#use(IdentifierUse::Usual)
+                .build()?,
+        ),
+        Some(map_default_identifier(
+            config.fall.system.clone(),
+            mothers_enc_number.to_string(),
+        )?),
+    ];
+
+    Ok(Some(
+        Reference::builder()
+            .reference(upsert_reference(
+                &ResourceType::Encounter,
+                select_identifier(&identifiers, config)?,
+                config,
+            )?)
+            .build()?,
+    ))
 }
-fn map_aufnahmegrund(msg: &Message) -> Result<Option<Vec<Extension>>, MappingError> {
+/// Aufnahmegrund (§301) from PV2-3 / PV1-4 or the configured message locations
+/// (see [`AufnahmegrundConfig`]).
+fn map_aufnahmegrund(msg: &Message, config: &Fhir) -> Result<Option<Vec<Extension>>, MappingError> {
+    let erste_und_zweite_field = config
+        .fall
+        .aufnahmegrund
+        .erste_und_zweite
+        .as_deref()
+        .unwrap_or(PV2_3_1);
+    let dritte_und_vierte_field = config
+        .fall
+        .aufnahmegrund
+        .dritte_und_vierte
+        .as_deref()
+        .unwrap_or(PV1_4__2_1);
     let mut result = vec![];
 
     // Aufnahmegrund
     // 1. und 2. Stelle
-    if let Some(pv2_3_1) = query(msg, PV2_3_1)
-        && check_is_numeric_ascii(pv2_3_1, PV2_3_1)?
-        && let Some(coding) = Option::<Coding>::from(AufnahmeGrundStelle::ErsteUndZweite(pv2_3_1))
+    if let Some(erste_und_zweite) = query(msg, erste_und_zweite_field)
+        && check_is_numeric_ascii(erste_und_zweite, erste_und_zweite_field)?
+        && let Some(coding) =
+            Option::<Coding>::from(AufnahmeGrundStelle::ErsteUndZweite(erste_und_zweite))
     {
         result.push(
             Extension::builder()
@@ -261,12 +798,12 @@ fn map_aufnahmegrund(msg: &Message) -> Result<Option<Vec<Extension>>, MappingErr
         );
     }
 
-    if let Some(r) = query(msg, PV1_4__2_1) {
-        check_is_numeric_ascii(r, PV1_4__2_1)?;
+    if let Some(r) = query(msg, dritte_und_vierte_field) {
+        check_is_numeric_ascii(r, dritte_und_vierte_field)?;
     }
 
     // 3. und 4. Stelle
-    if let Some((Some(dritte), Some(vierte))) = query(msg, PV1_4__2_1)
+    if let Some((Some(dritte), Some(vierte))) = query(msg, dritte_und_vierte_field)
         .filter(|r| r.chars().count() == 2)
         .map(|r| {
             let mut chars = r.chars().take(2);
@@ -309,11 +846,32 @@ fn map_aufnahmegrund(msg: &Message) -> Result<Option<Vec<Extension>>, MappingErr
     }
 }
 
-fn map_entlassgrund(msg: &Message) -> Result<Vec<Extension>, MappingError> {
+/// Entlassungsgrund from PV1-36 and PV1-40, site specific PV1-36 codes are translated by
+/// the configured code table (see [`DischargeReasonConfig`]).
+fn map_entlassgrund(msg: &Message, config: &Fhir) -> Result<Vec<Extension>, MappingError> {
     let mut extension_components = vec![];
 
+    let pv1_36 = query(msg, PV1_36_1);
+    let translated = pv1_36.and_then(|code| {
+        config
+            .fall
+            .discharge_reasons
+            .iter()
+            .find(|r| r.code == code)
+            .map(|r| r.entlassgrund.as_str())
+    });
+    let (erste_und_zweite, dritte) = match translated {
+        Some(entlassgrund) => match entlassgrund.split_at_checked(2) {
+            Some((erste_und_zweite, dritte)) if !dritte.is_empty() => {
+                (Some(erste_und_zweite), Some(dritte))
+            }
+            _ => (Some(entlassgrund), query(msg, PV1_40_1)),
+        },
+        None => (pv1_36, query(msg, PV1_40_1)),
+    };
+
     // 1. und 2. Stelle
-    if let Some(erste_und_zweite) = query(msg, PV1_36_1)
+    if let Some(erste_und_zweite) = erste_und_zweite
         .map(EntlassgrundStelle::ErsteUndZweite)
         .and_then(Option::<Coding>::from)
         .map(|c| {
@@ -327,7 +885,7 @@ fn map_entlassgrund(msg: &Message) -> Result<Vec<Extension>, MappingError> {
     }
 
     // 3. Stelle
-    if let Some(dritte) = query(msg, PV1_40_1)
+    if let Some(dritte) = dritte
         .map(EntlassgrundStelle::Dritte)
         .and_then(Option::<Coding>::from)
         .map(|c| {
@@ -351,18 +909,18 @@ fn map_entlassgrund(msg: &Message) -> Result<Vec<Extension>, MappingError> {
 }
 
 fn map_abteilungskontakt(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     resources: &ResourceMap,
 ) -> Result<Option<Encounter>, MappingError> {
+    let msg = ctx.msg;
+    if !config.fall.abteilungskontakt.enabled {
+        return Ok(None);
+    }
     if let Some(service_type) = get_service_type(msg, resources, config)? {
         // base encounter
-        let mut enc = base_encounter(msg, config, resources, &Fachabteilungskontakt)?
-            .part_of(resource_ref(
-                &ResourceType::Encounter,
-                map_visit_number(msg)?,
-                &config.fall.einrichtungskontakt.system,
-            )?)
+        let mut enc = base_encounter(ctx, config, resources, &Fachabteilungskontakt)?
+            .part_of(einrichtungskontakt_ref(ctx, config)?)
             .build()?;
 
         enc.service_type = Some(service_type);
@@ -381,59 +939,142 @@ fn map_abteilungskontakt(
     }
 }
 
+const FACHABTEILUNGSSCHLUESSEL_SYSTEM: &str =
+    "http://fhir.de/CodeSystem/dkgev/Fachabteilungsschluessel-erweitert";
+
 fn get_service_type(
     msg: &Message,
     resources: &ResourceMap,
     config: &Fhir,
 ) -> Result<Option<CodeableConcept>, MappingError> {
-    let system_fachabteilungs_schluessel: &str =
-        "http://fhir.de/CodeSystem/dkgev/Fachabteilungsschluessel-erweitert";
-
-    if let Some(fab) = parse_fab(msg) {
-        match resources.map_fab_schluessel(fab, get_message_key(msg)?, config, resources) {
-            Ok(Some(fab_from_short_name)) => return Ok(Some(fab_from_short_name)),
-            Err(e) => return Err(e),
-            Ok(None) => {}
+    for source in config.fall.service_type_sources() {
+        let location = match source {
+            ServiceTypeSource::Location => {
+                if let Some(fab) = parse_fab(msg)
+                    && let Some(fab_from_short_name) = resources.map_fab_schluessel(
+                        fab,
+                        get_message_key(msg)?,
+                        config,
+                        resources,
+                    )?
+                {
+                    return Ok(Some(fab_from_short_name));
+                }
+                continue;
+            }
+            ServiceTypeSource::Pv1_39 => PV1_39_1,
+            ServiceTypeSource::Pv1_10 => PV1_10_1,
+            ServiceTypeSource::Zbe7 => ZBE_7_10,
         };
+        if let Some(fab_schluessel) = query(msg, location) {
+            return Ok(Some(get_cc_with_one_code(
+                fab_schluessel.to_string(),
+                FACHABTEILUNGSSCHLUESSEL_SYSTEM.to_string(),
+            )?));
+        }
     }
 
-    if let Some(fab_schluessel) = query(msg, PV1_39_1) {
-        Ok(Some(get_cc_with_one_code(
-            fab_schluessel.to_string(),
-            system_fachabteilungs_schluessel.to_string(),
-        )?))
-    } else {
-        Ok(None)
-    }
+    Ok(None)
 }
 
 fn base_encounter(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     resources: &ResourceMap,
     enc_type: &EncounterType,
 ) -> Result<EncounterBuilder, MappingError> {
-    let visit_number = map_visit_number(msg)?;
-
-    let admit = Encounter::builder()
-        .meta(map_meta(config)?)
-        .identifier(vec![
-            // identifier for Einrichtungskontakt
-            Some(map_level_identifier(enc_type, config, msg)?),
-            // common identifier is last
-            Some(map_default_identifier(
-                config.fall.system.clone(),
-                visit_number.to_string(),
-            )?),
-        ])
-        .class(map_encounter_class(msg)?)
-        .r#type(map_encounter_type(msg, enc_type, resources)?)
-        .subject(subject_ref(msg, &config.person.system)?)
-        .period(map_period(msg, enc_type)?)
-        // set status depends on period.start / period.end
-        .status(map_encounter_status(&map_period(msg, enc_type)?));
-
-    Ok(admit)
+    let msg = ctx.msg;
+    let period = map_period(ctx, enc_type)?;
+    let class = map_encounter_class(msg, config)?;
+    let status = map_encounter_status(ctx, config, class.code.as_deref(), &period)?;
+
+    let mut admit = Encounter::builder()
+        .meta(map_meta(msg, config, enc_type)?)
+        .identifier(map_encounter_identifiers(enc_type, config, ctx)?)
+        .class(class)
+        .r#type(map_encounter_type(ctx, config, enc_type, resources)?)
+        .subject(ctx.subject_ref(&config.person.system)?)
+        .status(status)
+        .extension(map_vip(msg, config)?.extension);
+    if let Some(length) = map_length(&period, config.fall.length_unit)? {
+        admit = admit.length(length);
+    }
+
+    Ok(admit.period(period))
+}
+
+/// Length of stay from the period, if both start and end are set.
+fn map_length(period: &Period, unit: LengthUnit) -> Result<Option<Duration>, MappingError> {
+    let (Some(DateTime::DateTime(Instant(start))), Some(DateTime::DateTime(Instant(end)))) =
+        (&period.start, &period.end)
+    else {
+        return Ok(None);
+    };
+
+    let minutes = (*end - *start).whole_minutes().max(0) as f64;
+    let (value, code, display) = match unit {
+        LengthUnit::Days => (minutes / (24.0 * 60.0), "d", "days"),
+        LengthUnit::Hours => (minutes / 60.0, "h", "hours"),
+    };
+
+    Ok(Some(
+        Duration::builder()
+            // rounded to 2 decimal places
+            .value((value * 100.0).round() / 100.0)
+            .system("http://unitsofmeasure.org".to_string())
+            .code(code.to_string())
+            .unit(display.to_string())
+            .build()?,
+    ))
+}
+
+/// Identifiers of the [`EncounterType`]: the level identifier and the common visit number,
+/// which is last.
+fn map_encounter_identifiers(
+    enc_type: &EncounterType,
+    config: &Fhir,
+    ctx: &MappingContext,
+) -> Result<Vec<Option<Identifier>>, MappingError> {
+    Ok(vec![
+        Some(map_level_identifier(enc_type, config, ctx)?),
+        Some(map_default_identifier(
+            config.fall.system.clone(),
+            ctx.require_visit_number()?.to_string(),
+        )?),
+    ])
+}
+
+/// Identifier of the [`EncounterType`] as selected by the configured identifier selection (see
+/// [`select_identifier`]).
+fn map_selected_identifier(
+    enc_type: &EncounterType,
+    config: &Fhir,
+    ctx: &MappingContext,
+) -> Result<Identifier, MappingError> {
+    Ok(select_identifier(&map_encounter_identifiers(enc_type, config, ctx)?, config)?.clone())
+}
+
+/// Conditional reference to the encounter of the [`EncounterType`] by its selected identifier.
+fn encounter_ref(
+    enc_type: &EncounterType,
+    config: &Fhir,
+    ctx: &MappingContext,
+) -> Result<Reference, MappingError> {
+    Ok(Reference::builder()
+        .reference(upsert_reference(
+            &ResourceType::Encounter,
+            &map_selected_identifier(enc_type, config, ctx)?,
+            config,
+        )?)
+        .build()?)
+}
+
+/// Conditional reference to the 'Einrichtungskontakt' of the message.
+pub(crate) fn einrichtungskontakt_ref(
+    ctx: &MappingContext,
+    config: &Fhir,
+) -> Result<Reference, MappingError> {
+    encounter_ref(&Einrichtungskontakt, config, ctx)
 }
 
 fn map_default_identifier(system: String, value: String) -> Result<Identifier, MappingError> {
@@ -454,32 +1095,52 @@ fn map_default_identifier(system: String, value: String) -> Result<Identifier, M
         .build()?)
 }
 
+/// Config of the Kontaktebene.
+fn kontaktebene<'a>(config: &'a Fhir, enc_type: &EncounterType) -> &'a KontaktebeneConfig {
+    match enc_type {
+        Einrichtungskontakt => &config.fall.einrichtungskontakt,
+        Fachabteilungskontakt => &config.fall.abteilungskontakt,
+        Versorgungsstellenkontakt => &config.fall.versorgungsstellenkontakt,
+    }
+}
+
+/// The levels, which are enabled by their [`KontaktebeneConfig`].
+fn enabled_levels(
+    config: &Fhir,
+    levels: impl IntoIterator<Item = EncounterType>,
+) -> Vec<EncounterType> {
+    levels
+        .into_iter()
+        .filter(|l| kontaktebene(config, l).enabled)
+        .collect()
+}
+
 /// Maps the [`IdentifierUse::Usual`] identifier depending on the [`EncounterType`].
 fn map_level_identifier(
     encounter_type: &EncounterType,
     config: &Fhir,
-    msg: &Message,
+    ctx: &MappingContext,
 ) -> Result<Identifier, MappingError> {
-    let zbe_id = query(msg, ZBE_1_1).ok_or(MessageAccessError::Other(anyhow!(
+    let zbe_id = query(ctx.msg, ZBE_1_1).ok_or(MessageAccessError::Other(anyhow!(
         "Failed to create Identifier: ZBE-1.1 is missing or empty"
     )));
-    let visit_number = map_visit_number(msg)?;
+    let visit_number = ctx.require_visit_number()?;
 
-    let (system, value) = match encounter_type {
-        Einrichtungskontakt => (&config.fall.einrichtungskontakt.system, visit_number),
-        Fachabteilungskontakt => (&config.fall.abteilungskontakt.system, zbe_id?),
-        Versorgungsstellenkontakt => (&config.fall.versorgungsstellenkontakt.system, zbe_id?),
+    let value = match encounter_type {
+        Einrichtungskontakt => visit_number,
+        Fachabteilungskontakt | Versorgungsstellenkontakt => zbe_id?,
     };
 
     Ok(Identifier::builder()
-        .system(system.clone())
+        .system(kontaktebene(config, encounter_type).system.clone())
         .value(value.to_string())
         .r#use(IdentifierUse::Usual)
         .build()?)
 }
 
 fn map_encounter_type(
-    msg: &Message,
+    ctx: &MappingContext,
+    config: &Fhir,
     enc_type: &EncounterType,
     resources: &ResourceMap,
 ) -> Result<Vec<Option<CodeableConcept>>, MappingError> {
@@ -489,7 +1150,7 @@ fn map_encounter_type(
         .build()?;
 
     let kontaktart: Option<CodeableConcept> = {
-        if let Some(art) = map_kontaktart(msg, resources, enc_type)? {
+        if let Some(art) = map_kontaktart(ctx, config, resources, enc_type)? {
             // Kontaktart
             Some(CodeableConcept::builder().coding(vec![Some(art)]).build()?)
         } else {
@@ -504,25 +1165,35 @@ fn map_encounter_type(
 }
 
 fn fab_ref(fab: &str, config: &Fhir) -> Result<Reference, MappingError> {
-    resource_ref(
+    canonical_ref(
         &ResourceType::Organization,
         fab,
         config.organization.department.system.as_str(),
+        config,
     )
 }
 
-fn map_hospitalization(msg: &Message) -> Result<Option<EncounterHospitalization>, MappingError> {
+fn map_hospitalization(
+    ctx: &MappingContext,
+    config: &Fhir,
+) -> Result<Option<EncounterHospitalization>, MappingError> {
+    let msg = ctx.msg;
     if let Some(bed_status) = query(msg, PV1_2)
         && bed_status.eq("O")
     {
         return Ok(None);
     }
 
-    let discharge = map_entlassgrund(msg)?;
-    let admit_source = map_admit_source(msg)?;
+    // a cancelled discharge has no discharge reason
+    let discharge = match ctx.message_type() {
+        MessageType::A13 => vec![],
+        _ => map_entlassgrund(msg, config)?,
+    };
+    let admit_source = map_admit_source(msg, config)?;
+    let re_admission = map_re_admission(msg)?;
 
-    // Wenn beide None sind, gibt es keine Hospitalization
-    if discharge.is_empty() && admit_source.is_none() {
+    // Wenn alle None sind, gibt es keine Hospitalization
+    if discharge.is_empty() && admit_source.is_none() && re_admission.is_none() {
         return Ok(None);
     }
 
@@ -541,13 +1212,46 @@ fn map_hospitalization(msg: &Message) -> Result<Option<EncounterHospitalization>
         );
     }
 
+    if let Some(re_admission) = re_admission {
+        builder = builder.re_admission(re_admission);
+    }
+
     Ok(Some(builder.build()?))
 }
 
-fn map_admit_source(msg: &Message) -> Result<Option<Coding>, MappingError> {
-    let code = query(msg, PV1_4_1).ok_or(MappingError::Other(anyhow!(
-        "Missing PV1-4.1 field / component for Encounter.hospitalization.admitSource"
+/// Wiederaufnahme, flagged by the re-admission indicator (PV1-13).
+fn map_re_admission(msg: &Message) -> Result<Option<CodeableConcept>, MappingError> {
+    if query(msg, PV1_13) != Some("R") {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        CodeableConcept::builder()
+            .coding(vec![Some(
+                Coding::builder()
+                    .system("http://terminology.hl7.org/CodeSystem/v2-0092".to_string())
+                    .code("R".to_string())
+                    .display("Re-admission".to_string())
+                    .build()?,
+            )])
+            .build()?,
+    ))
+}
+
+/// Aufnahmeanlass, site specific codes are translated by the configured code table
+/// (see [`AdmitSourceConfig`]).
+fn map_admit_source(msg: &Message, config: &Fhir) -> Result<Option<Coding>, MappingError> {
+    let admit_source = &config.fall.admit_source;
+    let field = admit_source.field.as_deref().unwrap_or(PV1_4_1);
+    let value = query(msg, field).ok_or(MappingError::Other(anyhow!(
+        "Missing {} field / component for Encounter.hospitalization.admitSource",
+        field
     )))?;
+    let code = admit_source
+        .codes
+        .iter()
+        .find(|c| c.code == value)
+        .map_or(value, |c| c.aufnahmeanlass.as_str());
 
     let display = match code {
         "E" => Ok("Einweisung durch einen Arzt"),
@@ -561,8 +1265,9 @@ fn map_admit_source(msg: &Message) -> Result<Option<Coding>, MappingError> {
         "G" => Ok("Geburt"),
         "B" => Ok("Begleitperson oder mitaufgenommene Pflegekraft"),
         other => Err(MappingError::Other(anyhow!(
-            "Unknown code {} in PV1-4.1 for Encounter.hospitalization.admitSource",
-            other
+            "Unknown code {} in {} for Encounter.hospitalization.admitSource",
+            other,
+            field
         ))),
     }?;
 
@@ -575,10 +1280,26 @@ fn map_admit_source(msg: &Message) -> Result<Option<Coding>, MappingError> {
     ))
 }
 
-fn map_period(msg: &Message, lvl: &EncounterType) -> Result<Period, MappingError> {
+/// A14 and A27 of a pending admission without admit date refer to the planned encounter.
+fn is_pending_admit(ctx: &MappingContext) -> bool {
+    match ctx.message_type() {
+        MessageType::A14 => true,
+        MessageType::A27 => query(ctx.msg, PV1_44).is_none(),
+        _ => false,
+    }
+}
+
+fn map_period(ctx: &MappingContext, lvl: &EncounterType) -> Result<Period, MappingError> {
+    let msg = ctx.msg;
     let start: DateTime;
     let end: Option<DateTime>;
     match lvl {
+        Einrichtungskontakt if is_pending_admit(ctx) => {
+            // planned admission date
+            start =
+                parse_datetime(query(msg, PV2_8).ok_or(MissingMessageValue("PV2.8".to_string()))?)?;
+            end = None;
+        }
         Einrichtungskontakt => {
             start = parse_datetime(
                 query(msg, PV1_44).ok_or(MissingMessageValue("PV1.44".to_string()))?,
@@ -596,7 +1317,7 @@ fn map_period(msg: &Message, lvl: &EncounterType) -> Result<Period, MappingError
                 Some(end) => Some(parse_datetime(end)?),
                 None => {
                     // A04 get never an end date form source system - therefore we use start date here as well
-                    if MessageType::A04 == message_type(msg).map_err(MessageAccessError::from)? {
+                    if ctx.message_type() == MessageType::A04 {
                         Some(start.clone())
                     } else {
                         None
@@ -606,124 +1327,130 @@ fn map_period(msg: &Message, lvl: &EncounterType) -> Result<Period, MappingError
         }
     }
 
+    // a cancelled discharge reopens the encounter
+    let reopened = ctx.message_type() == MessageType::A13;
+
     let mut period: Period = Period::builder().start(start).build()?;
-    if end.is_some() {
+    if end.is_some() && !reopened {
         period.end = end;
     }
 
     Ok(period)
 }
 
-fn map_encounter_status(period: &Period) -> EncounterStatus {
-    match (period.start.as_ref(), period.end.as_ref()) {
+/// Status of the first matching transition (see [`StatusTransitionConfig`]), otherwise
+/// derived from the period: finished if it has ended, in progress if it has started.
+fn map_encounter_status(
+    ctx: &MappingContext,
+    config: &Fhir,
+    class: Option<&str>,
+    period: &Period,
+) -> Result<EncounterStatus, MappingError> {
+    let msg_type = ctx.message_type();
+    let transition = config.fall.status_transitions.iter().find(|t| {
+        t.message_types.contains(&msg_type)
+            && (t.classes.is_empty() || class.is_some_and(|c| t.classes.iter().any(|t| t == c)))
+    });
+    if let Some(transition) = transition {
+        return Ok(transition.status);
+    }
+
+    Ok(match (period.start.as_ref(), period.end.as_ref()) {
         (None, None) => EncounterStatus::Unknown,
         (_, Some(_)) => EncounterStatus::Finished,
         (Some(_), _) => EncounterStatus::InProgress,
-    }
+    })
 }
 
-fn map_meta(config: &Fhir) -> Result<Meta, anyhow::Error> {
+fn map_meta(msg: &Message, config: &Fhir, enc_type: &EncounterType) -> Result<Meta, anyhow::Error> {
+    let profile = kontaktebene(config, enc_type)
+        .profile
+        .clone()
+        .unwrap_or_else(|| config.fall.profile.clone());
+
     Ok(Meta::builder()
-        .profile(vec![Some(config.fall.profile.clone())])
+        .profile(vec![Some(profile)])
         .source(config.meta_source.to_string())
+        .security(map_vip(msg, config)?.security)
         .build()?)
 }
 
-fn map_encounter_class(msg: &Message) -> Result<Coding, anyhow::Error> {
+fn map_encounter_class(msg: &Message, config: &Fhir) -> Result<Coding, anyhow::Error> {
+    if let Some(patient_type) = map_patient_type(msg, config) {
+        return class_coding(&patient_type.class);
+    }
+
     let code = query(msg, PV1_2).ok_or(MissingMessageValue("PV1.2".to_string()))?;
     match code {
-        "I" => Ok(Coding::builder()
-            .system("http://terminology.hl7.org/CodeSystem/v3-ActCode".to_string())
-            .code("IMP".to_string())
-            .display("inpatient encounter".to_string())
-            .build()?),
-        "O" | "NS" | "VS" | "V" => Ok(Coding::builder()
-            .system("http://terminology.hl7.org/CodeSystem/v3-ActCode".to_string())
-            .code("AMB".to_string())
-            .display("ambulatory".to_string())
-            .build()?),
-        "P" => Ok(Coding::builder()
-            .system("http://terminology.hl7.org/CodeSystem/v3-ActCode".to_string())
-            .code("PRENC".to_string())
-            .display("pre-admission".to_string())
-            .build()?),
-        "TS" => Ok(Coding::builder()
-            .system("http://terminology.hl7.org/CodeSystem/v3-ActCode".to_string())
-            .code("SS".to_string())
-            .display("short stay".to_string())
-            .build()?),
+        "I" => class_coding("IMP"),
+        "O" | "NS" | "VS" | "V" => class_coding("AMB"),
+        "P" => class_coding("PRENC"),
+        "TS" => class_coding("SS"),
         _ => Err(anyhow!("Invalid encounter_class code (PV1.2): {}", code)),
     }
 }
 
+fn class_coding(code: &str) -> Result<Coding, anyhow::Error> {
+    let display = match code {
+        "IMP" => "inpatient encounter",
+        "AMB" => "ambulatory",
+        "PRENC" => "pre-admission",
+        "SS" => "short stay",
+        _ => return Err(anyhow!("Invalid encounter class: {}", code)),
+    };
+
+    Ok(Coding::builder()
+        .system("http://terminology.hl7.org/CodeSystem/v3-ActCode".to_string())
+        .code(code.to_string())
+        .display(display.to_string())
+        .build()?)
+}
+
+/// Looks up the configured class and Kontaktart of the patient type (PV1-18).
+fn map_patient_type<'a>(msg: &Message, config: &'a Fhir) -> Option<&'a PatientTypeConfig> {
+    let patient_type = query(msg, PV1_18)?;
+    config
+        .fall
+        .patient_types
+        .iter()
+        .find(|t| t.code == patient_type)
+}
+
 fn map_kontaktart(
-    msg: &Message,
+    ctx: &MappingContext,
+    config: &Fhir,
     resources: &ResourceMap,
     enc_type: &EncounterType,
 ) -> Result<Option<Coding>, MappingError> {
+    let msg = ctx.msg;
     if &Versorgungsstellenkontakt == enc_type {
         let is_valid_ward = is_ward_valid_icu(msg, resources);
         if is_valid_ward {
-            return Ok(Some(
-                Coding::builder()
-                    .system("http://fhir.de/CodeSystem/kontaktart-de".to_string())
-                    .code("intensivstationaer".to_string())
-                    .display("Intensivstationär".to_string())
-                    .build()?,
-            ));
+            return Ok(Some(kontaktart_coding("intensivstationaer")?));
         }
     }
 
+    if let Some(patient_type) = map_patient_type(msg, config) {
+        return match patient_type.kontaktart.as_deref() {
+            Some(code) => Ok(Some(kontaktart_coding(code)?)),
+            None => Ok(None),
+        };
+    }
+
     if let Some(code) = query(msg, PV1_2) {
         match code {
             "I" | "O" => {
-                if message_type(msg).ok() == Some(MessageType::A04) {
-                    Ok(Some(
-                        Coding::builder()
-                            .system("http://fhir.de/CodeSystem/kontaktart-de".to_string())
-                            .code("ub".to_string())
-                            .display("Untersuchung und Behandlung".to_string())
-                            .build()?,
-                    ))
+                if ctx.message_type() == MessageType::A04 {
+                    Ok(Some(kontaktart_coding("ub")?))
                 } else {
                     Ok(None)
                 }
             }
-            "H" => Ok(Some(
-                Coding::builder()
-                    .system("http://fhir.de/CodeSystem/kontaktart-de".to_string())
-                    .code("begleitperson".to_string())
-                    .display("Begleitperson".to_string())
-                    .build()?,
-            )),
-            "TS" => Ok(Some(
-                Coding::builder()
-                    .system("http://fhir.de/CodeSystem/kontaktart-de".to_string())
-                    .code("teilstationaer".to_string())
-                    .display("Teilstationäre Behandlung".to_string())
-                    .build()?,
-            )),
-            "NS" => Ok(Some(
-                Coding::builder()
-                    .system("http://fhir.de/CodeSystem/kontaktart-de".to_string())
-                    .code("nachstationaer".to_string())
-                    .display("Nachstationär".to_string())
-                    .build()?,
-            )),
-            "UB" => Ok(Some(
-                Coding::builder()
-                    .system("http://fhir.de/CodeSystem/kontaktart-de".to_string())
-                    .code("ub".to_string())
-                    .display("Untersuchung und Behandlung".to_string())
-                    .build()?,
-            )),
-            "V" | "VS" => Ok(Some(
-                Coding::builder()
-                    .system("http://fhir.de/CodeSystem/kontaktart-de".to_string())
-                    .code("vorstationaer".to_string())
-                    .display("Vorstationär".to_string())
-                    .build()?,
-            )),
+            "H" => Ok(Some(kontaktart_coding("begleitperson")?)),
+            "TS" => Ok(Some(kontaktart_coding("teilstationaer")?)),
+            "NS" => Ok(Some(kontaktart_coding("nachstationaer")?)),
+            "UB" => Ok(Some(kontaktart_coding("ub")?)),
+            "V" | "VS" => Ok(Some(kontaktart_coding("vorstationaer")?)),
             _ => Err(anyhow!("Invalid kontakt_art code (PV1.2): {}", code))
                 .map_err(MappingError::Other)?,
         }
@@ -732,57 +1459,93 @@ fn map_kontaktart(
     }
 }
 
+fn kontaktart_coding(code: &str) -> Result<Coding, MappingError> {
+    let display = match code {
+        "intensivstationaer" => "Intensivstationär",
+        "ub" => "Untersuchung und Behandlung",
+        "begleitperson" => "Begleitperson",
+        "teilstationaer" => "Teilstationäre Behandlung",
+        "nachstationaer" => "Nachstationär",
+        "vorstationaer" => "Vorstationär",
+        "normalstationaer" => "Normalstationär",
+        "operation" => "Operation",
+        _ => return Err(anyhow!("Invalid Kontaktart: {}", code).into()),
+    };
+
+    Ok(Coding::builder()
+        .system("http://fhir.de/CodeSystem/kontaktart-de".to_string())
+        .code(code.to_string())
+        .display(display.to_string())
+        .build()?)
+}
+
 fn map_versorgungsstellenkontakt(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     resources: &ResourceMap,
 ) -> Result<Option<Encounter>, MappingError> {
-    let mapped_locations = map_lvl_3_locations(msg, config, resources)?;
+    let msg = ctx.msg;
+    if !config.fall.versorgungsstellenkontakt.enabled {
+        return Ok(None);
+    }
+    let mapped_locations = map_lvl_3_locations(ctx, config, resources)?;
     if mapped_locations.is_empty() {
         return Ok(None);
     }
-    let versorgungskontakt = base_encounter(msg, config, resources, &Versorgungsstellenkontakt)?
-        .part_of(resource_ref(
-            &ResourceType::Encounter,
-            query(msg, ZBE_1_1)
-                .ok_or(MessageAccessError::MissingMessageSegment("ZBE".to_string()))?,
-            &config.fall.abteilungskontakt.system,
-        )?)
-        .location(mapped_locations)
-        .status(map_encounter_status(&map_period(
-            msg,
-            &Versorgungsstellenkontakt,
-        )?));
+    // part of the Abteilungskontakt, or the Einrichtungskontakt if disabled
+    let part_of = match config.fall.abteilungskontakt.enabled {
+        true => encounter_ref(&Fachabteilungskontakt, config, ctx)?,
+        false => einrichtungskontakt_ref(ctx, config)?,
+    };
+    let versorgungskontakt = base_encounter(ctx, config, resources, &Versorgungsstellenkontakt)?
+        .part_of(part_of)
+        .location(mapped_locations);
 
     let mut kontakt = versorgungskontakt
         .build()
         .map_err(MappingError::BuilderError)?;
 
     kontakt.service_provider = query(msg, PV1_3_1).and_then(|f| {
-        resource_ref(
+        canonical_ref(
             &ResourceType::Organization,
             f,
             config.organization.ward.system.as_str(),
+            config,
         )
         .ok()
     });
+    // intensive care at ICU wards (InfoStation.json), e.g. for the detection of ICU stays
+    if is_ward_valid_icu(msg, resources) {
+        kontakt.service_type = Some(
+            CodeableConcept::builder()
+                .coding(vec![Some(
+                    Coding::builder()
+                        .system(FACHABTEILUNGSSCHLUESSEL_SYSTEM.to_string())
+                        .code("3600".to_string())
+                        .display("Intensivmedizin".to_string())
+                        .build()?,
+                )])
+                .build()?,
+        );
+    }
 
     Ok(Some(kontakt))
 }
 
 fn map_lvl_3_locations(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     resources: &ResourceMap,
 ) -> Result<Vec<Option<EncounterLocation>>, MappingError> {
+    let msg = ctx.msg;
     let mut locations: Vec<Option<EncounterLocation>> = vec![];
 
     if let (Some(_department), Some(loc)) =
         (parse_fab(msg), map_ward_location(msg, config, resources)?)
     {
         // department location should be always available
-        let mut department_location = to_encounter_location(loc)?;
-        department_location.status = Some(get_location_status(msg)?);
+        let mut department_location = to_encounter_location(loc, config)?;
+        department_location.status = Some(get_location_status(ctx));
 
         locations.push(Some(department_location));
 
@@ -793,15 +1556,15 @@ fn map_lvl_3_locations(
             if let (Some(ward), Some(room)) = (ward, room)
                 && let Some(l) = map_room_location(config, ward, room)?
             {
-                let mut room_location = to_encounter_location(l)?;
-                room_location.status = Some(get_location_status(msg)?);
+                let mut room_location = to_encounter_location(l, config)?;
+                room_location.status = Some(get_location_status(ctx));
                 locations.push(Some(room_location));
             }
 
             if let (Some(ward), Some(room), Some(bed)) = (ward, room, bed) {
                 let mut bed_location =
-                    to_encounter_location(map_bed_location(config, ward, room, bed)?)?;
-                bed_location.status = Some(get_location_status(msg)?);
+                    to_encounter_location(map_bed_location(config, ward, room, bed)?, config)?;
+                bed_location.status = Some(get_location_status(ctx));
                 locations.push(Some(bed_location));
             }
         }
@@ -816,18 +1579,11 @@ fn map_lvl_3_locations(
     }
 }
 
-fn get_location_status(msg: &Message) -> Result<EncounterLocationStatus, MessageAccessError> {
-    match message_type(msg) {
-        Ok(MessageType::A04) | Ok(MessageType::A03) => Ok(EncounterLocationStatus::Completed),
-        Ok(_) => {
-            let movement_end = query(msg, ZBE_3);
-            if movement_end.is_some() {
-                Ok(EncounterLocationStatus::Completed)
-            } else {
-                Ok(EncounterLocationStatus::Active)
-            }
-        }
-        Err(e) => Err(e.into()),
+fn get_location_status(ctx: &MappingContext) -> EncounterLocationStatus {
+    match ctx.message_type() {
+        MessageType::A04 | MessageType::A03 => EncounterLocationStatus::Completed,
+        _ if query(ctx.msg, ZBE_3).is_some() => EncounterLocationStatus::Completed,
+        _ => EncounterLocationStatus::Active,
     }
 }
 
@@ -873,6 +1629,7 @@ fn map_conditions(
                 &ResourceType::Condition,
                 map_bar_identifier(condition_id, priority)?.as_str(),
                 &config.condition.system,
+                config,
             )?;
 
             let codings =
@@ -902,7 +1659,10 @@ fn map_conditions(
 /// It seems .1 is only added to ADT message if .2 priority is present, too.
 /// Since we build identifier from this value, we need to unify it
 /// to standard, which are set by HL7 BAR messages.
-fn map_bar_identifier(condition_id: &Field, priority: &Field) -> Result<String, MappingError> {
+pub(crate) fn map_bar_identifier(
+    condition_id: &Field,
+    priority: &Field,
+) -> Result<String, MappingError> {
     let split_by_point = priority.raw_value().split(".").collect::<Vec<&str>>();
 
     match split_by_point.len() > 1 {
@@ -1044,10 +1804,16 @@ fn map_diagnose_local_codes(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{CheckMode, FallConfig, LocationConfig, PatientConfig, SystemConfig};
+    use crate::config::{
+        AccountConfig, AdmitSourceCodeConfig, CheckMode, FallConfig, LocationConfig, PatientConfig,
+        SelectedIdentifierUse, WahlleistungCodeConfig,
+    };
     use crate::error::MessageAccessError::UnsupportedContentError;
-    use crate::test_utils::tests::{get_dummy_resources, get_test_config, read_test_resource};
+    use crate::test_utils::tests::{
+        get_dummy_resources, get_test_config, read_test_message, read_test_resource,
+    };
     use fhir_model::r4b::codes::HTTPVerb;
+    use fhir_model::r4b::resources::Resource;
     use hl7_parser::Message;
     use rstest::rstest;
     use std::default::Default;
@@ -1068,25 +1834,61 @@ ZBE|zbe_id^SAP-ISH~615^MEDOS|20030901163000||UPDATE"#;
             check_mode: CheckMode::Strict,
             bundle_identifier_system: "my-bundle".to_string(),
             fall: FallConfig {
-                einrichtungskontakt: SystemConfig {
+                einrichtungskontakt: KontaktebeneConfig {
                     system: "einrichtungskontakt".into(),
+                    ..Default::default()
                 },
-                abteilungskontakt: SystemConfig {
+                abteilungskontakt: KontaktebeneConfig {
                     system: "abteilungskontakt".into(),
+                    ..Default::default()
                 },
-                versorgungsstellenkontakt: SystemConfig {
+                versorgungsstellenkontakt: KontaktebeneConfig {
                     system: "versorgungsstellenkontakt".into(),
+                    ..Default::default()
                 },
                 profile: String::default(),
                 system: String::default(),
+                delete_stale_visit_number: false,
+                cancel_admit: CancelAdmitMode::Delete,
+                cancel_pending_admit: CancelPendingAdmitMode::Delete,
+                patient_types: vec![],
+                case_category: None,
+                financial_class: None,
+                wahlleistung: None,
+                admit_source: Default::default(),
+                aufnahmegrund: Default::default(),
+                discharge_reasons: vec![],
+                priorities: vec![],
+                length_unit: Default::default(),
+                service_type_sources: vec![],
+                account_system: None,
+                status_transitions: vec![],
             },
             person: PatientConfig::default(),
             facility_id: String::default(),
             location: LocationConfig::default(),
             meta_source: String::default(),
             condition: Default::default(),
+            procedure: Default::default(),
+            coverage: Default::default(),
+            practitioner: None,
+            account: None,
             observation: Default::default(),
             organization: Default::default(),
+            unknown_code_samples: false,
+            vip: Default::default(),
+            base_url: None,
+            absolute_references: false,
+            message_type_source: Default::default(),
+            target_preset: None,
+            bundle_type: None,
+            identifier_escaping: None,
+            reference_resolution: None,
+            entry_order: vec![],
+            identifier_selection: Default::default(),
+            protected_units: Default::default(),
+            full_url_namespace: None,
+            role: Default::default(),
         };
 
         let expected = Identifier::builder()
@@ -1096,7 +1898,12 @@ ZBE|zbe_id^SAP-ISH~615^MEDOS|20030901163000||UPDATE"#;
             .build()
             .unwrap();
 
-        let identifier = map_level_identifier(&level, &config, &msg).unwrap();
+        let identifier = map_level_identifier(
+            &level,
+            &config,
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+        )
+        .unwrap();
 
         assert_eq!(identifier, expected);
     }
@@ -1109,14 +1916,66 @@ PID|1|9999999|9999999|88888888|Nachname^Vorname^^^^^L||20251102|M|||Strasse. 1&S
 PV1|1|I|POL1234^BSP-2-2^2^POL^KLINIKUM^961640|R^^HL7~01^Normalfall^11||||^^^^^^^^^L^^^^^^^^^^^^^^^^^^^^^^^^^^^BSNR||N||||||N|||88888888||K|||||||||||||||01|||0800|9||||202511022120|202511022120||||||A
 ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
 "#, true).unwrap();
-        let actual =
-            map_versorgungsstellenkontakt(&msg, &get_test_config(), &get_dummy_resources())
-                .unwrap()
-                .unwrap();
+        let actual = map_versorgungsstellenkontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .unwrap()
+        .unwrap();
 
         assert_eq!(actual.location.len(), 3);
     }
 
+    #[test]
+    fn map_kontaktebenen_config() {
+        let msg = Message::parse_with_lenient_newlines(r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|20251102212117||ADT^A08^ADT_A01|12332112|P|2.5||123788998|NE|NE||8859/1
+EVN|A08|202511022120||11036_123456789|ZZZZZZZZ|202511022120
+PID|1|9999999|9999999|88888888|Nachname^Vorname^^^^^L||20251102|M|||Strasse. 1&Strasse.&1^^Stadt^^30000^DE^L~^^Stadt^^^^BDL||0000000000000^PRN^PH^^^00000^0000000^^^^^000000000000|||U|||||12345678^^^KH^VN~1234567^^^KH^PT||Stadt|J|1|DE|||201103240800|Y
+PV1|1|I|POL1234^BSP-2-2^2^POL^KLINIKUM^961640|R^^HL7~01^Normalfall^11||||^^^^^^^^^L^^^^^^^^^^^^^^^^^^^^^^^^^^^BSNR||N||||||N|||88888888||K|||||||||||||||01|||0800|9||||202511022120|202511022120||||||A
+ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
+"#, true).unwrap();
+        let mut config = get_test_config();
+        config.fall.abteilungskontakt.enabled = false;
+        config.fall.versorgungsstellenkontakt.profile = Some("https://example.org/ward".into());
+
+        assert_eq!(
+            map_abteilungskontakt(
+                &MappingContext::new(&msg, &get_test_config()).unwrap(),
+                &config,
+                &get_dummy_resources()
+            )
+            .unwrap(),
+            None
+        );
+        let ward = map_versorgungsstellenkontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            ward.meta.unwrap().profile,
+            vec![Some("https://example.org/ward".to_string())]
+        );
+        // part of the Einrichtungskontakt
+        let part_of = ward.part_of.unwrap().reference.unwrap();
+        assert!(part_of.contains(&config.fall.einrichtungskontakt.system));
+        assert!(part_of.ends_with("88888888"));
+
+        config.fall.versorgungsstellenkontakt.enabled = false;
+        assert_eq!(
+            map_versorgungsstellenkontakt(
+                &MappingContext::new(&msg, &get_test_config()).unwrap(),
+                &config,
+                &get_dummy_resources()
+            )
+            .unwrap(),
+            None
+        );
+    }
+
     #[test]
     fn map_entlassgrund_test() {
         let msg = Message::parse_with_lenient_newlines(r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|20251102212117||ADT^A08^ADT_A01|12332112|P|2.5||123788998|NE|NE||8859/1
@@ -1158,11 +2017,30 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
                 .unwrap(),
         ];
 
-        let actual = map_entlassgrund(&msg).unwrap();
+        let mut config = get_test_config();
+        let actual = map_entlassgrund(&msg, &config).unwrap();
 
         assert!(actual.len() == 1);
 
         assert_eq!(actual.first().unwrap().extension, expected);
+
+        // site specific code
+        config.fall.discharge_reasons = vec![DischargeReasonConfig {
+            code: "01".to_string(),
+            entlassgrund: "072".to_string(),
+        }];
+        let actual = map_entlassgrund(&msg, &config).unwrap();
+        let codes: Vec<_> = actual
+            .first()
+            .unwrap()
+            .extension
+            .iter()
+            .map(|e| match &e.value {
+                Some(ExtensionValue::Coding(c)) => c.code.clone(),
+                _ => panic!("expected coding value"),
+            })
+            .collect();
+        assert_eq!(codes, vec![Some("07".to_string()), Some("2".to_string())]);
     }
 
     #[test]
@@ -1267,7 +2145,13 @@ IN2|1||||||||||||||||||||||||||||^PC^0^K
 DG1|1||K42.9^Hernia umbilicalis ohne Einklemmung und ohne Gangrän^icd10gm2022||20230101131500|do-not-know|||||||||1|ABCDEFGH^^^^^^^^^^^^^^^^^^^^^^KCH||||12345677|U
 "#;
         let msg = Message::parse_with_lenient_newlines(input, true).unwrap();
-        let x = &map(&msg, &get_test_config(), &get_dummy_resources());
+        let x = &map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        );
         match x {
             Ok(_) => panic!("we have an unsupported condition type - this is not OK!"),
 
@@ -1285,7 +2169,13 @@ DG1|1||K42.9^Hernia umbilicalis ohne Einklemmung und ohne Gangrän^icd10gm2022||
 
         let config = get_test_config();
 
-        let result = map(&msg, &config, &get_dummy_resources());
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        );
 
         assert!(result.is_ok());
 
@@ -1312,6 +2202,7 @@ DG1|1||K42.9^Hernia umbilicalis ohne Einklemmung und ohne Gangrän^icd10gm2022||
                             &ResourceType::Encounter,
                             "12345678",
                             config.fall.einrichtungskontakt.system.as_str(),
+                            &config,
                         )
                         .unwrap();
 
@@ -1369,6 +2260,38 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
         assert_eq!(actual, Some("0800".into()));
     }
 
+    #[test]
+    fn test_service_type_sources() {
+        // PV1-10 1000, PV1-39 0800, ZBE-7.10 2200
+        let input = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|20251102212117||ADT^A08^ADT_A01|12332112|P|2.5||123788998|NE|NE||8859/1
+EVN|A08|202511022120||11036_123456789|ZZZZZZZZ|202511022120
+PID|1|9999999|9999999|88888888|Nachname^Vorname^^^^^L||20251102|M
+PV1|1|I|^^^KLINIKUM^961640|R^^HL7~01^Normalfall^11||||^^^^^^^^^L^^^^^^^^^^^^^^^^^^^^^^^^^^^BSNR||1000||||||N|||88888888||K|||||||||||||||01|||0800|9||||202511022120|202511022120||||||A
+ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE|||^^^^^^^^^2200
+"#;
+        let msg = Message::parse_with_lenient_newlines(input, true).unwrap();
+        let service_type = |sources: Vec<ServiceTypeSource>| {
+            let mut config = get_test_config();
+            config.fall.service_type_sources = sources;
+            get_service_type(&msg, &get_dummy_resources(), &config)
+                .unwrap()
+                .and_then(|c| c.coding.first().cloned().flatten())
+                .and_then(|c| c.code)
+        };
+
+        assert_eq!(service_type(vec![]), Some("0800".into()));
+        assert_eq!(
+            service_type(vec![ServiceTypeSource::Pv1_10, ServiceTypeSource::Pv1_39]),
+            Some("1000".into())
+        );
+        assert_eq!(
+            service_type(vec![ServiceTypeSource::Zbe7, ServiceTypeSource::Pv1_10]),
+            Some("2200".into())
+        );
+        // location without department
+        assert_eq!(service_type(vec![ServiceTypeSource::Location]), None);
+    }
+
     #[test]
     fn test_service_type_unknown_department() {
         let input = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|20251102212117||ADT^A08^ADT_A01|12332112|P|2.5||123788998|NE|NE||8859/1
@@ -1427,7 +2350,13 @@ ZBE|30674176^ORBIS|202208221309||INSERT
 "#;
         let msg = Message::parse_with_lenient_newlines(input, true).unwrap();
 
-        let result = map(&msg, &get_test_config(), &get_dummy_resources());
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        );
 
         result
             .map_err(|e| panic!("failed with error: {}", e.to_string()))
@@ -1489,7 +2418,14 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
 "#;
         let msg = Message::parse_with_lenient_newlines(input, true).unwrap();
 
-        let actual = map(&msg, &get_test_config(), &get_dummy_resources()).unwrap();
+        let actual = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        )
+        .unwrap();
 
         assert_eq!(actual.len(), 1);
     }
@@ -1504,20 +2440,29 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
 "#;
         let msg = Message::parse_with_lenient_newlines(input, true).unwrap();
 
-        let actual =
-            map_versorgungsstellenkontakt(&msg, &get_test_config(), &get_dummy_resources())
-                .unwrap()
-                .unwrap();
+        let actual = map_versorgungsstellenkontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .unwrap()
+        .unwrap();
 
         let type_coding = get_enc_type_coding(&actual, 1);
         assert_eq!(
             type_coding.code.clone().unwrap().as_str(),
             "intensivstationaer"
         );
+        let service_type = actual.service_type.as_ref().unwrap().coding[0].as_ref();
+        assert_eq!(service_type.unwrap().code.as_deref(), Some("3600"));
 
-        let actual = map_abteilungskontakt(&msg, &get_test_config(), &get_dummy_resources())
-            .unwrap()
-            .unwrap();
+        let actual = map_abteilungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .unwrap()
+        .unwrap();
 
         let type_coding = actual
             .r#type
@@ -1532,8 +2477,12 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
         let f = get_enc_type_coding(&actual, 0);
         assert_eq!(f.code.clone().unwrap().as_str(), "abteilungskontakt");
 
-        let actual =
-            map_einrichtungskontakt(&msg, &get_test_config(), &get_dummy_resources()).unwrap();
+        let actual = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .unwrap();
 
         let type_coding = actual
             .r#type
@@ -1575,7 +2524,13 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
 "#;
         let msg = Message::parse_with_lenient_newlines(input, true).unwrap();
 
-        let actual = map(&msg, &get_test_config(), &get_dummy_resources());
+        let actual = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        );
         assert!(actual.is_err());
         assert_eq!(
             actual.unwrap_err().to_string(),
@@ -1590,9 +2545,13 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
         let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
 
         let res = get_dummy_resources();
-        let abteilung_result = map_abteilungskontakt(&msg, &get_test_config(), &res)
-            .unwrap()
-            .unwrap();
+        let abteilung_result = map_abteilungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &res,
+        )
+        .unwrap()
+        .unwrap();
         assert_eq!(
             abteilung_result
                 .r#type
@@ -1613,7 +2572,12 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
         );
         assert_eq!(abteilung_result.class.code.as_ref().unwrap(), "AMB");
 
-        let einrichtung_result = map_einrichtungskontakt(&msg, &get_test_config(), &res).unwrap();
+        let einrichtung_result = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &res,
+        )
+        .unwrap();
         assert_eq!(
             einrichtung_result
                 .r#type
@@ -1640,9 +2604,13 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
         let hl7 = read_test_resource("a06_teilsstationaer_test.hl7");
         let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
         let res = get_dummy_resources();
-        let abteilung_result = map_abteilungskontakt(&msg, &get_test_config(), &res)
-            .unwrap()
-            .unwrap();
+        let abteilung_result = map_abteilungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &res,
+        )
+        .unwrap()
+        .unwrap();
         assert_eq!(
             abteilung_result
                 .r#type
@@ -1663,7 +2631,12 @@ ZBE|55555555^ORBIS|202511022120|202511022120|UPDATE
         );
         assert_eq!(abteilung_result.class.code.as_ref().unwrap(), "SS");
 
-        let einrichtung_result = map_einrichtungskontakt(&msg, &get_test_config(), &res).unwrap();
+        let einrichtung_result = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &res,
+        )
+        .unwrap();
         assert_eq!(
             einrichtung_result
                 .r#type
@@ -1752,16 +2725,62 @@ PID|1|9999999|9999999|88888888|Nachname^SäuglingVorname^^^^^L||20251102|M|||Str
 PV1|1|V|^^^KJM^KLINIKUM^|R^^HL7~01^Normalfall^11||||^^^^^^^^^L^^^^^^^^^^^^^^^^^^^^^^^^^^^BSNR||N||||||N|||88888888||K|||||||||||||||01|||1000|9||||202511022120|202511022120||||||A
 PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000||||||||||N||I||||||||||||Y"#;
         let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
-        let res = map_encounter_class(&msg).unwrap();
+        let res = map_encounter_class(&msg, &get_test_config()).unwrap();
         assert_eq!(res.code.as_ref().unwrap(), "AMB");
     }
+    #[rstest]
+    #[case("TK", "SS", Some("teilstationaer"))]
+    #[case("PRE", "AMB", None)]
+    #[case("XX", "IMP", None)]
+    fn test_map_patient_type(
+        #[case] patient_type: &str,
+        #[case] expected_class: &str,
+        #[case] expected_kontaktart: Option<&str>,
+    ) {
+        let hl7 = format!(
+            r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|20251102212117||ADT^A08^ADT_A01|12332112|P|2.5||123788998|NE|NE||8859/1
+EVN|A08|202511022120||11036_123456789|ZZZZZZZZ|202511022120
+PID|1|9999999|9999999|88888888|Nachname^Vorname^^^^^L||20251102|M
+PV1|1|I|^^^KJM^KLINIKUM^|R^^HL7~01^Normalfall^11||||||N||||||N||{patient_type}|88888888||K|||||||||||||||01|||1000|9||||202511022120|||||||A"#
+        );
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+        let mut config = get_test_config();
+        config.fall.patient_types = vec![
+            PatientTypeConfig {
+                code: "TK".to_string(),
+                class: "SS".to_string(),
+                kontaktart: Some("teilstationaer".to_string()),
+            },
+            PatientTypeConfig {
+                code: "PRE".to_string(),
+                class: "AMB".to_string(),
+                kontaktart: None,
+            },
+        ];
+
+        let class = map_encounter_class(&msg, &config).unwrap();
+        let kontaktart = map_kontaktart(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+            &Einrichtungskontakt,
+        )
+        .unwrap();
+
+        assert_eq!(class.code.as_deref(), Some(expected_class));
+        assert_eq!(
+            kontaktart.and_then(|k| k.code).as_deref(),
+            expected_kontaktart
+        );
+    }
+
     #[test]
     fn test_location_status() {
         let raw_msg = read_test_resource("a03_test.hl7");
         let msg = Message::parse_with_lenient_newlines(&raw_msg, true).unwrap();
 
         assert_eq!(
-            get_location_status(&msg).unwrap(),
+            get_location_status(&MappingContext::new(&msg, &get_test_config()).unwrap()),
             EncounterLocationStatus::Completed
         );
 
@@ -1769,7 +2788,7 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
         let msg = Message::parse_with_lenient_newlines(&raw_msg, true).unwrap();
 
         assert_eq!(
-            get_location_status(&msg).unwrap(),
+            get_location_status(&MappingContext::new(&msg, &get_test_config()).unwrap()),
             EncounterLocationStatus::Completed
         );
 
@@ -1777,7 +2796,7 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
         let msg = Message::parse_with_lenient_newlines(&raw_msg, true).unwrap();
 
         assert_eq!(
-            get_location_status(&msg).unwrap(),
+            get_location_status(&MappingContext::new(&msg, &get_test_config()).unwrap()),
             EncounterLocationStatus::Active
         );
 
@@ -1785,7 +2804,7 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
         let msg = Message::parse_with_lenient_newlines(&raw_msg, true).unwrap();
 
         assert_eq!(
-            get_location_status(&msg).unwrap(),
+            get_location_status(&MappingContext::new(&msg, &get_test_config()).unwrap()),
             EncounterLocationStatus::Completed
         );
 
@@ -1793,7 +2812,7 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
         let msg = Message::parse_with_lenient_newlines(&raw_msg, true).unwrap();
 
         assert_eq!(
-            get_location_status(&msg).unwrap(),
+            get_location_status(&MappingContext::new(&msg, &get_test_config()).unwrap()),
             EncounterLocationStatus::Active
         );
     }
@@ -1801,18 +2820,25 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
     fn a04_period_at_lvl2_lvl3_is_closed() {
         let raw_msg = read_test_resource("a04_test.hl7");
         let msg = Message::parse_with_lenient_newlines(&raw_msg, true).unwrap();
-        let abteilung = map_abteilungskontakt(&msg, &get_test_config(), &get_dummy_resources())
-            .unwrap()
-            .unwrap();
+        let abteilung = map_abteilungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .unwrap()
+        .unwrap();
 
         assert!(abteilung.period.as_ref().is_some_and(|s| s.start.is_some()));
         assert!(abteilung.period.as_ref().is_some_and(|s| s.end.is_some()));
         assert_eq!(abteilung.status, EncounterStatus::Finished);
 
-        let versorgungsstelle =
-            map_versorgungsstellenkontakt(&msg, &get_test_config(), &get_dummy_resources())
-                .unwrap()
-                .unwrap();
+        let versorgungsstelle = map_versorgungsstellenkontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .unwrap()
+        .unwrap();
 
         assert!(
             versorgungsstelle
@@ -1828,8 +2854,12 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
                 .is_some_and(|s| s.end.is_some())
         );
 
-        let einrichtungskontakt =
-            map_einrichtungskontakt(&msg, &get_test_config(), &get_dummy_resources()).unwrap();
+        let einrichtungskontakt = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .unwrap();
 
         assert!(
             einrichtungskontakt
@@ -1850,8 +2880,12 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
         let raw_msg = read_test_resource("a04_test.hl7");
         let msg = Message::parse_with_lenient_newlines(&raw_msg, true).unwrap();
 
-        let einrichtungskontakt =
-            map_einrichtungskontakt(&msg, &get_test_config(), &get_dummy_resources()).unwrap();
+        let einrichtungskontakt = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .unwrap();
 
         assert!(einrichtungskontakt.extension.is_empty());
     }
@@ -1865,7 +2899,13 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
         assert!(mapped_conditions.is_ok());
         assert_eq!(mapped_conditions.unwrap().len(), 23);
 
-        let mapped_enc = map(&msg, &get_test_config(), &get_dummy_resources());
+        let mapped_enc = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        );
         assert!(mapped_enc.is_ok());
 
         let enc_r = mapped_enc
@@ -1892,7 +2932,7 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
             Versorgungsstellenkontakt,
         ];
         levels.iter().for_each(|lvl| {
-            let result = map_period(&msg, lvl);
+            let result = map_period(&MappingContext::new(&msg, &get_test_config()).unwrap(), lvl);
             assert!(&result.is_ok());
 
             if &Einrichtungskontakt == lvl {
@@ -1925,7 +2965,7 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
             Versorgungsstellenkontakt,
         ];
         levels.iter().for_each(|lvl| {
-            let result = map_period(&msg, lvl);
+            let result = map_period(&MappingContext::new(&msg, &get_test_config()).unwrap(), lvl);
             assert!(&result.is_ok());
 
             if lvl == &Einrichtungskontakt {
@@ -1951,6 +2991,57 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
         });
     }
 
+    #[test]
+    fn test_map_length() {
+        let period = Period::builder()
+            .start(parse_datetime("202401010800").unwrap())
+            .end(parse_datetime("202401031200").unwrap())
+            .build()
+            .unwrap();
+
+        let days = map_length(&period, LengthUnit::Days).unwrap().unwrap();
+        assert_eq!(days.value, Some(2.17));
+        assert_eq!(days.code, Some("d".to_string()));
+
+        let hours = map_length(&period, LengthUnit::Hours).unwrap().unwrap();
+        assert_eq!(hours.value, Some(52.0));
+        assert_eq!(hours.code, Some("h".to_string()));
+
+        // open period
+        let period = Period::builder()
+            .start(parse_datetime("202401010800").unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(map_length(&period, LengthUnit::Days).unwrap(), None);
+    }
+
+    #[test]
+    fn map_cancel_discharge_a13() {
+        let hl7 = read_test_resource("a03_test.hl7").replace("A03", "A13");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        )
+        .unwrap();
+
+        assert!(!result.is_empty());
+        for entry in result {
+            assert_eq!(entry.request.unwrap().method, HTTPVerb::Put);
+            let enc: Encounter = entry.resource.unwrap().try_into().unwrap();
+            assert_eq!(enc.status, EncounterStatus::InProgress);
+            assert!(enc.period.unwrap().end.is_none());
+            assert!(
+                enc.hospitalization
+                    .is_none_or(|h| h.discharge_disposition.is_none())
+            );
+        }
+    }
+
     #[test]
     fn map_part_of_test() {
         let hl7 = read_test_resource("a03_test.hl7");
@@ -1958,11 +3049,21 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
 
         let config = &get_test_config();
         let resources = &get_dummy_resources();
-        let einrichtung = map_einrichtungskontakt(&msg, config, resources).unwrap();
+        let einrichtung = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            config,
+            resources,
+        )
+        .unwrap();
         let einrichtung_identifier = einrichtung.identifier.first().unwrap().clone().unwrap();
         let ident_value = einrichtung_identifier.value.as_ref().unwrap();
 
-        let abteilung = map_abteilungskontakt(&msg, config, resources).unwrap();
+        let abteilung = map_abteilungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            config,
+            resources,
+        )
+        .unwrap();
 
         let abteilung_part_of = abteilung
             .as_ref()
@@ -1973,7 +3074,7 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
             .reference
             .as_ref()
             .unwrap();
-        abteilung_part_of.ends_with(ident_value);
+        assert!(abteilung_part_of.ends_with(ident_value));
         let abteilung_identifier = abteilung
             .unwrap()
             .identifier
@@ -1983,7 +3084,12 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
             .unwrap();
         let abteilung_ident_value = abteilung_identifier.value.as_ref().unwrap();
 
-        let versorgung = map_versorgungsstellenkontakt(&msg, config, resources).unwrap();
+        let versorgung = map_versorgungsstellenkontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            config,
+            resources,
+        )
+        .unwrap();
 
         let versorgung_part_of = versorgung
             .as_ref()
@@ -1995,13 +3101,168 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
             .as_ref()
             .unwrap();
 
-        versorgung_part_of.ends_with(
+        assert!(
+            versorgung_part_of.ends_with(
+                format!(
+                    "{}|{}",
+                    abteilung_identifier.system.as_ref().unwrap(),
+                    abteilung_ident_value
+                )
+                .as_str(),
+            )
+        );
+    }
+
+    #[test]
+    fn map_patient_reassignment_a47() {
+        let hl7 = read_test_resource("a47_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let config = get_test_config();
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        );
+
+        // no conditional patch matching several encounters
+        assert!(matches!(result, Err(MappingError::ManualReview(_))));
+    }
+
+    #[test]
+    fn map_visit_number_update_a50() {
+        let hl7 = read_test_resource("a50_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let config = get_test_config();
+        // a movement recorded with the prior visit number
+        let history = LocationHistory::default();
+        history.apply(vec![LocationUpdate::Record(
+            "20000001".to_string(),
+            recorded_movement("77777777", &config),
+        )]);
+        let mut updates = vec![];
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+            &history,
+            &mut updates,
+        )
+        .unwrap();
+
+        // each encounter by its own level identifier
+        let requests: Vec<(HTTPVerb, String)> = result
+            .iter()
+            .map(|e| e.request.as_ref().unwrap())
+            .map(|r| (r.method, r.url.clone()))
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                (
+                    HTTPVerb::Patch,
+                    format!(
+                        "Encounter?identifier={}|20000001",
+                        config.fall.einrichtungskontakt.system
+                    )
+                ),
+                (
+                    HTTPVerb::Patch,
+                    format!(
+                        "Encounter?identifier={}|77777777",
+                        config.fall.abteilungskontakt.system
+                    )
+                ),
+                (
+                    HTTPVerb::Patch,
+                    format!(
+                        "Encounter?identifier={}|77777777",
+                        config.fall.versorgungsstellenkontakt.system
+                    )
+                ),
+            ]
+        );
+
+        // the 'Einrichtungskontakt' swaps both identifiers, the lower levels the official one
+        let paths: Vec<Vec<String>> = result
+            .into_iter()
+            .map(|e| {
+                let Some(Resource::Parameters(patch)) = e.resource else {
+                    panic!("expected patch parameters");
+                };
+                patch
+                    .parameter
+                    .iter()
+                    .flatten()
+                    .map(|op| match &op.part[1].as_ref().unwrap().value {
+                        Some(ParametersParameterValue::String(path)) => path.clone(),
+                        _ => panic!("expected patch path"),
+                    })
+                    .collect()
+            })
+            .collect();
+        let path = |system: &str| format!("Encounter.identifier.where(system='{system}').value");
+        assert_eq!(
+            paths,
+            vec![
+                vec![
+                    path(&config.fall.einrichtungskontakt.system),
+                    path(&config.fall.system)
+                ],
+                vec![path(&config.fall.system)],
+                vec![path(&config.fall.system)],
+            ]
+        );
+
+        // the movement is recorded with the current visit number
+        assert!(matches!(
+            updates.as_slice(),
+            [LocationUpdate::Clear(stale), LocationUpdate::Record(visit, _)]
+                if stale == "20000001" && visit == "20000002"
+        ));
+    }
+
+    #[test]
+    fn map_visit_number_update_a50_delete_stale() {
+        let hl7 = read_test_resource("a50_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        config.fall.delete_stale_visit_number = true;
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        )
+        .unwrap();
+
+        let admit: Encounter = result
+            .first()
+            .unwrap()
+            .resource
+            .clone()
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            admit.identifier.first().unwrap().as_ref().unwrap().value,
+            Some("20000002".to_string())
+        );
+
+        let stale = result.last().unwrap();
+        assert!(stale.resource.is_none());
+        assert_eq!(stale.request.as_ref().unwrap().method, HTTPVerb::Delete);
+        assert_eq!(
+            stale.request.as_ref().unwrap().url,
             format!(
-                "{}|{}",
-                abteilung_identifier.system.as_ref().unwrap(),
-                abteilung_ident_value
+                "Encounter?identifier={}|20000001",
+                config.fall.einrichtungskontakt.system
             )
-            .as_str(),
         );
     }
 
@@ -2009,7 +3270,14 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
     fn map_enc_for_a04() {
         let hl7 = read_test_resource("a04_test.hl7");
         let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
-        let result = map(&msg, &get_test_config(), &get_dummy_resources()).unwrap();
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        )
+        .unwrap();
 
         assert_eq!(
             result
@@ -2046,4 +3314,677 @@ PV2|||06^Geburt^11||||||202511022120|||Versicherten Nr. der Mutter 0000000000|||
             HTTPVerb::Put
         );
     }
+
+    fn movement(evn: &str, zbe_id: &str, room: &str, end: &str) -> String {
+        format!(
+            r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|20251102212117||ADT^{evn}^ADT_A01|12332112|P|2.5||123788998|NE|NE||8859/1
+EVN|{evn}|202511022120||11036_123456789|ZZZZZZZZ|202511022120
+PID|1|9999999|9999999|88888888|Nachname^Vorname^^^^^L||20251102|M|||Strasse. 1&Strasse.&1^^Stadt^^30000^DE^L||0000000000000^PRN^PH|||U|||||||Stadt|J|1|DE
+PV1|1|I|POL1234^{room}^2^POL^KLINIKUM^961640|R^^HL7~01^Normalfall^11||||||N||||||N|||88888888||K|||||||||||||||01|||0800|9||||202511022120|||||||A
+ZBE|{zbe_id}^ORBIS|202511022120|{end}|INSERT"#
+        )
+    }
+
+    /// Maps a transfer (A02) and its reversal (A12) and returns the reverted
+    /// 'Versorgungsstellenkontakt'.
+    fn revert_transfer(config: &Fhir) -> BundleEntry {
+        let resources = get_dummy_resources();
+        let history = LocationHistory::default();
+
+        for hl7 in [
+            movement("A01", "55555555", "BSP-2-2", "202511031000"),
+            movement("A02", "66666666", "BSP-2-3", ""),
+        ] {
+            let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+            let mut updates = vec![];
+            map(
+                &MappingContext::new(&msg, config).unwrap(),
+                config,
+                &resources,
+                &history,
+                &mut updates,
+            )
+            .unwrap();
+            // the bundle was produced
+            history.apply(updates);
+        }
+
+        let hl7 = movement("A12", "66666666", "BSP-2-3", "");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+        map(
+            &MappingContext::new(&msg, config).unwrap(),
+            config,
+            &resources,
+            &history,
+            &mut vec![],
+        )
+        .unwrap()
+        .pop()
+        .unwrap()
+    }
+
+    #[test]
+    fn map_transfer_reversal_a12() {
+        let reverted = revert_transfer(&get_test_config());
+
+        assert_eq!(reverted.request.as_ref().unwrap().method, HTTPVerb::Put);
+        let enc: Encounter = reverted.resource.clone().unwrap().try_into().unwrap();
+        assert_eq!(
+            enc.identifier.first().unwrap().as_ref().unwrap().value,
+            Some("55555555".to_string())
+        );
+        assert_eq!(enc.status, EncounterStatus::InProgress);
+        assert!(enc.period.unwrap().end.is_none());
+        assert!(
+            enc.location
+                .iter()
+                .flatten()
+                .all(|l| l.status == Some(EncounterLocationStatus::Active))
+        );
+    }
+
+    #[test]
+    fn encounter_ref_uses_identifier_selection() {
+        let hl7 = read_test_resource("a01_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        let ctx = MappingContext::new(&msg, &config).unwrap();
+
+        // level identifier by default
+        let expected = resource_ref(
+            &ResourceType::Encounter,
+            ctx.require_visit_number().unwrap(),
+            &config.fall.einrichtungskontakt.system,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(einrichtungskontakt_ref(&ctx, &config).unwrap(), expected);
+
+        config.identifier_selection.r#use = SelectedIdentifierUse::Official;
+        let expected = resource_ref(
+            &ResourceType::Encounter,
+            ctx.require_visit_number().unwrap(),
+            &config.fall.system,
+            &config,
+        )
+        .unwrap();
+        assert_eq!(einrichtungskontakt_ref(&ctx, &config).unwrap(), expected);
+    }
+
+    #[test]
+    fn map_transfer_reversal_protected_unit() {
+        let mut config = get_test_config();
+        config.protected_units.wards = vec!["POL1234".to_string()];
+
+        let reverted = revert_transfer(&config);
+
+        let enc: Encounter = reverted.resource.unwrap().try_into().unwrap();
+        assert!(enc.location.iter().flatten().all(|l| {
+            l.physical_type
+                .iter()
+                .flat_map(|t| t.coding.iter().flatten())
+                .all(|c| !matches!(c.code.as_deref(), Some("ro" | "bd")))
+        }));
+        assert!(!enc.location.is_empty());
+    }
+
+    #[test]
+    fn map_deleted_movement() {
+        let hl7 = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|20251102212117||ADT^A08^ADT_A01|12332112|P|2.5||123788998|NE|NE||8859/1
+EVN|A08|202511022120||11036_123456789|ZZZZZZZZ|202511022120
+PID|1|9999999|9999999|88888888|Nachname^Vorname^^^^^L||20251102|M|||Strasse. 1&Strasse.&1^^Stadt^^30000^DE^L||0000000000000^PRN^PH|||U|||||||Stadt|J|1|DE
+PV1|1|I|POL1234^BSP-2-2^2^POL^KLINIKUM^961640|R^^HL7~01^Normalfall^11||||||N||||||N|||88888888||K|||||||||||||||01|||0800|9||||202511022120|||||||A
+ZBE|55555555^ORBIS|202511022120||DELETE"#;
+        let msg = Message::parse_with_lenient_newlines(hl7, true).unwrap();
+        let mut updates = vec![];
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut updates,
+        )
+        .unwrap();
+
+        // the movement is removed from the location history
+        assert!(matches!(
+            updates.as_slice(),
+            [LocationUpdate::Remove(visit, movement)]
+                if visit == "88888888" && movement.value.as_deref() == Some("55555555")
+        ));
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].request.as_ref().unwrap().method, HTTPVerb::Put);
+        for entry in &result[1..] {
+            let request = entry.request.as_ref().unwrap();
+            assert_eq!(request.method, HTTPVerb::Delete);
+            assert!(request.url.ends_with("|55555555"));
+        }
+    }
+
+    /// Recorded 'Versorgungsstellenkontakt' of the movement (ZBE-1.1).
+    fn recorded_movement(zbe_id: &str, config: &Fhir) -> Encounter {
+        Encounter::builder()
+            .status(EncounterStatus::InProgress)
+            .class(Coding::builder().code("IMP".to_string()).build().unwrap())
+            .identifier(vec![Some(
+                Identifier::builder()
+                    .system(config.fall.versorgungsstellenkontakt.system.clone())
+                    .value(zbe_id.to_string())
+                    .r#use(IdentifierUse::Usual)
+                    .build()
+                    .unwrap(),
+            )])
+            .build()
+            .unwrap()
+    }
+
+    #[rstest]
+    #[case("a06_teilsstationaer_test.hl7", Some("SS"))]
+    #[case("a07_nachstationaer_test.hl7", None)]
+    fn map_class_change_a06_a07(#[case] file: &str, #[case] expected: Option<&str>) {
+        let hl7 = read_test_resource(file);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let config = get_test_config();
+        // a former and the current movement (ZBE-1) of the visit
+        let history = LocationHistory::default();
+        history.apply(
+            ["77777777", "88888888"]
+                .map(|id| {
+                    LocationUpdate::Record("87654321".to_string(), recorded_movement(id, &config))
+                })
+                .into(),
+        );
+        let mut updates = vec![];
+
+        let result = map_class_change(
+            &MappingContext::new(&msg, &config).unwrap(),
+            &config,
+            &history,
+            &mut updates,
+        )
+        .unwrap();
+
+        match expected {
+            Some(code) => {
+                // each level of the former movement by its own level identifier
+                let urls: Vec<_> = result
+                    .iter()
+                    .map(|e| e.request.as_ref().unwrap().url.clone())
+                    .collect();
+                assert_eq!(
+                    urls,
+                    vec![
+                        format!(
+                            "Encounter?identifier={}|77777777",
+                            config.fall.abteilungskontakt.system
+                        ),
+                        format!(
+                            "Encounter?identifier={}|77777777",
+                            config.fall.versorgungsstellenkontakt.system
+                        ),
+                    ]
+                );
+                for entry in result {
+                    let Some(Resource::Parameters(patch)) = entry.resource else {
+                        panic!("expected patch parameters");
+                    };
+                    let value = patch.parameter[0].as_ref().unwrap().part[2]
+                        .as_ref()
+                        .unwrap()
+                        .value
+                        .clone();
+                    let Some(ParametersParameterValue::Coding(coding)) = value else {
+                        panic!("expected coding value");
+                    };
+                    assert_eq!(coding.code, Some(code.to_string()));
+                }
+                // the recorded movements keep the new class
+                assert!(matches!(
+                    updates.as_slice(),
+                    [LocationUpdate::Class(visit, class)]
+                        if visit == "87654321" && class.code.as_deref() == Some(code)
+                ));
+            }
+            None => {
+                assert!(result.is_empty());
+                assert!(updates.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn map_planned_encounter_a14() {
+        let hl7 = read_test_resource("a14_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let config = get_test_config();
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        )
+        .unwrap();
+
+        assert_eq!(result.len(), 1);
+        let entry = result.first().unwrap();
+        assert_eq!(entry.request.as_ref().unwrap().method, HTTPVerb::Post);
+
+        let planned: Encounter = entry.resource.clone().unwrap().try_into().unwrap();
+        assert_eq!(planned.status, EncounterStatus::Planned);
+        let planned_start = planned
+            .extension
+            .iter()
+            .find(|e| e.url.ends_with("extension-Encounter.plannedStartDate"))
+            .expect("missing plannedStartDate extension");
+        assert_eq!(
+            planned_start.value,
+            Some(ExtensionValue::DateTime(
+                parse_datetime("202211101359").unwrap()
+            ))
+        );
+        assert_eq!(
+            planned.period.unwrap().start,
+            Some(parse_datetime("202211101359").unwrap())
+        );
+        // same visit number as the later admission
+        assert_eq!(
+            planned.identifier.first().unwrap().as_ref().unwrap().value,
+            Some("10000001".to_string())
+        );
+    }
+
+    #[test]
+    fn map_cancel_admit_entered_in_error() {
+        let hl7 = read_test_resource("a11_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        config.fall.cancel_admit = CancelAdmitMode::EnteredInError;
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        )
+        .unwrap();
+
+        assert_eq!(result.len(), 3);
+        for entry in result {
+            assert_eq!(entry.request.unwrap().method, HTTPVerb::Patch);
+            let Some(Resource::Parameters(patch)) = entry.resource else {
+                panic!("expected patch parameters");
+            };
+            let operation = patch.parameter[0].as_ref().unwrap();
+            assert_eq!(
+                operation.part[2].as_ref().unwrap().value,
+                Some(ParametersParameterValue::Code(
+                    "entered-in-error".to_string()
+                ))
+            );
+        }
+    }
+
+    #[rstest]
+    #[case(CancelPendingAdmitMode::Delete, HTTPVerb::Delete)]
+    #[case(CancelPendingAdmitMode::Cancelled, HTTPVerb::Patch)]
+    fn map_cancel_pending_admit_a27(
+        #[case] mode: CancelPendingAdmitMode,
+        #[case] expected_method: HTTPVerb,
+    ) {
+        let hl7 = read_test_resource("a14_test.hl7").replace("A14", "A27");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        config.fall.cancel_pending_admit = mode;
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+            &LocationHistory::default(),
+            &mut vec![],
+        )
+        .unwrap();
+
+        // planned 'Einrichtungskontakt' only
+        assert_eq!(result.len(), 1);
+        let entry = result.first().unwrap();
+        let request = entry.request.as_ref().unwrap();
+        assert_eq!(request.method, expected_method);
+        assert!(request.url.contains("10000001"));
+
+        if let Some(Resource::Parameters(patch)) = &entry.resource {
+            let operation = patch.parameter[0].as_ref().unwrap();
+            assert_eq!(
+                operation.part[2].as_ref().unwrap().value,
+                Some(ParametersParameterValue::Code("cancelled".to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_map_financial_class() {
+        let hl7 = read_test_message("a01_test.hl7", &[("PV1", 20, "DRG")], &[]);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        config.fall.financial_class = Some(FinancialClassConfig {
+            url: "https://example.org/abrechnungsart".to_string(),
+            system: "https://example.org/CodeSystem/abrechnungsart".to_string(),
+        });
+
+        let enc = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+        )
+        .unwrap();
+
+        let extension = enc
+            .extension
+            .iter()
+            .find(|e| e.url == "https://example.org/abrechnungsart")
+            .expect("missing financial class extension");
+        let Some(ExtensionValue::Coding(coding)) = &extension.value else {
+            panic!("expected coding value");
+        };
+        assert_eq!(coding.code.as_deref(), Some("DRG"));
+    }
+
+    #[rstest]
+    #[case(vec![], "AMB", EncounterStatus::InProgress)]
+    #[case(vec![(MessageType::A03, vec!["AMB"], EncounterStatus::Finished)], "AMB", EncounterStatus::Finished)]
+    #[case(vec![(MessageType::A03, vec!["AMB"], EncounterStatus::Finished)], "IMP", EncounterStatus::InProgress)]
+    #[case(vec![(MessageType::A03, vec![], EncounterStatus::Finished)], "IMP", EncounterStatus::Finished)]
+    #[case(vec![(MessageType::A01, vec![], EncounterStatus::Arrived)], "IMP", EncounterStatus::InProgress)]
+    #[case(
+        vec![
+            (MessageType::A03, vec!["PRENC"], EncounterStatus::Cancelled),
+            (MessageType::A03, vec![], EncounterStatus::Finished),
+        ],
+        "IMP",
+        EncounterStatus::Finished
+    )]
+    fn test_map_encounter_status(
+        #[case] transitions: Vec<(MessageType, Vec<&str>, EncounterStatus)>,
+        #[case] class: &str,
+        #[case] expected: EncounterStatus,
+    ) {
+        let hl7 = read_test_resource("a03_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        config.fall.status_transitions = transitions
+            .into_iter()
+            .map(|(message_type, classes, status)| StatusTransitionConfig {
+                message_types: vec![message_type],
+                classes: classes.into_iter().map(String::from).collect(),
+                status,
+            })
+            .collect();
+        // started only
+        let period = Period::builder()
+            .start(parse_datetime("202111230904").unwrap())
+            .build()
+            .unwrap();
+
+        let status = map_encounter_status(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            Some(class),
+            &period,
+        )
+        .unwrap();
+
+        assert_eq!(status, expected);
+    }
+
+    #[test]
+    fn test_map_account() {
+        let hl7 = read_test_message("a01_test.hl7", &[("PID", 18, "87654321")], &[]);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        assert_eq!(map_account(&msg, &config).unwrap(), None);
+
+        config.account = Some(AccountConfig {
+            system: "https://fhir.diz.uni-marburg.de/sid/account-id".to_string(),
+        });
+        let enc = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+        )
+        .unwrap();
+        assert_eq!(
+            enc.account[0].as_ref().unwrap().reference.as_deref(),
+            Some("Account?identifier=https://fhir.diz.uni-marburg.de/sid/account-id|87654321")
+        );
+
+        // own identifier system
+        config.fall.account_system = Some("https://example.org/sid/abrechnungsfall".to_string());
+        assert_eq!(
+            map_account(&msg, &config)
+                .unwrap()
+                .unwrap()
+                .reference
+                .as_deref(),
+            Some("Account?identifier=https://example.org/sid/abrechnungsfall|87654321")
+        );
+    }
+
+    #[test]
+    fn test_map_case_category() {
+        let hl7 = read_test_resource("a01_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+
+        let enc = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+        )
+        .unwrap();
+        // Aufnahmegrund only
+        assert_eq!(enc.extension.len(), 1);
+
+        config.fall.case_category = Some(CaseCategoryConfig {
+            field: "PV2.3.1".to_string(),
+            url: "https://example.org/fallart".to_string(),
+            system: "https://example.org/CodeSystem/fallart".to_string(),
+        });
+        let enc = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+        )
+        .unwrap();
+
+        let extension = enc
+            .extension
+            .iter()
+            .find(|e| e.url == "https://example.org/fallart")
+            .expect("missing case category extension");
+        let Some(ExtensionValue::Coding(coding)) = &extension.value else {
+            panic!("expected coding value");
+        };
+        assert_eq!(coding.code.as_deref(), Some("01"));
+        assert_eq!(
+            coding.system.as_deref(),
+            Some("https://example.org/CodeSystem/fallart")
+        );
+    }
+
+    #[test]
+    fn test_map_admit_source_code_table() {
+        let hl7 = read_test_resource("a01_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+
+        let coding = map_admit_source(&msg, &config).unwrap().unwrap();
+        assert_eq!(coding.code.as_deref(), Some("R"));
+
+        config.fall.admit_source.codes = vec![AdmitSourceCodeConfig {
+            code: "R".to_string(),
+            aufnahmeanlass: "N".to_string(),
+        }];
+        let coding = map_admit_source(&msg, &config).unwrap().unwrap();
+        assert_eq!(coding.code.as_deref(), Some("N"));
+        assert_eq!(coding.display.as_deref(), Some("Notfall"));
+
+        config.fall.admit_source.field = Some("PV1.14.1".to_string());
+        assert!(map_admit_source(&msg, &config).is_err());
+    }
+
+    #[test]
+    fn test_map_vip_extension() {
+        // flag as VIP (PV1-16)
+        let hl7 = read_test_message("a01_test.hl7", &[("PV1", 16, "Y")], &[]);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        assert!(map_vip(&msg, &config).unwrap().extension.is_empty());
+
+        config.vip.extension = Some("https://example.org/vip".to_string());
+        let enc = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+        )
+        .unwrap();
+
+        let extension = enc
+            .extension
+            .iter()
+            .find(|e| e.url == "https://example.org/vip")
+            .expect("missing vip extension");
+        assert_eq!(extension.value, Some(ExtensionValue::Boolean(true)));
+        // Aufnahmegrund is kept
+        assert!(
+            enc.extension
+                .iter()
+                .any(|e| e.url == "http://fhir.de/StructureDefinition/Aufnahmegrund")
+        );
+
+        config.vip.enabled = false;
+        assert!(map_vip(&msg, &config).unwrap().extension.is_empty());
+    }
+
+    #[test]
+    fn test_map_wahlleistungen() {
+        let hl7 = read_test_message("a01_test.hl7", &[], &["ZWL|CA|XX"]);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        assert!(map_wahlleistungen(&msg, &config).unwrap().is_empty());
+
+        config.fall.wahlleistung = Some(WahlleistungConfig {
+            fields: vec!["ZWL.1".to_string(), "ZWL.2".to_string()],
+            codes: vec![WahlleistungCodeConfig {
+                code: "CA".to_string(),
+                wahlleistung: "wahlaerztliche_leistung".to_string(),
+                display: "Wahlärztliche Leistung".to_string(),
+            }],
+        });
+        let enc = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+        )
+        .unwrap();
+
+        // untranslated code is skipped
+        let extensions: Vec<_> = enc
+            .extension
+            .iter()
+            .filter(|e| e.url == "http://fhir.de/StructureDefinition/Wahlleistung")
+            .collect();
+        assert_eq!(extensions.len(), 1);
+        let Some(ExtensionValue::Coding(coding)) = &extensions[0].value else {
+            panic!("expected coding value");
+        };
+        assert_eq!(coding.code.as_deref(), Some("wahlaerztliche_leistung"));
+        assert_eq!(
+            coding.system.as_deref(),
+            Some("http://fhir.de/CodeSystem/Wahlleistung")
+        );
+    }
+
+    #[test]
+    fn test_map_aufnahmegrund_z_segment() {
+        let hl7 = read_test_message("a01_test.hl7", &[], &["ZAG|03|07"]);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        config.fall.aufnahmegrund = AufnahmegrundConfig {
+            erste_und_zweite: Some("ZAG.1".to_string()),
+            dritte_und_vierte: Some("ZAG.2".to_string()),
+        };
+
+        let extensions = map_aufnahmegrund(&msg, &config).unwrap().unwrap();
+
+        let codes: Vec<_> = extensions
+            .iter()
+            .map(|e| match &e.value {
+                Some(ExtensionValue::Coding(c)) => (e.url.as_str(), c.code.as_deref()),
+                _ => panic!("expected coding value"),
+            })
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("ErsteUndZweiteStelle", Some("03")),
+                ("DritteStelle", Some("0")),
+                ("VierteStelle", Some("7")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_map_re_admission() {
+        let hl7 = read_test_resource("a01_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        assert_eq!(map_re_admission(&msg).unwrap(), None);
+
+        let hl7 = read_test_message("a01_test.hl7", &[("PV1", 13, "R")], &[]);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let config = get_test_config();
+
+        let enc = map_einrichtungskontakt(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+        )
+        .unwrap();
+
+        let re_admission = enc.hospitalization.unwrap().re_admission.unwrap();
+        let coding = re_admission.coding.first().unwrap().as_ref().unwrap();
+        assert_eq!(coding.code.as_deref(), Some("R"));
+        assert_eq!(
+            coding.system.as_deref(),
+            Some("http://terminology.hl7.org/CodeSystem/v2-0092")
+        );
+    }
+
+    #[test]
+    fn test_map_priority() {
+        let hl7 = read_test_resource("a01_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        assert_eq!(map_priority(&msg, &config).unwrap(), None);
+
+        // admission type (PV1-4.1)
+        config.fall.priorities = vec![PriorityConfig {
+            code: "R".to_string(),
+            priority: "EL".to_string(),
+        }];
+        let priority = map_priority(&msg, &config).unwrap().unwrap();
+        let coding = priority.coding.first().unwrap().as_ref().unwrap();
+        assert_eq!(coding.code.as_deref(), Some("EL"));
+        assert_eq!(coding.display.as_deref(), Some("elective"));
+
+        // visit priority code (PV2-25) precedes
+        let hl7 = read_test_message("a01_test.hl7", &[("PV2", 25, "1")], &[]);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let priority = map_priority(&msg, &config).unwrap().unwrap();
+        let coding = priority.coding.first().unwrap().as_ref().unwrap();
+        assert_eq!(coding.code.as_deref(), Some("EM"));
+    }
 }
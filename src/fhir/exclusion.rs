@@ -0,0 +1,93 @@
+use crate::config::{IdRange, PatientExclusionConfig};
+use crate::hl7::parser::{PID_3, PID_5, field_repeats, query, repeat_component};
+use hl7_parser::Message;
+use regex::Regex;
+
+/// Exclusion rules for test patients, which must not reach the FHIR store.
+#[derive(Default)]
+pub(crate) struct PatientExclusion {
+    names: Vec<Regex>,
+    id_ranges: Vec<IdRange>,
+}
+
+impl PatientExclusion {
+    pub(crate) fn new(config: &PatientExclusionConfig) -> Result<Self, regex::Error> {
+        Ok(PatientExclusion {
+            names: config
+                .names
+                .iter()
+                .map(|n| Regex::new(n))
+                .collect::<Result<_, _>>()?,
+            id_ranges: config.id_ranges.clone(),
+        })
+    }
+
+    /// Checks the patient (PID-3, PID-5) against the exclusion rules.
+    pub(crate) fn is_excluded(&self, msg: &Message) -> bool {
+        self.matches_name(msg) || self.matches_id(msg)
+    }
+
+    fn matches_name(&self, msg: &Message) -> bool {
+        query(msg, PID_5).is_some_and(|name| self.names.iter().any(|r| r.is_match(name)))
+    }
+
+    fn matches_id(&self, msg: &Message) -> bool {
+        if self.id_ranges.is_empty() {
+            return false;
+        }
+
+        field_repeats(msg, PID_3).is_some_and(|mut repeats| {
+            repeats.any(|r| {
+                repeat_component(r, 1)
+                    .and_then(|id| id.parse::<u64>().ok())
+                    .is_some_and(|id| {
+                        self.id_ranges
+                            .iter()
+                            .any(|range| (range.from..=range.to).contains(&id))
+                    })
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tests::read_test_resource;
+
+    fn exclusion(names: Vec<&str>, id_ranges: Vec<IdRange>) -> PatientExclusion {
+        PatientExclusion::new(&PatientExclusionConfig {
+            names: names.into_iter().map(String::from).collect(),
+            id_ranges,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn exclude_by_name() {
+        let hl7 = read_test_resource("a14_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+
+        assert!(!exclusion(vec!["(?i)^muster\\^"], vec![]).is_excluded(&msg));
+
+        assert!(exclusion(vec!["(?i)^muster\\^", "(?i)^test\\^"], vec![]).is_excluded(&msg));
+    }
+
+    #[test]
+    fn exclude_by_id_range() {
+        let hl7 = read_test_resource("a14_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+
+        let outside = IdRange {
+            from: 1,
+            to: 1_000_000,
+        };
+        assert!(!exclusion(vec![], vec![outside]).is_excluded(&msg));
+
+        let inside = IdRange {
+            from: 1_400_000,
+            to: 1_499_999,
+        };
+        assert!(exclusion(vec![], vec![inside]).is_excluded(&msg));
+    }
+}
@@ -1,49 +1,49 @@
 use crate::config::Fhir;
 use crate::error::MappingError;
 use crate::fhir::mapper::{
-    EntryRequestType, build_usual_identifier, bundle_entry, get_cc_with_one_code, get_meta,
-    is_inpatient_location, is_ward_valid_icu, parse_fab, resource_ref,
+    EntryRequestType, MappingContext, build_usual_identifier, bundle_entry, canonical_ref,
+    get_cc_with_one_code, get_meta, is_inpatient_location, is_ward_valid_icu, parse_fab,
+    resource_ref,
 };
 use crate::fhir::resources::ResourceMap;
-use crate::hl7::parser::{MessageType, PV1_3_1, PV1_3_2, PV1_3_3, message_type, query};
+use crate::hl7::parser::{MessageType, PV1_3_1, PV1_3_2, PV1_3_3, query};
 use anyhow::anyhow;
 use fhir_model::r4b::resources::{BundleEntry, EncounterLocation, Location, ResourceType};
 use hl7_parser::Message;
 use log::{Level, log};
 
 pub(super) fn map(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     resources: &ResourceMap,
 ) -> Result<Vec<BundleEntry>, MappingError> {
+    let msg = ctx.msg;
     let mut r: Vec<BundleEntry> = vec![];
-    if let Ok(msg_type) = message_type(msg) {
-        match msg_type {
-            // location changes only at patient movement and admission
-            MessageType::A02 | MessageType::A01 => {
-                if let Ok(Some(locations)) = create_locations(msg, config, resources) {
-                    for location in locations.iter() {
-                        r.push(bundle_entry(
-                            location.clone(),
-                            EntryRequestType::UpdateAsCreate,
-                            config,
-                        )?);
-                    }
+    match ctx.message_type() {
+        // location changes only at patient movement and admission
+        MessageType::A02 | MessageType::A01 => {
+            if let Ok(Some(locations)) = create_locations(msg, config, resources) {
+                for location in locations.iter() {
+                    r.push(bundle_entry(
+                        location.clone(),
+                        EntryRequestType::UpdateAsCreate,
+                        config,
+                    )?);
                 }
             }
+        }
 
-            // department stays the same - we have only a short contact at another location
-            MessageType::A04 => {
-                if let Some(loc) = map_ward_location(msg, config, resources)? {
-                    r.push(bundle_entry(loc, EntryRequestType::UpdateAsCreate, config)?);
-                }
+        // department stays the same - we have only a short contact at another location
+        MessageType::A04 => {
+            if let Some(loc) = map_ward_location(msg, config, resources)? {
+                r.push(bundle_entry(loc, EntryRequestType::UpdateAsCreate, config)?);
             }
-            _ => {
+        }
+        _ => {
 
-                // skip other messages, since they should not add any locations.
-                // also delete is not necessary since locations stay in the system,
-                // even if a patient movement is revoked.
-            }
+            // skip other messages, since they should not add any locations.
+            // also delete is not necessary since locations stay in the system,
+            // even if a patient movement is revoked.
         }
     }
     Ok(r)
@@ -105,30 +105,13 @@ pub(crate) fn map_ward_location(
     resources: &ResourceMap,
 ) -> Result<Option<Location>, MappingError> {
     if let (department, Some(ward_id)) = (parse_fab(msg), query(msg, PV1_3_1)) {
-        let mut location = Location::builder()
-            .meta(get_meta(config)?)
-            .physical_type(get_cc_with_one_code(
-                "wa".to_string(),
-                LOCATION_TYPE_SYSTEM.to_string(),
-            )?)
-            .identifier(vec![Some(build_usual_identifier(
-                vec![ward_id],
-                config.location.system_ward.to_string(),
-            )?)])
-            .build()
-            .map_err(MappingError::BuilderError)?;
-
-        if is_ward_valid_icu(msg, resources) {
-            location.r#type = vec![Some(get_cc_with_one_code(
-                "ICU".to_string(),
-                "http://terminology.hl7.org/CodeSystem/v3-RoleCode".to_string(),
-            )?)];
-        }
+        let mut location = ward_location(ward_id, is_ward_valid_icu(msg, resources), config)?;
         if let Some(dep_id) = department {
             location.managing_organization = Some(resource_ref(
                 &ResourceType::Organization,
                 dep_id,
                 config.organization.ward.system.as_str(),
+                config,
             )?)
         }
         Ok(Some(location))
@@ -137,6 +120,34 @@ pub(crate) fn map_ward_location(
     }
 }
 
+/// Ward location (Stationskürzel), typed as intensive care unit if `icu`.
+pub(crate) fn ward_location(
+    ward_id: &str,
+    icu: bool,
+    config: &Fhir,
+) -> Result<Location, MappingError> {
+    let mut location = Location::builder()
+        .meta(get_meta(config)?)
+        .physical_type(get_cc_with_one_code(
+            "wa".to_string(),
+            LOCATION_TYPE_SYSTEM.to_string(),
+        )?)
+        .identifier(vec![Some(build_usual_identifier(
+            vec![ward_id],
+            config.location.system_ward.to_string(),
+        )?)])
+        .build()
+        .map_err(MappingError::BuilderError)?;
+
+    if icu {
+        location.r#type = vec![Some(get_cc_with_one_code(
+            "ICU".to_string(),
+            "http://terminology.hl7.org/CodeSystem/v3-RoleCode".to_string(),
+        )?)];
+    }
+    Ok(location)
+}
+
 pub(crate) fn map_room_location(
     config: &Fhir,
     pv1_3_1: &str,
@@ -187,7 +198,10 @@ pub(crate) fn map_bed_location(
         .map_err(MappingError::BuilderError)
 }
 
-pub fn to_encounter_location(location: Location) -> Result<EncounterLocation, MappingError> {
+pub fn to_encounter_location(
+    location: Location,
+    config: &Fhir,
+) -> Result<EncounterLocation, MappingError> {
     let identifier = location
         .identifier
         .first()
@@ -211,10 +225,11 @@ pub fn to_encounter_location(location: Location) -> Result<EncounterLocation, Ma
 
     Ok(EncounterLocation::builder()
         .physical_type(phys_type)
-        .location(resource_ref(
+        .location(canonical_ref(
             &ResourceType::Location,
             identifier_value,
             system,
+            config,
         )?)
         .build()?)
 }
@@ -222,6 +237,7 @@ pub fn to_encounter_location(location: Location) -> Result<EncounterLocation, Ma
 #[cfg(test)]
 mod tests {
     use crate::fhir::location::map;
+    use crate::fhir::mapper::MappingContext;
     use crate::hl7::parser::{PV1_3_1, query};
     use crate::test_utils::tests::{get_dummy_resources, get_test_config, resource_from};
     use fhir_model::r4b::resources::Location;
@@ -254,7 +270,12 @@ PV1|1|I|{}|R^^HL7~01^Normalfall^301||||||N||||||N|||00000000||K|||||||||||||||01
         );
         let msg = Message::parse_with_lenient_newlines(&input, true).expect("parse hl7 failed");
 
-        let result = map(&msg, &get_test_config(), &get_dummy_resources()).expect("map failed");
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .expect("map failed");
 
         assert_eq!(result.len(), expected_number_locations);
 
@@ -335,7 +356,12 @@ ZBE|30674176^ORBIS|202208221309||INSERT
         );
         let msg = Message::parse_with_lenient_newlines(&input, true).expect("parse hl7 failed");
 
-        let result = map(&msg, &get_test_config(), &get_dummy_resources()).expect("map failed");
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &get_test_config(),
+            &get_dummy_resources(),
+        )
+        .expect("map failed");
 
         let loca: Location = resource_from(result.first().expect("one element expected"))
             .unwrap_or_else(|_| panic!("location expected - location entry is {}", pv1_3_value));
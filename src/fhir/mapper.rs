@@ -1,11 +1,21 @@
-use crate::config::Fhir;
+use crate::config::{
+    BundleKind, Fhir, IdentifierEscaping, IdentifierSelection, ReferenceResolution,
+    SelectedIdentifierUse,
+};
 use crate::error::{MappingError, MessageAccessError, ParsingError};
+use crate::fhir::exclusion::PatientExclusion;
+use crate::fhir::references::inline_references;
 use crate::fhir::resources::{ResourceMap, is_valid_date};
-use crate::fhir::{encounter, location, observation, organization, patient};
+use crate::fhir::state::{LocationHistory, LocationUpdate};
+use crate::fhir::{
+    account, condition, coverage, encounter, location, observation, organization, patient,
+    practitioner, procedure, protection, role,
+};
 use crate::hl7::parser::{
-    MessageType, PID_2, PID_4, PV1_2, PV1_3_1, PV1_3_4, PV1_3_5, PV1_19_1, ZBE_2, get_message_key,
-    message_type, query,
+    MessageType, PID_2, PID_4, PV1_2, PV1_3_1, PV1_3_4, PV1_3_5, PV1_16, PV1_19_1, ZBE_2,
+    get_message_key, message_type, query, redacted_segment,
 };
+use crate::metrics::excluded;
 use anyhow::anyhow;
 use chrono::{Datelike, NaiveDate, NaiveDateTime, TimeZone};
 use chrono_tz::Europe::Berlin;
@@ -13,50 +23,111 @@ use fhir_model::DateFormatError::InvalidDate;
 use fhir_model::r4b::codes::HTTPVerb::Patch;
 use fhir_model::r4b::codes::{BundleType, HTTPVerb, IdentifierUse};
 use fhir_model::r4b::resources::{
-    Bundle, BundleEntry, BundleEntryRequest, IdentifiableResource, Parameters, Resource,
-    ResourceType,
+    Bundle, BundleEntry, BundleEntryRequest, IdentifiableResource, Parameters, ParametersParameter,
+    ParametersParameterValue, Resource, ResourceType,
+};
+use fhir_model::r4b::types::{
+    CodeableConcept, Coding, Extension, ExtensionValue, Identifier, Meta, Reference,
 };
-use fhir_model::r4b::types::{CodeableConcept, Coding, Identifier, Meta, Reference};
 
 use fhir_model::time::{Month, OffsetDateTime};
 use fhir_model::{BuilderError, Instant};
 use fhir_model::{Date, DateTime, time};
 use hl7_parser::Message;
-use log::{Level, log};
+use log::{Level, log, warn};
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
 use std::slice;
 
 use uuid::Uuid;
 
+/// Mapped bundle of a message with its changes of the [`LocationHistory`], which are applied by
+/// [`FhirMapper::commit`] once the bundle was produced.
+#[derive(Debug)]
+pub(crate) struct Mapped {
+    pub(crate) bundle: String,
+    pub(crate) location_updates: Vec<LocationUpdate>,
+}
+
 pub(crate) struct FhirMapper {
     pub(crate) config: Fhir,
     pub(crate) resources: ResourceMap,
+    pub(crate) history: LocationHistory,
+    pub(crate) exclusion: PatientExclusion,
 }
 
 impl FhirMapper {
     pub(crate) fn new(config: Fhir) -> Result<Self, anyhow::Error> {
+        let resources = ResourceMap::new(&config)?;
+        Self::with_resources(config, resources)
+    }
+
+    /// Same as [`FhirMapper::new`], but with already loaded mapping files.
+    pub(crate) fn with_resources(
+        config: Fhir,
+        resources: ResourceMap,
+    ) -> Result<Self, anyhow::Error> {
+        if config.absolute_references && config.base_url.is_none() {
+            return Err(anyhow!("fhir.base_url is required for absolute references"));
+        }
+        config.fall.check_kontaktebenen()?;
+        config.person.check_skip_message_types()?;
+        if let Some(employer) = &config.person.employer {
+            employer.check()?;
+        }
+
         Ok(FhirMapper {
+            resources,
+            history: LocationHistory::new(config.identifier_selection.clone()),
+            exclusion: PatientExclusion::new(&config.person.exclude)?,
             config,
-            resources: ResourceMap::new()?,
         })
     }
 
+    /// Maps the HL7 message to a serialized bundle. Panics of the mapping are caught and
+    /// returned as [`MappingError::Panic`], so a single message can't kill the consumer.
+    ///
+    /// The location history is changed at once, use [`FhirMapper::map_message`] to change it
+    /// after the bundle was produced.
     pub(crate) fn map(&self, msg: &str) -> Result<Option<String>, MappingError> {
         // deserialize
         let v2_msg = Message::parse_with_lenient_newlines(msg, true)?;
 
+        Ok(self.map_message(&v2_msg)?.map(|mapped| {
+            self.commit(mapped.location_updates);
+            mapped.bundle
+        }))
+    }
+
+    /// Maps the parsed HL7 message to a serialized bundle, see [`FhirMapper::map`]. The location
+    /// history is not changed until the bundle is committed.
+    pub(crate) fn map_message(&self, msg: &Message) -> Result<Option<Mapped>, MappingError> {
+        catch_panic(|| self.map_bundle(msg))
+    }
+
+    /// Applies the location history changes of a produced bundle.
+    pub(crate) fn commit(&self, location_updates: Vec<LocationUpdate>) {
+        self.history.apply(location_updates);
+    }
+
+    fn map_bundle(&self, v2_msg: &Message) -> Result<Option<Mapped>, MappingError> {
         // map hl7 message
-        let resources = self.map_resources(&v2_msg)?;
+        let mut location_updates = vec![];
+        let resources = self.map_resources(v2_msg, &mut location_updates)?;
 
         if resources.is_empty() {
             return Ok(None);
         }
 
         let result = Bundle::builder()
-            .r#type(BundleType::Transaction)
+            .r#type(match self.config.bundle_type() {
+                BundleKind::Transaction => BundleType::Transaction,
+                BundleKind::Batch => BundleType::Batch,
+            })
             .entry(resources)
             .identifier(
                 Identifier::builder()
-                    .value(get_message_key(&v2_msg)?.to_string())
+                    .value(get_message_key(v2_msg)?.to_string())
                     .system(self.config.bundle_identifier_system.to_string())
                     .build()?,
             )
@@ -68,12 +139,28 @@ impl FhirMapper {
             .build()?;
 
         // serialize
-        let result = serde_json::to_string(&result).expect("failed to serialize output bundle");
+        let result = match self.config.reference_resolution() {
+            ReferenceResolution::Conditional => serde_json::to_string(&result),
+            ReferenceResolution::Inline => {
+                let mut bundle =
+                    serde_json::to_value(&result).expect("failed to serialize output bundle");
+                inline_references(&mut bundle, &self.config)?;
+                serde_json::to_string(&bundle)
+            }
+        }
+        .expect("failed to serialize output bundle");
 
-        Ok(Some(result))
+        Ok(Some(Mapped {
+            bundle: result,
+            location_updates,
+        }))
     }
 
-    fn map_resources(&self, v2_msg: &Message) -> Result<Vec<Option<BundleEntry>>, MappingError> {
+    fn map_resources(
+        &self,
+        v2_msg: &Message,
+        location_updates: &mut Vec<LocationUpdate>,
+    ) -> Result<Vec<Option<BundleEntry>>, MappingError> {
         if is_begleitperson(v2_msg)? {
             log!(
                 Level::Info,
@@ -84,24 +171,191 @@ impl FhirMapper {
             return Ok(vec![]);
         }
 
-        let p = patient::map(v2_msg, &self.config)?;
-        let e = encounter::map(v2_msg, &self.config, &self.resources)?;
-        let l = location::map(v2_msg, &self.config, &self.resources)?;
-        let obs = observation::map(v2_msg, &self.config)?;
-        let org = organization::map(v2_msg, &self.config, &self.resources)?;
-        let res = p
+        if self.exclusion.is_excluded(v2_msg) {
+            excluded().add(1, &[]);
+            log!(
+                Level::Info,
+                "Skipping message id '{}' since it targets a test patient.",
+                get_message_key(v2_msg)?
+            );
+
+            return Ok(vec![]);
+        }
+
+        let ctx = MappingContext::new(v2_msg, &self.config)?;
+
+        let p = patient::map(&ctx, &self.config, &self.resources)?;
+        let cov = coverage::map(&ctx, &self.config)?;
+        let acc = account::map(&ctx, &self.config)?;
+        let e = encounter::map(
+            &ctx,
+            &self.config,
+            &self.resources,
+            &self.history,
+            location_updates,
+        )?;
+        let c = condition::map(&ctx, &self.config)?;
+        let pr = procedure::map(&ctx, &self.config)?;
+        let prac = practitioner::map(&ctx, &self.config)?;
+        let l = location::map(&ctx, &self.config, &self.resources)?;
+        let obs = observation::map(&ctx, &self.config)?;
+        let org = organization::map(&ctx, &self.config, &self.resources)?;
+        let mut res: Vec<BundleEntry> = p
             .into_iter()
+            .chain(cov)
+            .chain(acc)
             .chain(e)
+            .chain(c)
+            .chain(pr)
+            .chain(prac)
             .chain(l)
             .chain(obs)
             .chain(org)
-            .map(Some)
             .collect();
+        protection::protect(v2_msg, &self.config.protected_units, &mut res);
+        role::apply(&self.config, &mut res)?;
+        sort_entries(&mut res, &self.config);
+
+        Ok(res.into_iter().map(Some).collect())
+    }
+}
+
+/// Resource types in dependency order, referenced resources precede the referencing ones.
+pub(crate) const ENTRY_ORDER: &[&str] = &[
+    "Organization",
+    "Location",
+    "Practitioner",
+    "Patient",
+    "RelatedPerson",
+    "Coverage",
+    "Account",
+    "Encounter",
+    "Condition",
+    "Procedure",
+    "Observation",
+];
+
+/// Orders the bundle entries by resource type (see [`Fhir::entry_order`]), for servers which
+/// process transaction entries in order. Entries of the same type keep the mapping order,
+/// unlisted types are last.
+fn sort_entries(entries: &mut [BundleEntry], config: &Fhir) {
+    entries.sort_by_key(|e| {
+        // the request url starts with the resource type, also for patch and delete
+        let resource_type = e
+            .request
+            .as_ref()
+            .and_then(|r| r.url.split(['?', '/']).next())
+            .unwrap_or_default();
+
+        if config.entry_order.is_empty() {
+            ENTRY_ORDER.iter().position(|&t| t == resource_type)
+        } else {
+            config.entry_order.iter().position(|t| t == resource_type)
+        }
+        .unwrap_or(usize::MAX)
+    });
+}
+
+/// Message values, which are shared by all resource mappers. They are read once
+/// per message.
+pub(crate) struct MappingContext<'a> {
+    pub(crate) msg: &'a Message<'a>,
+    message_type: MessageType,
+    /// PID-2
+    patient_id: Option<&'a str>,
+    /// see [`map_visit_number`]
+    visit_number: Option<&'a str>,
+    /// see [`Fhir::identifier_escaping`]
+    escaping: IdentifierEscaping,
+    /// patient identifier of the subject references, if the identifier selection is configured
+    subject: Option<Identifier>,
+}
+
+impl<'a> MappingContext<'a> {
+    pub(crate) fn new(msg: &'a Message<'a>, config: &Fhir) -> Result<Self, MappingError> {
+        let message_type =
+            message_type(msg, config.message_type_source).map_err(MessageAccessError::from)?;
+
+        // PID-2 is referenced by default, the patient identifiers are only mapped for a
+        // configured selection
+        let subject = if config.identifier_selection.is_configured() {
+            patient::create_patient_identifiers(msg, config)
+                .ok()
+                .and_then(|ids| selected_identifier(&ids, &config.identifier_selection).cloned())
+        } else {
+            None
+        };
+
+        Ok(MappingContext {
+            msg,
+            message_type,
+            patient_id: query(msg, PID_2),
+            visit_number: map_visit_number(msg, message_type).ok(),
+            escaping: config.identifier_escaping(),
+            subject,
+        })
+    }
+
+    pub(crate) fn message_type(&self) -> MessageType {
+        self.message_type
+    }
+
+    pub(crate) fn patient_id(&self) -> Option<&'a str> {
+        self.patient_id
+    }
+
+    pub(crate) fn visit_number(&self) -> Option<&'a str> {
+        self.visit_number
+    }
+
+    /// Same as [`map_visit_number`], but with the cached value.
+    pub(crate) fn require_visit_number(&self) -> Result<&'a str, MappingError> {
+        self.visit_number
+            .ok_or(anyhow!("empty visit number in PV1.19").into())
+    }
+
+    /// Conditional reference to the patient (PID-2), or by its selected identifier (see
+    /// [`select_identifier`]).
+    pub(crate) fn subject_ref(&self, sid: &str) -> Result<Reference, MappingError> {
+        let pid = self
+            .patient_id
+            .ok_or(anyhow!("missing pid value in PID.2"))?;
+        if let Some((system, value)) = self
+            .subject
+            .as_ref()
+            .and_then(|id| id.system.as_deref().zip(id.value.as_deref()))
+        {
+            return Ok(Reference::builder()
+                .reference(format!(
+                    "{}?{}",
+                    ResourceType::Patient,
+                    identifier_search(system, value, self.escaping)
+                ))
+                .build()?);
+        }
 
-        Ok(res)
+        Ok(Reference::builder()
+            .reference(format!(
+                "{}?{}",
+                ResourceType::Patient,
+                identifier_search(sid, pid, self.escaping)
+            ))
+            .build()?)
     }
 }
 
+/// Runs `f`, a panic is returned as [`MappingError::Panic`] with the panic message.
+fn catch_panic<T>(f: impl FnOnce() -> Result<T, MappingError>) -> Result<T, MappingError> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|e| {
+        let cause = e
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| e.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(MappingError::Panic(cause))
+    })
+}
+
 pub(crate) enum EntryRequestType {
     UpdateAsCreate,
     ConditionalCreate,
@@ -122,17 +376,12 @@ where
     let r = Resource::from(resource.clone());
 
     // identifier
-    let identifier = resource
-        .identifier()
-        .iter()
-        .flatten()
-        .find(|&id| id.r#use.is_some_and(|u| u == IdentifierUse::Usual))
-        .ok_or(anyhow!("missing identifier with use: 'usual'"))?;
+    let identifier = select_identifier(resource.identifier(), config)?;
 
     // resource type
     let resource_type = r.resource_type();
 
-    let request = bundle_entry_request(resource_type, identifier, request_type)?;
+    let request = bundle_entry_request(resource_type, identifier, request_type, config)?;
 
     let identifiers: Vec<Identifier> = resource.identifier().iter().flatten().cloned().collect();
 
@@ -146,26 +395,69 @@ where
         .map_err(|e| e.into())
 }
 
+/// Selects the identifier of conditional requests and references (see
+/// [`IdentifierSelection`]): the first prioritized system, otherwise the first identifier with
+/// the configured use.
+pub(crate) fn select_identifier<'a>(
+    identifiers: &'a [Option<Identifier>],
+    config: &Fhir,
+) -> Result<&'a Identifier, MappingError> {
+    selected_identifier(identifiers, &config.identifier_selection).ok_or_else(|| {
+        anyhow!(
+            "missing identifier with use: '{}'",
+            config.identifier_selection.r#use.code()
+        )
+        .into()
+    })
+}
+
+/// Same as [`select_identifier`], [`None`] if no identifier matches.
+pub(crate) fn selected_identifier<'a>(
+    identifiers: &'a [Option<Identifier>],
+    selection: &IdentifierSelection,
+) -> Option<&'a Identifier> {
+    let by_system = selection.system_priority.iter().find_map(|system| {
+        identifiers
+            .iter()
+            .flatten()
+            .find(|id| id.system.as_ref() == Some(system))
+    });
+    if by_system.is_some() {
+        return by_system;
+    }
+
+    let id_use = match selection.r#use {
+        SelectedIdentifierUse::Usual => IdentifierUse::Usual,
+        SelectedIdentifierUse::Official => IdentifierUse::Official,
+        SelectedIdentifierUse::Secondary => IdentifierUse::Secondary,
+    };
+    identifiers
+        .iter()
+        .flatten()
+        .find(|id| id.r#use == Some(id_use))
+}
+
 fn bundle_entry_request(
     resource_type: ResourceType,
     identifier: &Identifier,
     request_type: EntryRequestType,
+    config: &Fhir,
 ) -> Result<BundleEntryRequest, MappingError> {
     Ok(match request_type {
         EntryRequestType::UpdateAsCreate => BundleEntryRequest::builder()
             .method(HTTPVerb::Put)
-            .url(upsert_reference(&resource_type, identifier)?)
+            .url(upsert_reference(&resource_type, identifier, config)?)
             .build()?,
 
         EntryRequestType::ConditionalCreate => BundleEntryRequest::builder()
             .method(HTTPVerb::Post)
             .url(resource_type.to_string())
-            .if_none_exist(conditional_reference(identifier)?)
+            .if_none_exist(conditional_reference(identifier, config)?)
             .build()?,
 
         EntryRequestType::Delete => BundleEntryRequest::builder()
             .method(HTTPVerb::Delete)
-            .url(upsert_reference(&resource_type, identifier)?)
+            .url(upsert_reference(&resource_type, identifier, config)?)
             .build()?,
     })
 }
@@ -178,7 +470,7 @@ pub(crate) fn patch_bundle_entry(
 ) -> Result<BundleEntry, MappingError> {
     let request = BundleEntryRequest::builder()
         .method(Patch)
-        .url(upsert_reference(resource_type, identifier)?)
+        .url(upsert_reference(resource_type, identifier, config)?)
         .build()?;
 
     BundleEntry::builder()
@@ -192,26 +484,103 @@ pub(crate) fn patch_bundle_entry(
         .map_err(|e| e.into())
 }
 
+/// Creates a FHIR patch [`BundleEntry`] with a conditional request url.
+///
+/// In contrast to [`patch_bundle_entry`] the target resource is selected
+/// by arbitrary search criteria, which must match a single resource
+/// (the server rejects conditional patches with multiple matches).
+pub(crate) fn conditional_patch_bundle_entry(
+    resource: Parameters,
+    url: String,
+    config: &Fhir,
+) -> Result<BundleEntry, MappingError> {
+    let full_url = full_url_from_input(&url, config);
+
+    let request = BundleEntryRequest::builder()
+        .method(Patch)
+        .url(url)
+        .build()?;
+
+    BundleEntry::builder()
+        .resource(resource.into())
+        .request(request)
+        .full_url(full_url)
+        .build()
+        .map_err(|e| e.into())
+}
+
+/// Creates a [`BundleEntry`] which deletes the resource with the given identifier.
+///
+/// In contrast to [`bundle_entry`] no resource is needed.
+pub(crate) fn delete_bundle_entry(
+    resource_type: &ResourceType,
+    identifier: &Identifier,
+    config: &Fhir,
+) -> Result<BundleEntry, MappingError> {
+    let request = BundleEntryRequest::builder()
+        .method(HTTPVerb::Delete)
+        .url(upsert_reference(resource_type, identifier, config)?)
+        .build()?;
+
+    BundleEntry::builder()
+        .request(request)
+        .full_url(full_url_from_identifiers(
+            slice::from_ref(identifier),
+            config,
+        ))
+        .build()
+        .map_err(|e| e.into())
+}
+
+/// Creates a FHIR patch with a single `replace` operation.
+pub(crate) fn replace_patch(
+    path: String,
+    value: ParametersParameterValue,
+) -> Result<Parameters, MappingError> {
+    Ok(Parameters::builder()
+        .parameter(vec![Some(
+            ParametersParameter::builder()
+                .name("operation".to_string())
+                .part(vec![
+                    Some(
+                        ParametersParameter::builder()
+                            .name("type".to_string())
+                            .value(ParametersParameterValue::Code("replace".to_string()))
+                            .build()?,
+                    ),
+                    Some(
+                        ParametersParameter::builder()
+                            .name("path".to_string())
+                            .value(ParametersParameterValue::String(path))
+                            .build()?,
+                    ),
+                    Some(
+                        ParametersParameter::builder()
+                            .name("value".to_string())
+                            .value(value)
+                            .build()?,
+                    ),
+                ])
+                .build()?,
+        )])
+        .build()?)
+}
+
 pub(crate) fn upsert_reference(
     resource_type: &ResourceType,
     identifier: &Identifier,
+    config: &Fhir,
 ) -> Result<String, MappingError> {
     Ok(format!(
         "{resource_type}?{}",
-        identifier_search(
-            identifier
-                .system
-                .as_deref()
-                .ok_or(anyhow!("identifier.system missing"))?,
-            identifier
-                .value
-                .as_deref()
-                .ok_or(anyhow!("identifier.value missing"))?
-        )
+        conditional_reference(identifier, config)?
     ))
 }
 
-pub(crate) fn conditional_reference(identifier: &Identifier) -> Result<String, MappingError> {
+pub(crate) fn conditional_reference(
+    identifier: &Identifier,
+    config: &Fhir,
+) -> Result<String, MappingError> {
     Ok(identifier_search(
         identifier
             .system
@@ -221,13 +590,76 @@ pub(crate) fn conditional_reference(identifier: &Identifier) -> Result<String, M
             .value
             .as_deref()
             .ok_or(anyhow!("identifier.value missing"))?,
+        config.identifier_escaping(),
     ))
 }
 
-fn identifier_search(system: &str, value: &str) -> String {
+/// Reverts [`escape_identifier`].
+pub(crate) fn unescape_identifier(value: &str, escaping: IdentifierEscaping) -> String {
+    match escaping {
+        IdentifierEscaping::None => value.to_string(),
+        IdentifierEscaping::Search => {
+            let mut result = String::new();
+            let mut chars = value.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => result.extend(chars.next()),
+                    _ => result.push(c),
+                }
+            }
+            result
+        }
+        IdentifierEscaping::Url => {
+            let bytes = value.as_bytes();
+            let mut result = vec![];
+            let mut i = 0;
+            while i < bytes.len() {
+                match value
+                    .get(i + 1..i + 3)
+                    .filter(|_| bytes[i] == b'%')
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(b) => {
+                        result.push(b);
+                        i += 3;
+                    }
+                    None => {
+                        result.push(bytes[i]);
+                        i += 1;
+                    }
+                }
+            }
+            String::from_utf8_lossy(&result).into_owned()
+        }
+    }
+}
+
+fn identifier_search(system: &str, value: &str, escaping: IdentifierEscaping) -> String {
+    let value = escape_identifier(value, escaping);
     format!("identifier={system}|{value}")
 }
 
+fn escape_identifier(value: &str, escaping: IdentifierEscaping) -> String {
+    match escaping {
+        IdentifierEscaping::None => value.to_string(),
+        IdentifierEscaping::Search => value.chars().fold(String::new(), |mut s, c| {
+            if matches!(c, '\\' | '|' | ',' | '$') {
+                s.push('\\');
+            }
+            s.push(c);
+            s
+        }),
+        IdentifierEscaping::Url => value.bytes().fold(String::new(), |mut s, b| {
+            if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+                s.push(b as char);
+            } else {
+                s.push_str(&format!("%{b:02X}"));
+            }
+            s
+        }),
+    }
+}
+
 pub(crate) fn parse_datetime(input: &str) -> Result<DateTime, ParsingError> {
     let dt = NaiveDateTime::parse_from_str(input, "%Y%m%d%H%M")?;
     let dt_with_tz = Berlin
@@ -244,13 +676,52 @@ pub(crate) fn resource_ref(
     res_type: &ResourceType,
     id: &str,
     system: &str,
+    config: &Fhir,
 ) -> Result<Reference, MappingError> {
     Ok(Reference::builder()
-        .reference(format!("{res_type}?{}", identifier_search(system, id)))
+        .reference(format!(
+            "{res_type}?{}",
+            identifier_search(system, id, config.identifier_escaping())
+        ))
         .build()?)
 }
 
+/// Like [`resource_ref`], but the reference is absolute (prefixed with [`Fhir::base_url`])
+/// if enabled by [`Fhir::absolute_references`].
+pub(crate) fn canonical_ref(
+    res_type: &ResourceType,
+    id: &str,
+    system: &str,
+    config: &Fhir,
+) -> Result<Reference, MappingError> {
+    let mut reference = resource_ref(res_type, id, system, config)?;
+
+    if config.absolute_references
+        && let Some(base_url) = config.base_url.as_deref()
+    {
+        reference.reference = reference
+            .reference
+            .map(|r| format!("{}/{r}", base_url.trim_end_matches('/')));
+    }
+
+    Ok(reference)
+}
+
+/// Parses a date with year (`YYYY`), year-month (`YYYYMM`) or day precision (`YYYYMMDD`, the
+/// time is ignored).
 pub(crate) fn parse_date(input: &str) -> Result<Date, ParsingError> {
+    if input.bytes().all(|b| b.is_ascii_digit()) {
+        match input.len() {
+            4 => return Ok(Date::Year(input.parse()?)),
+            6 => {
+                return Ok(Date::YearMonth(
+                    input[..4].parse()?,
+                    Month::try_from(input[4..].parse::<u8>()?)?,
+                ));
+            }
+            _ => {}
+        }
+    }
     let dt = NaiveDate::parse_and_remainder(input, "%Y%m%d")?.0;
     let date = time::Date::from_calendar_date(
         dt.year(),
@@ -325,20 +796,101 @@ pub fn parse_fab<'a>(msg: &'a Message<'a>) -> Option<&'a str> {
     }
 }
 
+thread_local! {
+    /// Unknown code values of the mapping on this thread, only counted within
+    /// [`count_unknown_codes`].
+    static UNKNOWN_CODES: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Runs the mapping and returns the unknown code values it reported (see [`warn_unknown_code`]),
+/// e.g. for the data quality scorecard.
+pub(crate) fn count_unknown_codes<T>(map: impl FnOnce() -> T) -> (T, u64) {
+    let outer = UNKNOWN_CODES.replace(Some(0));
+    let result = map();
+    let count = UNKNOWN_CODES.replace(outer).unwrap_or_default();
+
+    (result, count)
+}
+
+/// Logs the fallback for an unknown code value at the given message location and counts it,
+/// if the mapping runs within [`count_unknown_codes`].
+///
+/// If enabled by [`Fhir::unknown_code_samples`], the redacted segment is attached,
+/// so mapping table maintainers get the context of the unknown value.
+pub(crate) fn warn_unknown_code(msg: &Message, config: &Fhir, location: &str, code: &str) {
+    let msg_id = get_message_key(msg).unwrap_or_default();
+    let segment = location.split('.').next().unwrap_or_default();
+
+    UNKNOWN_CODES.with(|count| count.set(count.get().map(|c| c + 1)));
+
+    match redacted_segment(msg, segment).filter(|_| config.unknown_code_samples) {
+        Some(sample) => {
+            warn!("MSG-ID {msg_id}: unknown code '{code}' at {location}, sample: {sample}")
+        }
+        None => warn!("MSG-ID {msg_id}: unknown code '{code}' at {location}"),
+    }
+}
+
+/// Marks of patients flagged as VIP in PV1-16 (see [`crate::config::VipConfig`]).
+#[derive(Default)]
+pub(crate) struct VipMarks {
+    /// security label of `Patient` and `Encounter`
+    pub(crate) security: Vec<Option<Coding>>,
+    /// optional `Encounter` extension
+    pub(crate) extension: Vec<Extension>,
+}
+
+/// Maps the VIP indicator (PV1-16) to the configured security label and extension, if enabled.
+pub(crate) fn map_vip(msg: &Message, config: &Fhir) -> Result<VipMarks, MappingError> {
+    let vip = &config.vip;
+    if !vip.enabled || !query(msg, PV1_16).is_some_and(|v| vip.indicators.iter().any(|i| i == v)) {
+        return Ok(VipMarks::default());
+    }
+
+    Ok(VipMarks {
+        security: vec![Some(
+            Coding::builder()
+                .system(vip.system.clone())
+                .code(vip.code.clone())
+                .build()?,
+        )],
+        extension: vip
+            .extension
+            .iter()
+            .map(|url| {
+                Extension::builder()
+                    .url(url.clone())
+                    .value(ExtensionValue::Boolean(true))
+                    .build()
+            })
+            .collect::<Result<_, _>>()?,
+    })
+}
+
 pub(crate) fn get_meta(config: &Fhir) -> Result<Meta, MappingError> {
     Ok(Meta::builder()
         .source(config.meta_source.to_string())
         .build()?)
 }
-pub(crate) fn subject_ref(msg: &Message, sid: &str) -> Result<Reference, MappingError> {
-    let pid = query(msg, PID_2).ok_or(anyhow!("missing pid value in PID.2"))?;
 
-    resource_ref(&ResourceType::Patient, pid, sid)
+/// Same as [`get_meta`], with the profile unless it's empty.
+pub(crate) fn get_meta_with_profile(profile: &str, config: &Fhir) -> Result<Meta, MappingError> {
+    let mut meta = get_meta(config)?;
+    if !profile.is_empty() {
+        meta.profile = vec![Some(profile.to_string())];
+    }
+    Ok(meta)
 }
 
-pub(crate) fn map_visit_number<'a>(msg: &'a Message) -> Result<&'a str, anyhow::Error> {
-    match message_type(msg)? {
-        MessageType::A14 => Ok(query(msg, PID_4).ok_or(anyhow!("empty visit number in PID.4"))?),
+pub(crate) fn map_visit_number<'a>(
+    msg: &'a Message,
+    message_type: MessageType,
+) -> Result<&'a str, anyhow::Error> {
+    match message_type {
+        // prefer the visit number of the later admission (A01), so both encounters unify
+        MessageType::A14 => Ok(query(msg, PV1_19_1)
+            .or(query(msg, PID_4))
+            .ok_or(anyhow!("empty visit number in PV1.19 and PID.4"))?),
         _ => Ok(query(msg, PV1_19_1).ok_or(anyhow!("empty visit number in PV1.19"))?),
     }
 }
@@ -347,8 +899,6 @@ pub(crate) fn map_visit_number<'a>(msg: &'a Message) -> Result<&'a str, anyhow::
 /// Mehrere Identifier werden sortiert und konkateniert, damit die Reihenfolge
 /// keinen Einfluss auf das Ergebnis hat.
 pub fn full_url_from_identifiers(identifiers: &[Identifier], config: &Fhir) -> String {
-    let namespace = Uuid::new_v5(&Uuid::NAMESPACE_DNS, config.facility_id.as_ref());
-
     let mut values: Vec<String> = identifiers
         .iter()
         .filter_map(|id| {
@@ -366,7 +916,11 @@ pub fn full_url_from_identifiers(identifiers: &[Identifier], config: &Fhir) -> S
     values.sort();
     let input = values.join(";");
 
-    let uuid = Uuid::new_v5(&namespace, input.as_bytes());
+    full_url_from_input(&input, config)
+}
+
+fn full_url_from_input(input: &str, config: &Fhir) -> String {
+    let uuid = Uuid::new_v5(&config.full_url_namespace(), input.as_bytes());
     format!("urn:uuid:{}", uuid)
 }
 
@@ -391,8 +945,10 @@ pub(crate) fn is_ward_valid_icu(msg: &Message, resources: &ResourceMap) -> bool
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::TargetPreset;
     use crate::test_utils::tests::{
-        filter_resources, get_dummy_resources, get_test_config, has_profile, read_test_resource,
+        assert_snapshot, filter_resources, get_dummy_resources, get_test_config, has_profile,
+        read_test_resource,
     };
     use fhir_model::DateTime::DateTime;
     use fhir_model::r4b::codes::HTTPVerb::Patch;
@@ -425,6 +981,29 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[rstest]
+    #[case("1982", Date::Year(1982))]
+    #[case("198203", Date::YearMonth(1982, Month::March))]
+    #[case(
+        "19820330",
+        Date::Date(time::Date::from_calendar_date(1982, Month::March, 30).unwrap())
+    )]
+    #[case(
+        "198203301036",
+        Date::Date(time::Date::from_calendar_date(1982, Month::March, 30).unwrap())
+    )]
+    fn test_parse_date(#[case] input: &str, #[case] expected: Date) {
+        assert_eq!(parse_date(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("82")]
+    #[case("198213")]
+    #[case("unknown")]
+    fn test_parse_date_invalid(#[case] input: &str) {
+        assert!(parse_date(input).is_err());
+    }
+
     #[test]
     fn map_test() {
         let hl7 = read_test_resource("a08_test.hl7");
@@ -433,6 +1012,8 @@ mod tests {
         let mapper = FhirMapper {
             config: config.clone(),
             resources: get_dummy_resources(),
+            history: Default::default(),
+            exclusion: Default::default(),
         };
 
         // act
@@ -441,7 +1022,8 @@ mod tests {
         // map back to assert
         let bundle: Bundle = serde_json::from_str(mapped.unwrap().as_str()).unwrap();
 
-        assert_eq!(bundle.entry.len(), 9);
+        // patient, encounters, locations, organizations (incl. payer) and 9 conditions
+        assert_eq!(bundle.entry.len(), 19);
 
         let patient: Vec<Patient> = filter_resources(&bundle);
         let encounter: Vec<Encounter> = filter_resources(&bundle);
@@ -459,6 +1041,94 @@ mod tests {
         );
     }
 
+    /// Fixture per trigger event, with the expected request method of the `Patient` entry. The
+    /// whole bundle is compared with the snapshot of the fixture.
+    #[rstest]
+    #[case("a01_test.hl7", Some(HTTPVerb::Put))]
+    #[case("a02_test.hl7", Some(HTTPVerb::Post))]
+    #[case("a03_test.hl7", Some(HTTPVerb::Post))]
+    #[case("a04_test2.hl7", Some(HTTPVerb::Put))]
+    #[case("a05_ns_test.hl7", Some(HTTPVerb::Put))]
+    #[case("a06_teilsstationaer_test.hl7", Some(HTTPVerb::Put))]
+    #[case("a07_nachstationaer_test.hl7", Some(HTTPVerb::Put))]
+    #[case("a08_test.hl7", Some(HTTPVerb::Put))]
+    #[case("a11_test.hl7", None)]
+    #[case("a12_test.hl7", None)]
+    #[case("a13_test.hl7", None)]
+    #[case("a14_test.hl7", None)]
+    #[case("a29_test.hl7", Some(HTTPVerb::Delete))]
+    #[case("a31_test.hl7", Some(HTTPVerb::Post))]
+    #[case("a34_test.hl7", Some(HTTPVerb::Patch))]
+    #[case("a38_test.hl7", None)]
+    #[case("a40_test.hl7", Some(HTTPVerb::Patch))]
+    #[case("a50_test.hl7", None)]
+    fn test_map_trigger_events(#[case] file: &str, #[case] patient_method: Option<HTTPVerb>) {
+        let map_bundle = || -> Value {
+            let mut bundle = FhirMapper::new(get_test_config())
+                .unwrap()
+                .map(&read_test_resource(file))
+                .unwrap_or_else(|e| panic!("failed to map {file}: {e}"))
+                .map_or(Value::Null, |b| serde_json::from_str(&b).unwrap());
+            // the bundle is stamped with the mapping time
+            if let Some(last_updated) = bundle.pointer_mut("/meta/lastUpdated") {
+                *last_updated = Value::from("1970-01-01T00:00:00Z");
+            }
+            bundle
+        };
+        let bundle = map_bundle();
+        // apart from the mapping time, the bundle doesn't change between runs
+        assert_eq!(bundle, map_bundle(), "{file}");
+        assert_snapshot(file.trim_end_matches(".hl7"), &bundle);
+
+        let entries: Vec<BundleEntry> = Some(&bundle)
+            .filter(|b| !b.is_null())
+            .map(|b| serde_json::from_value::<Bundle>(b.clone()).unwrap())
+            .into_iter()
+            .flat_map(|b| b.entry)
+            .flatten()
+            .collect();
+
+        // every entry is a conditional request
+        assert!(entries.iter().all(|e| e.request.is_some()), "{file}");
+        let patient = entries
+            .iter()
+            .filter_map(|e| e.request.as_ref())
+            .find(|r| r.url.split(['?', '/']).next() == Some("Patient"))
+            .map(|r| r.method);
+        assert_eq!(patient, patient_method, "{file}");
+    }
+
+    #[test]
+    fn test_department_service_provider_in_bundle() {
+        let config = get_test_config();
+        let mapped = FhirMapper::new(config.clone())
+            .unwrap()
+            .map(&read_test_resource("a01_test.hl7"))
+            .unwrap()
+            .unwrap();
+        let bundle: Bundle = serde_json::from_str(&mapped).unwrap();
+
+        // the department organization (InfoByAbteilungskuerzel.json) is an entry of the same
+        // bundle, so the conditional serviceProvider reference resolves
+        let department_refs: Vec<String> = filter_resources::<Encounter>(&bundle)
+            .into_iter()
+            .filter_map(|e| e.service_provider.and_then(|r| r.reference))
+            .filter(|r| r.contains(&config.organization.department.system))
+            .collect();
+        assert!(!department_refs.is_empty());
+        for reference in department_refs {
+            assert!(
+                bundle
+                    .entry
+                    .iter()
+                    .flatten()
+                    .filter(|e| matches!(e.resource, Some(Resource::Organization(_))))
+                    .any(|e| e.request.as_ref().is_some_and(|r| r.url == reference)),
+                "missing organization entry of {reference}"
+            );
+        }
+    }
+
     #[test]
     fn test_patch_bundle_entry() {
         let identifier = &Identifier::builder()
@@ -519,6 +1189,8 @@ ZBE|30674176^ORBIS|202111230904||DUMMY"#,
         let mapper = FhirMapper {
             config: config.clone(),
             resources: get_dummy_resources(),
+            history: Default::default(),
+            exclusion: Default::default(),
         };
 
         let expected_request_type = HTTPVerb::from_str(request_type_encounter.as_str()).unwrap();
@@ -750,6 +1422,304 @@ PV1|1|{}|{}|R^^HL7~01^Normalfall^301||||||N||||||N|||00000000||K|||||||||||||||0
             assert_eq!(parse_fab(&msg), Some(expected));
         }
     }
+
+    #[rstest]
+    #[case("Y", true, 1)]
+    #[case("N", true, 0)]
+    #[case("", true, 0)]
+    #[case("Y", false, 0)]
+    fn test_map_vip_security(#[case] pv1_16: &str, #[case] enabled: bool, #[case] expected: usize) {
+        let input = format!(
+            r#"MSH|^~\&|ORBIS|KH|RECAPP|ORBIS|202111221030||ADT^A01|62293727|P|2.5||123456789|NE|NE||8859/1
+EVN|A01|202111221030|202111221029||EIDAMN
+PID|1|1499653|1499653||Test^Meinrad||202301181003|M
+PV1|1|I|^^^KLINIKUM^KLINIKUM|||||||||||||{}|||00000000"#,
+            pv1_16
+        );
+        let msg = Message::parse_with_lenient_newlines(input.as_str(), true).unwrap();
+        let mut config = get_test_config();
+        config.vip.enabled = enabled;
+
+        let security = map_vip(&msg, &config).unwrap().security;
+
+        assert_eq!(security.len(), expected);
+        if let Some(Some(coding)) = security.first() {
+            assert_eq!(coding.system, Some(config.vip.system.clone()));
+            assert_eq!(coding.code, Some(config.vip.code.clone()));
+        }
+    }
+    #[rstest]
+    #[case("A14", "10000001", "20000009", "10000001")]
+    #[case("A14", "", "20000009", "20000009")]
+    #[case("A01", "10000001", "20000009", "10000001")]
+    fn test_map_visit_number(
+        #[case] msg_type: &str,
+        #[case] pv1_19: &str,
+        #[case] pid_4: &str,
+        #[case] expected: &str,
+    ) {
+        let hl7 = format!(
+            r#"MSH|^~\&|ORBIS|KH|RECAPP|ORBIS|202111221030||ADT^{msg_type}|62293730|P|2.5||123456789|NE|NE||8859/1
+EVN|{msg_type}|202111221030|202111221029||EIDAMN
+PID|1|1499653|1499653|{pid_4}|Test^Meinrad||202301181003|M
+PV1|1|I|POLPOLAMB^^^POL^POLPOL^945400^^^|R^^HL7~01^Normalfall^301||||||N||||||N|||{pv1_19}||K"#
+        );
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+
+        let message_type = MessageType::from_str(msg_type).unwrap();
+
+        assert_eq!(map_visit_number(&msg, message_type).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_mapping_context() {
+        let hl7 = read_test_resource("a14_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+
+        let config = get_test_config();
+        let ctx = MappingContext::new(&msg, &config).unwrap();
+
+        assert_eq!(ctx.message_type(), MessageType::A14);
+        assert_eq!(ctx.patient_id(), Some("1499653"));
+        assert_eq!(ctx.require_visit_number().unwrap(), "10000001");
+        assert_eq!(
+            ctx.subject_ref("pid").unwrap(),
+            resource_ref(&ResourceType::Patient, "1499653", "pid", &config).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case(None, false, "Location?identifier=ward|1")]
+    #[case(
+        Some("https://fhir.example.org/fhir/"),
+        false,
+        "Location?identifier=ward|1"
+    )]
+    #[case(
+        Some("https://fhir.example.org/fhir/"),
+        true,
+        "https://fhir.example.org/fhir/Location?identifier=ward|1"
+    )]
+    fn test_canonical_ref(
+        #[case] base_url: Option<&str>,
+        #[case] absolute: bool,
+        #[case] expected: &str,
+    ) {
+        let mut config = get_test_config();
+        config.base_url = base_url.map(String::from);
+        config.absolute_references = absolute;
+
+        let reference = canonical_ref(&ResourceType::Location, "1", "ward", &config).unwrap();
+
+        assert_eq!(reference.reference, Some(expected.to_string()));
+    }
+
+    fn mapped_types(config: Fhir) -> Vec<String> {
+        let mapper = FhirMapper {
+            config,
+            resources: get_dummy_resources(),
+            history: Default::default(),
+            exclusion: Default::default(),
+        };
+        let mapped = mapper.map(&read_test_resource("a08_test.hl7")).unwrap();
+        let bundle: Bundle = serde_json::from_str(&mapped.unwrap()).unwrap();
+
+        let mut types: Vec<String> = bundle
+            .entry
+            .iter()
+            .flatten()
+            .map(|e| e.resource.as_ref().unwrap().resource_type().to_string())
+            .collect();
+        types.dedup();
+        types
+    }
+
+    #[test]
+    fn test_select_identifier() {
+        let identifier = |system: &str, value: &str, id_use: IdentifierUse| {
+            Some(
+                Identifier::builder()
+                    .system(system.to_string())
+                    .value(value.to_string())
+                    .r#use(id_use)
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let identifiers = vec![
+            identifier("b", "2", IdentifierUse::Usual),
+            identifier("a", "1", IdentifierUse::Usual),
+            identifier("c", "3", IdentifierUse::Official),
+        ];
+        let mut config = get_test_config();
+
+        // the first identifier with the configured use
+        let selected = select_identifier(&identifiers, &config).unwrap();
+        assert_eq!(selected.system.as_deref(), Some("b"));
+
+        config.identifier_selection.r#use = SelectedIdentifierUse::Official;
+        let selected = select_identifier(&identifiers, &config).unwrap();
+        assert_eq!(selected.system.as_deref(), Some("c"));
+
+        // prioritized systems first
+        config.identifier_selection.system_priority = vec!["x".to_string(), "b".to_string()];
+        let selected = select_identifier(&identifiers, &config).unwrap();
+        assert_eq!(selected.system.as_deref(), Some("b"));
+
+        config.identifier_selection = Default::default();
+        config.identifier_selection.r#use = SelectedIdentifierUse::Secondary;
+        assert!(select_identifier(&identifiers, &config).is_err());
+    }
+
+    #[test]
+    fn test_catch_panic() {
+        let result: Result<(), MappingError> = catch_panic(|| todo!("admit source"));
+
+        match result {
+            Err(MappingError::Panic(cause)) => {
+                assert_eq!(cause, "not yet implemented: admit source")
+            }
+            other => panic!("expected panic error, got {other:?}"),
+        }
+        assert_eq!(catch_panic(|| Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_entry_order() {
+        // dependency order
+        let types = mapped_types(get_test_config());
+        let ranks: Vec<usize> = types
+            .iter()
+            .map(|t| ENTRY_ORDER.iter().position(|o| o == t).unwrap())
+            .collect();
+        assert!(ranks.is_sorted(), "unexpected entry order {types:?}");
+
+        // configured order, others follow
+        let mut config = get_test_config();
+        config.entry_order = vec!["Encounter".to_string(), "Patient".to_string()];
+        let types = mapped_types(config);
+        assert_eq!(types[..2], ["Encounter", "Patient"]);
+    }
+
+    #[test]
+    fn test_full_url_from_identifiers() {
+        let identifier = |system: &str, value: &str| {
+            Identifier::builder()
+                .system(system.to_string())
+                .value(value.to_string())
+                .build()
+                .unwrap()
+        };
+        let a = identifier("https://a", "1");
+        let b = identifier("https://b", "2");
+        let mut config = get_test_config();
+
+        // independent of the identifier order
+        let full_url = full_url_from_identifiers(&[a.clone(), b.clone()], &config);
+        assert_eq!(
+            full_url_from_identifiers(&[b.clone(), a.clone()], &config),
+            full_url
+        );
+        let default_namespace = Uuid::new_v5(&Uuid::NAMESPACE_DNS, config.facility_id.as_bytes());
+        assert_eq!(
+            full_url,
+            format!(
+                "urn:uuid:{}",
+                Uuid::new_v5(&default_namespace, b"https://a|1;https://b|2")
+            )
+        );
+
+        // configured namespace
+        let namespace = Uuid::new_v5(&Uuid::NAMESPACE_URL, b"https://fhir.example.org");
+        config.full_url_namespace = Some(namespace);
+        assert_eq!(
+            full_url_from_identifiers(&[a], &config),
+            format!("urn:uuid:{}", Uuid::new_v5(&namespace, b"https://a|1"))
+        );
+    }
+
+    #[rstest]
+    #[case(IdentifierEscaping::None, "AOK HESSEN|1")]
+    #[case(IdentifierEscaping::Search, "AOK HESSEN\\|1")]
+    #[case(IdentifierEscaping::Url, "AOK%20HESSEN%7C1")]
+    fn test_escape_identifier(#[case] escaping: IdentifierEscaping, #[case] expected: &str) {
+        assert_eq!(escape_identifier("AOK HESSEN|1", escaping), expected);
+        assert_eq!(unescape_identifier(expected, escaping), "AOK HESSEN|1");
+    }
+
+    #[rstest]
+    #[case(None, None, IdentifierEscaping::None)]
+    #[case(Some(TargetPreset::Hapi), None, IdentifierEscaping::Search)]
+    #[case(Some(TargetPreset::Blaze), None, IdentifierEscaping::Url)]
+    #[case(
+        Some(TargetPreset::Blaze),
+        Some(IdentifierEscaping::None),
+        IdentifierEscaping::None
+    )]
+    fn test_target_preset(
+        #[case] preset: Option<TargetPreset>,
+        #[case] escaping: Option<IdentifierEscaping>,
+        #[case] expected: IdentifierEscaping,
+    ) {
+        let mut config = get_test_config();
+        config.target_preset = preset;
+        config.identifier_escaping = escaping;
+
+        assert_eq!(config.identifier_escaping(), expected);
+    }
+
+    #[rstest]
+    #[case(None, None, BundleKind::Transaction)]
+    #[case(Some(TargetPreset::Hapi), None, BundleKind::Transaction)]
+    #[case(Some(TargetPreset::Blaze), None, BundleKind::Transaction)]
+    #[case(Some(TargetPreset::Firely), None, BundleKind::Transaction)]
+    #[case(Some(TargetPreset::Blaze), Some(BundleKind::Batch), BundleKind::Batch)]
+    fn test_target_preset_bundle_type(
+        #[case] preset: Option<TargetPreset>,
+        #[case] bundle_type: Option<BundleKind>,
+        #[case] expected: BundleKind,
+    ) {
+        let mut config = get_test_config();
+        config.target_preset = preset;
+        config.bundle_type = bundle_type;
+
+        assert_eq!(config.bundle_type(), expected);
+    }
+
+    #[test]
+    fn test_target_preset_reference_resolution() {
+        let mut config = get_test_config();
+        config.target_preset = Some(TargetPreset::Blaze);
+        assert_eq!(config.reference_resolution(), ReferenceResolution::Inline);
+
+        config.target_preset = Some(TargetPreset::Firely);
+        assert_eq!(
+            config.reference_resolution(),
+            ReferenceResolution::Conditional
+        );
+
+        config.reference_resolution = Some(ReferenceResolution::Inline);
+        assert_eq!(config.reference_resolution(), ReferenceResolution::Inline);
+    }
+
+    #[test]
+    fn test_escaping_per_mapper() {
+        // configurations of different mappers don't affect each other
+        let mut blaze = get_test_config();
+        blaze.target_preset = Some(TargetPreset::Blaze);
+        let mut hapi = get_test_config();
+        hapi.target_preset = Some(TargetPreset::Hapi);
+        let reference = |config: &Fhir| {
+            resource_ref(&ResourceType::Patient, "a|1", "pid", config)
+                .unwrap()
+                .reference
+                .unwrap()
+        };
+
+        assert_eq!(reference(&blaze), "Patient?identifier=pid|a%7C1");
+        assert_eq!(reference(&hapi), "Patient?identifier=pid|a\\|1");
+    }
+
     #[test]
     fn test_all_hl7_files() {
         let test_files = vec![
@@ -763,8 +1733,10 @@ PV1|1|{}|{}|R^^HL7~01^Normalfall^301||||||N||||||N|||00000000||K|||||||||||||||0
             "a06_teilsstationaer_test.hl7",
             "a07_nachstationaer_test.hl7",
             "a11_test.hl7",
+            "a14_test.hl7",
             "a34_test.hl7",
             "a38_test.hl7",
+            "a50_test.hl7",
         ];
         for test_file in test_files {
             let binding = read_test_resource(test_file);
@@ -823,4 +1795,21 @@ PV1|1|{}|{}|R^^HL7~01^Normalfall^301||||||N||||||N|||00000000||K|||||||||||||||0
             }
         }
     }
+
+    #[test]
+    fn test_count_unknown_codes() {
+        let hl7 = read_test_resource("a01_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+        let config = get_test_config();
+
+        // not counted outside of a mapping scope
+        warn_unknown_code(&msg, &config, "PV1.4", "X");
+        let ((), count) = count_unknown_codes(|| {
+            warn_unknown_code(&msg, &config, "PV1.4", "X");
+            warn_unknown_code(&msg, &config, "PV1.4", "Y");
+        });
+
+        assert_eq!(count, 2);
+        assert_eq!(count_unknown_codes(|| ()).1, 0);
+    }
 }
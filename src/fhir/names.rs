@@ -0,0 +1,172 @@
+use crate::config::NameNormalizationConfig;
+use std::borrow::Cow;
+use unicode_normalization::UnicodeNormalization;
+
+/// Normalizes a name part (e.g. family or given name) by the configured options.
+///
+/// The options are applied in order: HL7 escape sequences are decoded, UTF-8 names decoded as
+/// Latin-1 upstream are restored and the result is NFC normalized. Casing is never changed.
+pub(super) fn normalize(value: &str, config: &NameNormalizationConfig) -> String {
+    let mut value = Cow::Borrowed(value);
+    if config.unescape {
+        value = Cow::Owned(unescape(&value));
+    }
+    if config.repair_encoding
+        && let Some(repaired) = repair_encoding(&value)
+    {
+        value = Cow::Owned(repaired);
+    }
+    if config.nfc {
+        value = Cow::Owned(value.nfc().collect());
+    }
+
+    value.into_owned()
+}
+
+/// Decodes the HL7 escape sequences of delimiters (`\F\`, `\S\`, `\T\`, `\R\`, `\E\`) and
+/// hexadecimal data (e.g. `\XFC\` or `\XC3BC\` for `ü`).
+///
+/// Hexadecimal data is decoded as UTF-8, or Latin-1 if it's not valid UTF-8. Unknown escape
+/// sequences are kept as is.
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    // consecutive hex escapes, which may encode a single character (e.g. `\XC3\\XBC\`)
+    let mut bytes: Vec<u8> = vec![];
+    let mut rest = value;
+
+    while let Some(start) = rest.find('\\') {
+        let Some(len) = rest[start + 1..].find('\\') else {
+            break;
+        };
+        let sequence = &rest[start + 1..start + 1 + len];
+        if start > 0 {
+            flush_bytes(&mut bytes, &mut result);
+            result.push_str(&rest[..start]);
+        }
+
+        if let Some(hex) = sequence.strip_prefix('X').and_then(decode_hex) {
+            bytes.extend(hex);
+        } else {
+            flush_bytes(&mut bytes, &mut result);
+            match sequence {
+                "F" => result.push('|'),
+                "S" => result.push('^'),
+                "T" => result.push('&'),
+                "R" => result.push('~'),
+                "E" => result.push('\\'),
+                _ => result.push_str(&rest[start..start + len + 2]),
+            }
+        }
+        rest = &rest[start + len + 2..];
+    }
+    flush_bytes(&mut bytes, &mut result);
+    result.push_str(rest);
+
+    result
+}
+
+fn flush_bytes(bytes: &mut Vec<u8>, result: &mut String) {
+    if bytes.is_empty() {
+        return;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(s) => result.push_str(s),
+        // Latin-1 maps to the first 256 code points
+        Err(_) => result.extend(bytes.iter().map(|&b| char::from(b))),
+    }
+    bytes.clear();
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Restores UTF-8 encoded names, which were decoded as Latin-1 upstream (e.g. `MÃ¼ller`), or
+/// [`None`] if the value isn't affected.
+fn repair_encoding(value: &str) -> Option<String> {
+    if !value.contains(['Ã', 'Â']) {
+        return None;
+    }
+    let bytes = value
+        .chars()
+        .map(|c| u8::try_from(u32::from(c)).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn config(unescape: bool, repair_encoding: bool, nfc: bool) -> NameNormalizationConfig {
+        NameNormalizationConfig {
+            unescape,
+            repair_encoding,
+            nfc,
+        }
+    }
+
+    #[rstest]
+    #[case("M\\XFC\\ller", "Müller")]
+    #[case("M\\XC3BC\\ller", "Müller")]
+    #[case("M\\XC3\\\\XBC\\ller", "Müller")]
+    #[case("Stra\\XDF\\e", "Straße")]
+    #[case("\\XC4\\rger", "Ärger")]
+    #[case("Meier\\T\\Co", "Meier&Co")]
+    #[case("M\\XFC\\\\T\\", "Mü&")]
+    #[case("Schmidt\\S\\Meyer", "Schmidt^Meyer")]
+    #[case("Back\\E\\slash", "Back\\slash")]
+    #[case("M\\H\\ayer\\N\\", "M\\H\\ayer\\N\\")]
+    #[case("Unterminated\\XFC", "Unterminated\\XFC")]
+    #[case("Jürgen", "Jürgen")]
+    fn unescape_names(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(normalize(value, &config(true, false, false)), expected);
+    }
+
+    #[rstest]
+    #[case("MÃ¼ller", "Müller")]
+    #[case("BÃ¤cker", "Bäcker")]
+    #[case("Ã\u{96}zdemir", "Özdemir")]
+    #[case("Müller", "Müller")]
+    // not valid UTF-8, kept as is
+    #[case("Ã", "Ã")]
+    fn repair_names(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(normalize(value, &config(false, true, false)), expected);
+    }
+
+    #[test]
+    fn nfc_names() {
+        // u + combining diaeresis
+        let decomposed = "Mu\u{0308}ller";
+
+        assert_eq!(normalize(decomposed, &config(false, false, true)), "Müller");
+        assert_eq!(
+            normalize(decomposed, &config(false, false, false)),
+            decomposed
+        );
+    }
+
+    #[test]
+    fn casing_preserved() {
+        let all = config(true, true, true);
+
+        assert_eq!(normalize("von der MÜHLE", &all), "von der MÜHLE");
+        assert_eq!(normalize("Weiß", &all), "Weiß");
+        assert_eq!(normalize("d'Aubigné", &all), "d'Aubigné");
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let value = "M\\XFC\\ller MÃ¼ller";
+
+        assert_eq!(normalize(value, &NameNormalizationConfig::default()), value);
+    }
+}
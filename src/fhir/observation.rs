@@ -1,21 +1,25 @@
 use crate::config::Fhir;
 use crate::error::{MappingError, MessageAccessError, ParsingError};
+use crate::fhir::encounter::einrichtungskontakt_ref;
 use crate::fhir::mapper::{
-    EntryRequestType, build_usual_identifier, bundle_entry, get_cc_with_one_code, map_visit_number,
-    parse_datetime, resource_ref, subject_ref,
+    EntryRequestType, MappingContext, build_usual_identifier, bundle_entry, get_cc_with_one_code,
+    parse_datetime,
 };
 use crate::fhir::patient::map_deceased;
 use crate::hl7::parser::{
-    MessageType, PID_2, PV1_19_1, ZBE_2, ZNG_6, ZNG_7, ZNG_11, message_type, query,
+    MessageType, ZBE_2, ZNG_6, ZNG_7, ZNG_11, get_message_key, query, segment_value,
 };
 use anyhow::anyhow;
+use fhir_model::DateTime;
 use fhir_model::r4b::codes::ObservationStatus;
 use fhir_model::r4b::resources::{
     BundleEntry, Observation, ObservationBuilder, ObservationEffective, ObservationValue,
-    PatientDeceased, ResourceType,
+    PatientDeceased,
 };
-use fhir_model::r4b::types::{CodeableConcept, Coding, Identifier, Meta, Quantity, Reference};
+use fhir_model::r4b::types::{CodeableConcept, Coding, Identifier, Meta, Quantity};
 use hl7_parser::Message;
+use hl7_parser::message::Segment;
+use log::warn;
 use std::ops::Div;
 use std::sync::LazyLock;
 
@@ -117,23 +121,24 @@ const IS_ALIVE_CODING: LazyLock<Vec<Option<Coding>>> = LazyLock::new(|| {
     vec![Coding::builder().code("L".to_string()).system("https://www.medizininformatik-initiative.de/fhir/core/modul-person/CodeSystem/Vitalstatus".to_string()).build().ok()]
 });
 
-fn get_basic_observation_builder(msg: &Message) -> Result<ObservationBuilder, MappingError> {
-    Ok(Observation::builder()
+/// Date of the movement (ZBE-2).
+fn movement_datetime(msg: &Message) -> Result<DateTime, MappingError> {
+    Ok(parse_datetime(query(msg, ZBE_2).ok_or(
+        MessageAccessError::Other(anyhow!("ZBE.2 dateTime value missing!")),
+    )?)?)
+}
+
+fn get_basic_observation_builder(effective: DateTime) -> ObservationBuilder {
+    Observation::builder()
         .status(ObservationStatus::Final)
-        .effective(ObservationEffective::DateTime(parse_datetime(
-            query(msg, ZBE_2).ok_or(MessageAccessError::Other(anyhow!(
-                "ZBE.2 dateTime value missing!"
-            )))?,
-        )?)))
+        .effective(ObservationEffective::DateTime(effective))
 }
 
-pub(crate) fn map(msg: &Message, config: &Fhir) -> Result<Vec<BundleEntry>, MappingError> {
+pub(crate) fn map(ctx: &MappingContext, config: &Fhir) -> Result<Vec<BundleEntry>, MappingError> {
     let mut result: Vec<BundleEntry> = vec![];
-    let pid = query(msg, PID_2);
-    let visit = query(msg, PV1_19_1);
 
-    if let (Some(pid), Some(visit)) = (pid, visit) {
-        if let Some(is_alive) = map_vital_status(msg, config, pid, visit)? {
+    if let (Some(pid), Some(visit)) = (ctx.patient_id(), ctx.visit_number()) {
+        if let Some(is_alive) = map_vital_status(ctx, config, pid, visit)? {
             result.push(bundle_entry(
                 is_alive,
                 EntryRequestType::UpdateAsCreate,
@@ -141,7 +146,7 @@ pub(crate) fn map(msg: &Message, config: &Fhir) -> Result<Vec<BundleEntry>, Mapp
             )?);
         }
 
-        if let Some(head) = map_head_circumference(msg, config, pid, visit)? {
+        if let Some(head) = map_head_circumference(ctx, config, pid, visit)? {
             result.push(bundle_entry(
                 head,
                 EntryRequestType::UpdateAsCreate,
@@ -149,7 +154,7 @@ pub(crate) fn map(msg: &Message, config: &Fhir) -> Result<Vec<BundleEntry>, Mapp
             )?);
         }
 
-        if let Some(weight) = map_body_weight(msg, config, pid, visit)? {
+        if let Some(weight) = map_body_weight(ctx, config, pid, visit)? {
             result.push(bundle_entry(
                 weight,
                 EntryRequestType::UpdateAsCreate,
@@ -157,39 +162,39 @@ pub(crate) fn map(msg: &Message, config: &Fhir) -> Result<Vec<BundleEntry>, Mapp
             )?);
         }
 
-        if let Some(length) = map_body_length(msg, config, pid, visit)? {
+        if let Some(length) = map_body_length(ctx, config, pid, visit)? {
             result.push(bundle_entry(
                 length,
                 EntryRequestType::UpdateAsCreate,
                 config,
             )?);
         }
+
+        for obx in ctx.msg.segments().filter(|seg| seg.name.eq("OBX")) {
+            if let Some(obs) = map_obx(ctx, obx, config, pid, visit)? {
+                let entry = bundle_entry(obs, EntryRequestType::UpdateAsCreate, config)?;
+                // repeated segments are mapped once
+                if !result.iter().any(|e| e.full_url == entry.full_url) {
+                    result.push(entry);
+                }
+            }
+        }
     }
     Ok(result)
 }
-fn encounter_reference(msg: &Message, config: &Fhir) -> Result<Reference, MappingError> {
-    let visit_number = map_visit_number(msg)?;
-    resource_ref(
-        &ResourceType::Encounter,
-        visit_number,
-        &config.fall.einrichtungskontakt.system,
-    )
-}
 fn map_vital_status(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     pid: &str,
     visit: &str,
 ) -> Result<Option<Observation>, MappingError> {
-    if map_deceased(msg)?.is_none() || map_deceased(msg)? == Some(PatientDeceased::Boolean(false)) {
-        return match message_type(msg).ok() {
+    let deceased = map_deceased(ctx.msg, config)?;
+    if deceased.is_none() || deceased == Some(PatientDeceased::Boolean(false)) {
+        return match ctx.message_type() {
             // is alive observation will be created at patient admission,
             // discharge, movement, registration
-            Some(MessageType::A01)
-            | Some(MessageType::A02)
-            | Some(MessageType::A03)
-            | Some(MessageType::A04) => Ok(Some(
-                get_basic_observation_builder(msg)?
+            MessageType::A01 | MessageType::A02 | MessageType::A03 | MessageType::A04 => Ok(Some(
+                get_basic_observation_builder(movement_datetime(ctx.msg)?)
                     .category(vec![Some(get_cc_with_one_code(
                         SURVEY_CATEGORY_CODE.into(),
                         VITAL_SIGNS_CATEGORY_SYSTEM.into(),
@@ -214,8 +219,8 @@ fn map_vital_status(
                             .coding(IS_ALIVE_CODING.clone())
                             .build()?,
                     ))
-                    .subject(subject_ref(msg, &config.person.system)?)
-                    .encounter(encounter_reference(msg, config)?)
+                    .subject(ctx.subject_ref(&config.person.system)?)
+                    .encounter(einrichtungskontakt_ref(ctx, config)?)
                     .build()?,
             )),
             // current message type should not create a vital status observation
@@ -227,18 +232,19 @@ fn map_vital_status(
 }
 
 fn map_body_length(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     pid: &str,
     visit: &str,
 ) -> Result<Option<Observation>, MappingError> {
-    if let Some(quantity_value) = query(msg, ZNG_6)
+    if let Some(quantity_value) = query(ctx.msg, ZNG_6)
         .map(|val| val.parse::<f64>().map_err(ParsingError::ParseFloatError))
         .transpose()?
     {
         return Ok(Some(
             get_birth_obs_builder(
-                msg,
+                ctx,
+                movement_datetime(ctx.msg)?,
                 build_usual_identifier(
                     vec![LOINC_BODY_HEIGHT, pid, visit],
                     config.observation.system.clone(),
@@ -261,12 +267,12 @@ fn map_body_length(
 }
 
 fn map_body_weight(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     pid: &str,
     visit: &str,
 ) -> Result<Option<Observation>, MappingError> {
-    if let Some(quantity_value) = query(msg, ZNG_7)
+    if let Some(quantity_value) = query(ctx.msg, ZNG_7)
         .map(|val| val.parse::<f64>().map_err(ParsingError::ParseFloatError))
         .transpose()?
     {
@@ -278,7 +284,8 @@ fn map_body_weight(
         // current profile has fixed unit to kg
         return Ok(Some(
             get_birth_obs_builder(
-                msg,
+                ctx,
+                movement_datetime(ctx.msg)?,
                 identifier,
                 quantity_value.div(1000f64),
                 "kg".to_string(),
@@ -298,12 +305,12 @@ fn map_body_weight(
 }
 
 fn map_head_circumference(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     pid: &str,
     visit: &str,
 ) -> Result<Option<Observation>, MappingError> {
-    if let Some(quantity_value) = query(msg, ZNG_11)
+    if let Some(quantity_value) = query(ctx.msg, ZNG_11)
         .map(|val| val.parse::<f64>().map_err(ParsingError::ParseFloatError))
         .transpose()?
     {
@@ -313,7 +320,8 @@ fn map_head_circumference(
         )?;
         return Ok(Some(
             get_birth_obs_builder(
-                msg,
+                ctx,
+                movement_datetime(ctx.msg)?,
                 identifier,
                 quantity_value,
                 "cm".to_string(),
@@ -336,8 +344,74 @@ fn map_head_circumference(
     Ok(None)
 }
 
+/// Maps numeric OBX values (e.g. admission vitals) by the configured OBX-3 translation. The
+/// observations are identified by their date (OBX-14) or set id (OBX-1), non-numeric values and
+/// values without date (OBX-14, ZBE-2) are skipped.
+fn map_obx(
+    ctx: &MappingContext,
+    obx: &Segment,
+    config: &Fhir,
+    pid: &str,
+    visit: &str,
+) -> Result<Option<Observation>, MappingError> {
+    let Some(code) = segment_value(obx, 3, 1, 1)
+        .and_then(|c| config.observation.obx.iter().find(|o| o.code == c))
+    else {
+        return Ok(None);
+    };
+    let Some(value) = segment_value(obx, 5, 1, 1) else {
+        return Ok(None);
+    };
+    let msg_id = get_message_key(ctx.msg)?;
+    let Ok(quantity_value) = value.replace(',', ".").parse::<f64>() else {
+        warn!(
+            "Skipping non-numeric OBX-5 value '{value}' of {} at msg-id '{msg_id}'",
+            code.code
+        );
+        return Ok(None);
+    };
+    // observation date, ZBE-2 otherwise
+    let observed = segment_value(obx, 14, 1, 1);
+    let Some(effective) = observed.and_then(|d| d.get(..12)).or(query(ctx.msg, ZBE_2)) else {
+        warn!(
+            "Skipping OBX value of {} without date at msg-id '{msg_id}'",
+            code.code
+        );
+        return Ok(None);
+    };
+    let unit = segment_value(obx, 6, 1, 1).unwrap_or(&code.unit);
+
+    let mut identifier = vec![code.loinc.as_str(), pid, visit];
+    identifier.extend(observed.or(segment_value(obx, 1, 1, 1)));
+    let obs = get_birth_obs_builder(
+        ctx,
+        parse_datetime(effective)?,
+        build_usual_identifier(identifier, config.observation.system.clone())?,
+        quantity_value,
+        unit.to_string(),
+        unit.to_string(),
+        code.profile.clone(),
+        config,
+    )?
+    .code(
+        CodeableConcept::builder()
+            .coding(vec![Some(
+                Coding::builder()
+                    .code(code.loinc.clone())
+                    .system(LOINC_SYSTEM.to_string())
+                    .display(code.display.clone())
+                    .build()?,
+            )])
+            .build()?,
+    )
+    .build()?;
+
+    Ok(Some(obs))
+}
+
 fn get_birth_obs_builder(
-    msg: &Message,
+    ctx: &MappingContext,
+    effective: DateTime,
     identifier: Identifier,
     quantity_value: f64,
     unit_code: String,
@@ -345,7 +419,7 @@ fn get_birth_obs_builder(
     profile: String,
     config: &Fhir,
 ) -> Result<ObservationBuilder, MappingError> {
-    Ok(get_basic_observation_builder(msg)?
+    Ok(get_basic_observation_builder(effective)
         .meta(
             Meta::builder()
                 .profile(vec![Some(profile)])
@@ -365,19 +439,23 @@ fn get_birth_obs_builder(
                 .unit(unit)
                 .build()?,
         ))
-        .subject(subject_ref(msg, &config.person.system)?)
-        .encounter(encounter_reference(msg, config)?))
+        .subject(ctx.subject_ref(&config.person.system)?)
+        .encounter(einrichtungskontakt_ref(ctx, config)?))
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::config::ObxCodeConfig;
+    use crate::fhir::mapper::{MappingContext, parse_datetime};
     use crate::fhir::observation::{
         CODING_BODY_HEIGHT, CODING_BODY_WEIGHT, CODING_HEAD_CIRCUMFERENCE,
         CODING_PATIENT_DISPOSITION, LOINC_BODY_HEIGHT, LOINC_BODY_WEIGHT, LOINC_HEAD_CIRCUMFERENCE,
         LOINC_PATIENT_DISPOSITION, map,
     };
-    use crate::test_utils::tests::{get_test_config, read_test_resource};
-    use fhir_model::r4b::resources::{Observation, ObservationValue, Resource};
+    use crate::test_utils::tests::{get_test_config, read_test_message, read_test_resource};
+    use fhir_model::r4b::resources::{
+        Observation, ObservationEffective, ObservationValue, Resource,
+    };
     use hl7_parser::Message;
     use rstest::rstest;
     use std::collections::HashSet;
@@ -391,7 +469,11 @@ mod tests {
         let config = get_test_config();
         let expected_resource_count = obs_count_expected;
 
-        let mapped = map(&msg, &config).unwrap();
+        let mapped = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+        )
+        .unwrap();
 
         let mut used_codes: HashSet<String> = HashSet::new();
         let resources = mapped
@@ -511,4 +593,61 @@ mod tests {
         );
         assert_eq!(CODING_PATIENT_DISPOSITION.clone().len(), 1);
     }
+
+    #[test]
+    fn map_obx_observation() {
+        let hl7 = read_test_message(
+            "a01_test.hl7",
+            &[],
+            &[
+                "OBX|1|NM|GEW^Gewicht||72,5|kg|||||F|||202211101400",
+                "OBX|2|NM|RR^Blutdruck||120|mmHg",
+                "OBX|3|NM|GEW^Gewicht||73|kg|||||F|||202211111400",
+                "OBX|4|NM|GEW^Gewicht||n.a.|kg",
+            ],
+        );
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        config.observation.obx = vec![ObxCodeConfig {
+            code: "GEW".to_string(),
+            loinc: LOINC_BODY_WEIGHT.to_string(),
+            display: "Body weight".to_string(),
+            unit: "kg".to_string(),
+            profile: config.observation.profile_weight.clone(),
+        }];
+
+        let mapped = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+        )
+        .unwrap();
+
+        // unmapped OBX-3 codes and non-numeric values are skipped
+        let weights: Vec<Observation> = mapped
+            .into_iter()
+            .filter_map(|e| match e.resource {
+                Some(Resource::Observation(o))
+                    if o.code.coding[0].as_ref().unwrap().code.as_deref()
+                        == Some(LOINC_BODY_WEIGHT) =>
+                {
+                    Some(o)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(weights.len(), 2);
+        // repeated values are identified by their date
+        assert_ne!(weights[0].identifier, weights[1].identifier);
+        let Some(ObservationValue::Quantity(quantity)) = &weights[0].value else {
+            panic!("expected quantity");
+        };
+        assert_eq!(quantity.value, Some(72.5));
+        assert_eq!(quantity.code.as_deref(), Some("kg"));
+        assert_eq!(
+            weights[0].effective,
+            Some(ObservationEffective::DateTime(
+                parse_datetime("202211101400").unwrap()
+            ))
+        );
+    }
 }
@@ -3,19 +3,21 @@ use crate::error::MappingError;
 use fhir_model::r4b::codes::IdentifierUse;
 
 use crate::fhir::mapper::{
-    EntryRequestType, bundle_entry, get_cc_with_one_code, get_meta, parse_fab, resource_ref,
+    EntryRequestType, MappingContext, bundle_entry, get_cc_with_one_code, get_meta, parse_fab,
+    resource_ref,
 };
 use crate::fhir::resources::ResourceMap;
 use crate::hl7::parser::{PV1_3_1, query};
 use fhir_model::r4b::resources::{BundleEntry, Organization, ResourceType};
-use fhir_model::r4b::types::Identifier;
+use fhir_model::r4b::types::{Identifier, Reference};
 use hl7_parser::Message;
 
 pub(crate) fn map(
-    msg: &Message,
+    ctx: &MappingContext,
     config: &Fhir,
     resources: &ResourceMap,
 ) -> Result<Vec<BundleEntry>, MappingError> {
+    let msg = ctx.msg;
     let mut result = vec![];
     if let Some(department_org) = map_department_org(msg, config, resources)? {
         result.push(bundle_entry(
@@ -34,35 +36,76 @@ pub(crate) fn map(
     Ok(result)
 }
 
+/// Conditional reference to the configured managing organization of the patients.
+pub(super) fn managing_org_ref(config: &Fhir) -> Result<Option<Reference>, MappingError> {
+    config
+        .organization
+        .managing
+        .as_ref()
+        .map(|m| resource_ref(&ResourceType::Organization, &m.value, &m.system, config))
+        .transpose()
+}
+
+/// Entry of the managing organization (conditional create), if enabled.
+pub(super) fn map_managing_org(config: &Fhir) -> Result<Option<BundleEntry>, MappingError> {
+    let Some(managing) = config.organization.managing.as_ref().filter(|m| m.create) else {
+        return Ok(None);
+    };
+    let mut organization = Organization::builder()
+        .meta(get_meta(config)?)
+        .identifier(vec![Some(
+            Identifier::builder()
+                .value(managing.value.to_string())
+                .system(managing.system.to_string())
+                .r#use(IdentifierUse::Usual)
+                .build()?,
+        )])
+        .build()?;
+    organization.name = managing.name.clone();
+
+    Ok(Some(bundle_entry(
+        organization,
+        EntryRequestType::ConditionalCreate,
+        config,
+    )?))
+}
+
 fn map_department_org(
     msg: &Message,
     config: &Fhir,
     resources: &ResourceMap,
 ) -> Result<Option<Organization>, MappingError> {
-    if let Some(fab_ref) = parse_fab(msg) {
-        let mut organization = Organization::builder()
-            .meta(get_meta(config)?)
-            .identifier(vec![Some(
-                Identifier::builder()
-                    .value(fab_ref.to_string())
-                    .system(config.organization.department.system.to_string())
-                    .r#use(IdentifierUse::Usual)
-                    .build()?,
-            )])
-            .r#type(vec![Some(get_cc_with_one_code(
-                "dept".to_string(),
-                "http://terminology.hl7.org/CodeSystem/organization-type".to_string(),
-            )?)])
-            .build()?;
-
-        // local department name may differ from official medical department name
-        if let Some(department_entry) = resources.department_map.get(fab_ref) {
-            organization.name = Some(department_entry.abteilungs_bezeichnung.to_string());
-        }
-        Ok(Some(organization))
-    } else {
-        Ok(None)
+    parse_fab(msg)
+        .map(|fab_ref| department_org(fab_ref, config, resources))
+        .transpose()
+}
+
+/// Organization of the department (Fachabteilungskürzel), named by the department mapping.
+pub(crate) fn department_org(
+    fab_ref: &str,
+    config: &Fhir,
+    resources: &ResourceMap,
+) -> Result<Organization, MappingError> {
+    let mut organization = Organization::builder()
+        .meta(get_meta(config)?)
+        .identifier(vec![Some(
+            Identifier::builder()
+                .value(fab_ref.to_string())
+                .system(config.organization.department.system.to_string())
+                .r#use(IdentifierUse::Usual)
+                .build()?,
+        )])
+        .r#type(vec![Some(get_cc_with_one_code(
+            "dept".to_string(),
+            "http://terminology.hl7.org/CodeSystem/organization-type".to_string(),
+        )?)])
+        .build()?;
+
+    // local department name may differ from official medical department name
+    if let Some(department_entry) = resources.department_map.get(fab_ref) {
+        organization.name = Some(department_entry.abteilungs_bezeichnung.to_string());
     }
+    Ok(organization)
 }
 
 fn map_ward_org(msg: &Message, config: &Fhir) -> Result<Option<Organization>, MappingError> {
@@ -76,6 +119,7 @@ fn map_ward_org(msg: &Message, config: &Fhir) -> Result<Option<Organization>, Ma
                         &ResourceType::Organization,
                         fab_ref,
                         config.organization.department.system.as_str(),
+                        config,
                     )?)
                     .identifier(vec![Some(
                         Identifier::builder()
@@ -99,8 +143,12 @@ fn map_ward_org(msg: &Message, config: &Fhir) -> Result<Option<Organization>, Ma
 }
 #[cfg(test)]
 mod tests {
-    use crate::fhir::organization::{map_department_org, map_ward_org};
+    use crate::config::ManagingOrganizationConfig;
+    use crate::fhir::organization::{
+        managing_org_ref, map_department_org, map_managing_org, map_ward_org,
+    };
     use crate::test_utils::tests::{get_dummy_resources, get_test_config};
+    use fhir_model::r4b::resources::Resource;
     use hl7_parser::Message;
 
     #[test]
@@ -165,4 +213,38 @@ PV1|1|I|POLPOLAMB^^^POL^POLPOL^945400^^^|R^^HL7~01^Normalfall^301||||||N||||||N|
             }
         }
     }
+
+    #[test]
+    fn test_managing_org() {
+        let mut config = get_test_config();
+        assert_eq!(managing_org_ref(&config).unwrap(), None);
+        assert!(map_managing_org(&config).unwrap().is_none());
+
+        config.organization.managing = Some(ManagingOrganizationConfig {
+            system: "https://fhir.diz.uni-marburg.de/sid/organization".to_string(),
+            value: "UKGM-MR".to_string(),
+            name: Some("Universitätsklinikum Marburg".to_string()),
+            create: false,
+        });
+        let reference = managing_org_ref(&config).unwrap().unwrap();
+        assert_eq!(
+            reference.reference.as_deref(),
+            Some(
+                "Organization?identifier=https://fhir.diz.uni-marburg.de/sid/organization|UKGM-MR"
+            )
+        );
+        // referenced only
+        assert!(map_managing_org(&config).unwrap().is_none());
+
+        config.organization.managing.as_mut().unwrap().create = true;
+        let entry = map_managing_org(&config).unwrap().unwrap();
+        let Some(Resource::Organization(org)) = entry.resource else {
+            panic!("expected organization");
+        };
+        assert_eq!(org.name.as_deref(), Some("Universitätsklinikum Marburg"));
+        assert_eq!(
+            entry.request.unwrap().if_none_exist.as_deref(),
+            Some("identifier=https://fhir.diz.uni-marburg.de/sid/organization|UKGM-MR")
+        );
+    }
 }
@@ -1,43 +1,65 @@
-use crate::config::Fhir;
+use crate::config::{
+    DeceasedRule, DefaultGender, EmployerTarget, Fhir, MunicipalityKey, NameNormalizationConfig,
+    PatientDeletionMode,
+};
 use crate::error::MappingError;
 use crate::error::MessageAccessError;
 use crate::error::MessageAccessError::MissingMessageValue;
+use crate::error::ParsingError;
 use crate::fhir::mapper::EntryRequestType::{ConditionalCreate, Delete, UpdateAsCreate};
 use crate::fhir::mapper::{
-    bundle_entry, get_cc_with_one_code, parse_date, parse_datetime, patch_bundle_entry,
-    upsert_reference,
+    MappingContext, build_usual_identifier, bundle_entry, get_cc_with_one_code, map_vip,
+    parse_date, parse_datetime, patch_bundle_entry, upsert_reference, warn_unknown_code,
 };
+use crate::fhir::names::normalize;
+use crate::fhir::organization::{managing_org_ref, map_managing_org};
+use crate::fhir::resources::ResourceMap;
 use crate::hl7::parser::{
-    MRG_1, MessageType, PID_2, PID_5, PID_7, PID_8, PID_16_1, PID_24, PID_25, PID_29, PID_30,
-    field_repeats, get_message_key, message_type, query, repeat_component, repeat_subcomponents,
-    segment_value,
+    MRG_1, MessageType, PID_2, PID_3, PID_5, PID_6, PID_7, PID_8, PID_13, PID_14, PID_15_1,
+    PID_16_1, PID_17_1, PID_24, PID_25, PID_29, PID_30, field_repeats, get_message_key, query,
+    repeat_component, repeat_subcomponent, segment_value,
 };
 use anyhow::anyhow;
 use fhir_model::BuilderError;
-use fhir_model::r4b::codes::{AddressType, AdministrativeGender, IdentifierUse, NameUse};
+use fhir_model::r4b::codes::{
+    AddressType, AddressUse, AdministrativeGender, ContactPointSystem, ContactPointUse,
+    IdentifierUse, NameUse,
+};
 use fhir_model::r4b::resources::{
-    BundleEntry, ParametersParameter, ParametersParameterValue, PatientDeceased,
-    PatientMultipleBirth, ResourceType,
+    BundleEntry, ParametersParameter, ParametersParameterValue, PatientCommunication,
+    PatientContact, PatientDeceased, PatientMultipleBirth, RelatedPerson, ResourceType,
 };
 use fhir_model::r4b::resources::{Parameters, Patient};
 use fhir_model::r4b::types::{
-    Address, CodeableConcept, Coding, Extension, FieldExtension, Period, Reference,
+    Address, CodeableConcept, Coding, ContactPoint, Extension, FieldExtension, Period, Reference,
 };
 use fhir_model::r4b::types::{ExtensionValue, HumanName};
 use fhir_model::r4b::types::{Identifier, Meta};
 use hl7_parser::Message;
-use hl7_parser::message::Segment;
+use hl7_parser::message::{Repeat, Segment};
 use log::{Level, log, warn};
 use regex::Regex;
 use std::fmt::Debug;
+use std::num::NonZeroU32;
 use std::sync::LazyLock;
 use std::vec;
 
-pub(super) fn map(msg: &Message, config: &Fhir) -> Result<Vec<BundleEntry>, MappingError> {
-    let msg_type = message_type(msg);
+pub(super) fn map(
+    ctx: &MappingContext,
+    config: &Fhir,
+    resources: &ResourceMap,
+) -> Result<Vec<BundleEntry>, MappingError> {
+    let msg = ctx.msg;
+
+    if is_skipped(ctx.message_type(), config) {
+        // the subject reference is resolved by identifier, the patient needs no update. Its
+        // related persons are still mapped.
+        let mut entries = map_related_persons(ctx, config)?;
+        entries.extend(map_employer_persons(ctx, config)?);
+        return Ok(entries);
+    }
 
-    let message_type_value = msg_type.map_err(MessageAccessError::MessageTypeError)?;
-    match message_type_value {
+    match ctx.message_type() {
         MessageType::A01
         | MessageType::A04
         | MessageType::A05
@@ -45,16 +67,24 @@ pub(super) fn map(msg: &Message, config: &Fhir) -> Result<Vec<BundleEntry>, Mapp
         | MessageType::A07
         | MessageType::A08
         => {
-            let patient = map_patient(msg, &config)?;
+            let patient = map_patient(msg, &config, resources)?;
             // update-as-create
-            Ok(vec![bundle_entry(patient, UpdateAsCreate, &config)?])
+            let mut entries = vec![bundle_entry(patient, UpdateAsCreate, &config)?];
+            entries.extend(map_related_persons(ctx, config)?);
+            entries.extend(map_employer_persons(ctx, config)?);
+            entries.extend(map_managing_org(config)?);
+            Ok(entries)
         }
         MessageType::A02 | MessageType::A03 | MessageType::A31 => {
-            let patient = map_patient(msg, &config)?;
+            let patient = map_patient(msg, &config, resources)?;
             // conditional-create
-            Ok(vec![bundle_entry(patient, ConditionalCreate, &config)?])
+            let mut entries = vec![bundle_entry(patient, ConditionalCreate, &config)?];
+            entries.extend(map_related_persons(ctx, config)?);
+            entries.extend(map_employer_persons(ctx, config)?);
+            entries.extend(map_managing_org(config)?);
+            Ok(entries)
         }
-        MessageType::A34 | MessageType::A40 => {
+        MessageType::A34 | MessageType::A40 | MessageType::A47 => {
             // create fhir-patch
             let (identifier, patch) = create_patient_merge(msg, &config)?;
             Ok(vec![patch_bundle_entry(
@@ -75,48 +105,56 @@ pub(super) fn map(msg: &Message, config: &Fhir) -> Result<Vec<BundleEntry>, Mapp
         | MessageType::A22
         | MessageType::A27
         | MessageType::A28
-        | MessageType::A38 => {
+        | MessageType::A38
+        // visit number changed only
+        | MessageType::A50 => {
             // ignore
 
             // A11 & A27 should not create any patient resource
             Ok(vec![])
         }
         MessageType::A29 => {
-            let patient = map_patient(msg, &config)?;
-            // delete
-            Ok(vec![bundle_entry(patient, Delete, &config)?])
+            let mut patient = map_patient(msg, &config, resources)?;
+            match config.person.delete_person_information {
+                PatientDeletionMode::Delete => Ok(vec![bundle_entry(patient, Delete, &config)?]),
+                PatientDeletionMode::Deactivate => {
+                    patient.active = Some(false);
+                    Ok(vec![bundle_entry(patient, UpdateAsCreate, &config)?])
+                }
+            }
         }
         other => Err(MappingError::from(anyhow!("Invalid message type: {other}"))),
     }
 }
 
-fn map_addresses(msg: &Message) -> Result<Vec<Option<Address>>, MappingError> {
+/// Message types configured to skip the `Patient`, e.g. movement-only events with
+/// minimal PID data.
+fn is_skipped(message_type: MessageType, config: &Fhir) -> bool {
+    config.person.skip_message_types.contains(&message_type)
+}
+
+fn map_addresses(
+    msg: &Message,
+    config: &Fhir,
+    resources: &ResourceMap,
+) -> Result<Vec<Option<Address>>, MappingError> {
     let mut res = vec![];
 
     if let Some(addr_repeats) = field_repeats(msg, "PID.11") {
         for addr_elem in addr_repeats {
-            let mut addr = Address::builder().r#type(AddressType::Both).build()?;
-
-            // line
-            if let Some(lines) = repeat_subcomponents(addr_elem, 1) {
-                addr.line = lines.into_iter().map(|l| Some(l.to_string())).collect();
-            }
-            // city
-            if let Some(city) = repeat_component(addr_elem, 3) {
-                addr.city = Some(city.to_string());
-            }
-            // postal code
-            if let Some(postal_code) = repeat_component(addr_elem, 5) {
-                addr.postal_code = Some(postal_code.to_string());
-            }
-            // country
-            if let Some(country) = repeat_component(addr_elem, 6) {
-                addr.country = Some(country.to_string());
-            }
-
-            if !addr.line.is_empty() && addr.line.iter().all(|l| l.is_some()) && addr.city.is_some()
+            // XAD-7 address type
+            if let Some(code) = repeat_component(addr_elem, 7)
+                && address_use(code).is_none()
             {
-                // street must have at least 1 line and city must also have a value
+                warn_unknown_code(msg, config, "PID.11.7", code);
+            }
+            if let Some(mut addr) = map_address(addr_elem)? {
+                if addr.country.is_none() {
+                    addr.country = config.person.defaults.country.clone();
+                }
+                if let Some(key) = config.person.municipality_key {
+                    map_municipality(&mut addr, addr_elem, key, resources)?;
+                }
                 res.push(Some(addr));
             }
         }
@@ -125,6 +163,452 @@ fn map_addresses(msg: &Message) -> Result<Vec<Option<Address>>, MappingError> {
     Ok(res)
 }
 
+/// Amtlicher Gemeindeschlüssel (`destatis/ags` extension of `Address.city`) and district of the
+/// address, looked up by postal code or county code (XAD-9).
+fn map_municipality(
+    addr: &mut Address,
+    addr_elem: &Repeat,
+    key: MunicipalityKey,
+    resources: &ResourceMap,
+) -> Result<(), MappingError> {
+    let value = match key {
+        MunicipalityKey::PostalCode => addr.postal_code.as_deref(),
+        MunicipalityKey::CountyCode => repeat_component(addr_elem, 9),
+    };
+    let Some(municipality) = value.and_then(|v| resources.municipality_map.get(v)) else {
+        return Ok(());
+    };
+
+    if addr.district.is_none() {
+        addr.district = municipality.district.clone();
+    }
+    let ags = Extension::builder()
+        .url("http://fhir.de/StructureDefinition/destatis/ags".to_string())
+        .value(ExtensionValue::Coding(
+            Coding::builder()
+                .system("http://fhir.de/sid/destatis/ags".to_string())
+                .code(municipality.gemeindeschluessel.clone())
+                .build()?,
+        ))
+        .build()?;
+    addr.city_ext = Some(FieldExtension::builder().extension(vec![ags]).build()?);
+
+    Ok(())
+}
+
+/// Use and type of an XAD-7 address type (HL7 table 0190), [`None`] if the code is unknown.
+fn address_use(code: &str) -> Option<(Option<AddressUse>, AddressType)> {
+    match code {
+        // home, permanent, legal and registry home address
+        "H" | "P" | "L" | "RH" => Some((Some(AddressUse::Home), AddressType::Both)),
+        // firm/business and office
+        "B" | "O" => Some((Some(AddressUse::Work), AddressType::Both)),
+        // current or temporary and vacation
+        "C" | "V" => Some((Some(AddressUse::Temp), AddressType::Both)),
+        "BA" => Some((Some(AddressUse::Old), AddressType::Both)),
+        "BI" => Some((Some(AddressUse::Billing), AddressType::Postal)),
+        "M" => Some((None, AddressType::Postal)),
+        // birth delivery location, residence at birth, birth (nee) and country of origin
+        "BDL" | "BR" | "N" | "F" => Some((None, AddressType::Physical)),
+        _ => None,
+    }
+}
+
+fn map_address(addr_elem: &Repeat) -> Result<Option<Address>, MappingError> {
+    let (address_use, address_type) = repeat_component(addr_elem, 7)
+        .and_then(address_use)
+        .unwrap_or((None, AddressType::Both));
+    let mut addr = Address::builder().r#type(address_type).build()?;
+    addr.r#use = address_use;
+
+    // line
+    if let Some((line, line_ext)) = map_address_line(addr_elem)? {
+        addr.line = vec![Some(line)];
+        addr.line_ext = vec![line_ext];
+    }
+    // city
+    if let Some(city) = repeat_component(addr_elem, 3) {
+        addr.city = Some(city.to_string());
+    }
+    // postal code
+    if let Some(postal_code) = repeat_component(addr_elem, 5) {
+        addr.postal_code = Some(postal_code.to_string());
+    }
+    // country
+    if let Some(country) = repeat_component(addr_elem, 6) {
+        addr.country = Some(country.to_string());
+    }
+
+    // street must have a line and city must also have a value
+    if !addr.line.is_empty() && addr.city.is_some() {
+        Ok(Some(addr))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Street line (XAD-1) with the street name and house number extensions (MII Person). These are
+/// taken from SAD-2 and SAD-3, or parsed from the street address (SAD-1).
+fn map_address_line(
+    addr_elem: &Repeat,
+) -> Result<Option<(String, Option<FieldExtension>)>, MappingError> {
+    let street_name = repeat_subcomponent(addr_elem, 1, 2);
+    let house_number = repeat_subcomponent(addr_elem, 1, 3);
+    let Some(line) = repeat_subcomponent(addr_elem, 1, 1)
+        .map(String::from)
+        .or_else(|| {
+            street_name.map(|s| match house_number {
+                Some(n) => format!("{s} {n}"),
+                None => s.to_string(),
+            })
+        })
+    else {
+        return Ok(None);
+    };
+
+    let (street_name, house_number) = match street_name {
+        Some(street_name) => (Some(street_name), house_number),
+        None => split_street(&line).unzip(),
+    };
+    let line_ext = [
+        (STREET_NAME_URL, street_name),
+        (HOUSE_NUMBER_URL, house_number),
+    ]
+    .into_iter()
+    .filter_map(|(url, value)| {
+        value.map(|v| {
+            Extension::builder()
+                .url(url.to_string())
+                .value(ExtensionValue::String(v.to_string()))
+                .build()
+        })
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+    if line_ext.is_empty() {
+        return Ok(Some((line, None)));
+    }
+
+    Ok(Some((
+        line,
+        Some(FieldExtension::builder().extension(line_ext).build()?),
+    )))
+}
+
+const STREET_NAME_URL: &str = "http://hl7.org/fhir/StructureDefinition/iso21090-ADXP-streetName";
+const HOUSE_NUMBER_URL: &str = "http://hl7.org/fhir/StructureDefinition/iso21090-ADXP-houseNumber";
+
+/// Street name and house number of a street address (e.g. `Hauptstr. 12a`), [`None`] if there's
+/// no trailing house number or the address is a post box.
+fn split_street(line: &str) -> Option<(&str, &str)> {
+    static RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^(?<street>.*\S)\s+(?<number>\d+\s?[a-zA-Z]?(\s?[-/]\s?\d+\s?[a-zA-Z]?)?)$")
+            .unwrap()
+    });
+    if line.to_lowercase().starts_with("postfach") {
+        return None;
+    }
+    let captures = RE.captures(line.trim())?;
+
+    Some((
+        captures.name("street")?.as_str(),
+        captures.name("number")?.as_str(),
+    ))
+}
+
+/// Kontaktpersonen (NK1)
+struct Contact {
+    set_id: Option<String>,
+    relationship: Option<CodeableConcept>,
+    name: Option<HumanName>,
+    telecom: Vec<Option<ContactPoint>>,
+    address: Option<Address>,
+}
+
+fn map_contacts(
+    msg: &Message,
+    normalization: &NameNormalizationConfig,
+) -> Result<Vec<Contact>, MappingError> {
+    msg.segments()
+        .filter(|s| s.name == "NK1")
+        .map(|nk1| map_contact(nk1, normalization))
+        .collect()
+}
+
+fn map_contact(
+    nk1: &Segment,
+    normalization: &NameNormalizationConfig,
+) -> Result<Contact, MappingError> {
+    // NK1-2 name
+    let name = match (segment_value(nk1, 2, 1, 1), segment_value(nk1, 2, 1, 2)) {
+        (None, None) => None,
+        (family, given) => {
+            let mut name = HumanName::builder()
+                .given(
+                    given
+                        .map(|g| vec![Some(normalize(g, normalization))])
+                        .unwrap_or_default(),
+                )
+                .build()?;
+            name.family = family.map(|f| normalize(f, normalization));
+            Some(name)
+        }
+    };
+
+    // NK1-7 contact role (HL7 table 0131), NK1-3 relationship as text
+    let relationship = match (segment_value(nk1, 7, 1, 1), segment_value(nk1, 3, 1, 2)) {
+        (None, None) => None,
+        (role, text) => {
+            let mut relationship = CodeableConcept::builder()
+                .coding(
+                    role.map(|r| {
+                        Coding::builder()
+                            .system("http://terminology.hl7.org/CodeSystem/v2-0131".to_string())
+                            .code(r.to_string())
+                            .build()
+                    })
+                    .transpose()?
+                    .map(|c| vec![Some(c)])
+                    .unwrap_or_default(),
+                )
+                .build()?;
+            relationship.text = text.map(String::from);
+            Some(relationship)
+        }
+    };
+
+    // NK1-5 phone, placeholders like 's.Pat.' are skipped
+    let telecom = nk1
+        .field(5)
+        .into_iter()
+        .flat_map(|f| f.repeats())
+        .filter_map(|r| map_xtn(r, None))
+        .map(|(system, contact_use, value)| -> Result<_, MappingError> {
+            let mut contact_point = ContactPoint::builder()
+                .system(system)
+                .value(value)
+                .build()?;
+            contact_point.r#use = contact_use;
+            Ok(Some(contact_point))
+        })
+        .collect::<Result<_, _>>()?;
+
+    // NK1-4 address
+    let address = match nk1.field(4).and_then(|f| f.repeat(1)) {
+        Some(addr_elem) => map_address(addr_elem)?,
+        None => None,
+    };
+
+    Ok(Contact {
+        set_id: segment_value(nk1, 1, 1, 1).map(String::from),
+        relationship,
+        name,
+        telecom,
+        address,
+    })
+}
+
+fn map_patient_contacts(contacts: &[Contact]) -> Result<Vec<Option<PatientContact>>, MappingError> {
+    contacts
+        .iter()
+        .map(|c| {
+            let mut contact = PatientContact::builder()
+                .relationship(c.relationship.iter().cloned().map(Some).collect())
+                .telecom(c.telecom.clone())
+                .build()?;
+            contact.name = c.name.clone();
+            contact.address = c.address.clone();
+            Ok(Some(contact))
+        })
+        .collect()
+}
+
+/// Kontaktpersonen als eigenständige `RelatedPerson` Ressourcen
+fn map_related_persons(
+    ctx: &MappingContext,
+    config: &Fhir,
+) -> Result<Vec<BundleEntry>, MappingError> {
+    if !config.person.contact.related_person {
+        return Ok(vec![]);
+    }
+    let pid = ctx
+        .patient_id()
+        .ok_or(MissingMessageValue("PID.2".to_string()))?;
+
+    map_contacts(ctx.msg, &config.person.name_normalization)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let set_id = c.set_id.unwrap_or_else(|| (i + 1).to_string());
+            let mut person = RelatedPerson::builder()
+                .meta(
+                    Meta::builder()
+                        .source(config.meta_source.to_string())
+                        .build()?,
+                )
+                .identifier(vec![Some(build_usual_identifier(
+                    vec![pid, set_id.as_str()],
+                    config.person.contact.system.to_string(),
+                )?)])
+                .patient(ctx.subject_ref(&config.person.system)?)
+                .relationship(c.relationship.into_iter().map(Some).collect())
+                .name(c.name.into_iter().map(Some).collect())
+                .telecom(c.telecom)
+                .address(c.address.into_iter().map(Some).collect())
+                .build()?;
+            person.active = Some(true);
+
+            bundle_entry(person, UpdateAsCreate, config)
+        })
+        .collect()
+}
+
+/// Arbeitgeber des Garanten (GT1)
+struct Employer {
+    set_id: Option<String>,
+    name: Option<HumanName>,
+    address: Option<Address>,
+}
+
+fn map_employers(
+    msg: &Message,
+    normalization: &NameNormalizationConfig,
+) -> Result<Vec<Employer>, MappingError> {
+    msg.segments()
+        .filter(|s| s.name == "GT1")
+        .map(|gt1| map_employer(gt1, normalization))
+        .filter_map(Result::transpose)
+        .collect()
+}
+
+fn map_employer(
+    gt1: &Segment,
+    normalization: &NameNormalizationConfig,
+) -> Result<Option<Employer>, MappingError> {
+    // GT1-16 employer name
+    let name = match (segment_value(gt1, 16, 1, 1), segment_value(gt1, 16, 1, 2)) {
+        (None, None) => None,
+        (family, given) => {
+            let mut name = HumanName::builder()
+                .given(
+                    given
+                        .map(|g| vec![Some(normalize(g, normalization))])
+                        .unwrap_or_default(),
+                )
+                .build()?;
+            name.family = family.map(|f| normalize(f, normalization));
+            Some(name)
+        }
+    };
+    // GT1-17 employer address
+    let address = match gt1.field(17).and_then(|f| f.repeat(1)) {
+        Some(addr_elem) => map_address(addr_elem)?,
+        None => None,
+    };
+    if name.is_none() && address.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(Employer {
+        set_id: segment_value(gt1, 1, 1, 1).map(String::from),
+        name,
+        address,
+    }))
+}
+
+/// Guarantor employers as complex `Patient` extensions, if enabled.
+fn map_employer_extensions(msg: &Message, config: &Fhir) -> Result<Vec<Extension>, MappingError> {
+    let Some(employer) = config
+        .person
+        .employer
+        .as_ref()
+        .filter(|e| e.target == EmployerTarget::Extension)
+    else {
+        return Ok(vec![]);
+    };
+
+    map_employers(msg, &config.person.name_normalization)?
+        .into_iter()
+        .map(|e| -> Result<Extension, MappingError> {
+            let mut parts = vec![];
+            if let Some(name) = e.name {
+                parts.push(
+                    Extension::builder()
+                        .url("name".to_string())
+                        .value(ExtensionValue::HumanName(name))
+                        .build()?,
+                );
+            }
+            if let Some(address) = e.address {
+                parts.push(
+                    Extension::builder()
+                        .url("address".to_string())
+                        .value(ExtensionValue::Address(address))
+                        .build()?,
+                );
+            }
+
+            Ok(Extension::builder()
+                .url(employer.url.clone())
+                .extension(parts)
+                .build()?)
+        })
+        .collect()
+}
+
+/// Guarantor employers as `RelatedPerson` resources, if enabled.
+fn map_employer_persons(
+    ctx: &MappingContext,
+    config: &Fhir,
+) -> Result<Vec<BundleEntry>, MappingError> {
+    let Some(employer) = config
+        .person
+        .employer
+        .as_ref()
+        .filter(|e| e.target == EmployerTarget::RelatedPerson)
+    else {
+        return Ok(vec![]);
+    };
+    let pid = ctx
+        .patient_id()
+        .ok_or(MissingMessageValue("PID.2".to_string()))?;
+
+    map_employers(ctx.msg, &config.person.name_normalization)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, e)| {
+            let set_id = e.set_id.unwrap_or_else(|| (i + 1).to_string());
+            let mut person = RelatedPerson::builder()
+                .meta(
+                    Meta::builder()
+                        .source(config.meta_source.to_string())
+                        .build()?,
+                )
+                .identifier(vec![Some(build_usual_identifier(
+                    vec![pid, "employer", set_id.as_str()],
+                    employer.system.to_string(),
+                )?)])
+                .patient(ctx.subject_ref(&config.person.system)?)
+                .relationship(vec![Some(
+                    CodeableConcept::builder()
+                        .coding(vec![Some(
+                            Coding::builder()
+                                .system("http://terminology.hl7.org/CodeSystem/v2-0131".to_string())
+                                .code("E".to_string())
+                                .display("Employer".to_string())
+                                .build()?,
+                        )])
+                        .build()?,
+                )])
+                .name(e.name.into_iter().map(Some).collect())
+                .address(e.address.into_iter().map(Some).collect())
+                .build()?;
+            person.active = Some(true);
+
+            bundle_entry(person, UpdateAsCreate, config)
+        })
+        .collect()
+}
+
 fn create_patient_merge(
     msg: &Message,
     config: &Fhir,
@@ -166,6 +650,7 @@ fn create_patient_merge(
                                                 .reference(upsert_reference(
                                                     &ResourceType::Patient,
                                                     &create_patient_identifier(msg, config)?,
+                                                    config,
                                                 )?)
                                                 .r#type(ResourceType::Patient.to_string())
                                                 .build()?,
@@ -235,7 +720,7 @@ fn create_patient_identifier(msg: &Message, config: &Fhir) -> Result<Identifier,
 ///
 /// _Hinweis:_ Es gibt HL7 Nachrichten, die in denen IN1 Segmente fehlen.
 ///
-fn create_patient_identifiers(
+pub(crate) fn create_patient_identifiers(
     msg: &Message,
     config: &Fhir,
 ) -> Result<Vec<Option<Identifier>>, MappingError> {
@@ -254,7 +739,6 @@ fn create_patient_identifiers(
 
     // first pick is insurance number of 10 literals without expiration date
     // second pick is first number without expiration date
-    const GKV10_SYSTEM: &str = "http://fhir.de/sid/gkv/kvid-10";
     let selected = ids
         .iter()
         .find(|v| {
@@ -271,53 +755,256 @@ fn create_patient_identifiers(
         identifiers.push(Some(id));
     }
 
+    // PID-3 identifiers, which are not already present
+    for id in map_identifier_list(msg, config)? {
+        if !identifiers
+            .iter()
+            .flatten()
+            .any(|i| i.system == id.system && i.value == id.value)
+        {
+            identifiers.push(Some(id));
+        }
+    }
+
     Ok(identifiers)
 }
 
-fn map_patient(msg: &Message, config: &Fhir) -> Result<Patient, MappingError> {
+/// Identifiers of the repeating PID-3 with a configured identifier type code (PID-3.5).
+///
+/// Values of the GKV system (10-digit KVNR) are validated.
+fn map_identifier_list(msg: &Message, config: &Fhir) -> Result<Vec<Identifier>, MappingError> {
+    let Some(repeats) = field_repeats(msg, PID_3) else {
+        return Ok(vec![]);
+    };
+
+    repeats
+        .filter_map(|r| {
+            let value = repeat_component(r, 1)?;
+            let type_code = repeat_component(r, 5)?;
+            let id_type = config
+                .person
+                .identifier_types
+                .iter()
+                .find(|t| t.code == type_code)?;
+            if id_type.system == GKV10_SYSTEM && !is_valid_gkv10(value) {
+                warn!("Invalid KVNR in PID-3 with identifier type {type_code}");
+                return None;
+            }
+            Some((value, id_type))
+        })
+        .map(|(value, id_type)| -> Result<Identifier, MappingError> {
+            Identifier::builder()
+                .r#use(IdentifierUse::Official)
+                .system(id_type.system.clone())
+                .value(value.to_string())
+                .r#type(get_cc_with_one_code(
+                    id_type.type_code.clone(),
+                    id_type.type_system.clone(),
+                )?)
+                .build()
+                .map_err(MappingError::from)
+        })
+        .collect()
+}
+
+/// Home (PID-13) and business (PID-14) phone numbers and email addresses, ranked in order.
+fn map_telecom(msg: &Message) -> Result<Vec<Option<ContactPoint>>, MappingError> {
+    [
+        (PID_13, ContactPointUse::Home),
+        (PID_14, ContactPointUse::Work),
+    ]
+    .into_iter()
+    .flat_map(|(field, contact_use)| {
+        field_repeats(msg, field)
+            .into_iter()
+            .flatten()
+            .filter_map(move |r| map_xtn(r, Some(contact_use)))
+    })
+    .zip(1..)
+    .map(
+        |((system, contact_use, value), rank)| -> Result<_, MappingError> {
+            let mut contact_point = ContactPoint::builder()
+                .system(system)
+                .value(value)
+                .rank(NonZeroU32::new(rank).expect("rank starts at 1"))
+                .build()?;
+            contact_point.r#use = contact_use;
+            Ok(Some(contact_point))
+        },
+    )
+    .collect()
+}
+
+/// System, use and value of an XTN repeat. Phone numbers without digits (placeholders) are
+/// skipped.
+fn map_xtn(
+    repeat: &Repeat,
+    contact_use: Option<ContactPointUse>,
+) -> Option<(ContactPointSystem, Option<ContactPointUse>, String)> {
+    // XTN-2 telecommunication use code, XTN-3 equipment type
+    let use_code = repeat_component(repeat, 2);
+    let equipment = repeat_component(repeat, 3);
+
+    if use_code == Some("NET") || matches!(equipment, Some("Internet" | "X.400")) {
+        // XTN-4 communication address
+        let email = repeat_component(repeat, 4).or(repeat_component(repeat, 1))?;
+        return Some((ContactPointSystem::Email, contact_use, email.to_string()));
+    }
+
+    // XTN-1 (deprecated), XTN-12 unformatted or XTN-6 area code and XTN-7 local number
+    let number = repeat_component(repeat, 1)
+        .map(String::from)
+        .or(repeat_component(repeat, 12).map(String::from))
+        .or_else(|| {
+            let local = repeat_component(repeat, 7)?;
+            Some(match repeat_component(repeat, 6) {
+                Some(area) => format!("{area} {local}"),
+                None => local.to_string(),
+            })
+        })
+        .filter(|n| n.chars().any(|c| c.is_ascii_digit()))?;
+
+    match equipment {
+        Some("FX") => Some((ContactPointSystem::Fax, contact_use, number)),
+        Some("BP") => Some((ContactPointSystem::Pager, contact_use, number)),
+        Some("CP") => Some((
+            ContactPointSystem::Phone,
+            Some(ContactPointUse::Mobile),
+            number,
+        )),
+        _ => Some((ContactPointSystem::Phone, contact_use, number)),
+    }
+}
+
+fn map_patient(
+    msg: &Message,
+    config: &Fhir,
+    resources: &ResourceMap,
+) -> Result<Patient, MappingError> {
     // patient resource
     let mut patient = Patient::builder()
         .meta(
             Meta::builder()
                 .profile(vec![Some(config.person.profile.to_owned())])
                 .source(config.meta_source.to_string())
+                .security(map_vip(msg, config)?.security)
                 .build()?,
         )
         .identifier(create_patient_identifiers(msg, config)?)
-        .address(map_addresses(msg)?)
-        .name(map_name(msg)?)
+        .address(map_addresses(msg, config, resources)?)
+        .name(map_name(msg, &config.person.name_normalization)?)
+        .telecom(map_telecom(msg)?)
         .build()?;
 
     // birth_date
-    if let Some(b) = query(msg, PID_7) {
-        patient.birth_date = Some(parse_date(b)?)
+    match query(msg, PID_7).map(|b| (b, parse_date(b))) {
+        Some((b, Ok(birth_date))) => {
+            patient.birth_date = Some(birth_date);
+            patient.birth_date_ext = map_birth_time(b)?;
+        }
+        Some((b, Err(e))) => {
+            warn!(
+                "MSG-ID {}: invalid birth date '{b}' at {PID_7}: {e}",
+                get_message_key(msg)?
+            );
+            patient.birth_date_ext = Some(data_absent_reason("error")?);
+        }
+        None => patient.birth_date_ext = Some(data_absent_reason("unknown")?),
     }
     // gender
     if let Some(g) = query(msg, PID_8) {
-        patient.gender = Some(map_gender(g));
+        let gender = map_gender(g);
+        if matches!(gender, AdministrativeGender::Unknown) {
+            warn_unknown_code(msg, config, PID_8, g);
+        }
+        patient.gender = Some(gender);
+        patient.gender_ext = map_gender_amtlich(g)?;
+    }
+    if patient.gender.is_none() {
+        patient.gender = config.person.defaults.gender.map(|g| match g {
+            DefaultGender::Male => AdministrativeGender::Male,
+            DefaultGender::Female => AdministrativeGender::Female,
+            DefaultGender::Other => AdministrativeGender::Other,
+            DefaultGender::Unknown => AdministrativeGender::Unknown,
+        });
     }
     // marital_status
-    patient.marital_status = map_marital_status(msg)?;
+    patient.marital_status = map_marital_status(msg, config)?;
+    // primary language
+    patient.communication = map_communication(msg, config)?;
+    // guarantor employer, if enabled
+    patient
+        .extension
+        .append(&mut map_employer_extensions(msg, config)?);
+    // religion, if enabled
+    if let Some(religion) = map_religion(msg, config)? {
+        patient.extension.push(religion);
+    }
     // deceased flag
-    patient.deceased = map_deceased(msg)?;
+    patient.deceased = map_deceased(msg, config)?;
+    patient.managing_organization = managing_org_ref(config)?;
 
     patient.multiple_birth = map_multiple_birth(msg)?;
+    // next of kin
+    patient.contact = map_patient_contacts(&map_contacts(msg, &config.person.name_normalization)?)?;
 
     Ok(patient)
 }
 
-pub fn map_deceased(msg: &Message) -> Result<Option<PatientDeceased>, MappingError> {
+/// Vital status of the patient by the configured [`DeceasedRule`]. An invalid date of death
+/// (PID-29) is logged and mapped as deceased flag.
+pub fn map_deceased(msg: &Message, config: &Fhir) -> Result<Option<PatientDeceased>, MappingError> {
     // patient vital status
     let death_time = query(msg, PID_29);
     let death_confirm = query(msg, PID_30);
+    let deceased = |death_time: &str| -> Result<PatientDeceased, MappingError> {
+        match parse_death_time(death_time) {
+            Ok(death_time) => Ok(PatientDeceased::DateTime(death_time)),
+            Err(e) => {
+                warn!(
+                    "MSG-ID {}: invalid date of death '{death_time}' at {PID_29}: {e}",
+                    get_message_key(msg)?
+                );
+                Ok(PatientDeceased::Boolean(true))
+            }
+        }
+    };
 
-    match (death_time, death_confirm) {
-        (Some(death_time), _) => Ok(Some(PatientDeceased::DateTime(parse_datetime(death_time)?))),
-        (None, Some(confirm)) => Ok(Some(PatientDeceased::Boolean(confirm == "Y"))),
+    match (config.person.deceased_rule, death_time, death_confirm) {
+        (DeceasedRule::Date, Some(death_time), _) => Ok(Some(deceased(death_time)?)),
+        (DeceasedRule::Indicator, Some(death_time), Some("Y")) => Ok(Some(deceased(death_time)?)),
+        (_, _, Some(confirm)) => Ok(Some(PatientDeceased::Boolean(confirm == "Y"))),
         _ => Ok(None),
     }
 }
 
+/// Date of death with minute (`YYYYMMDDHHMM`, seconds are ignored) or day precision.
+fn parse_death_time(death_time: &str) -> Result<fhir_model::DateTime, ParsingError> {
+    match death_time.get(..12) {
+        Some(minutes) => parse_datetime(minutes),
+        None => Ok(fhir_model::DateTime::Date(parse_date(death_time)?)),
+    }
+}
+
+/// `data-absent-reason` extension of a missing value.
+fn data_absent_reason(reason: &str) -> Result<FieldExtension, BuilderError> {
+    field_extension(
+        "http://hl7.org/fhir/StructureDefinition/data-absent-reason".to_string(),
+        ExtensionValue::Code(reason.to_string()),
+    )
+}
+
+/// Birth time extension, if PID-7 has at least minute precision (YYYYMMDDHHMM).
+fn map_birth_time(pid_7: &str) -> Result<Option<FieldExtension>, MappingError> {
+    match pid_7.get(..12) {
+        Some(birth_time) => Ok(Some(field_extension(
+            "http://hl7.org/fhir/StructureDefinition/patient-birthTime".into(),
+            ExtensionValue::DateTime(parse_datetime(birth_time)?),
+        )?)),
+        None => Ok(None),
+    }
+}
+
 fn map_multiple_birth(msg: &Message) -> Result<Option<PatientMultipleBirth>, MappingError> {
     let is_multi_birth = query(msg, PID_24);
     let multi_birth_number = query(msg, PID_25);
@@ -384,71 +1071,153 @@ fn map_multiple_birth(msg: &Message) -> Result<Option<PatientMultipleBirth>, Map
     }
 }
 
-fn map_marital_status(msg: &Message) -> Result<Option<CodeableConcept>, MappingError> {
-    // marital status
-    query(msg, PID_16_1)
-        .map(|status| {
-            match status {
-                "A" | "E" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("L".to_string())
-                    .display("Legally Separated".to_string())
-                    .build(),
-                "D" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("D".to_string())
-                    .display("Divorced".to_string())
-                    .build(),
-                "M" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("M".to_string())
-                    .display("Married".to_string())
-                    .build(),
-                "S" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("S".to_string())
-                    .display("Never Married".to_string())
-                    .build(),
-                "W" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("W".to_string())
-                    .display("Widowed".to_string())
-                    .build(),
-                "C" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("C".to_string())
-                    .display("Common Law".to_string())
-                    .build(),
-                "G" | "P" | "R" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("T".to_string())
-                    .display("Domestic partner".to_string())
-                    .build(),
-                "N" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("A".to_string())
-                    .display("Annulled".to_string())
-                    .build(),
-                "I" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("I".to_string())
-                    .display("Interlocutory".to_string())
-                    .build(),
-                "B" => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
-                    .code("U".to_string())
-                    .display("Unmarried".to_string())
-                    .build(),
-                _a => Coding::builder()
-                    .system("http://terminology.hl7.org/CodeSystem/v3-NullFlavor".to_string())
-                    .code("UNK".to_string())
-                    .display("Unknown".to_string())
-                    .build(),
+/// Primary language (PID-15) as preferred `Patient.communication`. Local codes are translated,
+/// ISO 639-1 codes (e.g. `de`) are taken as they are.
+fn map_communication(
+    msg: &Message,
+    config: &Fhir,
+) -> Result<Vec<Option<PatientCommunication>>, MappingError> {
+    let Some(code) = query(msg, PID_15_1) else {
+        return Ok(vec![]);
+    };
+    let language = match config.person.languages.iter().find(|l| l.code == code) {
+        Some(l) => l.language.clone(),
+        None if code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()) => {
+            code.to_ascii_lowercase()
+        }
+        None => {
+            warn_unknown_code(msg, config, PID_15_1, code);
+            return Ok(vec![]);
+        }
+    };
+
+    Ok(vec![Some(
+        PatientCommunication::builder()
+            .language(get_cc_with_one_code(
+                language,
+                "urn:ietf:bcp:47".to_string(),
+            )?)
+            .preferred(true)
+            .build()?,
+    )])
+}
+
+/// Religion (PID-17) as `patient-religion` extension (v3-ReligiousAffiliation), if enabled by
+/// [`crate::config::ReligionConfig`]. Local codes are translated, v3 codes (e.g. `1041`) are
+/// taken as they are.
+fn map_religion(msg: &Message, config: &Fhir) -> Result<Option<Extension>, MappingError> {
+    let Some(religion) = &config.person.religion else {
+        return Ok(None);
+    };
+    let Some(value) = query(msg, PID_17_1) else {
+        return Ok(None);
+    };
+
+    let mut coding = Coding::builder()
+        .system("http://terminology.hl7.org/CodeSystem/v3-ReligiousAffiliation".to_string())
+        .build()?;
+    match religion.codes.iter().find(|c| c.code == value) {
+        Some(c) => {
+            coding.code = Some(c.religion.clone());
+            coding.display = c.display.clone();
+        }
+        None if value.len() == 4 && value.chars().all(|c| c.is_ascii_digit()) => {
+            coding.code = Some(value.to_string());
+        }
+        None => {
+            warn_unknown_code(msg, config, PID_17_1, value);
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(
+        Extension::builder()
+            .url("http://hl7.org/fhir/StructureDefinition/patient-religion".to_string())
+            .value(ExtensionValue::CodeableConcept(
+                CodeableConcept::builder()
+                    .coding(vec![Some(coding)])
+                    .build()?,
+            ))
+            .build()?,
+    ))
+}
+
+/// Marital status (PID-16) as v3-MaritalStatus. Local codes are translated by
+/// [`crate::config::MaritalStatusConfig`], otherwise HL7 table 0002 is expected.
+fn map_marital_status(
+    msg: &Message,
+    config: &Fhir,
+) -> Result<Option<CodeableConcept>, MappingError> {
+    let Some(status) = query(msg, PID_16_1) else {
+        return Ok(None);
+    };
+
+    let code = match config
+        .person
+        .marital_statuses
+        .iter()
+        .find(|m| m.code == status)
+    {
+        Some(m) => m.marital_status.as_str(),
+        None => match status {
+            "A" | "E" => "L",
+            "D" => "D",
+            "M" => "M",
+            "S" => "S",
+            "W" => "W",
+            "C" => "C",
+            "G" | "P" | "R" => "T",
+            "N" => "A",
+            "I" => "I",
+            "B" => "U",
+            _ => {
+                warn_unknown_code(msg, config, PID_16_1, status);
+                return Ok(Some(
+                    CodeableConcept::builder()
+                        .coding(vec![Some(
+                            Coding::builder()
+                                .system(
+                                    "http://terminology.hl7.org/CodeSystem/v3-NullFlavor"
+                                        .to_string(),
+                                )
+                                .code("UNK".to_string())
+                                .display("Unknown".to_string())
+                                .build()?,
+                        )])
+                        .build()?,
+                ));
             }
-            .and_then(|c| CodeableConcept::builder().coding(vec![Some(c)]).build())
-            .map_err(MappingError::from)
-        })
-        .transpose()
+        },
+    };
+
+    let mut coding = Coding::builder()
+        .system("http://terminology.hl7.org/CodeSystem/v3-MaritalStatus".to_string())
+        .code(code.to_string())
+        .build()?;
+    coding.display = marital_status_display(code).map(String::from);
+
+    Ok(Some(
+        CodeableConcept::builder()
+            .coding(vec![Some(coding)])
+            .build()?,
+    ))
+}
+
+fn marital_status_display(code: &str) -> Option<&'static str> {
+    match code {
+        "A" => Some("Annulled"),
+        "C" => Some("Common Law"),
+        "D" => Some("Divorced"),
+        "I" => Some("Interlocutory"),
+        "L" => Some("Legally Separated"),
+        "M" => Some("Married"),
+        "P" => Some("Polygamous"),
+        "S" => Some("Never Married"),
+        "T" => Some("Domestic partner"),
+        "U" => Some("Unmarried"),
+        "W" => Some("Widowed"),
+        _ => None,
+    }
 }
 
 fn map_gender(gender: &str) -> AdministrativeGender {
@@ -456,15 +1225,42 @@ fn map_gender(gender: &str) -> AdministrativeGender {
         "F" => AdministrativeGender::Female,
         "M" => AdministrativeGender::Male,
         "U" => AdministrativeGender::Other,
+        // divers, unbestimmt
+        "D" | "X" => AdministrativeGender::Other,
         _ => AdministrativeGender::Unknown,
     }
 }
 
-fn map_name(v2_msg: &Message) -> Result<Vec<Option<HumanName>>, MappingError> {
+/// `gender-amtlich-de` extension of the gender `other` (German base profile) for divers (`D`) and
+/// unbestimmt (`X`).
+fn map_gender_amtlich(gender: &str) -> Result<Option<FieldExtension>, MappingError> {
+    let display = match gender {
+        "D" => "divers",
+        "X" => "unbestimmt",
+        _ => return Ok(None),
+    };
+
+    Ok(Some(field_extension(
+        "http://fhir.de/StructureDefinition/gender-amtlich-de".to_string(),
+        ExtensionValue::Coding(
+            Coding::builder()
+                .system("http://fhir.de/CodeSystem/gender-amtlich-de".to_string())
+                .code(gender.to_string())
+                .display(display.to_string())
+                .build()?,
+        ),
+    )?))
+}
+
+fn map_name(
+    v2_msg: &Message,
+    normalization: &NameNormalizationConfig,
+) -> Result<Vec<Option<HumanName>>, MappingError> {
     let mut names = vec![];
 
     if let Some(name_fields) = field_repeats(v2_msg, PID_5) {
         for name_field in name_fields {
+            let part = |c| repeat_component(name_field, c).map(|v| normalize(v, normalization));
             let name_use = repeat_component(name_field, 7).and_then(|u| match u {
                 "L" => Some(NameUse::Official),
                 "M" | "B" => Some(NameUse::Maiden),
@@ -472,48 +1268,154 @@ fn map_name(v2_msg: &Message) -> Result<Vec<Option<HumanName>>, MappingError> {
             });
 
             let mut name = HumanName::builder()
-                .given(
-                    repeat_component(name_field, 2)
-                        .map(|e| vec![Some(e.to_string())])
-                        .unwrap_or_default(),
-                )
+                .given(part(2).map(|e| vec![Some(e)]).unwrap_or_default())
                 .build()?;
 
             name.r#use = name_use;
-            name.family = repeat_component(name_field, 1).map(String::from);
 
-            // prefix
-            if let Some(prefix) = repeat_component(name_field, 6) {
-                name.prefix = vec![Some(prefix.to_string())];
-                name.prefix_ext = vec![Some(field_extension(
+            // academic titles (PID-5.6, or PID-5.5 like 'Dr.')
+            let (title, vorsatzwort) = match part(5) {
+                Some(p) if is_academic_title(&p) => (Some(p), None),
+                p => (None, p),
+            };
+            for title in title.into_iter().chain(part(6)) {
+                name.prefix.push(Some(title));
+                name.prefix_ext.push(Some(field_extension(
                     "http://hl7.org/fhir/StructureDefinition/iso21090-EN-qualifier".into(),
                     ExtensionValue::Code("AC".into()),
-                )?)];
+                )?));
             }
 
-            // namenszusatz
-            if let Some(namenszusatz) = repeat_component(name_field, 4) {
-                name.family_ext = Some(field_extension(
-                    "http://fhir.de/StructureDefinition/humanname-namenszusatz".into(),
-                    ExtensionValue::String(namenszusatz.to_string()),
-                )?);
+            if let Some(family) = part(1) {
+                let namenszusatz = part(4);
+                // e.g. 'Graf von Test'
+                name.family = Some(
+                    [
+                        namenszusatz.as_deref(),
+                        vorsatzwort.as_deref(),
+                        Some(family.as_str()),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                );
+                name.family_ext =
+                    map_family_parts(&family, namenszusatz.as_deref(), vorsatzwort.as_deref())?;
             }
+            names.push(Some(name));
+        }
+    }
+
+    // birth name, unless already sent as maiden name in PID-5
+    if let Some(name_fields) = field_repeats(v2_msg, PID_6) {
+        for name_field in name_fields {
+            let Some(family) = repeat_component(name_field, 1).map(|f| normalize(f, normalization))
+            else {
+                continue;
+            };
+            let birth_name = map_birth_name(name_field, family, normalization)?;
+            if names
+                .iter()
+                .flatten()
+                .any(|n| n.r#use == Some(NameUse::Maiden) && n.family == birth_name.family)
+            {
+                continue;
+            }
+            names.push(Some(birth_name));
+        }
+    }
+
+    Ok(names)
+}
+
+/// Academic title (e.g. `Dr.`, `Prof. Dr.`, `Dipl.-Ing.`), as opposed to a Vorsatzwort.
+fn is_academic_title(value: &str) -> bool {
+    static RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)^(prof|dr|dipl|pd|priv\.?-?doz|mag|lic)(\.|\s|-|$)").unwrap()
+    });
+    RE.is_match(value)
+}
+
+/// Parts of a composed family name (German base profile): Namenszusatz (e.g. `Graf`), Vorsatzwort
+/// (e.g. `von`) and the own name, [`None`] if the family name isn't composed.
+fn map_family_parts(
+    family: &str,
+    namenszusatz: Option<&str>,
+    vorsatzwort: Option<&str>,
+) -> Result<Option<FieldExtension>, MappingError> {
+    if namenszusatz.is_none() && vorsatzwort.is_none() {
+        return Ok(None);
+    }
+    let extension = |url: &str, value: &str| {
+        Extension::builder()
+            .url(url.to_string())
+            .value(ExtensionValue::String(value.to_string()))
+            .build()
+    };
+
+    let mut family_ext = vec![];
+    if let Some(namenszusatz) = namenszusatz {
+        family_ext.push(extension(
+            "http://fhir.de/StructureDefinition/humanname-namenszusatz",
+            namenszusatz,
+        )?);
+    }
+    if let Some(vorsatzwort) = vorsatzwort {
+        family_ext.push(extension(
+            "http://hl7.org/fhir/StructureDefinition/humanname-own-prefix",
+            vorsatzwort,
+        )?);
+    }
+    family_ext.push(extension(
+        "http://hl7.org/fhir/StructureDefinition/humanname-own-name",
+        family,
+    )?);
+
+    Ok(Some(
+        FieldExtension::builder().extension(family_ext).build()?,
+    ))
+}
+
+/// Birth name (PID-6) with the own-name parts of the family name.
+fn map_birth_name(
+    name_field: &Repeat,
+    family: String,
+    normalization: &NameNormalizationConfig,
+) -> Result<HumanName, MappingError> {
+    let part = |c| repeat_component(name_field, c).map(|v| normalize(v, normalization));
+    let mut family_ext = vec![
+        Extension::builder()
+            .url("http://hl7.org/fhir/StructureDefinition/humanname-own-name".into())
+            .value(ExtensionValue::String(family.clone()))
+            .build()?,
+    ];
+    let mut name = HumanName::builder()
+        .r#use(NameUse::Maiden)
+        .given(part(2).map(|e| vec![Some(e)]).unwrap_or_default())
+        .build()?;
 
-            // vorsatzwort
-            if let Some(vorsatzwort) = repeat_component(name_field, 5) {
-                name.family_ext = Some(field_extension(
-                    "http://hl7.org/fhir/StructureDefinition/humanname-own-prefix".into(),
-                    ExtensionValue::String(vorsatzwort.to_string()),
-                )?);
-            }
-            names.push(Some(name));
+    // vorsatzwort
+    match part(5) {
+        Some(vorsatzwort) => {
+            name.family = Some(format!("{vorsatzwort} {family}"));
+            family_ext.push(
+                Extension::builder()
+                    .url("http://hl7.org/fhir/StructureDefinition/humanname-own-prefix".into())
+                    .value(ExtensionValue::String(vorsatzwort))
+                    .build()?,
+            );
         }
+        None => name.family = Some(family),
     }
+    name.family_ext = Some(FieldExtension::builder().extension(family_ext).build()?);
 
-    Ok(names)
+    Ok(name)
 }
 
-fn is_valid_gkv10(insurance_number: &str) -> bool {
+const GKV10_SYSTEM: &str = "http://fhir.de/sid/gkv/kvid-10";
+
+pub(crate) fn is_valid_gkv10(insurance_number: &str) -> bool {
     static RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[A-Z][0-9]{9}$").unwrap());
     RE.is_match(insurance_number)
 }
@@ -574,7 +1476,7 @@ fn map_versicherungsdaten(
 
     if is_valid_gkv10(insurance_number) {
         // GKV
-        result.system = Some("http://fhir.de/sid/gkv/kvid-10".to_string());
+        result.system = Some(GKV10_SYSTEM.to_string());
         result.r#type = Some(
             CodeableConcept::builder()
                 .coding(vec![Some(
@@ -595,7 +1497,7 @@ fn map_versicherungsdaten(
     Ok(Some(result))
 }
 
-fn get_identifier_period(in1: &Segment) -> Result<Option<Period>, MappingError> {
+pub(crate) fn get_identifier_period(in1: &Segment) -> Result<Option<Period>, MappingError> {
     // Gültigkeitszeitraum
     let start = in1
         .field(12)
@@ -628,20 +1530,38 @@ fn field_extension(url: String, ext_value: ExtensionValue) -> Result<FieldExtens
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::test_utils::tests::get_test_config;
+    use crate::config::{
+        EmployerConfig, IdentifierTypeConfig, LanguageConfig, MaritalStatusConfig,
+        ReligionCodeConfig, ReligionConfig,
+    };
+    use crate::test_utils::tests::{get_dummy_resources, get_test_config, read_test_resource};
     use fhir_model::Date;
     use fhir_model::DateTime;
+    use fhir_model::r4b::codes::HTTPVerb;
     use fhir_model::r4b::codes::HTTPVerb::Delete;
     use fhir_model::r4b::codes::IdentifierUse;
     use fhir_model::r4b::resources::{
         BundleEntryRequest, ParametersParameter, ParametersParameterValue, PatientMultipleBirth,
-        ResourceType,
+        Resource, ResourceType,
     };
     use fhir_model::r4b::types::{CodeableConcept, Coding, Identifier, Period, Reference};
     use fhir_model::time;
     use hl7_parser::Message;
     use rstest::rstest;
 
+    #[test]
+    fn test_map_birth_time() {
+        assert_eq!(map_birth_time("20230118").unwrap(), None);
+
+        let birth_time = map_birth_time("202301181003").unwrap().unwrap();
+        assert_eq!(
+            birth_time.extension[0].value,
+            Some(ExtensionValue::DateTime(
+                parse_datetime("202301181003").unwrap()
+            ))
+        );
+    }
+
     #[test]
     fn test_multibirth_empty() {
         let msg = Message::parse_with_lenient_newlines(r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|20251102212117||ADT^A08^ADT_A01|12332112|P|2.5||123788998|NE|NE||8859/1
@@ -780,7 +1700,12 @@ EVN|A29|202211211427||12127_684450133|MEDCO-TOBL|202211211427
 PID|1|1234567|1234567||Test-UCH^Endoprothese^^^^^L~Test^^^^^^B||19450201|M|||Baldinger Strasse&Baldinger Strasse^^Marburg^^35037^DE^L|||||S||||||||||DE||||N"#, true)
             .unwrap();
 
-        let entry = map(&msg, config).unwrap();
+        let entry = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            config,
+            &get_dummy_resources(),
+        )
+        .unwrap();
 
         assert_eq!(
             entry.first().unwrap().request,
@@ -798,6 +1723,29 @@ PID|1|1234567|1234567||Test-UCH^Endoprothese^^^^^L~Test^^^^^^B||19450201|M|||Bal
         );
     }
 
+    #[test]
+    fn test_deactivate_patient() {
+        let mut config = get_test_config();
+        config.person.delete_person_information = PatientDeletionMode::Deactivate;
+
+        let msg = Message::parse_with_lenient_newlines(r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|20221121142711||ADT^A29^ADT_A21|71546182|P|2.5||684450133|NE|NE||8859/1
+EVN|A29|202211211427||12127_684450133|MEDCO-TOBL|202211211427
+PID|1|1234567|1234567||Test-UCH^Endoprothese^^^^^L~Test^^^^^^B||19450201|M|||Baldinger Strasse&Baldinger Strasse^^Marburg^^35037^DE^L|||||S||||||||||DE||||N"#, true)
+            .unwrap();
+
+        let entry = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+            &get_dummy_resources(),
+        )
+        .unwrap();
+        let entry = entry.first().unwrap();
+
+        assert_eq!(entry.request.as_ref().unwrap().method, HTTPVerb::Put);
+        let patient: Patient = entry.resource.clone().unwrap().try_into().unwrap();
+        assert_eq!(patient.active, Some(false));
+    }
+
     #[test]
     fn test_map_versicherung_missing_insurance_number() {
         let msg = Message::parse_with_lenient_newlines(r#"MSH|^~\&|ORBIS||RECAPP|ORBIS|201111280725||ADT^A04|11657277|P|2.5|||||DE||DE
@@ -913,6 +1861,64 @@ IN2|4||12345TES^TEST GmbH||||||||||||||||||||||||||^PC^0.0||||DE|||N|||kl|||||||
         assert_eq!(identifiers.len(), 2);
     }
 
+    #[test]
+    fn test_map_identifier_list() {
+        let msg = Message::parse_with_lenient_newlines(
+            r#"MSH|^~\&|ORBIS||RECAPP|ORBIS|201111280725||ADT^A04|11657277|P|2.5|||||DE||DE
+EVN|A04|201111280722|201111280722||TEST
+PID|1|111111|111111^^^KH^PI~K454874316^^^AOK^GKV~454874316^^^AOK^GKV~P123^^^DEMO^PKV~42^^^KH^XX||Mustermann^Max|Mustermann|19500118|M"#,
+            true,
+        )
+        .unwrap();
+        let mut config = get_test_config();
+        config.person.identifier_types = vec![
+            IdentifierTypeConfig {
+                code: "PI".to_string(),
+                system: config.person.system.clone(),
+                type_system: "http://terminology.hl7.org/CodeSystem/v2-0203".to_string(),
+                type_code: "MR".to_string(),
+            },
+            IdentifierTypeConfig {
+                code: "GKV".to_string(),
+                system: "http://fhir.de/sid/gkv/kvid-10".to_string(),
+                type_system: "http://fhir.de/CodeSystem/identifier-type-de-basis".to_string(),
+                type_code: "KVZ10".to_string(),
+            },
+            IdentifierTypeConfig {
+                code: "PKV".to_string(),
+                system: "https://example.org/sid/pkv".to_string(),
+                type_system: "http://fhir.de/CodeSystem/identifier-type-de-basis".to_string(),
+                type_code: "PKV".to_string(),
+            },
+        ];
+
+        let identifiers = create_patient_identifiers(&msg, &config).unwrap();
+
+        // the PI identifier equals PID-2, the invalid KVNR and unknown type are skipped
+        let values: Vec<_> = identifiers
+            .iter()
+            .flatten()
+            .map(|i| (i.system.as_deref().unwrap(), i.value.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("https://fhir.diz.uni-marburg.de/sid/patient-id", "111111"),
+                ("http://fhir.de/sid/gkv/kvid-10", "K454874316"),
+                ("https://example.org/sid/pkv", "P123"),
+            ]
+        );
+        let kvnr = identifiers[1].as_ref().unwrap();
+        assert_eq!(
+            kvnr.r#type.as_ref().unwrap().coding[0]
+                .as_ref()
+                .unwrap()
+                .code
+                .as_deref(),
+            Some("KVZ10")
+        );
+    }
+
     #[test]
     fn test_patient_multiple_insurance_select_kvid() {
         let msg_full = Message::parse_with_lenient_newlines(r#"MSH|^~\&|ORBIS||RECAPP|ORBIS|201111280725||ADT^A04|11657277|P|2.5|||||DE||DE
@@ -1098,36 +2104,422 @@ IN1|1||AOK HSA HESSEN|AOK - Die Gesundheitskasse in Hessen-|Musterstrasse 1^^Mus
         ));
     }
 
+    fn adxp(street_name: &str, house_number: &str) -> Option<FieldExtension> {
+        let extension = |url: &str, value: &str| {
+            Extension::builder()
+                .url(url.to_string())
+                .value(ExtensionValue::String(value.to_string()))
+                .build()
+                .unwrap()
+        };
+        Some(
+            FieldExtension::builder()
+                .extension(vec![
+                    extension(STREET_NAME_URL, street_name),
+                    extension(HOUSE_NUMBER_URL, house_number),
+                ])
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[rstest]
+    #[case("Talstraße 16&Talstraße&16", "Talstraße 16", Some(("Talstraße", "16")))]
+    #[case("&Talstraße&16", "Talstraße 16", Some(("Talstraße", "16")))]
+    #[case("Test Str.  27", "Test Str.  27", Some(("Test Str.", "27")))]
+    #[case("Am Markt 3a", "Am Markt 3a", Some(("Am Markt", "3a")))]
+    #[case("Weg 12-14", "Weg 12-14", Some(("Weg", "12-14")))]
+    #[case("Straße des 17. Juni 5", "Straße des 17. Juni 5", Some(("Straße des 17. Juni", "5")))]
+    #[case("Hinterhaus", "Hinterhaus", None)]
+    #[case("Postfach 12", "Postfach 12", None)]
+    fn test_map_address_line(
+        #[case] xad_1: &str,
+        #[case] expected_line: &str,
+        #[case] expected_parts: Option<(&str, &str)>,
+    ) {
+        let field =
+            hl7_parser::parser::parse_field(&format!("{xad_1}^^Stadt^^30000^DE^L")).unwrap();
+        let (line, line_ext) = map_address_line(field.repeat(1).unwrap()).unwrap().unwrap();
+
+        assert_eq!(line, expected_line);
+        assert_eq!(
+            line_ext,
+            expected_parts.and_then(|(street_name, house_number)| adxp(street_name, house_number))
+        );
+    }
+
     #[test]
     fn test_map_addresses() {
         let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
-PID|1|1212121|1212121|21600000|Sokolovski, Malina||19820101101139|F|||Hexengasse 1^^Traumstadt^^12345^D^L~Wettergasse 42^^Wetter^^54321^D^L||012345/1234^^PH~0123451234^^CP~max-muster.mann@web.de^^X.400|||S|ev||||12345~23456|||||D||||N"#;
+PID|1|1212121|1212121|21600000|Sokolovski, Malina||19820101101139|F|||Hexengasse 1^^Traumstadt^^12345^D^L~Wettergasse 42^^Wetter^^54321^D^C~Postfach 12^^Wetter^^54322^D^M||012345/1234^^PH~0123451234^^CP~max-muster.mann@web.de^^X.400|||S|ev||||12345~23456|||||D||||N"#;
         let msg = Message::parse_with_lenient_newlines(msg, true).unwrap();
 
-        // two addresses
+        // three addresses
         let expected = vec![
             Address::builder()
+                .r#use(AddressUse::Home)
                 .r#type(AddressType::Both)
                 .line(vec![Some("Hexengasse 1".into())])
+                .line_ext(vec![adxp("Hexengasse", "1")])
                 .city("Traumstadt".into())
                 .postal_code("12345".into())
                 .country("D".into())
                 .build()
                 .unwrap(),
             Address::builder()
+                .r#use(AddressUse::Temp)
                 .r#type(AddressType::Both)
                 .line(vec![Some("Wettergasse 42".into())])
+                .line_ext(vec![adxp("Wettergasse", "42")])
                 .city("Wetter".into())
                 .postal_code("54321".into())
                 .country("D".into())
                 .build()
                 .unwrap(),
+            Address::builder()
+                .r#type(AddressType::Postal)
+                .line(vec![Some("Postfach 12".into())])
+                .line_ext(vec![None])
+                .city("Wetter".into())
+                .postal_code("54322".into())
+                .country("D".into())
+                .build()
+                .unwrap(),
         ];
-        let addresses: Vec<Address> = map_addresses(&msg).unwrap().into_iter().flatten().collect();
+        let addresses: Vec<Address> =
+            map_addresses(&msg, &get_test_config(), &get_dummy_resources())
+                .unwrap()
+                .into_iter()
+                .flatten()
+                .collect();
 
         assert_eq!(addresses, expected);
     }
 
+    #[test]
+    fn test_map_municipality() {
+        let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
+PID|1|1212121|1212121|21600000|Sokolovski^Malina||19820101|F|||Ketzerbach 1^^Marburg^^35037^D^H^^MR~Hexengasse 1^^Traumstadt^^12345^D^H"#;
+        let msg = Message::parse_with_lenient_newlines(msg, true).unwrap();
+        let ags = |address: &Address| {
+            address
+                .city_ext
+                .iter()
+                .flat_map(|e| e.extension.iter())
+                .find(|e| e.url == "http://fhir.de/StructureDefinition/destatis/ags")
+                .and_then(|e| match &e.value {
+                    Some(ExtensionValue::Coding(c)) => c.code.clone(),
+                    _ => None,
+                })
+        };
+
+        // not mapped by default
+        let addresses = map_addresses(&msg, &get_test_config(), &get_dummy_resources()).unwrap();
+        assert!(addresses.iter().flatten().all(|a| ags(a).is_none()));
+
+        let mut config = get_test_config();
+        config.person.municipality_key = Some(MunicipalityKey::PostalCode);
+        let addresses: Vec<Address> = map_addresses(&msg, &config, &get_dummy_resources())
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+
+        assert_eq!(ags(&addresses[0]).as_deref(), Some("06534014"));
+        assert_eq!(addresses[0].district.as_deref(), Some("Marburg-Biedenkopf"));
+        // unknown postal code
+        assert_eq!(ags(&addresses[1]), None);
+        assert_eq!(addresses[1].district, None);
+
+        // county code (XAD-9)
+        let mut resources = get_dummy_resources();
+        let municipality = resources.municipality_map.remove("35037").unwrap();
+        resources
+            .municipality_map
+            .insert("MR".to_string(), municipality);
+        config.person.municipality_key = Some(MunicipalityKey::CountyCode);
+        let addresses = map_addresses(&msg, &config, &resources).unwrap();
+        assert_eq!(
+            ags(addresses[0].as_ref().unwrap()).as_deref(),
+            Some("06534014")
+        );
+    }
+
+    #[rstest]
+    #[case("1982", Some(Date::Year(1982)), None)]
+    #[case("198203", Some(Date::YearMonth(1982, time::Month::March)), None)]
+    #[case("", None, Some("unknown"))]
+    #[case("1982-3", None, Some("error"))]
+    fn test_map_birth_date(
+        #[case] pid_7: &str,
+        #[case] expected: Option<Date>,
+        #[case] expected_reason: Option<&str>,
+    ) {
+        let msg = format!(
+            "MSH|^~\\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5\r\
+            PID|1|1212121|1212121|21600000|Sokolovski^Malina||{pid_7}|F"
+        );
+        let msg = Message::parse_with_lenient_newlines(&msg, true).unwrap();
+
+        let patient = map_patient(&msg, &get_test_config(), &get_dummy_resources()).unwrap();
+
+        assert_eq!(patient.birth_date, expected);
+        let reason = patient
+            .birth_date_ext
+            .iter()
+            .flat_map(|e| e.extension.iter())
+            .find(|e| e.url == "http://hl7.org/fhir/StructureDefinition/data-absent-reason")
+            .and_then(|e| match &e.value {
+                Some(ExtensionValue::Code(c)) => Some(c.clone()),
+                _ => None,
+            });
+        assert_eq!(reason.as_deref(), expected_reason);
+    }
+
+    #[rstest]
+    #[case(DeceasedRule::Date, "", "", None)]
+    #[case(DeceasedRule::Date, "202401021030", "", Some(datetime("202401021030")))]
+    #[case(DeceasedRule::Date, "20240102", "", Some(date("20240102")))]
+    #[case(
+        DeceasedRule::Date,
+        "2024-01",
+        "",
+        Some(PatientDeceased::Boolean(true))
+    )]
+    #[case(DeceasedRule::Date, "", "Y", Some(PatientDeceased::Boolean(true)))]
+    #[case(DeceasedRule::Date, "", "N", Some(PatientDeceased::Boolean(false)))]
+    #[case(DeceasedRule::Indicator, "20240102", "", None)]
+    #[case(DeceasedRule::Indicator, "20240102", "Y", Some(date("20240102")))]
+    #[case(
+        DeceasedRule::Indicator,
+        "20240102",
+        "N",
+        Some(PatientDeceased::Boolean(false))
+    )]
+    fn test_map_deceased(
+        #[case] rule: DeceasedRule,
+        #[case] pid_29: &str,
+        #[case] pid_30: &str,
+        #[case] expected: Option<PatientDeceased>,
+    ) {
+        let msg = format!(
+            "MSH|^~\\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A08^ADT_A01|65298857|P|2.5\r\
+            PID|1|1212121|1212121|21600000|Sokolovski^Malina||19820101|F|||||||||||||||||||||{pid_29}|{pid_30}"
+        );
+        let msg = Message::parse_with_lenient_newlines(&msg, true).unwrap();
+        let mut config = get_test_config();
+        config.person.deceased_rule = rule;
+
+        assert_eq!(map_deceased(&msg, &config).unwrap(), expected);
+    }
+
+    fn datetime(value: &str) -> PatientDeceased {
+        PatientDeceased::DateTime(parse_datetime(value).unwrap())
+    }
+
+    fn date(value: &str) -> PatientDeceased {
+        PatientDeceased::DateTime(DateTime::Date(parse_date(value).unwrap()))
+    }
+
+    #[rstest]
+    #[case("F", AdministrativeGender::Female, None)]
+    #[case("M", AdministrativeGender::Male, None)]
+    #[case("D", AdministrativeGender::Other, Some("D"))]
+    #[case("X", AdministrativeGender::Other, Some("X"))]
+    #[case("Y", AdministrativeGender::Unknown, None)]
+    fn test_map_gender(
+        #[case] pid_8: &str,
+        #[case] expected: AdministrativeGender,
+        #[case] expected_amtlich: Option<&str>,
+    ) {
+        let msg = format!(
+            "MSH|^~\\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5\r\
+            PID|1|1212121|1212121|21600000|Sokolovski^Malina||19820101|{pid_8}"
+        );
+        let msg = Message::parse_with_lenient_newlines(&msg, true).unwrap();
+
+        let patient = map_patient(&msg, &get_test_config(), &get_dummy_resources()).unwrap();
+
+        assert_eq!(patient.gender, Some(expected));
+        let amtlich = patient
+            .gender_ext
+            .iter()
+            .flat_map(|e| e.extension.iter())
+            .find(|e| e.url == "http://fhir.de/StructureDefinition/gender-amtlich-de")
+            .and_then(|e| match &e.value {
+                Some(ExtensionValue::Coding(c)) => c.code.clone(),
+                _ => None,
+            });
+        assert_eq!(amtlich.as_deref(), expected_amtlich);
+    }
+
+    #[test]
+    fn test_patient_defaults() {
+        let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
+EVN|A04|202208200651
+PID|1|1212121|1212121|21600000|Sokolovski^Malina||19820101||||Hexengasse 1^^Traumstadt^^12345"#;
+        let msg = Message::parse_with_lenient_newlines(msg, true).unwrap();
+
+        let patient = map_patient(&msg, &get_test_config(), &get_dummy_resources()).unwrap();
+        assert_eq!(patient.gender, None);
+        assert_eq!(patient.address[0].as_ref().unwrap().country, None);
+
+        let mut config = get_test_config();
+        config.person.defaults.gender = Some(DefaultGender::Unknown);
+        config.person.defaults.country = Some("DE".to_string());
+
+        let patient = map_patient(&msg, &config, &get_dummy_resources()).unwrap();
+        assert_eq!(patient.gender, Some(AdministrativeGender::Unknown));
+        assert_eq!(
+            patient.address[0].as_ref().unwrap().country.as_deref(),
+            Some("DE")
+        );
+    }
+
+    #[test]
+    fn test_skip_message_types() {
+        let hl7 = read_test_resource("a03_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+        let ctx = MappingContext::new(&msg, &get_test_config()).unwrap();
+        let mut config = get_test_config();
+
+        assert!(
+            !map(&ctx, &config, &get_dummy_resources())
+                .unwrap()
+                .is_empty()
+        );
+
+        config.person.skip_message_types = vec![MessageType::A02, MessageType::A03];
+        assert!(
+            map(&ctx, &config, &get_dummy_resources())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_skip_message_types_keeps_related_persons() {
+        let hl7 = read_test_resource("a01_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+        let ctx = MappingContext::new(&msg, &get_test_config()).unwrap();
+        let mut config = get_test_config();
+        config.person.contact.related_person = true;
+        config.person.skip_message_types = vec![MessageType::A01];
+
+        let entries = map(&ctx, &config, &get_dummy_resources()).unwrap();
+
+        assert!(!entries.is_empty());
+        assert!(
+            entries
+                .iter()
+                .all(|e| matches!(e.resource, Some(Resource::RelatedPerson(_))))
+        );
+    }
+
+    #[test]
+    fn test_map_contacts() {
+        let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
+EVN|A04|202208200651
+PID|1|1212121|1212121|21600000|Sokolovski^Malina||19820101|F
+NK1|1|Test^Anna|14^Ehefrau|Hexengasse 1^^Traumstadt^^12345^D^L|0151/123123||N
+NK1|2|Fr. Test|15^Tochter||s.Pat."#;
+        let msg = Message::parse_with_lenient_newlines(msg, true).unwrap();
+
+        let contacts =
+            map_patient_contacts(&map_contacts(&msg, &Default::default()).unwrap()).unwrap();
+        assert_eq!(contacts.len(), 2);
+
+        let first = contacts[0].as_ref().unwrap();
+        let name = first.name.as_ref().unwrap();
+        assert_eq!(name.family.as_deref(), Some("Test"));
+        assert_eq!(name.given, vec![Some("Anna".to_string())]);
+        let relationship = first.relationship[0].as_ref().unwrap();
+        assert_eq!(relationship.text.as_deref(), Some("Ehefrau"));
+        assert_eq!(
+            relationship.coding[0].as_ref().unwrap().code.as_deref(),
+            Some("N")
+        );
+        assert_eq!(
+            first.telecom[0].as_ref().unwrap().value.as_deref(),
+            Some("0151/123123")
+        );
+        assert_eq!(
+            first.address.as_ref().unwrap().city.as_deref(),
+            Some("Traumstadt")
+        );
+
+        // placeholder phone skipped
+        assert!(contacts[1].as_ref().unwrap().telecom.is_empty());
+
+        // related persons
+        let mut config = get_test_config();
+        config.person.contact.related_person = true;
+        let entries = map_related_persons(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[1].request.as_ref().unwrap().url,
+            format!(
+                "RelatedPerson?identifier={}|1212121_2",
+                config.person.contact.system
+            )
+        );
+    }
+
+    #[test]
+    fn test_map_employer() {
+        let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
+EVN|A04|202208200651
+PID|1|1212121|1212121|21600000|Sokolovski^Malina||19820101|F
+GT1|1||Muster^Max|||||||||||||Stadtwerke Marburg|Am Krekel 55^^Marburg^^35039^D
+GT1|2||Muster^Erika"#;
+        let msg = Message::parse_with_lenient_newlines(msg, true).unwrap();
+        let mut config = get_test_config();
+
+        // disabled by default
+        assert!(map_employer_extensions(&msg, &config).unwrap().is_empty());
+        let ctx = MappingContext::new(&msg, &get_test_config()).unwrap();
+        assert!(map_employer_persons(&ctx, &config).unwrap().is_empty());
+
+        config.person.employer = Some(EmployerConfig {
+            target: EmployerTarget::Extension,
+            url: "https://example.org/employer".to_string(),
+            system: String::new(),
+        });
+        // GT1 without employer is skipped
+        let extensions = map_employer_extensions(&msg, &config).unwrap();
+        assert_eq!(extensions.len(), 1);
+        assert_eq!(extensions[0].url, "https://example.org/employer");
+        assert_eq!(
+            extensions[0]
+                .extension
+                .iter()
+                .map(|e| e.url.as_str())
+                .collect::<Vec<_>>(),
+            vec!["name", "address"]
+        );
+        let Some(ExtensionValue::Address(address)) = &extensions[0].extension[1].value else {
+            panic!("expected Address");
+        };
+        assert_eq!(address.city.as_deref(), Some("Marburg"));
+
+        config.person.employer = Some(EmployerConfig {
+            target: EmployerTarget::RelatedPerson,
+            url: String::new(),
+            system: "https://example.org/sid/employer-id".to_string(),
+        });
+        assert!(map_employer_extensions(&msg, &config).unwrap().is_empty());
+        let entries = map_employer_persons(&ctx, &config).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].request.as_ref().unwrap().url,
+            "RelatedPerson?identifier=https://example.org/sid/employer-id|1212121_employer_1"
+        );
+    }
+
     #[test]
     fn test_map_names() {
         let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
@@ -1148,7 +2540,7 @@ PID|||||Schuster^Regine^^^^^L~Musterfrau^Regine^^^^^M|||||||||||||||||||||||||"#
                 .build()
                 .unwrap(),
         ];
-        let names = map_name(&msg)
+        let names = map_name(&msg, &Default::default())
             .unwrap()
             .into_iter()
             .flatten()
@@ -1156,4 +2548,300 @@ PID|||||Schuster^Regine^^^^^L~Musterfrau^Regine^^^^^M|||||||||||||||||||||||||"#
 
         assert_eq!(names, expected);
     }
+
+    #[rstest]
+    #[case("M", "http://terminology.hl7.org/CodeSystem/v3-MaritalStatus", "M")]
+    #[case("E", "http://terminology.hl7.org/CodeSystem/v3-MaritalStatus", "L")]
+    #[case("VH", "http://terminology.hl7.org/CodeSystem/v3-MaritalStatus", "M")]
+    #[case("XX", "http://terminology.hl7.org/CodeSystem/v3-NullFlavor", "UNK")]
+    fn test_map_marital_status(#[case] pid_16: &str, #[case] system: &str, #[case] code: &str) {
+        let msg = Message::parse_with_lenient_newlines(
+            &format!(
+                r#"MSH|^~\&|ORBIS||RECAPP|ORBIS|201111280725||ADT^A04|11657277|P|2.5|||||DE||DE
+PID|1|111111|111111||Mustermann^Max||19500118|M||||||||{pid_16}"#
+            ),
+            true,
+        )
+        .unwrap();
+        let mut config = get_test_config();
+        config.person.marital_statuses = vec![MaritalStatusConfig {
+            code: "VH".to_string(),
+            marital_status: "M".to_string(),
+        }];
+
+        let status = map_marital_status(&msg, &config).unwrap().unwrap();
+
+        let coding = status.coding[0].as_ref().unwrap();
+        assert_eq!(coding.system.as_deref(), Some(system));
+        assert_eq!(coding.code.as_deref(), Some(code));
+        assert!(coding.display.is_some());
+    }
+
+    #[rstest]
+    #[case("D", Some("de"))]
+    #[case("EN", Some("en"))]
+    #[case("XYZ", None)]
+    #[case("", None)]
+    fn test_map_communication(#[case] pid_15: &str, #[case] expected: Option<&str>) {
+        let msg = Message::parse_with_lenient_newlines(
+            &format!(
+                r#"MSH|^~\&|ORBIS||RECAPP|ORBIS|201111280725||ADT^A04|11657277|P|2.5|||||DE||DE
+PID|1|111111|111111||Mustermann^Max||19500118|M|||||||{pid_15}"#
+            ),
+            true,
+        )
+        .unwrap();
+        let mut config = get_test_config();
+        config.person.languages = vec![LanguageConfig {
+            code: "D".to_string(),
+            language: "de".to_string(),
+        }];
+
+        let communication = map_communication(&msg, &config).unwrap();
+
+        let language = communication.first().map(|c| {
+            let c = c.as_ref().unwrap();
+            assert_eq!(c.preferred, Some(true));
+            let coding = c.language.coding[0].as_ref().unwrap();
+            assert_eq!(coding.system.as_deref(), Some("urn:ietf:bcp:47"));
+            coding.code.clone().unwrap()
+        });
+        assert_eq!(language.as_deref(), expected);
+    }
+
+    #[test]
+    fn test_map_telecom() {
+        let msg = Message::parse_with_lenient_newlines(
+            r#"MSH|^~\&|ORBIS||RECAPP|ORBIS|201111280725||ADT^A04|11657277|P|2.5|||||DE||DE
+PID|1|111111|111111||Mustermann^Max||19500118|M|||||06421/12345^PRN^PH~^NET^Internet^max@example.org~s.Pat.^PRN^PH~^PRN^CP^^^0170^1234567|^WPN^FX^^^06421^58-0"#,
+            true,
+        )
+        .unwrap();
+
+        let telecom: Vec<_> = map_telecom(&msg)
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .map(|t| {
+                (
+                    t.system.unwrap(),
+                    t.r#use.unwrap(),
+                    t.value.unwrap(),
+                    t.rank.unwrap().get(),
+                )
+            })
+            .collect();
+
+        // placeholder is skipped
+        assert_eq!(
+            telecom,
+            vec![
+                (
+                    ContactPointSystem::Phone,
+                    ContactPointUse::Home,
+                    "06421/12345".to_string(),
+                    1
+                ),
+                (
+                    ContactPointSystem::Email,
+                    ContactPointUse::Home,
+                    "max@example.org".to_string(),
+                    2
+                ),
+                (
+                    ContactPointSystem::Phone,
+                    ContactPointUse::Mobile,
+                    "0170 1234567".to_string(),
+                    3
+                ),
+                (
+                    ContactPointSystem::Fax,
+                    ContactPointUse::Work,
+                    "06421 58-0".to_string(),
+                    4
+                ),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("Dr.", true)]
+    #[case("Prof. Dr. med.", true)]
+    #[case("Dipl.-Ing.", true)]
+    #[case("PD Dr.", true)]
+    #[case("von", false)]
+    #[case("van der", false)]
+    #[case("Drost", false)]
+    fn test_is_academic_title(#[case] value: &str, #[case] expected: bool) {
+        assert_eq!(is_academic_title(value), expected);
+    }
+
+    #[test]
+    fn test_map_name_parts() {
+        let msg = Message::parse_with_lenient_newlines(&read_test_resource("a01_test.hl7"), true)
+            .unwrap();
+
+        let names = map_name(&msg, &Default::default()).unwrap();
+        let name = names[0].as_ref().unwrap();
+
+        // Test^Meinrad^^Graf^von^Dr.^L
+        assert_eq!(name.family.as_deref(), Some("Graf von Test"));
+        assert_eq!(name.prefix, vec![Some("Dr.".to_string())]);
+        assert_eq!(
+            name.prefix_ext[0].as_ref().unwrap().extension[0].value,
+            Some(ExtensionValue::Code("AC".into()))
+        );
+        assert_eq!(
+            name.family_ext
+                .as_ref()
+                .unwrap()
+                .extension
+                .iter()
+                .map(|e| (e.url.as_str(), e.value.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "http://fhir.de/StructureDefinition/humanname-namenszusatz",
+                    Some(ExtensionValue::String("Graf".into()))
+                ),
+                (
+                    "http://hl7.org/fhir/StructureDefinition/humanname-own-prefix",
+                    Some(ExtensionValue::String("von".into()))
+                ),
+                (
+                    "http://hl7.org/fhir/StructureDefinition/humanname-own-name",
+                    Some(ExtensionValue::String("Test".into()))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_map_name_title_as_prefix() {
+        let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
+PID|||||Muster^Max^^^Prof.^Dr. med.^L|||||||||||||||||||||||||"#;
+        let msg = Message::parse_with_lenient_newlines(msg, true).unwrap();
+
+        let names = map_name(&msg, &Default::default()).unwrap();
+        let name = names[0].as_ref().unwrap();
+
+        assert_eq!(name.family.as_deref(), Some("Muster"));
+        assert_eq!(name.family_ext, None);
+        assert_eq!(
+            name.prefix,
+            vec![Some("Prof.".to_string()), Some("Dr. med.".to_string())]
+        );
+        assert_eq!(name.prefix_ext.len(), 2);
+    }
+
+    #[test]
+    fn test_map_birth_name() {
+        let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
+PID|||||Schuster^Regine^^^^^L|Berg^Regine^^^von||||||||||||||||||||||||"#;
+        let msg = Message::parse_with_lenient_newlines(msg, true).unwrap();
+
+        let names = map_name(&msg, &Default::default())
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect::<Vec<HumanName>>();
+
+        assert_eq!(names.len(), 2);
+        let birth_name = &names[1];
+        assert_eq!(birth_name.r#use, Some(NameUse::Maiden));
+        assert_eq!(birth_name.family.as_deref(), Some("von Berg"));
+        let family_ext = &birth_name.family_ext.as_ref().unwrap().extension;
+        assert_eq!(
+            family_ext
+                .iter()
+                .map(|e| (e.url.as_str(), e.value.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "http://hl7.org/fhir/StructureDefinition/humanname-own-name",
+                    Some(ExtensionValue::String("Berg".into()))
+                ),
+                (
+                    "http://hl7.org/fhir/StructureDefinition/humanname-own-prefix",
+                    Some(ExtensionValue::String("von".into()))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_map_names_normalized() {
+        let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
+PID|||||M\XFC\ller^J\XC3BC\rgen^^^^^L|GÃ¶bel^JÃ¼rgen||||||||||||||||||||||||"#;
+        let msg = Message::parse_with_lenient_newlines(msg, true).unwrap();
+        let normalization = NameNormalizationConfig {
+            unescape: true,
+            repair_encoding: true,
+            nfc: true,
+        };
+
+        let names = map_name(&msg, &normalization)
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect::<Vec<HumanName>>();
+
+        assert_eq!(names[0].family.as_deref(), Some("Müller"));
+        assert_eq!(names[0].given, vec![Some("Jürgen".to_string())]);
+        assert_eq!(names[1].family.as_deref(), Some("Göbel"));
+        assert_eq!(names[1].given, vec![Some("Jürgen".to_string())]);
+
+        // raw values by default
+        let names = map_name(&msg, &Default::default()).unwrap();
+        assert_eq!(
+            names[0].as_ref().unwrap().family.as_deref(),
+            Some("M\\XFC\\ller")
+        );
+    }
+
+    #[test]
+    fn test_map_religion() {
+        let msg = Message::parse_with_lenient_newlines(&read_test_resource("a01_test.hl7"), true)
+            .unwrap();
+        let mut config = get_test_config();
+
+        // disabled by default
+        assert_eq!(map_religion(&msg, &config).unwrap(), None);
+
+        config.person.religion = Some(ReligionConfig { codes: vec![] });
+        // unknown local code
+        assert_eq!(map_religion(&msg, &config).unwrap(), None);
+
+        config.person.religion = Some(ReligionConfig {
+            codes: vec![ReligionCodeConfig {
+                code: "rk".to_string(),
+                religion: "1041".to_string(),
+                display: Some("Roman Catholic Church".to_string()),
+            }],
+        });
+        let extension = map_religion(&msg, &config).unwrap().unwrap();
+        assert_eq!(
+            extension.url,
+            "http://hl7.org/fhir/StructureDefinition/patient-religion"
+        );
+        let Some(ExtensionValue::CodeableConcept(religion)) = extension.value else {
+            panic!("expected CodeableConcept");
+        };
+        let coding = religion.coding[0].as_ref().unwrap();
+        assert_eq!(
+            coding.system.as_deref(),
+            Some("http://terminology.hl7.org/CodeSystem/v3-ReligiousAffiliation")
+        );
+        assert_eq!(coding.code.as_deref(), Some("1041"));
+        assert_eq!(coding.display.as_deref(), Some("Roman Catholic Church"));
+    }
+
+    #[test]
+    fn test_map_birth_name_already_maiden() {
+        let msg = r#"MSH|^~\&|ORBIS|KH|WEBEPA|KH|202208200651||ADT^A04^ADT_A04|65298857|P|2.5||640340718|NE|NE||8859/1
+PID|||||Schuster^Regine^^^^^L~Musterfrau^Regine^^^^^M|Musterfrau^Regine||||||||||||||||||||||||"#;
+        let msg = Message::parse_with_lenient_newlines(msg, true).unwrap();
+
+        assert_eq!(map_name(&msg, &Default::default()).unwrap().len(), 2);
+    }
 }
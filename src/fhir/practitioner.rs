@@ -0,0 +1,245 @@
+use crate::config::{Fhir, PractitionerConfig};
+use crate::error::MappingError;
+use crate::fhir::mapper::{
+    EntryRequestType, MappingContext, build_usual_identifier, bundle_entry, resource_ref,
+};
+use crate::hl7::parser::{MessageType, repeat_component, segment_value};
+use fhir_model::r4b::codes::IdentifierUse;
+use fhir_model::r4b::resources::{BundleEntry, EncounterParticipant, Practitioner, ResourceType};
+use fhir_model::r4b::types::{CodeableConcept, Coding, HumanName, Identifier, Meta};
+use hl7_parser::Message;
+use hl7_parser::message::Repeat;
+
+const PARTICIPATION_TYPE_SYSTEM: &str =
+    "http://terminology.hl7.org/CodeSystem/v3-ParticipationType";
+const LANR_SYSTEM: &str = "https://fhir.kbv.de/NamingSystem/KBV_NS_Base_ANR";
+
+/// Maps the physicians of the visit (PV1-7, PV1-8, PV1-17 and ROL) to `Practitioner` resources.
+pub(crate) fn map(ctx: &MappingContext, config: &Fhir) -> Result<Vec<BundleEntry>, MappingError> {
+    let Some(practitioner_config) = &config.practitioner else {
+        return Ok(vec![]);
+    };
+    let mut result: Vec<BundleEntry> = vec![];
+
+    match ctx.message_type() {
+        // same messages, which map the 'Einrichtungskontakt'
+        MessageType::A01
+        | MessageType::A02
+        | MessageType::A03
+        | MessageType::A04
+        | MessageType::A05
+        | MessageType::A06
+        | MessageType::A07
+        | MessageType::A08
+        | MessageType::A13
+        | MessageType::A50 => {
+            for (_, xcn) in physicians(ctx.msg) {
+                let entry = bundle_entry(
+                    map_practitioner(xcn, practitioner_config, config)?,
+                    EntryRequestType::UpdateAsCreate,
+                    config,
+                )?;
+                // same physician in multiple roles
+                if !result.iter().any(|e| e.full_url == entry.full_url) {
+                    result.push(entry);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(result)
+}
+
+/// `Encounter.participant` entries of the physicians, referencing the mapped `Practitioner`
+/// resources.
+pub(crate) fn map_participants(
+    msg: &Message,
+    config: &Fhir,
+) -> Result<Vec<Option<EncounterParticipant>>, MappingError> {
+    let Some(practitioner_config) = &config.practitioner else {
+        return Ok(vec![]);
+    };
+
+    physicians(msg)
+        .into_iter()
+        .map(|(participation_type, xcn)| {
+            let id = repeat_component(xcn, 1).unwrap_or_default();
+            Ok(Some(
+                EncounterParticipant::builder()
+                    .r#type(vec![Some(
+                        CodeableConcept::builder()
+                            .coding(vec![Some(
+                                Coding::builder()
+                                    .system(PARTICIPATION_TYPE_SYSTEM.to_string())
+                                    .code(participation_type.to_string())
+                                    .build()?,
+                            )])
+                            .build()?,
+                    )])
+                    .individual(resource_ref(
+                        &ResourceType::Practitioner,
+                        id,
+                        &practitioner_config.system,
+                        config,
+                    )?)
+                    .build()?,
+            ))
+        })
+        .collect()
+}
+
+/// Physicians (XCN) with their participation type. Entries without id are skipped.
+fn physicians<'a>(msg: &'a Message) -> Vec<(&'static str, &'a Repeat<'a>)> {
+    let mut result = vec![];
+
+    if let Some(pv1) = msg.segment("PV1") {
+        for (field, participation_type) in [(7, "ATND"), (8, "REF"), (17, "ADM")] {
+            result.extend(
+                pv1.field(field)
+                    .into_iter()
+                    .flat_map(|f| f.repeats())
+                    .map(|xcn| (participation_type, xcn)),
+            );
+        }
+    }
+
+    for rol in msg.segments().filter(|s| s.name == "ROL") {
+        // ROL-3 role (HL7 table 0443)
+        let participation_type = match segment_value(rol, 3, 1, 1) {
+            Some("AT") => "ATND",
+            Some("RP") => "REF",
+            Some("AD") => "ADM",
+            Some("PP") => "PPRF",
+            Some("CP") => "CON",
+            _ => continue,
+        };
+        result.extend(
+            rol.field(4)
+                .into_iter()
+                .flat_map(|f| f.repeats())
+                .map(|xcn| (participation_type, xcn)),
+        );
+    }
+
+    result
+        .into_iter()
+        .filter(|(_, xcn)| repeat_component(xcn, 1).is_some())
+        .collect()
+}
+
+fn map_practitioner(
+    xcn: &Repeat,
+    practitioner_config: &PractitionerConfig,
+    config: &Fhir,
+) -> Result<Practitioner, MappingError> {
+    let id = repeat_component(xcn, 1).unwrap_or_default();
+
+    let mut identifiers = vec![Some(build_usual_identifier(
+        vec![id],
+        practitioner_config.system.clone(),
+    )?)];
+    // lebenslange Arztnummer, if declared by assigning authority (XCN-9) or type (XCN-13)
+    if (repeat_component(xcn, 9) == Some("LANR") || repeat_component(xcn, 13) == Some("LANR"))
+        && id.len() == 9
+        && id.chars().all(|c| c.is_ascii_digit())
+    {
+        identifiers.push(Some(
+            Identifier::builder()
+                .r#use(IdentifierUse::Official)
+                .system(LANR_SYSTEM.to_string())
+                .value(id.to_string())
+                .build()?,
+        ));
+    }
+
+    let mut name = HumanName::builder()
+        .given(
+            repeat_component(xcn, 3)
+                .map(|g| vec![Some(g.to_string())])
+                .unwrap_or_default(),
+        )
+        .build()?;
+    name.family = repeat_component(xcn, 2).map(String::from);
+    if let Some(prefix) = repeat_component(xcn, 6) {
+        name.prefix = vec![Some(prefix.to_string())];
+    }
+
+    let mut practitioner = Practitioner::builder()
+        .meta(
+            Meta::builder()
+                .source(config.meta_source.to_string())
+                .build()?,
+        )
+        .identifier(identifiers)
+        .build()?;
+    if name.family.is_some() || !name.given.is_empty() {
+        practitioner.name = vec![Some(name)];
+    }
+
+    Ok(practitioner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tests::{get_test_config, read_test_message};
+    use fhir_model::r4b::resources::Resource;
+
+    #[test]
+    fn map_physicians() {
+        let hl7 = read_test_message("a03_test.hl7", &[], &["ROL|1|AD|AD|12345^Admit^Anna"]);
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+        let mut config = get_test_config();
+        config.practitioner = Some(PractitionerConfig {
+            system: "https://fhir.diz.uni-marburg.de/sid/practitioner-id".to_string(),
+        });
+
+        let entries = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+        )
+        .unwrap();
+        let practitioners: Vec<Practitioner> = entries
+            .into_iter()
+            .filter_map(|e| match e.resource {
+                Some(Resource::Practitioner(p)) => Some(p),
+                _ => None,
+            })
+            .collect();
+
+        // referring (PV1-8) and admitting (ROL) physician
+        assert_eq!(practitioners.len(), 2);
+        let name = practitioners[0].name[0].as_ref().unwrap();
+        assert_eq!(name.family.as_deref(), Some("Test"));
+        assert_eq!(name.prefix, vec![Some("Dr. med.".to_string())]);
+
+        let participants = map_participants(&msg, &config).unwrap();
+        let types: Vec<&str> = participants
+            .iter()
+            .flatten()
+            .map(|p| {
+                p.r#type[0].as_ref().unwrap().coding[0]
+                    .as_ref()
+                    .unwrap()
+                    .code
+                    .as_deref()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(types, vec!["REF", "ADM"]);
+        assert_eq!(
+            participants[0]
+                .as_ref()
+                .unwrap()
+                .individual
+                .as_ref()
+                .unwrap()
+                .reference,
+            Some(
+                "Practitioner?identifier=https://fhir.diz.uni-marburg.de/sid/practitioner-id|35576TEO"
+                    .to_string()
+            )
+        );
+    }
+}
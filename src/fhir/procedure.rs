@@ -0,0 +1,218 @@
+use crate::config::Fhir;
+use crate::error::MappingError;
+use crate::fhir::encounter::einrichtungskontakt_ref;
+use crate::fhir::mapper::{
+    EntryRequestType, MappingContext, build_usual_identifier, bundle_entry, get_meta_with_profile,
+    parse_datetime,
+};
+use crate::hl7::parser::{MessageType, segment_value};
+use fhir_model::r4b::codes::EventStatus;
+use fhir_model::r4b::resources::{BundleEntry, Procedure, ProcedurePerformed};
+use fhir_model::r4b::types::{CodeableConcept, Coding};
+use hl7_parser::message::Segment;
+use log::warn;
+
+const OPS_SYSTEM: &str = "http://fhir.de/CodeSystem/bfarm/ops";
+const SNOMED_SYSTEM: &str = "http://snomed.info/sct";
+
+/// Maps the procedures (PR1) to `Procedure` resources of the 'Einrichtungskontakt'.
+pub(crate) fn map(ctx: &MappingContext, config: &Fhir) -> Result<Vec<BundleEntry>, MappingError> {
+    let mut result = vec![];
+
+    match ctx.message_type() {
+        // same messages, which map the 'Einrichtungskontakt'
+        MessageType::A01
+        | MessageType::A02
+        | MessageType::A03
+        | MessageType::A04
+        | MessageType::A05
+        | MessageType::A06
+        | MessageType::A07
+        | MessageType::A08
+        | MessageType::A13
+        | MessageType::A50 => {
+            for pr1 in ctx.msg.segments().filter(|seg| seg.name.eq("PR1")) {
+                if let Some(procedure) = map_procedure(ctx, pr1, config)? {
+                    result.push(bundle_entry(
+                        procedure,
+                        EntryRequestType::UpdateAsCreate,
+                        config,
+                    )?);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(result)
+}
+
+fn map_procedure(
+    ctx: &MappingContext,
+    pr1: &Segment,
+    config: &Fhir,
+) -> Result<Option<Procedure>, MappingError> {
+    let Some(code) = segment_value(pr1, 3, 1, 1) else {
+        return Ok(None);
+    };
+    let visit_number = ctx.require_visit_number()?;
+    // procedure date (YYYYMMDDHHMM[SS]), invalid dates are skipped
+    let performed = segment_value(pr1, 5, 1, 1)
+        .and_then(|d| d.get(..12))
+        .and_then(|d| {
+            parse_datetime(d)
+                .inspect_err(|e| warn!("invalid procedure date {d} in PR1-5: {e}"))
+                .ok()
+        });
+
+    // PR1-19 is the procedure identifier, otherwise the procedure is identified by its code
+    // and date within the visit
+    let identifier = match segment_value(pr1, 19, 1, 1) {
+        Some(id) => build_usual_identifier(vec![id], config.procedure.system.clone())?,
+        None => build_usual_identifier(
+            vec![
+                visit_number,
+                code,
+                segment_value(pr1, 5, 1, 1).unwrap_or_default(),
+            ],
+            config.procedure.system.clone(),
+        )?,
+    };
+
+    let mut coding = Coding::builder()
+        .system(OPS_SYSTEM.to_string())
+        .code(code.to_string())
+        .build()?;
+    coding.display = segment_value(pr1, 3, 1, 2).map(String::from);
+    // e.g. OPS2023
+    coding.version = segment_value(pr1, 3, 1, 3)
+        .map(|s| s.trim_start_matches(|c: char| !c.is_ascii_digit()))
+        .filter(|v| !v.is_empty())
+        .map(String::from);
+
+    let mut procedure = Procedure::builder()
+        .meta(get_meta_with_profile(&config.procedure.profile, config)?)
+        .identifier(vec![Some(identifier)])
+        .status(EventStatus::Completed)
+        .code(
+            CodeableConcept::builder()
+                .coding(vec![Some(coding)])
+                .build()?,
+        )
+        .subject(ctx.subject_ref(&config.person.system)?)
+        .encounter(einrichtungskontakt_ref(ctx, config)?)
+        .build()?;
+
+    procedure.category = map_category(code)?;
+    procedure.performed = performed.map(ProcedurePerformed::DateTime);
+
+    Ok(Some(procedure))
+}
+
+/// SNOMED CT category by OPS chapter
+fn map_category(code: &str) -> Result<Option<CodeableConcept>, MappingError> {
+    let category = match code.chars().next() {
+        Some('1') => ("103693007", "Diagnostic procedure"),
+        Some('3') => ("363679005", "Imaging"),
+        Some('5') => ("387713003", "Surgical procedure"),
+        Some('6') => ("18629005", "Administration of drug or medicament"),
+        Some('8') => ("277132007", "Therapeutic procedure"),
+        Some('9') => ("394841004", "Other category"),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(
+        CodeableConcept::builder()
+            .coding(vec![Some(
+                Coding::builder()
+                    .system(SNOMED_SYSTEM.to_string())
+                    .code(category.0.to_string())
+                    .display(category.1.to_string())
+                    .build()?,
+            )])
+            .build()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::tests::{get_test_config, read_test_message};
+    use fhir_model::r4b::resources::Resource;
+    use hl7_parser::Message;
+
+    #[test]
+    fn map_pr1_procedures() {
+        let hl7 = read_test_message(
+            "a01_test.hl7",
+            &[],
+            &[
+                "PR1|1||5-530.31^Verschluss einer Hernia umbilicalis^OPS2023||202211101400",
+                "PR1|2||^^OPS2023",
+            ],
+        );
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let config = get_test_config();
+
+        let result = map(
+            &MappingContext::new(&msg, &get_test_config()).unwrap(),
+            &config,
+        )
+        .unwrap();
+
+        // PR1 without code is skipped
+        assert_eq!(result.len(), 1);
+        let Some(Resource::Procedure(procedure)) = &result[0].resource else {
+            panic!("expected procedure");
+        };
+
+        let coding = procedure.code.as_ref().unwrap().coding[0].as_ref().unwrap();
+        assert_eq!(coding.code.as_deref(), Some("5-530.31"));
+        assert_eq!(coding.version.as_deref(), Some("2023"));
+        assert_eq!(
+            procedure.performed,
+            Some(ProcedurePerformed::DateTime(
+                parse_datetime("202211101400").unwrap()
+            ))
+        );
+        assert_eq!(
+            procedure.category.as_ref().unwrap().coding[0]
+                .as_ref()
+                .unwrap()
+                .code
+                .as_deref(),
+            Some("387713003")
+        );
+        assert_eq!(
+            procedure.encounter.as_ref().unwrap().reference.as_deref(),
+            Some(
+                format!(
+                    "Encounter?identifier={}|10000001",
+                    config.fall.einrichtungskontakt.system
+                )
+                .as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn map_pr1_invalid_date_without_profile() {
+        let hl7 = read_test_message(
+            "a01_test.hl7",
+            &[],
+            &["PR1|1||5-530.31^Verschluss einer Hernia umbilicalis^OPS2023||202213401400"],
+        );
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).expect("parse hl7 failed");
+        let mut config = get_test_config();
+        config.procedure.profile = String::new();
+
+        let result = map(&MappingContext::new(&msg, &config).unwrap(), &config).unwrap();
+
+        // the procedure is mapped without date and profile
+        let Some(Resource::Procedure(procedure)) = &result[0].resource else {
+            panic!("expected procedure");
+        };
+        assert_eq!(procedure.performed, None);
+        assert!(procedure.meta.as_ref().unwrap().profile.is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::config::ProtectedUnitsConfig;
+use crate::fhir::mapper::parse_fab;
+use crate::hl7::parser::{PV1_3_1, get_message_key, query};
+use fhir_model::r4b::resources::{BundleEntry, Encounter, Resource};
+use fhir_model::r4b::types::CodeableConcept;
+use hl7_parser::Message;
+use log::debug;
+
+/// Physical types of exact locations (room and bed), which are removed for protected units.
+const EXACT_LOCATION_TYPES: &[&str] = &["ro", "bd"];
+
+/// The message belongs to a protected (e.g. psychiatric) department (FAB) or ward (PV1-3.1).
+pub(super) fn is_protected(msg: &Message, config: &ProtectedUnitsConfig) -> bool {
+    parse_fab(msg).is_some_and(|fab| config.departments.iter().any(|d| d == fab))
+        || query(msg, PV1_3_1).is_some_and(|ward| config.wards.iter().any(|w| w == ward))
+}
+
+/// Suppresses the diagnoses and generalizes the locations of protected units to the ward: the
+/// `Condition` entries, `Encounter.diagnosis` and the room and bed locations are removed.
+pub(super) fn protect(
+    msg: &Message,
+    config: &ProtectedUnitsConfig,
+    entries: &mut Vec<BundleEntry>,
+) {
+    if !is_protected(msg, config) {
+        return;
+    }
+    debug!(
+        "Suppressing diagnoses and exact locations of protected unit, message id '{}'",
+        get_message_key(msg).unwrap_or_default()
+    );
+
+    entries.retain(|e| {
+        // the request url starts with the resource type, also for patch and delete
+        let is_condition = e
+            .request
+            .as_ref()
+            .and_then(|r| r.url.split(['?', '/']).next())
+            == Some("Condition");
+        let is_exact_location = matches!(
+            &e.resource,
+            Some(Resource::Location(l)) if is_exact(l.physical_type.as_ref())
+        );
+        !is_condition && !is_exact_location
+    });
+
+    for entry in entries.iter_mut() {
+        if let Some(Resource::Encounter(enc)) = &mut entry.resource {
+            protect_encounter(enc);
+        }
+    }
+}
+
+/// Removes the diagnoses and the room and bed locations of the encounter of a protected unit.
+pub(super) fn protect_encounter(enc: &mut Encounter) {
+    enc.diagnosis.clear();
+    enc.location.retain(|l| {
+        !l.as_ref()
+            .is_some_and(|l| is_exact(l.physical_type.as_ref()))
+    });
+}
+
+fn is_exact(physical_type: Option<&CodeableConcept>) -> bool {
+    physical_type
+        .into_iter()
+        .flat_map(|t| t.coding.iter().flatten())
+        .filter_map(|c| c.code.as_deref())
+        .any(|c| EXACT_LOCATION_TYPES.contains(&c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fhir::mapper::FhirMapper;
+    use crate::test_utils::tests::{get_test_config, read_test_resource};
+    use fhir_model::r4b::resources::Bundle;
+
+    fn entries(config: ProtectedUnitsConfig) -> Vec<BundleEntry> {
+        let mut fhir = get_test_config();
+        fhir.protected_units = config;
+        let bundle = FhirMapper::new(fhir)
+            .unwrap()
+            .map(&read_test_resource("a01_test.hl7"))
+            .unwrap()
+            .unwrap();
+
+        serde_json::from_str::<Bundle>(&bundle)
+            .unwrap()
+            .entry
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    fn count(entries: &[BundleEntry], resource_type: &str) -> usize {
+        entries
+            .iter()
+            .filter(|e| {
+                e.request
+                    .as_ref()
+                    .is_some_and(|r| r.url.starts_with(resource_type))
+            })
+            .count()
+    }
+
+    #[test]
+    fn protected_ward() {
+        let msg = Message::parse_with_lenient_newlines(&read_test_resource("a01_test.hl7"), true)
+            .unwrap();
+        let ward = query(&msg, PV1_3_1).unwrap().to_string();
+        let unprotected = entries(ProtectedUnitsConfig::default());
+        assert!(count(&unprotected, "Condition") > 0);
+
+        let protected = entries(ProtectedUnitsConfig {
+            departments: vec![],
+            wards: vec![ward],
+        });
+
+        assert_eq!(count(&protected, "Condition"), 0);
+        for entry in &protected {
+            match &entry.resource {
+                Some(Resource::Encounter(enc)) => {
+                    assert!(enc.diagnosis.is_empty());
+                    assert!(
+                        enc.location
+                            .iter()
+                            .flatten()
+                            .all(|l| !is_exact(l.physical_type.as_ref()))
+                    );
+                }
+                Some(Resource::Location(l)) => assert!(!is_exact(l.physical_type.as_ref())),
+                _ => {}
+            }
+        }
+        assert_eq!(count(&protected, "Patient"), count(&unprotected, "Patient"));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::config::Fhir;
+use crate::error::MappingError;
+use crate::fhir::mapper::{
+    EntryRequestType, build_usual_identifier, bundle_entry, unescape_identifier,
+};
+use fhir_model::r4b::resources::{BundleEntry, Location, Organization, Patient};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// e.g. `Location?identifier=system|value`
+static CONDITIONAL_REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([A-Za-z]+)\?identifier=([^|]+)\|(.+)$").unwrap());
+
+type TargetKey = (String, String, String);
+
+/// Replaces conditional references in the bundle by the `fullUrl` of the target entry.
+/// `Patient`, `Location` and `Organization` targets, which are not part of the bundle, are
+/// added as stubs (conditional create), so the references resolve within the transaction.
+pub(crate) fn inline_references(bundle: &mut Value, config: &Fhir) -> Result<(), MappingError> {
+    let Some(entries) = bundle.get_mut("entry").and_then(Value::as_array_mut) else {
+        return Ok(());
+    };
+
+    let mut targets: HashMap<TargetKey, String> = entries.iter().flat_map(entry_targets).collect();
+    let mut stubs = vec![];
+
+    for entry in entries.iter_mut() {
+        if let Some(resource) = entry.get_mut("resource") {
+            resolve(resource, &mut targets, &mut stubs, config)?;
+        }
+    }
+
+    entries.extend(stubs);
+
+    Ok(())
+}
+
+/// Identifiers of the entry, which can be referenced
+fn entry_targets(entry: &Value) -> Vec<(TargetKey, String)> {
+    let (Some(full_url), Some(resource)) = (
+        entry.get("fullUrl").and_then(Value::as_str),
+        entry.get("resource"),
+    ) else {
+        return vec![];
+    };
+    // deleted resources are no valid targets
+    if entry.pointer("/request/method").and_then(Value::as_str) == Some("DELETE") {
+        return vec![];
+    }
+    let resource_type = resource
+        .get("resourceType")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    resource
+        .get("identifier")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|id| {
+            Some((
+                (
+                    resource_type.to_string(),
+                    id.get("system")?.as_str()?.to_string(),
+                    id.get("value")?.as_str()?.to_string(),
+                ),
+                full_url.to_string(),
+            ))
+        })
+        .collect()
+}
+
+fn resolve(
+    value: &mut Value,
+    targets: &mut HashMap<TargetKey, String>,
+    stubs: &mut Vec<Value>,
+    config: &Fhir,
+) -> Result<(), MappingError> {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                match v {
+                    Value::String(reference) if key == "reference" => {
+                        let Some(target) = conditional_target(reference, config) else {
+                            continue;
+                        };
+                        if !targets.contains_key(&target)
+                            && let Some(stub) = stub_entry(&target, config)?
+                        {
+                            stubs.push(
+                                serde_json::to_value(&stub)
+                                    .expect("failed to serialize bundle entry"),
+                            );
+                            targets.insert(target.clone(), stub.full_url.unwrap_or_default());
+                        }
+                        if let Some(full_url) = targets.get(&target) {
+                            *reference = full_url.clone();
+                        }
+                    }
+                    _ => resolve(v, targets, stubs, config)?,
+                }
+            }
+        }
+        Value::Array(values) => {
+            for v in values {
+                resolve(v, targets, stubs, config)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn conditional_target(reference: &str, config: &Fhir) -> Option<TargetKey> {
+    // absolute references are prefixed with the base url (see `canonical_ref`)
+    let reference = config
+        .base_url
+        .as_deref()
+        .and_then(|base_url| {
+            reference
+                .strip_prefix(base_url.trim_end_matches('/'))?
+                .strip_prefix('/')
+        })
+        .unwrap_or(reference);
+    let captures = CONDITIONAL_REFERENCE.captures(reference)?;
+
+    Some((
+        captures[1].to_string(),
+        captures[2].to_string(),
+        unescape_identifier(&captures[3], config.identifier_escaping()),
+    ))
+}
+
+/// Resource with only the identifier, created if it does not exist. Other resource types
+/// are not stubbed, their references are kept.
+fn stub_entry(
+    (resource_type, system, value): &TargetKey,
+    config: &Fhir,
+) -> Result<Option<BundleEntry>, MappingError> {
+    let identifier = vec![Some(build_usual_identifier(
+        vec![value.as_str()],
+        system.to_string(),
+    )?)];
+
+    let entry = match resource_type.as_str() {
+        "Patient" => bundle_entry(
+            Patient::builder().identifier(identifier).build()?,
+            EntryRequestType::ConditionalCreate,
+            config,
+        )?,
+        "Location" => bundle_entry(
+            Location::builder().identifier(identifier).build()?,
+            EntryRequestType::ConditionalCreate,
+            config,
+        )?,
+        "Organization" => bundle_entry(
+            Organization::builder().identifier(identifier).build()?,
+            EntryRequestType::ConditionalCreate,
+            config,
+        )?,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReferenceResolution;
+    use crate::fhir::mapper::FhirMapper;
+    use crate::test_utils::tests::{get_dummy_resources, get_test_config, read_test_resource};
+
+    fn references(value: &Value, result: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => map.iter().for_each(|(k, v)| match v {
+                Value::String(r) if k == "reference" => result.push(r.clone()),
+                _ => references(v, result),
+            }),
+            Value::Array(values) => values.iter().for_each(|v| references(v, result)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn conditional_target_of_absolute_reference() {
+        let mut config = get_test_config();
+        config.base_url = Some("http://localhost/fhir/".to_string());
+        let expected = Some((
+            "Location".to_string(),
+            "http://fhir.local/location".to_string(),
+            "ST01".to_string(),
+        ));
+
+        assert_eq!(
+            conditional_target(
+                "Location?identifier=http://fhir.local/location|ST01",
+                &config
+            ),
+            expected
+        );
+        assert_eq!(
+            conditional_target(
+                "http://localhost/fhir/Location?identifier=http://fhir.local/location|ST01",
+                &config
+            ),
+            expected
+        );
+        // other servers are not resolved
+        assert_eq!(
+            conditional_target(
+                "http://remote/fhir/Location?identifier=http://fhir.local/location|ST01",
+                &config
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn inline_references_resolve_within_bundle() {
+        let mut config = get_test_config();
+        config.reference_resolution = Some(ReferenceResolution::Inline);
+        let mapper = FhirMapper {
+            config,
+            resources: get_dummy_resources(),
+            history: Default::default(),
+            exclusion: Default::default(),
+        };
+
+        let mapped = mapper
+            .map(&read_test_resource("a01_test.hl7"))
+            .unwrap()
+            .unwrap();
+        let bundle: Value = serde_json::from_str(&mapped).unwrap();
+
+        let full_urls: Vec<&str> = bundle["entry"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|e| e["fullUrl"].as_str())
+            .collect();
+        let mut refs = vec![];
+        references(&bundle, &mut refs);
+
+        assert!(!refs.is_empty());
+        for r in refs {
+            assert!(
+                !["Patient?", "Location?", "Organization?"]
+                    .iter()
+                    .any(|t| r.starts_with(t)),
+                "reference '{r}' was not inlined"
+            );
+            if r.starts_with("urn:uuid:") {
+                assert!(full_urls.contains(&r.as_str()));
+            }
+        }
+    }
+}
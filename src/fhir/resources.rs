@@ -9,7 +9,7 @@ use log::{Level, log};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -54,6 +54,18 @@ pub(crate) struct ValidPeriod {
     pub(crate) valid_to: Option<NaiveDate>,
 }
 
+/// Gemeinde
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[derive(Clone)]
+pub(crate) struct Municipality {
+    /// amtlicher Gemeindeschlüssel (AGS)
+    pub(crate) gemeindeschluessel: String,
+    /// Kreis
+    #[serde(default)]
+    pub(crate) district: Option<String>,
+}
+
 /// Mappings for Fachabteilung (encounter department and location)
 pub(crate) struct ResourceMap {
     /// Map with key: Fachabteilungsschlüssel
@@ -62,6 +74,8 @@ pub(crate) struct ResourceMap {
     pub(crate) ward_map: HashMap<String, Ward>,
     /// Map medical department id (Fachabteilungschluessel) as key to its official name
     pub(crate) department_id_map: HashMap<String, String>,
+    /// Map with key: postal code or county code of the HIS
+    pub(crate) municipality_map: HashMap<String, Municipality>,
 }
 
 impl ResourceMap {
@@ -73,11 +87,28 @@ impl ResourceMap {
     /// [department_map](ResourceMap::department_map): `InfoByAbteilungskuerzel.json`
     ///
     /// [ward_map](ResourceMap::ward_map): `InfoStation.json`
-    pub(crate) fn new() -> Result<Self, anyhow::Error> {
+    ///
+    /// [municipality_map](ResourceMap::municipality_map): `Gemeindeschluessel.json`, only if
+    /// [`person.municipality_key`](crate::config::PatientConfig::municipality_key) is configured
+    pub(crate) fn new(config: &Fhir) -> Result<Self, anyhow::Error> {
+        Self::from_dir(
+            &PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/mapping"),
+            config,
+        )
+    }
+
+    /// Same as [`ResourceMap::new`], but the json files are read from `dir`.
+    pub(crate) fn from_dir(dir: &Path, config: &Fhir) -> Result<Self, anyhow::Error> {
+        let municipality_map = match config.person.municipality_key {
+            Some(_) => init_municipality_map(dir)?,
+            None => HashMap::new(),
+        };
+
         Ok(ResourceMap {
-            department_map: init_department_map()?,
-            ward_map: init_ward_map()?,
-            department_id_map: init_departments_id_map()?,
+            department_map: init_department_map(dir)?,
+            ward_map: init_ward_map(dir)?,
+            department_id_map: init_departments_id_map(dir)?,
+            municipality_map,
         })
     }
 
@@ -220,28 +251,33 @@ pub(crate) fn is_valid_date(period: &ValidPeriod, date: &NaiveDate) -> bool {
         && (period.valid_to.is_none() || date.le(&period.valid_to.unwrap_or(NaiveDate::MAX)))
 }
 
-fn init_department_map() -> Result<HashMap<String, Department>, anyhow::Error> {
-    let resource_data = read_mapping_resource("InfoByAbteilungskuerzel.json")?;
+fn init_department_map(dir: &Path) -> Result<HashMap<String, Department>, anyhow::Error> {
+    let resource_data = read_mapping_resource(dir, "InfoByAbteilungskuerzel.json")?;
 
     Ok(serde_json::from_str(&resource_data)?)
 }
 
-fn init_ward_map() -> Result<HashMap<String, Ward>, anyhow::Error> {
-    let resource_data = read_mapping_resource("InfoStation.json")?;
+fn init_ward_map(dir: &Path) -> Result<HashMap<String, Ward>, anyhow::Error> {
+    let resource_data = read_mapping_resource(dir, "InfoStation.json")?;
 
     Ok(serde_json::from_str(&resource_data)?)
 }
 
-fn read_mapping_resource(file_name: &str) -> Result<String, anyhow::Error> {
-    let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    file_path.push("resources/mapping");
-    file_path.push(file_name);
+fn init_municipality_map(dir: &Path) -> Result<HashMap<String, Municipality>, anyhow::Error> {
+    let resource_data = read_mapping_resource(dir, "Gemeindeschluessel.json")?;
 
-    Ok(fs::read_to_string(file_path.display().to_string())?)
+    Ok(serde_json::from_str(&resource_data)?)
+}
+
+fn read_mapping_resource(dir: &Path, file_name: &str) -> Result<String, anyhow::Error> {
+    let file_path = dir.join(file_name);
+
+    fs::read_to_string(&file_path)
+        .with_context(|| format!("failed to read {}", file_path.display()))
 }
 
-fn init_departments_id_map() -> Result<HashMap<String, String>, anyhow::Error> {
-    let resource_data = read_mapping_resource("Fachabteilungsschluessel-erweitert.json")
+fn init_departments_id_map(dir: &Path) -> Result<HashMap<String, String>, anyhow::Error> {
+    let resource_data = read_mapping_resource(dir, "Fachabteilungsschluessel-erweitert.json")
         .context("Konnte Fachabteilungsschluessel-erweitert.json nicht lesen")?;
 
     let code_system: CodeSystem = serde_json::from_str(&resource_data)
@@ -265,9 +301,11 @@ fn init_departments_id_map() -> Result<HashMap<String, String>, anyhow::Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::MunicipalityKey;
     use crate::fhir::resources::{Department, ResourceMap};
     use crate::test_utils::tests::{get_dummy_resources, get_test_config};
     use std::collections::HashMap;
+    use std::{env, process};
 
     #[test]
     fn test_map_fab_schluessel() {
@@ -291,6 +329,7 @@ mod tests {
             ]),
             ward_map: Default::default(),
             department_id_map: get_dummy_resources().department_id_map.clone(),
+            municipality_map: Default::default(),
         };
 
         let expected = Coding::builder()
@@ -414,8 +453,33 @@ mod tests {
 
     #[test]
     fn test_init_department_map() {
-        let r = ResourceMap::new().unwrap();
+        let r = ResourceMap::new(&get_test_config()).unwrap();
         assert!(!r.department_map.is_empty());
         assert!(!r.ward_map.is_empty());
     }
+
+    #[test]
+    fn test_init_municipality_map_if_configured() {
+        // mapping files without Gemeindeschluessel.json
+        let mapping = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/mapping");
+        let dir = env::temp_dir().join(format!("adt-to-fhir-mapping-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for file in [
+            "InfoByAbteilungskuerzel.json",
+            "InfoStation.json",
+            "Fachabteilungsschluessel-erweitert.json",
+        ] {
+            fs::copy(mapping.join(file), dir.join(file)).unwrap();
+        }
+        let mut config = get_test_config();
+
+        config.person.municipality_key = None;
+        let resources = ResourceMap::from_dir(&dir, &config);
+        config.person.municipality_key = Some(MunicipalityKey::PostalCode);
+        let missing = ResourceMap::from_dir(&dir, &config);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(resources.unwrap().municipality_map.is_empty());
+        assert!(missing.is_err());
+    }
 }
@@ -0,0 +1,129 @@
+use crate::config::{Fhir, Role};
+use crate::error::MappingError;
+use crate::fhir::mapper::{EntryRequestType, bundle_entry};
+use fhir_model::r4b::codes::HTTPVerb;
+use fhir_model::r4b::resources::{BundleEntry, Resource};
+
+/// Resource types of the `demographics` role, in addition to the managing organization.
+const DEMOGRAPHIC_TYPES: &[&str] = &["Patient", "RelatedPerson"];
+
+/// Restricts the entries to the resources of the configured [`Role`]. The `encounters` role
+/// keeps the patients as conditional create, which is skipped if no other entry is left.
+pub(super) fn apply(config: &Fhir, entries: &mut Vec<BundleEntry>) -> Result<(), MappingError> {
+    match config.role {
+        Role::Full => {}
+        Role::Demographics => entries.retain(|e| is_demographic(e, config)),
+        Role::Encounters => {
+            entries.retain(|e| !is_demographic(e, config) || is_patient_upsert(e));
+            if entries.iter().all(|e| resource_type(e) == Some("Patient")) {
+                entries.clear();
+            }
+            for entry in entries.iter_mut() {
+                if let Some(Resource::Patient(patient)) = &entry.resource
+                    && resource_type(entry) == Some("Patient")
+                {
+                    *entry =
+                        bundle_entry(patient.clone(), EntryRequestType::ConditionalCreate, config)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Resource type of the entry by the request url, which starts with the resource type, also
+/// for patch and delete.
+fn resource_type(entry: &BundleEntry) -> Option<&str> {
+    entry
+        .request
+        .as_ref()
+        .and_then(|r| r.url.split(['?', '/']).next())
+}
+
+fn is_demographic(entry: &BundleEntry, config: &Fhir) -> bool {
+    resource_type(entry).is_some_and(|t| DEMOGRAPHIC_TYPES.contains(&t))
+        || is_managing_org(entry, config)
+}
+
+fn is_managing_org(entry: &BundleEntry, config: &Fhir) -> bool {
+    let Some(managing) = &config.organization.managing else {
+        return false;
+    };
+    matches!(
+        &entry.resource,
+        Some(Resource::Organization(org)) if org.identifier.iter().flatten().any(|id| {
+            id.system.as_ref() == Some(&managing.system) && id.value.as_ref() == Some(&managing.value)
+        })
+    )
+}
+
+/// Created or updated patient, i.e. no merge (patch) or deletion.
+fn is_patient_upsert(entry: &BundleEntry) -> bool {
+    matches!(entry.resource, Some(Resource::Patient(_)))
+        && entry
+            .request
+            .as_ref()
+            .is_some_and(|r| matches!(r.method, HTTPVerb::Put | HTTPVerb::Post))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fhir::mapper::FhirMapper;
+    use crate::test_utils::tests::{get_test_config, read_test_resource};
+    use fhir_model::r4b::resources::Bundle;
+
+    fn entries(role: Role) -> Vec<BundleEntry> {
+        let mut fhir = get_test_config();
+        fhir.role = role;
+        FhirMapper::new(fhir)
+            .unwrap()
+            .map(&read_test_resource("a01_test.hl7"))
+            .unwrap()
+            .map(|b| serde_json::from_str::<Bundle>(&b).unwrap())
+            .into_iter()
+            .flat_map(|b| b.entry)
+            .flatten()
+            .collect()
+    }
+
+    fn types(entries: &[BundleEntry]) -> Vec<&str> {
+        entries.iter().filter_map(resource_type).collect()
+    }
+
+    #[test]
+    fn demographics_role() {
+        let entries = entries(Role::Demographics);
+
+        assert!(types(&entries).contains(&"Patient"));
+        assert!(
+            types(&entries)
+                .iter()
+                .all(|t| DEMOGRAPHIC_TYPES.contains(t))
+        );
+    }
+
+    #[test]
+    fn encounters_role() {
+        let full = entries(Role::Full);
+        let entries = entries(Role::Encounters);
+
+        assert!(types(&entries).contains(&"Encounter"));
+        assert!(!types(&entries).contains(&"RelatedPerson"));
+        assert_eq!(
+            entries.len(),
+            full.iter()
+                .filter(|e| resource_type(e) != Some("RelatedPerson"))
+                .count()
+        );
+        // patient as conditional create
+        let patient = entries
+            .iter()
+            .find(|e| resource_type(e) == Some("Patient"))
+            .unwrap();
+        let request = patient.request.as_ref().unwrap();
+        assert_eq!(request.method, HTTPVerb::Post);
+        assert!(request.if_none_exist.is_some());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::config::IdentifierSelection;
+use crate::fhir::mapper::selected_identifier;
+use fhir_model::r4b::resources::Encounter;
+use fhir_model::r4b::types::{Coding, Identifier};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, PoisonError};
+
+/// maximum number of movements kept per visit number
+const MAX_MOVEMENTS: usize = 5;
+
+/// Last known 'Versorgungsstellenkontakt' encounters (i.e. patient locations)
+/// per visit number.
+///
+/// A cancelled transfer (A12) identifies the cancelled movement only, so the
+/// location before the transfer must be known to revert it. The history is
+/// changed by the [`LocationUpdate`]s of a mapped message, once its bundle was
+/// produced. Movements are identified by the configured identifier selection.
+///
+/// __note:__ the state is kept in memory and is therefore lost on restart
+#[derive(Default)]
+pub(crate) struct LocationHistory {
+    visits: Mutex<HashMap<String, VecDeque<Encounter>>>,
+    selection: IdentifierSelection,
+}
+
+/// Change of the [`LocationHistory`] by a mapped message.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum LocationUpdate {
+    /// encounter of the latest movement of the visit
+    Record(String, Encounter),
+    /// cancelled or deleted movement of the visit
+    Remove(String, Identifier),
+    /// all movements of the visit
+    Clear(String),
+    /// class of all movements of the visit
+    Class(String, Coding),
+}
+
+impl LocationHistory {
+    pub(crate) fn new(selection: IdentifierSelection) -> Self {
+        LocationHistory {
+            visits: Mutex::default(),
+            selection,
+        }
+    }
+
+    pub(crate) fn apply(&self, updates: Vec<LocationUpdate>) {
+        for update in updates {
+            match update {
+                LocationUpdate::Record(visit_number, enc) => self.record(&visit_number, enc),
+                LocationUpdate::Remove(visit_number, movement) => {
+                    self.remove(&visit_number, &movement)
+                }
+                LocationUpdate::Clear(visit_number) => self.clear(&visit_number),
+                LocationUpdate::Class(visit_number, class) => self.set_class(&visit_number, class),
+            }
+        }
+    }
+
+    /// Returns the encounter of the movement before the cancelled one, if known.
+    pub(crate) fn previous(&self, visit_number: &str, cancelled: &Identifier) -> Option<Encounter> {
+        let visits = self.visits.lock().unwrap_or_else(PoisonError::into_inner);
+
+        visits
+            .get(visit_number)?
+            .iter()
+            .rev()
+            .find(|m| self.movement_id(m) != Some(cancelled))
+            .cloned()
+    }
+
+    /// Returns the encounters of the recorded movements of the visit, the latest last.
+    pub(crate) fn movements(&self, visit_number: &str) -> Vec<Encounter> {
+        let visits = self.visits.lock().unwrap_or_else(PoisonError::into_inner);
+
+        visits
+            .get(visit_number)
+            .map(|movements| movements.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Records the encounter of the latest movement. Updates of the same
+    /// movement replace the recorded encounter.
+    fn record(&self, visit_number: &str, enc: Encounter) {
+        let mut visits = self.visits.lock().unwrap_or_else(PoisonError::into_inner);
+        let movements = visits.entry(visit_number.to_string()).or_default();
+
+        if let Some(i) = movements
+            .iter()
+            .position(|m| self.movement_id(m) == self.movement_id(&enc))
+        {
+            movements.remove(i);
+        }
+        movements.push_back(enc);
+
+        if movements.len() > MAX_MOVEMENTS {
+            movements.pop_front();
+        }
+    }
+
+    fn remove(&self, visit_number: &str, movement: &Identifier) {
+        let mut visits = self.visits.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(movements) = visits.get_mut(visit_number) {
+            movements.retain(|m| self.movement_id(m) != Some(movement));
+        }
+    }
+
+    /// Forgets all movements of the visit.
+    fn clear(&self, visit_number: &str) {
+        self.visits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(visit_number);
+    }
+
+    fn set_class(&self, visit_number: &str, class: Coding) {
+        let mut visits = self.visits.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(movements) = visits.get_mut(visit_number) {
+            movements.iter_mut().for_each(|m| m.class = class.clone());
+        }
+    }
+
+    fn movement_id<'a>(&self, enc: &'a Encounter) -> Option<&'a Identifier> {
+        selected_identifier(&enc.identifier, &self.selection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fhir_model::r4b::codes::{EncounterStatus, IdentifierUse};
+
+    fn movement(id: &str) -> Encounter {
+        Encounter::builder()
+            .status(EncounterStatus::InProgress)
+            .class(Coding::builder().build().unwrap())
+            .identifier(vec![Some(usual_identifier(id))])
+            .build()
+            .unwrap()
+    }
+
+    fn usual_identifier(id: &str) -> Identifier {
+        Identifier::builder()
+            .system("ward-id".to_string())
+            .value(id.to_string())
+            .r#use(IdentifierUse::Usual)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn previous_movement() {
+        let history = LocationHistory::default();
+        history.record("1", movement("a"));
+        history.record("1", movement("b"));
+        // update of the same movement
+        history.record("1", movement("b"));
+
+        assert_eq!(
+            history.previous("1", &usual_identifier("b")),
+            Some(movement("a"))
+        );
+        // the history is changed by the applied updates only
+        assert_eq!(
+            history.previous("1", &usual_identifier("b")),
+            Some(movement("a"))
+        );
+
+        history.apply(vec![LocationUpdate::Remove(
+            "1".to_string(),
+            usual_identifier("b"),
+        )]);
+        assert_eq!(history.previous("1", &usual_identifier("a")), None);
+    }
+
+    #[test]
+    fn previous_of_unknown_visit() {
+        let history = LocationHistory::default();
+        history.apply(vec![
+            LocationUpdate::Record("1".to_string(), movement("a")),
+            LocationUpdate::Clear("1".to_string()),
+        ]);
+
+        assert_eq!(history.previous("1", &usual_identifier("b")), None);
+        assert_eq!(history.previous("2", &usual_identifier("a")), None);
+    }
+
+    #[test]
+    fn class_of_movements() {
+        let history = LocationHistory::default();
+        let class = Coding::builder().code("IMP".to_string()).build().unwrap();
+        history.apply(vec![
+            LocationUpdate::Record("1".to_string(), movement("a")),
+            LocationUpdate::Record("1".to_string(), movement("b")),
+            LocationUpdate::Class("1".to_string(), class.clone()),
+        ]);
+
+        let movements = history.movements("1");
+        assert_eq!(movements.len(), 2);
+        assert!(movements.iter().all(|m| m.class == class));
+        assert!(history.movements("2").is_empty());
+    }
+
+    #[test]
+    fn record_keeps_latest_movements() {
+        let history = LocationHistory::default();
+        (0..=MAX_MOVEMENTS).for_each(|i| history.record("1", movement(&i.to_string())));
+
+        let visits = history.visits.lock().unwrap();
+        let movements = visits.get("1").unwrap();
+        assert_eq!(movements.len(), MAX_MOVEMENTS);
+        assert_eq!(movements.front(), Some(&movement("1")));
+    }
+}
@@ -1 +1,2 @@
+pub(crate) mod message;
 pub(crate) mod parser;
@@ -0,0 +1,90 @@
+/// Read access to a parsed HL7 v2 message for the message level helpers of
+/// [`crate::hl7::parser`] (e.g. [`crate::hl7::parser::query`],
+/// [`crate::hl7::parser::message_type`]), which are implemented on top of this trait.
+///
+/// __note:__ only these helpers are independent of the parser. The resource mappers in
+/// [`crate::fhir`] still take the `hl7-parser` types ([`hl7_parser::Message`],
+/// [`hl7_parser::message::Segment`], [`hl7_parser::message::Repeat`]), so another parser backend
+/// requires changes of the mapping modules as well.
+pub(crate) trait Hl7Message {
+    /// Raw value at the location (e.g. `PID.3.1`), [`None`] if missing or empty.
+    fn value(&self, location: &str) -> Option<&str>;
+
+    /// Raw field values (starting with field 1) of the first segment with the given name.
+    fn segment_fields(&self, name: &str) -> Option<Vec<&str>>;
+}
+
+impl<T: Hl7Message + ?Sized> Hl7Message for &T {
+    fn value(&self, location: &str) -> Option<&str> {
+        (**self).value(location)
+    }
+
+    fn segment_fields(&self, name: &str) -> Option<Vec<&str>> {
+        (**self).segment_fields(name)
+    }
+}
+
+impl Hl7Message for hl7_parser::Message<'_> {
+    fn value(&self, location: &str) -> Option<&str> {
+        self.query(location)
+            .map(|l| l.raw_value())
+            .filter(|s| !s.is_empty())
+    }
+
+    fn segment_fields(&self, name: &str) -> Option<Vec<&str>> {
+        let segment = self.segment(name)?;
+
+        Some(
+            (1..)
+                .map_while(|i| segment.field(i))
+                .map(|f| f.raw_value())
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::MessageTypeSource;
+    use crate::hl7::message::Hl7Message;
+    use crate::hl7::parser::{MessageType, get_message_key, message_type, query};
+    use hl7_parser::Message;
+    use std::collections::HashMap;
+
+    /// Minimal backend, as an alternative parser would implement it
+    struct MapMessage(HashMap<&'static str, &'static str>);
+
+    impl Hl7Message for MapMessage {
+        fn value(&self, location: &str) -> Option<&str> {
+            self.0.get(location).copied().filter(|s| !s.is_empty())
+        }
+
+        fn segment_fields(&self, _: &str) -> Option<Vec<&str>> {
+            None
+        }
+    }
+
+    #[test]
+    fn helpers_with_alternative_backend() {
+        let msg = MapMessage(HashMap::from([
+            ("MSH.10", "62293727"),
+            ("MSH.9.2", "A01"),
+            ("PID.2", ""),
+        ]));
+
+        assert_eq!(get_message_key(&msg).unwrap(), "62293727");
+        assert_eq!(
+            message_type(&msg, MessageTypeSource::Msh).unwrap(),
+            MessageType::A01
+        );
+        assert_eq!(query(&msg, "PID.2"), None);
+    }
+
+    #[test]
+    fn segment_fields() {
+        let msg = Message::parse("MSH|^~\\&|ORBIS|KH\rPV1|1|I||R^01").unwrap();
+
+        assert_eq!(msg.segment_fields("PV1"), Some(vec!["1", "I", "", "R^01"]));
+        assert_eq!(msg.segment_fields("PID"), None);
+    }
+}
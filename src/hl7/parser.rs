@@ -1,10 +1,15 @@
+use crate::config::MessageTypeSource;
 use crate::error::MessageTypeError::MissingMessageType;
 use crate::error::{MessageTypeError, ParsingError};
+use crate::hl7::message::Hl7Message;
 use crate::hl7::parser::MessageType::*;
 use anyhow::anyhow;
+use chrono::{NaiveDateTime, TimeZone};
+use chrono_tz::Europe::Berlin;
 use hl7_parser::Message;
 use hl7_parser::message::{Repeat, Segment};
 use hl7_parser::query::LocationQueryResult;
+use serde::Deserialize;
 use std::fmt;
 use std::fmt::Display;
 use std::str::FromStr;
@@ -13,16 +18,36 @@ use std::str::FromStr;
 ///
 /// __note:__ only used at correction of patient data (e.g. merge operation)
 pub(crate) const MRG_1: &str = "MRG.1";
+/// old patient identifier value, without the assigning authority
+pub(crate) const MRG_1_1: &str = "MRG.1.1";
+/// prior visit number (medical case id)
+///
+/// __note:__ only used at correction of the visit number (A50)
+pub(crate) const MRG_5_1: &str = "MRG.5.1";
 
 /// message key
 ///
 /// __note:__ always present
 pub(crate) const MSH_10: &str = "MSH.10";
+/// trigger event
+pub(crate) const MSH_9_2: &str = "MSH.9.2";
+/// sending facility
+pub(crate) const MSH_4_1: &str = "MSH.4.1";
+/// message creation date time
+pub(crate) const MSH_7: &str = "MSH.7";
+/// event type code
+pub(crate) const EVN_1: &str = "EVN.1";
+/// event occurred date time
+pub(crate) const EVN_2: &str = "EVN.2";
 
 /// patient identifier
 ///
 /// __note:__ always present (preferred before PID.3)
 pub(crate) const PID_2: &str = "PID.2";
+/// patient identifier, without the assigning authority
+pub(crate) const PID_2_1: &str = "PID.2.1";
+/// patient identifier list
+pub(crate) const PID_3: &str = "PID.3";
 /// patient identifier list
 ///
 /// __note:__ always present
@@ -35,12 +60,30 @@ pub(crate) const PID_4: &str = "PID.4";
 /// PID.5.7 (L) legal name, (M) maiden name
 /// __note:__ repeats and components inside
 pub(crate) const PID_5: &str = "PID.5";
+/// birth name (mother's maiden name)
+///
+/// __note:__ repeats and components inside
+pub(crate) const PID_6: &str = "PID.6";
 /// patient birthdate
 pub(crate) const PID_7: &str = "PID.7";
 /// patient gender
 pub(crate) const PID_8: &str = "PID.8";
+/// home phone / email
+///
+/// __note:__ repeats and components inside
+pub(crate) const PID_13: &str = "PID.13";
+/// business phone / email
+///
+/// __note:__ repeats and components inside
+pub(crate) const PID_14: &str = "PID.14";
+/// primary language
+pub(crate) const PID_15_1: &str = "PID.15.1";
 /// marital status
 pub(crate) const PID_16_1: &str = "PID.16.1";
+/// religion
+pub(crate) const PID_17_1: &str = "PID.17.1";
+/// patient account number (_Abrechnungsfall_)
+pub(crate) const PID_18_1: &str = "PID.18.1";
 
 /// mothers encounter number
 ///
@@ -81,10 +124,22 @@ pub(crate) const PV1_4_1: &str = "PV1.4.1";
 ///
 /// digit 3 & 4
 pub(crate) const PV1_4__2_1: &str = "PV1.4[2].1";
+/// hospital service
+///
+/// __note:__ used as clinical department code by some senders
+pub(crate) const PV1_10_1: &str = "PV1.10.1";
+/// re-admission indicator (R)
+pub(crate) const PV1_13: &str = "PV1.13";
+/// VIP indicator
+pub(crate) const PV1_16: &str = "PV1.16";
+/// patient type
+pub(crate) const PV1_18: &str = "PV1.18";
 /// encounter number (medical case id)
 ///
 /// __note:__ usually set, may be missing first messages at encounter planning
 pub(crate) const PV1_19_1: &str = "PV1.19.1";
+/// financial class (e.g. DRG, PEPP, ambulant billing)
+pub(crate) const PV1_20_1: &str = "PV1.20.1";
 /// discharge reason
 pub(crate) const PV1_36_1: &str = "PV1.36.1";
 /// clinical department code (german §301 Fachabteilungsschlüssel)
@@ -102,6 +157,12 @@ pub(crate) const PV1_45: &str = "PV1.45";
 ///
 /// digit 1 & 2
 pub(crate) const PV2_3_1: &str = "PV2.3.1";
+/// expected admit date/time
+pub(crate) const PV2_8: &str = "PV2.8";
+/// expected discharge date/time
+pub(crate) const PV2_9: &str = "PV2.9";
+/// visit priority code (1 emergency, 2 urgent, 3 elective)
+pub(crate) const PV2_25: &str = "PV2.25";
 
 /// patient movement identifier
 ///
@@ -115,6 +176,10 @@ pub(crate) const ZBE_2: &str = "ZBE.2.1";
 ///
 /// __note:__ mandatory present at most message types. Missing at message types: A28-A34, A40-A47
 pub(crate) const ZBE_3: &str = "ZBE.3.1";
+/// movement action (INSERT, UPDATE, DELETE)
+pub(crate) const ZBE_4: &str = "ZBE.4";
+/// responsible medical department (organization identifier)
+pub(crate) const ZBE_7_10: &str = "ZBE.7.10";
 
 /// birth weight
 ///
@@ -129,7 +194,7 @@ pub(crate) const ZNG_11: &str = "ZNG.11";
 /// __note:__ segment only at birth context present
 pub(crate) const ZNG_6: &str = "ZNG.6";
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
 pub enum MessageType {
     /// Admit
     A01,
@@ -221,17 +286,89 @@ impl FromStr for MessageType {
     }
 }
 
-pub(crate) fn message_type(msg: &Message) -> Result<MessageType, MessageTypeError> {
-    MessageType::from_str(
-        msg.segment("EVN")
-            .ok_or(MissingMessageType("missing EVN segment".to_string()))?
-            .field(1)
-            .ok_or(MissingMessageType(
-                "missing message type segment".to_string(),
-            ))?
-            .raw_value(),
+/// Message type (trigger event) from EVN-1 or MSH-9.2, the preferred `source` with the other one
+/// as fallback.
+pub(crate) fn message_type<M: Hl7Message + ?Sized>(
+    msg: &M,
+    source: MessageTypeSource,
+) -> Result<MessageType, MessageTypeError> {
+    let evn = query(msg, EVN_1);
+    // e.g. ADT^A11_A09
+    let msh = query(msg, MSH_9_2).and_then(|t| t.split('_').next());
+
+    let value = match source {
+        MessageTypeSource::Evn => evn.or(msh),
+        MessageTypeSource::Msh => msh.or(evn),
+    };
+
+    MessageType::from_str(value.ok_or(MissingMessageType(
+        "missing message type in EVN-1 and MSH-9.2".to_string(),
+    ))?)
+}
+/// Header values of the message, which are read once per record for the processing around the
+/// mapping (e.g. CloudEvents attributes, audit log, sequence check).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct MessageInfo {
+    /// message control id (MSH-10)
+    pub(crate) control_id: Option<String>,
+    /// sending facility (MSH-4.1)
+    pub(crate) sending_facility: Option<String>,
+    /// trigger event (MSH-9.2), e.g. `A11_A09`
+    pub(crate) trigger_event: Option<String>,
+    /// event type code (EVN-1)
+    pub(crate) event_type: Option<String>,
+    /// message creation time (MSH-7) in milliseconds
+    pub(crate) created: Option<i64>,
+    /// event occurred time (EVN-2) in milliseconds
+    pub(crate) event_time: Option<i64>,
+    /// patient identifier (PID-2.1)
+    pub(crate) patient_id: Option<String>,
+    /// old patient identifier of merges (MRG-1.1)
+    pub(crate) prior_patient_id: Option<String>,
+    /// visit number (PV1-19.1)
+    pub(crate) visit_number: Option<String>,
+    /// admit time (PV1-44) in milliseconds
+    pub(crate) admit_time: Option<i64>,
+    /// discharge time (PV1-45) in milliseconds
+    pub(crate) discharge_time: Option<i64>,
+}
+
+impl MessageInfo {
+    pub(crate) fn new<M: Hl7Message + ?Sized>(msg: &M) -> Self {
+        let value = |location| query(msg, location).map(String::from);
+        let timestamp = |location| query(msg, location).and_then(timestamp_millis);
+
+        MessageInfo {
+            control_id: value(MSH_10),
+            sending_facility: value(MSH_4_1),
+            trigger_event: value(MSH_9_2),
+            event_type: value(EVN_1),
+            created: timestamp(MSH_7),
+            event_time: timestamp(EVN_2),
+            patient_id: value(PID_2_1),
+            prior_patient_id: value(MRG_1_1),
+            visit_number: value(PV1_19_1),
+            admit_time: timestamp(PV1_44),
+            discharge_time: timestamp(PV1_45),
+        }
+    }
+}
+
+/// HL7 timestamp (local time, at least minute precision) as unix timestamp in milliseconds.
+pub(crate) fn timestamp_millis(value: &str) -> Option<i64> {
+    let dt = match value.get(..14) {
+        Some(s) => NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M%S").ok(),
+        None => NaiveDateTime::parse_from_str(value.get(..12)?, "%Y%m%d%H%M").ok(),
+    }?;
+
+    Some(
+        Berlin
+            .from_local_datetime(&dt)
+            .earliest()?
+            .timestamp_millis(),
     )
 }
+
 /// Query message value by location.
 ///
 /// # Examples
@@ -239,10 +376,8 @@ pub(crate) fn message_type(msg: &Message) -> Result<MessageType, MessageTypeErro
 /// let value = query(msg, "PID.1");
 /// ```
 /// [`None`] is returned if segments are empty or missing.
-pub(crate) fn query<'a>(msg: &'a Message<'_>, location: &str) -> Option<&'a str> {
-    msg.query(location)
-        .map(|l| l.raw_value())
-        .filter(|s| !s.is_empty())
+pub(crate) fn query<'a, M: Hl7Message + ?Sized>(msg: &'a M, location: &str) -> Option<&'a str> {
+    msg.value(location)
 }
 
 /// Get component value of a repeating field.
@@ -255,6 +390,21 @@ pub(crate) fn repeat_component<'a>(repeat: &Repeat<'a>, component: usize) -> Opt
         .filter(|s| !s.is_empty())
 }
 
+/// Get a subcomponent value of a repeating field.
+///
+/// Returns non-empty string slices ([`Option<&str>`]) or [`None`].
+pub(crate) fn repeat_subcomponent<'a>(
+    repeat: &Repeat<'a>,
+    component: usize,
+    subcomponent: usize,
+) -> Option<&'a str> {
+    repeat
+        .component(component)
+        .and_then(|c| c.subcomponents.get(subcomponent.checked_sub(1)?))
+        .map(|s| s.raw_value())
+        .filter(|s| !s.is_empty())
+}
+
 /// Get subcomponent values of a repeating field.
 ///
 /// Subcomponent values are non-empty string slices ([`Option<&str>`]) or [`None`].
@@ -310,7 +460,48 @@ pub(crate) fn segment_value<'a>(
         .and_then(|r| repeat_component(r, component_number))
 }
 
-pub(crate) fn get_message_key<'a>(msg: &'a Message<'_>) -> Result<&'a str, ParsingError> {
+/// Fields per segment which may identify a person or an encounter
+static IDENTIFYING_FIELDS: &[(&str, &[usize])] = &[
+    (
+        "PID",
+        &[2, 3, 4, 5, 6, 7, 9, 11, 13, 14, 18, 19, 20, 21, 29],
+    ),
+    ("PV1", &[7, 8, 9, 17, 19, 50]),
+    ("MRG", &[1, 2, 3, 4, 5, 6, 7]),
+    ("NK1", &[2, 4, 5, 6, 30, 31, 32, 33]),
+    ("IN1", &[16, 18, 19, 36, 49]),
+];
+
+/// Returns the first segment with the given name, with all identifying field values masked.
+///
+/// Used to attach a sample of the message context to operational warnings,
+/// without exposing the whole message.
+pub(crate) fn redacted_segment<M: Hl7Message + ?Sized>(msg: &M, name: &str) -> Option<String> {
+    let fields = msg.segment_fields(name)?;
+    let masked = IDENTIFYING_FIELDS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, fields)| *fields)
+        .unwrap_or_default();
+
+    let fields = fields
+        .into_iter()
+        .enumerate()
+        .map(|(i, f)| {
+            if masked.contains(&(i + 1)) && !f.is_empty() {
+                "***"
+            } else {
+                f
+            }
+        })
+        .collect::<Vec<&str>>();
+
+    Some(format!("{name}|{}", fields.join("|")))
+}
+
+pub(crate) fn get_message_key<'a, M: Hl7Message + ?Sized>(
+    msg: &'a M,
+) -> Result<&'a str, ParsingError> {
     query(msg, MSH_10).ok_or(ParsingError::Other(anyhow!("failed to parse message key")))
 }
 
@@ -434,6 +625,66 @@ EVN|A01|202111221030|202111221029||
 
         assert!(matches!(get_message_key(&msg), Err(ParsingError::Other(_))));
     }
+    #[rstest]
+    #[case(MessageTypeSource::Evn, "EVN|A08|202111221030", MessageType::A08)]
+    #[case(MessageTypeSource::Msh, "EVN|A08|202111221030", MessageType::A11)]
+    #[case(MessageTypeSource::Evn, "EVN||202111221030", MessageType::A11)]
+    #[case(MessageTypeSource::Evn, "", MessageType::A11)]
+    fn test_message_type_source(
+        #[case] source: MessageTypeSource,
+        #[case] evn: &str,
+        #[case] expected: MessageType,
+    ) {
+        let input = format!(
+            "MSH|^~\\&|ORBIS|KH|RECAPP|ORBIS|202111230904||ADT^A11_A09|62325574|P|2.5\r{evn}"
+        );
+        let msg = Message::parse_with_lenient_newlines(&input, true).expect("parse hl7 failed");
+
+        assert_eq!(message_type(&msg, source).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("202111221030", Some(1637573400000))]
+    #[case("20211122103015", Some(1637573415000))]
+    #[case("2021112210", None)]
+    fn test_timestamp_millis(#[case] value: &str, #[case] expected: Option<i64>) {
+        assert_eq!(timestamp_millis(value), expected);
+    }
+
+    #[test]
+    fn test_message_info() {
+        let input = "MSH|^~\\&|ORBIS|KH|RECAPP|ORBIS|20211122103015||ADT^A11_A09|62293727|P|2.5\rEVN|A11|202111221030\rPID|1|9999999^^^KH|9999999\rPV1|1|I|||||||||||||||||12345678";
+        let msg = Message::parse_with_lenient_newlines(input, true).expect("parse hl7 failed");
+
+        let info = MessageInfo::new(&msg);
+
+        assert_eq!(info.control_id.as_deref(), Some("62293727"));
+        assert_eq!(info.sending_facility.as_deref(), Some("KH"));
+        assert_eq!(info.trigger_event.as_deref(), Some("A11_A09"));
+        assert_eq!(info.event_type.as_deref(), Some("A11"));
+        assert_eq!(info.created, Some(1637573415000));
+        assert_eq!(info.event_time, Some(1637573400000));
+        assert_eq!(info.patient_id.as_deref(), Some("9999999"));
+        assert_eq!(info.visit_number.as_deref(), Some("12345678"));
+        assert_eq!(info.admit_time, None);
+        assert_eq!(info.prior_patient_id, None);
+    }
+
+    #[test]
+    fn test_redacted_segment() {
+        let input = r#"MSH|^~\&|ORBIS|KH|RECAPP|ORBIS|202111221030||ADT^A01|62293727|P|2.3|||||D||DE
+EVN|A01|202111221030|202111221029||EIDAMN
+PID|1|1499653|1499653||Test^Meinrad^^Graf^von^Dr.^L|Test|202301181003|X|||Test Str.  27^^Bad Test^^57334^D^L||02752/1672^^PH|||Q|rk
+"#;
+        let msg = Message::parse_with_lenient_newlines(input, true).expect("parse hl7 failed");
+
+        assert_eq!(
+            redacted_segment(&msg, "PID"),
+            Some("PID|1|***|***||***|***|***|X|||***||***|||Q|rk".to_string())
+        );
+        assert_eq!(redacted_segment(&msg, "PV1"), None);
+    }
+
     #[test]
     fn check_is_numeric_ascii_test() {
         assert!(check_is_numeric_ascii("01", "test").unwrap());
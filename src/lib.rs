@@ -0,0 +1,19 @@
+//! Mapping library with a C ABI (feature `ffi`), so tools written in other languages reuse the
+//! mapping of the service (see [`ffi::map_adt_to_fhir`]).
+#![cfg(feature = "ffi")]
+
+// the modules are shared with the binary, parts of them (e.g. the Kafka config or the admin
+// bootstrap) are only used by the service. Their dead code is reported by the binary.
+#[allow(dead_code)]
+mod config;
+#[allow(dead_code)]
+mod error;
+pub mod ffi;
+#[allow(dead_code)]
+mod fhir;
+#[allow(dead_code)]
+mod hl7;
+#[allow(dead_code)]
+mod metrics;
+#[cfg(test)]
+mod test_utils;
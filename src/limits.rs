@@ -0,0 +1,374 @@
+use crate::config::{LengthLimitConfig, LengthPolicy};
+use crate::error::MappingError;
+use crate::metrics::length_limit_count;
+use crate::pipeline::{Next, Outcome, Record, Stage};
+use anyhow::anyhow;
+use log::warn;
+use opentelemetry::KeyValue;
+use serde_json::Value;
+
+/// Maximum lengths of string elements (see [`LengthLimitConfig`]), applied to the mapped bundle.
+///
+/// Values exceeding the maximum length are truncated, or the entry of the resource is removed
+/// from the bundle (`reject`), so a single value doesn't fail the whole transaction. Entries
+/// referencing a removed entry are removed as well. Patches are limited by the values of their
+/// operations.
+pub(crate) struct LimitStage {
+    limits: Vec<LengthLimitConfig>,
+}
+
+impl LimitStage {
+    pub(crate) fn new(limits: Vec<LengthLimitConfig>) -> anyhow::Result<Self> {
+        // identifier values are part of the conditional request urls and references
+        if let Some(limit) = limits
+            .iter()
+            .find(|l| l.path.split('.').nth(1) == Some("identifier"))
+        {
+            return Err(anyhow!(
+                "length limit of '{}' not supported, identifiers are referenced by conditional urls",
+                limit.path
+            ));
+        }
+
+        Ok(LimitStage { limits })
+    }
+
+    /// Applies the limits to the bundle, [`None`] if no entry is left.
+    fn apply(&self, key: &str, bundle: &str) -> Result<Option<String>, MappingError> {
+        let mut bundle: Value =
+            serde_json::from_str(bundle).map_err(|e| MappingError::Other(e.into()))?;
+        let Some(entries) = bundle.get_mut("entry").and_then(Value::as_array_mut) else {
+            return Ok(Some(bundle.to_string()));
+        };
+
+        // urls of the rejected entries
+        let mut rejected_urls = vec![];
+        entries.retain_mut(|entry| {
+            let (resource_type, patch) = target_type(entry);
+            let Some(resource) = entry.get_mut("resource") else {
+                return true;
+            };
+
+            let mut rejected = false;
+            for limit in &self.limits {
+                let mut path = limit.path.split('.');
+                if path.next() != Some(resource_type.as_str()) {
+                    continue;
+                }
+                let violations = if patch {
+                    enforce_patch(resource, &limit.path, limit.max_length, limit.policy)
+                } else {
+                    enforce(
+                        resource,
+                        &path.collect::<Vec<_>>(),
+                        limit.max_length,
+                        limit.policy,
+                    )
+                };
+                if violations == 0 {
+                    continue;
+                }
+
+                warn!(
+                    "{violations} value(s) of {} [key={key}] exceed the maximum length of {} ({:?})",
+                    limit.path, limit.max_length, limit.policy
+                );
+                length_limit_count().add(
+                    violations,
+                    &[
+                        KeyValue::new("path", limit.path.clone()),
+                        KeyValue::new("policy", format!("{:?}", limit.policy).to_lowercase()),
+                    ],
+                );
+                rejected |= limit.policy == LengthPolicy::Reject;
+            }
+            if rejected {
+                rejected_urls.extend(entry_urls(entry));
+            }
+            !rejected
+        });
+        remove_referencing(entries, rejected_urls, key);
+        if entries.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            serde_json::to_string(&bundle).map_err(|e| MappingError::Other(e.into()))?,
+        ))
+    }
+}
+
+/// Resource type of the entry's resource, or the target type of a patch (`Parameters` resource).
+fn target_type(entry: &Value) -> (String, bool) {
+    let resource_type = entry
+        .pointer("/resource/resourceType")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let request = entry.get("request");
+
+    match request
+        .and_then(|r| r.get("method"))
+        .and_then(Value::as_str)
+    {
+        Some("PATCH") if resource_type == "Parameters" => (
+            request
+                .and_then(|r| r.get("url"))
+                .and_then(Value::as_str)
+                .and_then(|url| url.split(['?', '/']).next())
+                .unwrap_or_default()
+                .to_string(),
+            true,
+        ),
+        _ => (resource_type.to_string(), false),
+    }
+}
+
+/// Removes the entries referencing the removed entries (by their [`entry_urls`]), so no
+/// references to removed entries are left.
+pub(crate) fn remove_referencing(entries: &mut Vec<Value>, removed_urls: Vec<String>, key: &str) {
+    let mut removed_urls = removed_urls;
+    while !removed_urls.is_empty() {
+        let urls = std::mem::take(&mut removed_urls);
+        entries.retain(|entry| {
+            let referencing = entry
+                .get("resource")
+                .is_some_and(|r| references_any(r, &urls));
+            if referencing {
+                warn!("Removing entry [key={key}] referencing a removed entry");
+                removed_urls.extend(entry_urls(entry));
+            }
+            !referencing
+        });
+    }
+}
+
+/// Urls the entry may be referenced with: the `fullUrl` and the conditional url of the request.
+pub(crate) fn entry_urls(entry: &Value) -> Vec<String> {
+    let value = |pointer| entry.pointer(pointer).and_then(Value::as_str);
+    let conditional = match (value("/request/url"), value("/request/ifNoneExist")) {
+        (Some(url), Some(search)) => Some(format!("{url}?{search}")),
+        (Some(url), None) if url.contains('?') => Some(url.to_string()),
+        _ => None,
+    };
+
+    value("/fullUrl")
+        .map(str::to_string)
+        .into_iter()
+        .chain(conditional)
+        .collect()
+}
+
+/// Whether a `reference` of the value targets one of the urls (also absolute ones).
+fn references_any(value: &Value, urls: &[String]) -> bool {
+    match value {
+        Value::Object(object) => object.iter().any(|(name, v)| match v {
+            Value::String(reference) if name == "reference" => urls
+                .iter()
+                .any(|url| reference == url || reference.ends_with(&format!("/{url}"))),
+            _ => references_any(v, urls),
+        }),
+        Value::Array(items) => items.iter().any(|item| references_any(item, urls)),
+        _ => false,
+    }
+}
+
+/// Number of string values of the patch operations at the limit path (FHIRPath `path` part with
+/// the element of the `value` part) exceeding the maximum length.
+fn enforce_patch(
+    parameters: &mut Value,
+    limit_path: &str,
+    max_length: usize,
+    policy: LengthPolicy,
+) -> u64 {
+    let Some(operations) = parameters
+        .get_mut("parameter")
+        .and_then(Value::as_array_mut)
+    else {
+        return 0;
+    };
+
+    operations
+        .iter_mut()
+        .filter_map(|operation| operation.get_mut("part")?.as_array_mut())
+        .map(|parts| {
+            let Some(path) = parts
+                .iter()
+                .find(|p| p["name"] == "path")
+                .and_then(|p| p["valueString"].as_str())
+                .map(str::to_string)
+            else {
+                return 0;
+            };
+            // remaining path within the value
+            let rest: Vec<&str> = match limit_path.strip_prefix(path.as_str()) {
+                Some("") => vec![],
+                Some(rest) => match rest.strip_prefix('.') {
+                    Some(rest) => rest.split('.').collect(),
+                    None => return 0,
+                },
+                None => return 0,
+            };
+
+            parts
+                .iter_mut()
+                .filter(|p| p["name"] == "value")
+                .filter_map(Value::as_object_mut)
+                .flat_map(|p| {
+                    p.iter_mut()
+                        .filter(|(name, _)| name.starts_with("value"))
+                        .map(|(_, v)| v)
+                })
+                .map(|v| enforce(v, &rest, max_length, policy))
+                .sum()
+        })
+        .sum()
+}
+
+/// Number of string values at the path exceeding the maximum length. The values are truncated by
+/// the `truncate` policy.
+fn enforce(value: &mut Value, path: &[&str], max_length: usize, policy: LengthPolicy) -> u64 {
+    match (value, path.split_first()) {
+        (Value::Array(items), _) => items
+            .iter_mut()
+            .map(|item| enforce(item, path, max_length, policy))
+            .sum(),
+        (Value::Object(object), Some((name, rest))) => object
+            .get_mut(*name)
+            .map(|v| enforce(v, rest, max_length, policy))
+            .unwrap_or_default(),
+        (Value::String(s), None) if s.chars().count() > max_length => {
+            if policy == LengthPolicy::Truncate {
+                *s = s.chars().take(max_length).collect();
+            }
+            1
+        }
+        _ => 0,
+    }
+}
+
+impl Stage for LimitStage {
+    fn handle(&self, record: Record, next: Next<'_>) -> Result<Outcome, MappingError> {
+        match next.run(record)? {
+            Outcome::Produce(mut produced) if !self.limits.is_empty() => {
+                let Some(bundle) = produced.payload.as_deref() else {
+                    return Ok(Outcome::Produce(produced));
+                };
+                match self.apply(&produced.key, bundle)? {
+                    Some(bundle) => {
+                        produced.payload = Some(bundle);
+                        Ok(Outcome::Produce(produced))
+                    }
+                    None => Ok(Outcome::Skip),
+                }
+            }
+            outcome => Ok(outcome),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(path: &str, max_length: usize, policy: LengthPolicy) -> LengthLimitConfig {
+        LengthLimitConfig {
+            path: path.to_string(),
+            max_length,
+            policy,
+        }
+    }
+
+    const BUNDLE: &str = r#"{"resourceType":"Bundle","entry":[
+        {"fullUrl":"urn:uuid:1","resource":{"resourceType":"Patient","name":[{"family":"Müller-Lüdenscheidt","given":["Anna","Annemarie"]}]},
+         "request":{"method":"PUT","url":"Patient?identifier=pid|1"}},
+        {"fullUrl":"urn:uuid:2","resource":{"resourceType":"Encounter","serviceType":{"text":"Innere Medizin"},"subject":{"reference":"Patient?identifier=pid|1"}},
+         "request":{"method":"PUT","url":"Encounter?identifier=visit|12345678"}}]}"#;
+
+    #[test]
+    fn truncate_values() {
+        let stage = LimitStage::new(vec![
+            limit("Patient.name.family", 6, LengthPolicy::Truncate),
+            limit("Patient.name.given", 4, LengthPolicy::Truncate),
+        ])
+        .unwrap();
+
+        let bundle = stage.apply("test", BUNDLE).unwrap().unwrap();
+        let bundle: Value = serde_json::from_str(&bundle).unwrap();
+
+        let name = &bundle["entry"][0]["resource"]["name"][0];
+        assert_eq!(name["family"], "Müller");
+        assert_eq!(name["given"], serde_json::json!(["Anna", "Anne"]));
+        assert_eq!(bundle["entry"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reject_entries() {
+        let stage = LimitStage::new(vec![limit(
+            "Encounter.serviceType.text",
+            4,
+            LengthPolicy::Reject,
+        )])
+        .unwrap();
+
+        let bundle = stage.apply("test", BUNDLE).unwrap().unwrap();
+        let bundle: Value = serde_json::from_str(&bundle).unwrap();
+
+        let entries = bundle["entry"].as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["resource"]["resourceType"], "Patient");
+
+        // the encounter references the rejected patient: no entry left
+        let stage =
+            LimitStage::new(vec![limit("Patient.name.family", 4, LengthPolicy::Reject)]).unwrap();
+        assert_eq!(stage.apply("test", BUNDLE).unwrap(), None);
+    }
+
+    #[test]
+    fn identifier_limits_are_invalid() {
+        assert!(
+            LimitStage::new(vec![limit(
+                "Encounter.identifier.value",
+                64,
+                LengthPolicy::Truncate
+            )])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn truncate_patch_values() {
+        let stage = LimitStage::new(vec![limit(
+            "Patient.name.family",
+            6,
+            LengthPolicy::Truncate,
+        )])
+        .unwrap();
+        let patch = r#"{"resourceType":"Bundle","entry":[
+            {"resource":{"resourceType":"Parameters","parameter":[{"name":"operation","part":[
+                {"name":"type","valueCode":"replace"},
+                {"name":"path","valueString":"Patient.name"},
+                {"name":"value","valueHumanName":{"family":"Müller-Lüdenscheidt"}}]}]},
+             "request":{"method":"PATCH","url":"Patient?identifier=pid|1"}}]}"#;
+
+        let bundle = stage.apply("test", patch).unwrap().unwrap();
+        let bundle: Value = serde_json::from_str(&bundle).unwrap();
+
+        assert_eq!(
+            bundle["entry"][0]["resource"]["parameter"][0]["part"][2]["valueHumanName"]["family"],
+            "Müller"
+        );
+    }
+
+    #[test]
+    fn values_within_limit() {
+        let stage =
+            LimitStage::new(vec![limit("Patient.name.family", 50, LengthPolicy::Reject)]).unwrap();
+
+        let bundle = stage.apply("test", BUNDLE).unwrap().unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<Value>(&bundle).unwrap(),
+            serde_json::from_str::<Value>(BUNDLE).unwrap()
+        );
+    }
+}
@@ -1,21 +1,39 @@
 extern crate core;
 
+mod admin;
+mod audit;
+mod canary;
+mod cloudevents;
 mod config;
+mod demographics;
 mod error;
 mod fhir;
 mod hl7;
+mod limits;
 mod metrics;
+mod pipeline;
 mod processor;
+mod quality;
+mod readiness;
+mod rules;
+mod sequence;
 pub mod test_utils;
 
+use crate::audit::AuditLog;
+use crate::canary::Canary;
+use crate::demographics::Demographics;
 use crate::fhir::mapper::FhirMapper;
 use crate::metrics::init_meter_provider;
+use crate::pipeline::{Checks, Pipeline};
 use crate::processor::{Context, Processor};
+use crate::quality::DataQuality;
+use crate::readiness::Readiness;
+use crate::sequence::SequenceCheck;
 use config::AppConfig;
 use log::{error, info};
 use rdkafka::ClientConfig;
-use std::process;
 use std::sync::Arc;
+use std::{env, process};
 use tokio::signal::unix::{SignalKind, signal};
 use tokio_util::sync::CancellationToken;
 use tracing_subscriber::EnvFilter;
@@ -26,11 +44,26 @@ async fn main() {
     let config = match AppConfig::new() {
         Ok(config) => config,
         Err(e) => {
-            println!("Failed to parse app settings: {e}");
+            eprintln!("Failed to parse app settings: {e}");
             process::exit(1)
         }
     };
 
+    // admin cli
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|a| a == "admin") {
+        let result = admin::Command::parse(&args[1..]).map(|command| admin::run(command, config));
+        let result = match result {
+            Ok(run) => run.await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("{e}");
+            process::exit(1)
+        }
+        return;
+    }
+
     // logging / tracing
     let filter = format!(
         "{}={level}",
@@ -68,11 +101,30 @@ async fn main() {
     let ctx = Context {
         cancel,
         on_commit: None,
+        readiness: Readiness::new(config.app.readiness_file.clone()),
     };
 
     let mapper = Arc::new(FhirMapper::new(config.fhir).expect("failed to create mapper"));
+    let checks = Checks {
+        sequence_check: config.sequence_check.map(SequenceCheck::new),
+        quality: config
+            .quality
+            .map(|quality| DataQuality::new(quality).expect("failed to create data quality")),
+    };
+    let mut pipeline =
+        Pipeline::new(&config.pipeline, mapper, checks).expect("failed to create pipeline");
+    if let Some(canary) = config.canary {
+        pipeline = pipeline.with_canary(Canary::new(canary).expect("failed to create canary"));
+    }
+    if let Some(audit) = config.audit {
+        pipeline = pipeline.with_audit(AuditLog::new(audit).expect("failed to create audit log"));
+    }
+    if let Some(demographics) = config.demographics {
+        pipeline = pipeline.with_demographics(Demographics::new(demographics));
+    }
+    let pipeline = Arc::new(pipeline);
 
-    Processor::new(config.kafka, mapper, ctx).start().await;
+    Processor::new(config.kafka, pipeline, ctx).start().await;
 
     if let Err(e) = meter_provider.shutdown() {
         error!("Error shutting down meter provider: {e:?}");
@@ -1,5 +1,5 @@
 use opentelemetry::global;
-use opentelemetry::metrics::{Counter, Histogram};
+use opentelemetry::metrics::{Counter, Gauge, Histogram};
 use opentelemetry_otlp::{MetricExporter, WithExportConfig};
 use opentelemetry_sdk::Resource;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
@@ -8,6 +8,13 @@ use std::sync::OnceLock;
 static PROCESS_COUNTER: OnceLock<Counter<u64>> = OnceLock::new();
 static PROCESS_LATENCY: OnceLock<Histogram<u64>> = OnceLock::new();
 static ERRORS: OnceLock<Counter<u64>> = OnceLock::new();
+static EXCLUDED: OnceLock<Counter<u64>> = OnceLock::new();
+static CANARY: OnceLock<Counter<u64>> = OnceLock::new();
+static LENGTH_LIMITS: OnceLock<Counter<u64>> = OnceLock::new();
+static E2E_LATENCY: OnceLock<Histogram<u64>> = OnceLock::new();
+static EVENT_WATERMARK: OnceLock<Gauge<u64>> = OnceLock::new();
+static CONSUMER_LAG: OnceLock<Gauge<u64>> = OnceLock::new();
+static CONSUMER_LAG_SECONDS: OnceLock<Gauge<u64>> = OnceLock::new();
 
 pub(crate) fn process_count() -> &'static Counter<u64> {
     PROCESS_COUNTER.get_or_init(|| {
@@ -31,6 +38,42 @@ pub(crate) fn process_latency() -> &'static Histogram<u64> {
     })
 }
 
+pub(crate) fn e2e_latency() -> &'static Histogram<u64> {
+    E2E_LATENCY.get_or_init(|| {
+        global::meter("processor")
+            .u64_histogram("e2e_latency_millis")
+            .with_description("The time from message creation (MSH-7) to produce completion")
+            .build()
+    })
+}
+
+pub(crate) fn event_watermark() -> &'static Gauge<u64> {
+    EVENT_WATERMARK.get_or_init(|| {
+        global::meter("processor")
+            .u64_gauge("event_time_watermark_millis")
+            .with_description("The latest event time (EVN-2) processed per topic partition")
+            .build()
+    })
+}
+
+pub(crate) fn consumer_lag() -> &'static Gauge<u64> {
+    CONSUMER_LAG.get_or_init(|| {
+        global::meter("processor")
+            .u64_gauge("consumer_lag_records")
+            .with_description("The number of records behind the high watermark per topic partition")
+            .build()
+    })
+}
+
+pub(crate) fn consumer_lag_seconds() -> &'static Gauge<u64> {
+    CONSUMER_LAG_SECONDS.get_or_init(|| {
+        global::meter("processor")
+            .u64_gauge("consumer_lag_seconds")
+            .with_description("The age of the latest consumed record per topic partition")
+            .build()
+    })
+}
+
 pub(crate) fn errors() -> &'static Counter<u64> {
     ERRORS.get_or_init(|| {
         global::meter("processor")
@@ -40,6 +83,33 @@ pub(crate) fn errors() -> &'static Counter<u64> {
     })
 }
 
+pub(crate) fn excluded() -> &'static Counter<u64> {
+    EXCLUDED.get_or_init(|| {
+        global::meter("processor")
+            .u64_counter("records_excluded_total")
+            .with_description("The number of records of excluded test patients")
+            .build()
+    })
+}
+
+pub(crate) fn canary_count() -> &'static Counter<u64> {
+    CANARY.get_or_init(|| {
+        global::meter("processor")
+            .u64_counter("canary_records_total")
+            .with_description("The number of canary records by comparison with the stable mapping")
+            .build()
+    })
+}
+
+pub(crate) fn length_limit_count() -> &'static Counter<u64> {
+    LENGTH_LIMITS.get_or_init(|| {
+        global::meter("processor")
+            .u64_counter("length_limit_violations_total")
+            .with_description("The number of string elements exceeding their maximum length")
+            .build()
+    })
+}
+
 pub(crate) fn init_meter_provider(endpoint: &str) -> anyhow::Result<SdkMeterProvider> {
     let exporter = MetricExporter::builder()
         .with_tonic()
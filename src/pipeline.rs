@@ -0,0 +1,399 @@
+use crate::audit::AuditLog;
+use crate::canary::Canary;
+use crate::config::{PipelineConfig, StageKind};
+use crate::demographics::Demographics;
+use crate::error::MappingError;
+use crate::fhir::mapper::{FhirMapper, count_unknown_codes};
+use crate::fhir::state::LocationUpdate;
+use crate::hl7::parser::MessageInfo;
+use crate::limits::LimitStage;
+use crate::quality::DataQuality;
+use crate::rules::RuleStage;
+use crate::sequence::{SequenceCheck, SequenceWarning};
+use anyhow::anyhow;
+use hl7_parser::Message;
+use std::sync::Arc;
+
+/// A consumed record passing through the pipeline stages.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Record {
+    pub(crate) key: String,
+    pub(crate) payload: Option<String>,
+    /// header values of the HL7 message, read by the `map` stage
+    pub(crate) info: Option<MessageInfo>,
+    /// impossible event sequence, detected by the `map` stage
+    pub(crate) sequence_warning: Option<SequenceWarning>,
+    /// changes of the location history, applied by [`Pipeline::commit`] after produce
+    pub(crate) location_updates: Vec<LocationUpdate>,
+}
+
+impl Record {
+    pub(crate) fn new(key: String, payload: Option<String>) -> Self {
+        Record {
+            key,
+            payload,
+            info: None,
+            sequence_warning: None,
+            location_updates: vec![],
+        }
+    }
+}
+
+/// Result of the pipeline for a single record.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Outcome {
+    /// produce the record (payload) to the output topic
+    Produce(Record),
+    /// nothing to produce, the record is done
+    Skip,
+}
+
+/// A middleware stage of the processing pipeline.
+///
+/// Each stage receives the record together with the remaining stages, so it
+/// may alter the record, stop processing or act before and after the
+/// remaining stages (e.g. for metrics).
+pub(crate) trait Stage: Send + Sync {
+    fn handle(&self, record: Record, next: Next<'_>) -> Result<Outcome, MappingError>;
+}
+
+/// The remaining stages of the pipeline.
+pub(crate) struct Next<'a> {
+    stages: &'a [Box<dyn Stage>],
+}
+
+impl Next<'_> {
+    pub(crate) fn run(self, record: Record) -> Result<Outcome, MappingError> {
+        match self.stages.split_first() {
+            Some((stage, rest)) => stage.handle(record, Next { stages: rest }),
+            None => Ok(Outcome::Produce(record)),
+        }
+    }
+}
+
+/// Checks of the consumed messages. They are run by the `map` stage on the parsed message, so
+/// records skipped before (e.g. by the rules) are not counted.
+#[derive(Default)]
+pub(crate) struct Checks {
+    pub(crate) sequence_check: Option<SequenceCheck>,
+    pub(crate) quality: Option<DataQuality>,
+}
+
+/// The configured [`Stage`]s, along with the fixed steps of the processor. Canary mapping, audit
+/// log and demographics produce to their own topics asynchronously, so they are run by the
+/// processor around the stages (see [`Pipeline::process`]) and can't be reordered.
+pub(crate) struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+    mapper: Arc<FhirMapper>,
+    canary: Option<Canary>,
+    audit: Option<AuditLog>,
+    sequence_check: Option<Arc<SequenceCheck>>,
+    demographics: Option<Demographics>,
+    quality: Option<Arc<DataQuality>>,
+}
+
+impl Pipeline {
+    /// Builds the pipeline stages in the configured order.
+    pub(crate) fn new(
+        config: &PipelineConfig,
+        mapper: Arc<FhirMapper>,
+        checks: Checks,
+    ) -> anyhow::Result<Self> {
+        if config
+            .stages
+            .iter()
+            .filter(|&&s| s == StageKind::Map)
+            .count()
+            != 1
+        {
+            return Err(anyhow!("pipeline must contain exactly one 'map' stage"));
+        }
+        // rules are evaluated on the HL7 payload
+        let position = |kind| config.stages.iter().position(|&s| s == kind);
+        if position(StageKind::Rules) > position(StageKind::Map) {
+            return Err(anyhow!("'rules' stage must precede the 'map' stage"));
+        }
+        if !config.rules.is_empty() && position(StageKind::Rules).is_none() {
+            return Err(anyhow!("'pipeline.rules' require the 'rules' stage"));
+        }
+        // limits are applied to the mapped bundle
+        if position(StageKind::Limits).is_some_and(|p| Some(p) < position(StageKind::Map)) {
+            return Err(anyhow!("'limits' stage must follow the 'map' stage"));
+        }
+
+        let sequence_check = checks.sequence_check.map(Arc::new);
+        let quality = checks.quality.map(Arc::new);
+        let stages = config
+            .stages
+            .iter()
+            .map(|kind| -> anyhow::Result<Box<dyn Stage>> {
+                Ok(match kind {
+                    StageKind::Filter => Box::new(TombstoneFilter),
+                    StageKind::Rules => Box::new(RuleStage::new(config.rules.clone())?),
+                    StageKind::Map => Box::new(MapStage {
+                        mapper: mapper.clone(),
+                        sequence_check: sequence_check.clone(),
+                        quality: quality.clone(),
+                    }),
+                    StageKind::Limits => Box::new(LimitStage::new(config.limits.clone())?),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Pipeline {
+            stages,
+            mapper,
+            canary: None,
+            audit: None,
+            sequence_check,
+            demographics: None,
+            quality,
+        })
+    }
+
+    /// Maps a share of the records additionally with the canary mapping.
+    pub(crate) fn with_canary(mut self, canary: Canary) -> Self {
+        self.canary = Some(canary);
+        self
+    }
+
+    pub(crate) fn canary(&self) -> Option<&Canary> {
+        self.canary.as_ref()
+    }
+
+    /// Records the produced entries per patient in the audit log.
+    pub(crate) fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    pub(crate) fn audit(&self) -> Option<&AuditLog> {
+        self.audit.as_ref()
+    }
+
+    pub(crate) fn sequence_check(&self) -> Option<&SequenceCheck> {
+        self.sequence_check.as_deref()
+    }
+
+    /// Produces the latest demographics per patient to a compacted topic.
+    pub(crate) fn with_demographics(mut self, demographics: Demographics) -> Self {
+        self.demographics = Some(demographics);
+        self
+    }
+
+    pub(crate) fn demographics(&self) -> Option<&Demographics> {
+        self.demographics.as_ref()
+    }
+
+    pub(crate) fn quality(&self) -> Option<&DataQuality> {
+        self.quality.as_deref()
+    }
+
+    /// Applies the location history changes of a produced record.
+    pub(crate) fn commit(&self, location_updates: Vec<LocationUpdate>) {
+        self.mapper.commit(location_updates);
+    }
+
+    /// Runs the configured stages. The fixed steps are not part of it.
+    pub(crate) fn process(&self, record: Record) -> Result<Outcome, MappingError> {
+        Next {
+            stages: &self.stages,
+        }
+        .run(record)
+    }
+}
+
+/// Skips tombstone records (records without payload).
+struct TombstoneFilter;
+
+impl Stage for TombstoneFilter {
+    fn handle(&self, record: Record, next: Next<'_>) -> Result<Outcome, MappingError> {
+        match record.payload {
+            Some(_) => next.run(record),
+            None => Ok(Outcome::Skip),
+        }
+    }
+}
+
+/// Maps the HL7 payload to a FHIR bundle. The message is parsed once for the mapping and the
+/// [`Checks`], its header values are passed on with the record.
+struct MapStage {
+    mapper: Arc<FhirMapper>,
+    sequence_check: Option<Arc<SequenceCheck>>,
+    quality: Option<Arc<DataQuality>>,
+}
+
+impl Stage for MapStage {
+    fn handle(&self, record: Record, next: Next<'_>) -> Result<Outcome, MappingError> {
+        let Some(payload) = record.payload.as_deref() else {
+            return Ok(Outcome::Skip);
+        };
+
+        let msg = match Message::parse_with_lenient_newlines(payload, true) {
+            Ok(msg) => msg,
+            Err(e) => {
+                let e = MappingError::from(e);
+                if let Some(quality) = &self.quality {
+                    quality.record_unparsable(e.name());
+                }
+                return Err(e);
+            }
+        };
+        let info = MessageInfo::new(&msg);
+        let facility = self.quality.as_ref().map(|q| q.record(&msg));
+
+        let (mapped, unknown_codes) = count_unknown_codes(|| self.mapper.map_message(&msg));
+        if let Some(quality) = &self.quality
+            && let Some(facility) = &facility
+        {
+            quality.record_unknown_codes(facility, unknown_codes);
+        }
+        let mapped = match mapped {
+            Ok(mapped) => mapped,
+            Err(e) => {
+                if let Some(quality) = &self.quality
+                    && let Some(facility) = &facility
+                {
+                    quality.record_failure(facility, e.name());
+                }
+                return Err(e);
+            }
+        };
+        let Some(mapped) = mapped else {
+            return Ok(Outcome::Skip);
+        };
+
+        next.run(Record {
+            key: record.key,
+            payload: Some(mapped.bundle),
+            sequence_warning: self.sequence_check.as_ref().and_then(|c| c.check(&info)),
+            info: Some(info),
+            location_updates: mapped.location_updates,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RuleConfig;
+    use crate::test_utils::tests::{get_dummy_resources, get_test_config, read_test_resource};
+
+    fn pipeline(stages: Vec<StageKind>) -> anyhow::Result<Pipeline> {
+        let mapper = Arc::new(FhirMapper {
+            config: get_test_config(),
+            resources: get_dummy_resources(),
+            history: Default::default(),
+            exclusion: Default::default(),
+        });
+        Pipeline::new(
+            &PipelineConfig {
+                stages,
+                ..Default::default()
+            },
+            mapper,
+            Checks::default(),
+        )
+    }
+
+    #[test]
+    fn default_pipeline_maps_payload() {
+        let p = pipeline(PipelineConfig::default().stages).unwrap();
+
+        let outcome = p
+            .process(Record::new(
+                "test".to_string(),
+                Some(read_test_resource("a01_test.hl7")),
+            ))
+            .unwrap();
+
+        let Outcome::Produce(record) = outcome else {
+            panic!("expected record to be produced");
+        };
+        assert_eq!(record.key, "test");
+        assert_eq!(
+            record.info.and_then(|i| i.sending_facility).as_deref(),
+            Some("KH")
+        );
+        assert!(
+            record
+                .payload
+                .unwrap()
+                .contains("\"resourceType\":\"Bundle\"")
+        );
+    }
+
+    #[test]
+    fn tombstone_is_skipped() {
+        let p = pipeline(vec![StageKind::Filter, StageKind::Map]).unwrap();
+
+        let outcome = p.process(Record::new("test".to_string(), None)).unwrap();
+
+        assert_eq!(outcome, Outcome::Skip);
+    }
+
+    #[test]
+    fn pipeline_requires_map_stage() {
+        assert!(pipeline(vec![StageKind::Filter]).is_err());
+        assert!(pipeline(vec![StageKind::Map, StageKind::Map]).is_err());
+        assert!(pipeline(vec![StageKind::Map, StageKind::Rules]).is_err());
+        assert!(pipeline(vec![StageKind::Limits, StageKind::Map]).is_err());
+        assert!(pipeline(vec![StageKind::Map, StageKind::Limits]).is_ok());
+    }
+
+    #[test]
+    fn rules_skip_and_drop() {
+        let mapper = Arc::new(FhirMapper {
+            config: get_test_config(),
+            resources: get_dummy_resources(),
+            history: Default::default(),
+            exclusion: Default::default(),
+        });
+        let rules: Vec<RuleConfig> = serde_json::from_str(
+            r#"[
+                {"conditions": [{"field": "PV1.2", "operator": "eq", "values": ["I"]}],
+                 "action": "drop", "resource_types": ["Encounter"]},
+                {"conditions": [{"field": "PV1.2", "operator": "in", "values": ["O"]}],
+                 "action": "skip"}
+            ]"#,
+        )
+        .unwrap();
+        // rules without stage
+        assert!(
+            Pipeline::new(
+                &PipelineConfig {
+                    stages: vec![StageKind::Filter, StageKind::Map],
+                    rules: rules.clone(),
+                    limits: vec![],
+                },
+                mapper.clone(),
+                Checks::default(),
+            )
+            .is_err()
+        );
+        let p = Pipeline::new(
+            &PipelineConfig {
+                stages: vec![StageKind::Filter, StageKind::Rules, StageKind::Map],
+                rules,
+                limits: vec![],
+            },
+            mapper,
+            Checks::default(),
+        )
+        .unwrap();
+        let record = |payload: String| Record::new("test".to_string(), Some(payload));
+
+        // inpatient: encounters are dropped
+        let hl7 = read_test_resource("a01_test.hl7");
+        let Outcome::Produce(produced) = p.process(record(hl7.clone())).unwrap() else {
+            panic!("expected record to be produced");
+        };
+        let bundle = produced.payload.unwrap();
+        assert!(bundle.contains("\"url\":\"Patient"));
+        assert!(!bundle.contains("\"url\":\"Encounter"));
+
+        // outpatient: skipped
+        let outpatient = hl7.replace("PV1|1|I|", "PV1|1|O|");
+        assert_eq!(p.process(record(outpatient)).unwrap(), Outcome::Skip);
+    }
+}
@@ -1,44 +1,87 @@
 use crate::ClientConfig;
-use crate::config::{Kafka, Ssl};
+use crate::cloudevents::{CONTENT_TYPE, EventAttributes};
+use crate::config::{Kafka, MessageAgeAction, OutputFormat, OutputTimestamp, Ssl};
+use crate::demographics::PatientUpdate;
 use crate::error::{MappingError, ProcessingError};
-use crate::fhir::mapper::FhirMapper;
-use crate::metrics::{errors, process_count, process_latency};
-use futures::TryStreamExt;
-use futures::future::join_all;
-use futures::stream::FuturesUnordered;
+use crate::hl7::parser::MessageInfo;
+use crate::metrics::{
+    consumer_lag, consumer_lag_seconds, e2e_latency, errors, event_watermark, process_count,
+    process_latency,
+};
+use crate::pipeline::{Outcome, Pipeline, Record};
+use crate::readiness::Readiness;
+use crate::sequence::SequenceWarning;
+use chrono::Utc;
+use futures::stream::{self, FuturesUnordered, Stream};
+use futures::{StreamExt, TryStreamExt};
 use itertools::Itertools;
 use log::{debug, error, info, trace, warn};
 use opentelemetry::KeyValue;
 use rdkafka::config::RDKafkaLogLevel;
 use rdkafka::consumer::{BaseConsumer, Consumer, ConsumerContext, Rebalance, StreamConsumer};
 use rdkafka::error::KafkaResult;
-use rdkafka::message::{BorrowedMessage, Headers};
+use rdkafka::message::{BorrowedMessage, Header, Headers, OwnedHeaders};
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use rdkafka::util::Timeout;
 use rdkafka::{ClientContext, Message, Offset, TopicPartitionList};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
 use std::time::{Duration, Instant};
 use tokio::select;
 use tokio::sync::mpsc::Sender;
+use tokio::task::JoinError;
 use tokio_util::sync::CancellationToken;
 
+/// maximum time to wait for a full producer queue
+const PRODUCE_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub(crate) struct Processor {
     config: Kafka,
-    mapper: Arc<FhirMapper>,
+    pipeline: Arc<Pipeline>,
     producer: Arc<FutureProducer>,
     ctx: Context,
+    /// the first consumed record was checked by the replay guard
+    replay_checked: AtomicBool,
+    watermarks: Watermarks,
+}
+
+/// Latest event time (EVN-2) of the produced records per topic partition, i.e. the FHIR
+/// store is current up to this clinical event time.
+#[derive(Default)]
+struct Watermarks(Mutex<HashMap<(String, i32), i64>>);
+
+impl Watermarks {
+    /// Returns the new watermark, if the event time advanced it.
+    fn advance(&self, topic: &str, partition: i32, event_time: i64) -> Option<i64> {
+        let mut watermarks = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let current = watermarks
+            .entry((topic.to_string(), partition))
+            .or_insert(i64::MIN);
+        if event_time > *current {
+            *current = event_time;
+            Some(event_time)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Clone)]
 pub(crate) struct Context {
     pub(crate) on_commit: Option<Sender<TopicPartitionList>>,
     pub(crate) cancel: CancellationToken,
+    pub(crate) readiness: Readiness,
 }
 type ProcessingConsumer = StreamConsumer<Context>;
 impl ClientContext for Context {}
 impl ConsumerContext for Context {
     fn pre_rebalance(&self, _: &BaseConsumer<Self>, rebalance: &Rebalance) {
         info!("[Rebalance] pre {}", format_rebalance(rebalance));
+        if let Rebalance::Revoke(topic_parts) = rebalance {
+            reset_lag(topic_parts);
+        }
     }
 
     fn post_rebalance(&self, _: &BaseConsumer<Self>, rebalance: &Rebalance) {
@@ -102,60 +145,141 @@ fn format_offsets_from_parts(topic_parts: &TopicPartitionList) -> String {
         .collect::<String>()
 }
 
+/// Merges the streams of the priority lanes by weight.
+///
+/// A lane yields up to `weight` items in a row, before the next lane is polled.
+/// Lanes without a pending item are skipped, ended lanes are removed.
+fn weighted_merge<S: Stream + Unpin>(lanes: Vec<(S, u32)>) -> impl Stream<Item = S::Item> {
+    let mut lanes: Vec<(S, u32)> = lanes.into_iter().map(|(s, w)| (s, w.max(1))).collect();
+    let mut current = 0;
+    let mut credit = lanes.first().map(|(_, w)| *w).unwrap_or_default();
+
+    stream::poll_fn(move |cx| {
+        let mut polled = 0;
+        while polled < lanes.len() {
+            match lanes[current].0.poll_next_unpin(cx) {
+                Poll::Ready(Some(item)) => {
+                    credit -= 1;
+                    if credit == 0 {
+                        current = (current + 1) % lanes.len();
+                        credit = lanes[current].1;
+                    }
+                    return Poll::Ready(Some(item));
+                }
+                Poll::Ready(None) => {
+                    lanes.remove(current);
+                    if lanes.is_empty() {
+                        return Poll::Ready(None);
+                    }
+                    current %= lanes.len();
+                    credit = lanes[current].1;
+                }
+                Poll::Pending => {
+                    polled += 1;
+                    current = (current + 1) % lanes.len();
+                    credit = lanes[current].1;
+                }
+            }
+        }
+
+        if lanes.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    })
+}
+
 impl Processor {
-    pub(crate) fn new(config: Kafka, mapper: Arc<FhirMapper>, ctx: Context) -> Self {
+    pub(crate) fn new(config: Kafka, pipeline: Arc<Pipeline>, ctx: Context) -> Self {
         let producer = Arc::new(create_producer(config.clone()));
         Self {
             config,
-            mapper,
+            pipeline,
             producer,
             ctx,
+            replay_checked: AtomicBool::new(false),
+            watermarks: Watermarks::default(),
         }
     }
 
+    /// Runs a consumer task per partition. Panicked tasks are restarted.
     pub(crate) async fn start(self) {
         let this = Arc::new(self);
 
-        let tasks = (1..=this.config.num_partitions)
-            .map(|id| {
-                let this = this.clone();
-                tokio::spawn(this.run(id))
-            })
+        if this.pipeline.quality().is_some() {
+            tokio::spawn(this.clone().publish_scorecards());
+        }
+
+        let mut tasks = (1..=this.config.num_partitions)
+            .map(|id| this.clone().spawn(id))
             .collect::<FuturesUnordered<_>>();
 
-        join_all(tasks).await;
+        while let Some((id, result)) = tasks.next().await {
+            if let Err(e) = result
+                && e.is_panic()
+                && !this.ctx.cancel.is_cancelled()
+            {
+                error!("[ALERT] Consumer[{id}] panicked: {e}. Restarting..");
+                errors().add(1, &[KeyValue::new("type", "WorkerPanic")]);
+                tasks.push(this.clone().spawn(id));
+            }
+        }
+    }
+
+    fn spawn(self: Arc<Self>, id: i32) -> impl Future<Output = (i32, Result<(), JoinError>)> {
+        let handle = tokio::spawn(self.run(id));
+        async move { (id, handle.await) }
     }
 
     async fn run(self: Arc<Self>, id: i32) {
         loop {
-            // create consumer
-            let instance_id = format!("{}_{id}", self.config.consumer_group);
-            let consumer = self.create_consumer(&instance_id);
-            let topic = &self.config.input_topic;
-            match consumer.subscribe(&[topic]) {
-                Ok(()) => {
-                    info!(
-                        "Consumer[{id}] Successfully subscribed to topic {topic} with instance id: {instance_id}"
-                    );
+            // create a consumer per lane
+            let mut consumers = vec![];
+            for (topic, weight) in self.config.topic_lanes() {
+                let mut instance_id = format!("{}_{id}", self.config.consumer_group);
+                if topic != self.config.input_topic {
+                    instance_id = format!("{instance_id}_{topic}");
                 }
-                Err(e) => {
-                    error!("Consumer[{id}] Failed to subscribe to topic {topic}: {e}");
-                    // exit
-                    break;
+                let consumer = self.create_consumer(&instance_id);
+                match consumer.subscribe(&[topic]) {
+                    Ok(()) => {
+                        info!(
+                            "Consumer[{id}] Successfully subscribed to topic {topic} with instance id: {instance_id}"
+                        );
+                    }
+                    Err(e) => {
+                        error!("Consumer[{id}] Failed to subscribe to topic {topic}: {e}");
+                        // exit
+                        return;
+                    }
                 }
+                consumers.push((Arc::new(consumer), weight));
             }
+            self.ctx.readiness.set_ready(true);
 
-            let consumer = Arc::new(consumer);
+            let topic = self.config.topic_lanes().iter().map(|(t, _)| *t).join(", ");
+            let lanes = consumers
+                .iter()
+                .map(|(consumer, weight)| {
+                    let owner = consumer.clone();
+                    let stream = consumer
+                        .stream()
+                        .map_ok(move |m| (owner.clone(), m))
+                        .boxed();
+                    (stream, *weight)
+                })
+                .collect();
 
             select! {
                 _ = self.ctx.cancel.cancelled() =>  {
                     info!("Consumer[{id}] for topic {topic} was stopped by cancellation");
                     return
                 }
-                stream = consumer.stream().map_err(ProcessingError::from)
-                .try_for_each(|m| {
+                stream = weighted_merge(lanes).map_err(ProcessingError::from)
+                .try_for_each(|(consumer, m)| {
                     let start = Instant::now();
-                    let result= self.process_message(m, id, consumer.clone());
+                    let result= self.process_message(m, id, consumer);
                     let duration = start.elapsed().as_nanos();
 
                     // record latency
@@ -165,12 +289,16 @@ impl Processor {
                     );
                     result
                 }) => {
-                    info!("Starting Consumer[{instance_id}] for topic {}",
-                        self.config.input_topic);
+                    info!("Starting Consumer[{id}] for topic {topic}");
                     match stream {
                             // exit
-                            Err(ProcessingError::Mapping(e)) => {
-                                consumer.unsubscribe();
+                            Err(
+                                e @ (ProcessingError::Mapping(_)
+                                | ProcessingError::Replay(_)
+                                | ProcessingError::Audit(_)),
+                            ) => {
+                                consumers.iter().for_each(|(c, _)| c.unsubscribe());
+                                self.ctx.readiness.set_ready(false);
                                 error!("{e}. Exiting.");
                                 // cancel all consumer instances
                                 self.ctx.cancel.cancel();
@@ -179,7 +307,7 @@ impl Processor {
                             }
                             // continue
                             Err(ProcessingError::Kafka(e)) => {
-                                consumer.unsubscribe();
+                                consumers.iter().for_each(|(c, _)| c.unsubscribe());
                                 error!("Failed to process message: {e}. Retrying..");
                             }
                             // exit
@@ -234,11 +362,62 @@ impl Processor {
             }
         }
 
-        // filter tombstone records
-        if let Some(payload) = payload {
-            let result = match self.mapper.map(&payload) {
-                Ok(Some(r)) => r,
-                Ok(None) => {
+        record_lag(&m, &consumer);
+
+        if let Some(days) = self.config.max_message_age
+            && let Some(timestamp) = m.timestamp().to_millis()
+        {
+            let first = !self.replay_checked.swap(true, Ordering::Relaxed);
+
+            if exceeds_max_age(timestamp, Utc::now().timestamp_millis(), days) {
+                if first && !self.config.confirm_replay {
+                    error!(
+                        "[ALERT] First record [key={key}] at {topic} is older than {days} days. \
+                        Set kafka.confirm_replay to process the topic from this offset. Stopping Consumer[{id}]."
+                    );
+                    return Err(ProcessingError::Replay(format!(
+                        "record older than {days} days"
+                    )));
+                }
+
+                match self.config.max_message_age_action {
+                    MessageAgeAction::Skip => {
+                        debug!("Skipping record [key={key}], older than {days} days");
+                        consumer.store_offset_from_message(&m)?;
+                        return Ok(());
+                    }
+                    MessageAgeAction::Warn => {
+                        warn!("Processing record [key={key}], older than {days} days")
+                    }
+                    MessageAgeAction::Process => {}
+                }
+            }
+        }
+
+        // input of the canary mapping, if sampled
+        let canary_input = self
+            .pipeline
+            .canary()
+            .filter(|c| c.is_sampled(&key))
+            .and_then(|_| payload.clone());
+
+        let (output_key, result, info, location_updates) =
+            match self.pipeline.process(Record::new(key.clone(), payload)) {
+                Ok(Outcome::Produce(Record {
+                    key,
+                    payload: Some(r),
+                    info,
+                    sequence_warning,
+                    location_updates,
+                })) => {
+                    if let Some(check) = self.pipeline.sequence_check()
+                        && let Some(warning) = sequence_warning
+                    {
+                        self.produce_sequence_warning(&check.topic, &warning).await;
+                    }
+                    (key, r, info.unwrap_or_default(), location_updates)
+                }
+                Ok(_) => {
                     consumer.store_offset_from_message(&m)?;
                     return Ok(());
                 }
@@ -255,6 +434,14 @@ impl Processor {
                             error!("Fatal error, stopping Consumer[{id}].");
                             Err(ProcessingError::Mapping(e))
                         }
+                        MappingError::ManualReview(_) => {
+                            self.produce_review(&m).await?;
+                            consumer.store_offset_from_message(&m)?;
+                            process_count().add(1, &[KeyValue::new("status", "review")]);
+                            errors().add(1, &[KeyValue::new("type", e.name().to_string())]);
+
+                            Ok(())
+                        }
                         _ => {
                             consumer.store_offset_from_message(&m)?;
                             process_count().add(1, &[KeyValue::new("status", "error")]);
@@ -265,31 +452,274 @@ impl Processor {
                     };
                 }
             };
+        let event_attributes = match self.config.output_format {
+            OutputFormat::CloudEvents => Some(EventAttributes::new(&info, &key)),
+            OutputFormat::Bundle => None,
+        };
 
-            // send to output topic
+        // CloudEvents envelope of the bundle, if configured
+        let event = match event_attributes.as_ref().map(|a| a.wrap(&result)) {
+            Some(Ok(event)) => Some(event),
+            Some(Err(e)) => {
+                error!("Failed to wrap bundle with [key={key}] in CloudEvents envelope: {e}");
+                consumer.store_offset_from_message(&m)?;
+                process_count().add(1, &[KeyValue::new("status", "error")]);
+                errors().add(1, &[KeyValue::new("type", "CloudEventsError")]);
+
+                return Ok(());
+            }
+            None => None,
+        };
+
+        // send to output topic, failed records are retried in place to keep their offset
+        let mut failures = 0;
+        let mut paused = false;
+        loop {
             let mut record = FutureRecord::to(&self.config.output_topic)
-                .key(&key)
-                .payload(result.as_str());
-            record.timestamp = m.timestamp().to_millis();
+                .key(&output_key)
+                .payload(event.as_deref().unwrap_or(&result));
+            record.timestamp = output_timestamp(
+                self.config.output_timestamp,
+                m.timestamp().to_millis(),
+                info.event_time,
+                Utc::now().timestamp_millis(),
+            );
+            if event.is_some() {
+                record = record.headers(OwnedHeaders::new().insert(Header {
+                    key: "content-type",
+                    value: Some(CONTENT_TYPE),
+                }));
+            }
 
-            let produce_future = self.producer.send(record, Timeout::Never);
-            match produce_future.await {
+            match self
+                .producer
+                .send(record, Timeout::After(PRODUCE_TIMEOUT))
+                .await
+            {
                 Ok(delivery) => {
                     debug!(
                         "[Sent] key: {key}, partition: {}, offset: {}",
                         delivery.partition, delivery.offset
                     );
+                    if paused {
+                        // recovered from escalation
+                        consumer.resume(&consumer.assignment()?)?;
+                        self.ctx.readiness.resume();
+                    }
+                    // the audit log must be complete, the record is processed again after restart
+                    if let Some(patient) = info.patient_id.as_deref() {
+                        self.write_audit(patient, &result).await?;
+                    }
                     // store offset
                     consumer.store_offset_from_message(&m)?;
+                    // failed records don't change the location history
+                    self.pipeline.commit(location_updates);
                     process_count().add(1, &[KeyValue::new("status", "ok")]);
+                    if let Some(created) = info.created {
+                        let latency = Utc::now().timestamp_millis() - created;
+                        e2e_latency().record(latency.max(0) as u64, &[]);
+                    }
+                    if let Some(input) = canary_input.as_deref() {
+                        self.produce_canary(&output_key, input, &result).await;
+                    }
+                    self.produce_demographics(&info, &result).await;
+                    if let Some(event_time) = info.event_time
+                        && let Some(watermark) =
+                            self.watermarks.advance(topic, m.partition(), event_time)
+                    {
+                        event_watermark().record(
+                            watermark.max(0) as u64,
+                            &[
+                                KeyValue::new("topic", topic.to_string()),
+                                KeyValue::new("partition", m.partition() as i64),
+                            ],
+                        );
+                    }
+
+                    return Ok(());
+                }
+                Err((e, _)) => {
+                    failures += 1;
+                    errors().add(1, &[KeyValue::new("type", "ProduceError")]);
+
+                    if failures == self.config.produce_retries {
+                        // escalate: stop consuming until the output topic is available again
+                        error!(
+                            "[ALERT] Producing to {} failed {failures} times: {e}. Pausing Consumer[{id}]",
+                            self.config.output_topic
+                        );
+                        consumer.pause(&consumer.assignment()?)?;
+                        self.ctx.readiness.pause();
+                        paused = true;
+                    } else {
+                        warn!("Error producing record (attempt {failures}): {e}. Retrying..");
+                    }
+
+                    // exponential backoff, up to 64 seconds
+                    if self
+                        .is_cancelled(Duration::from_secs(2u64.pow(failures.min(6))))
+                        .await
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Produces the canary mapping of the record to the shadow topic. Failures don't affect
+    /// the stable mapping.
+    async fn produce_canary(&self, key: &str, input: &str, stable: &str) {
+        let Some(canary) = self.pipeline.canary() else {
+            return;
+        };
+        let Some(bundle) = canary.process(key, input, stable) else {
+            return;
+        };
+
+        let record = FutureRecord::to(&canary.topic)
+            .key(key)
+            .payload(bundle.as_str());
+        if let Err((e, _)) = self
+            .producer
+            .send(record, Timeout::After(Duration::from_secs(5)))
+            .await
+        {
+            warn!("Failed to produce canary record [key={key}]: {e}");
+            errors().add(1, &[KeyValue::new("type", "CanaryProduceError")]);
+        }
+    }
+
+    /// Records the produced entries in the audit log (file and/or topic). Failures stop the
+    /// consumer, since the offset of an unaudited record must not be committed.
+    async fn write_audit(&self, patient: &str, bundle: &str) -> Result<(), ProcessingError> {
+        let Some(audit) = self.pipeline.audit() else {
+            return Ok(());
+        };
+        let records = audit.records(patient, bundle);
+
+        if let Err(e) = audit.append(&records) {
+            error!("[ALERT] Failed to write audit records: {e}");
+            errors().add(1, &[KeyValue::new("type", "AuditError")]);
+            return Err(ProcessingError::Audit(e.to_string()));
+        }
+        let Some(topic) = &audit.topic else {
+            return Ok(());
+        };
+        for record in records {
+            let payload = serde_json::to_string(&record).expect("failed to serialize audit record");
+            let audit_record = FutureRecord::to(topic)
+                .key(record.patient.as_str())
+                .payload(payload.as_str());
+            if let Err((e, _)) = self
+                .producer
+                .send(audit_record, Timeout::After(Duration::from_secs(5)))
+                .await
+            {
+                error!("[ALERT] Failed to produce audit record: {e}");
+                errors().add(1, &[KeyValue::new("type", "AuditError")]);
+                return Err(ProcessingError::Audit(e.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Produces the current `Patient` resources of the bundle (or tombstones of deleted and merged
+    /// patients) to the compacted demographics topic. Failures don't affect the bundle.
+    async fn produce_demographics(&self, info: &MessageInfo, bundle: &str) {
+        let Some(demographics) = self.pipeline.demographics() else {
+            return;
+        };
+
+        for (patient, update) in demographics.updates(info, bundle) {
+            let mut record = FutureRecord::<str, str>::to(&demographics.topic).key(&patient);
+            if let PatientUpdate::Upsert(resource) = &update {
+                record = record.payload(resource.as_str());
+            }
+            if let Err((e, _)) = self
+                .producer
+                .send(record, Timeout::After(Duration::from_secs(5)))
+                .await
+            {
+                error!("Failed to produce demographics record: {e}");
+                errors().add(1, &[KeyValue::new("type", "DemographicsProduceError")]);
+            }
+        }
+    }
+
+    /// Produces the data quality scorecards per sending facility each interval, until cancelled.
+    async fn publish_scorecards(self: Arc<Self>) {
+        let Some(quality) = self.pipeline.quality() else {
+            return;
+        };
+
+        while !self.is_cancelled(quality.interval).await {
+            for scorecard in quality.scorecards() {
+                let payload =
+                    serde_json::to_string(&scorecard).expect("failed to serialize scorecard");
+                let record = FutureRecord::to(&quality.topic)
+                    .key(scorecard.sending_facility.as_str())
+                    .payload(payload.as_str());
+                if let Err((e, _)) = self
+                    .producer
+                    .send(record, Timeout::After(Duration::from_secs(5)))
+                    .await
+                {
+                    warn!("Failed to produce data quality scorecard: {e}");
+                    errors().add(1, &[KeyValue::new("type", "QualityProduceError")]);
                 }
-                Err((e, _)) => error!("Error producing record: {:?}", e),
             }
         }
+    }
+
+    /// Produces the record unchanged (with its headers) to the review topic, if configured. The
+    /// consumer stops, if it can't be produced.
+    async fn produce_review(&self, m: &BorrowedMessage<'_>) -> Result<(), ProcessingError> {
+        let Some(topic) = &self.config.review_topic else {
+            return Ok(());
+        };
+
+        let mut record = FutureRecord::<[u8], [u8]>::to(topic);
+        if let Some(key) = m.key() {
+            record = record.key(key);
+        }
+        if let Some(payload) = m.payload() {
+            record = record.payload(payload);
+        }
+        if let Some(headers) = m.headers() {
+            record = record.headers(headers.detach());
+        }
+
+        self.producer
+            .send(record, Timeout::After(PRODUCE_TIMEOUT))
+            .await
+            .map_err(|(e, _)| ProcessingError::Kafka(e))?;
 
         Ok(())
     }
 
+    /// Produces the warning of an impossible event sequence. The record is processed anyway.
+    async fn produce_sequence_warning(&self, topic: &str, warning: &SequenceWarning) {
+        warn!(
+            "Impossible event sequence {:?} of visit {} at msg-id {:?}",
+            warning.r#type, warning.visit_number, warning.message_id
+        );
+        errors().add(1, &[KeyValue::new("type", "SequenceWarning")]);
+
+        let payload = serde_json::to_string(warning).expect("failed to serialize sequence warning");
+        let record = FutureRecord::to(topic)
+            .key(warning.visit_number.as_str())
+            .payload(payload.as_str());
+        if let Err((e, _)) = self
+            .producer
+            .send(record, Timeout::After(Duration::from_secs(5)))
+            .await
+        {
+            error!("Failed to produce sequence warning: {e}");
+        }
+    }
+
     async fn is_cancelled(&self, timeout: Duration) -> bool {
         select! {
             _ =  self.ctx.cancel.cancelled() => {
@@ -335,6 +765,74 @@ impl Processor {
     }
 }
 
+/// Timestamp (unix millis) of the produced record.
+fn output_timestamp(
+    mode: OutputTimestamp,
+    input: Option<i64>,
+    event: Option<i64>,
+    now: i64,
+) -> Option<i64> {
+    match mode {
+        OutputTimestamp::Input => input,
+        OutputTimestamp::Now => Some(now),
+        OutputTimestamp::Event => event.or(input),
+    }
+}
+
+/// Record timestamp (unix millis) is older than `days`.
+fn exceeds_max_age(timestamp: i64, now: i64, days: u32) -> bool {
+    now - timestamp > i64::from(days) * 24 * 60 * 60 * 1000
+}
+
+/// Records the consumer lag of the record's partition, in records (by the cached high watermark,
+/// without a broker request) and in seconds (by the record timestamp).
+fn record_lag(m: &BorrowedMessage, consumer: &ProcessingConsumer) {
+    let attributes = lag_attributes(m.topic(), m.partition());
+    let behind = consumer
+        .get_watermark_offsets(m.topic(), m.partition())
+        .ok()
+        .map(|(_, high)| lag_records(high, m.offset()));
+    if let Some(behind) = behind {
+        consumer_lag().record(behind, &attributes);
+    }
+    if let Some(timestamp) = m.timestamp().to_millis() {
+        // the gauge keeps its value while the partition is idle, so a caught up partition
+        // has no lag, although its latest record may be old
+        let seconds = match behind {
+            Some(0) => 0,
+            _ => lag_seconds(timestamp, Utc::now().timestamp_millis()),
+        };
+        consumer_lag_seconds().record(seconds, &attributes);
+    }
+}
+
+/// Resets the consumer lag of revoked partitions, which are no longer consumed by this
+/// instance. Otherwise the gauges keep exporting the lag of the last consumed record.
+fn reset_lag(topic_parts: &TopicPartitionList) {
+    for e in topic_parts.elements() {
+        let attributes = lag_attributes(e.topic(), e.partition());
+        consumer_lag().record(0, &attributes);
+        consumer_lag_seconds().record(0, &attributes);
+    }
+}
+
+fn lag_attributes(topic: &str, partition: i32) -> [KeyValue; 2] {
+    [
+        KeyValue::new("topic", topic.to_string()),
+        KeyValue::new("partition", partition as i64),
+    ]
+}
+
+/// Records behind the high watermark, after the record at `offset` is processed.
+fn lag_records(high: i64, offset: i64) -> u64 {
+    (high - offset - 1).max(0) as u64
+}
+
+/// Age of the record timestamp (unix millis) in seconds.
+fn lag_seconds(timestamp: i64, now: i64) -> u64 {
+    ((now - timestamp) / 1000).max(0) as u64
+}
+
 fn deserialize_message(m: &BorrowedMessage) -> (String, Option<String>) {
     let key = match m.key_view::<str>() {
         None => "",
@@ -356,7 +854,7 @@ fn deserialize_message(m: &BorrowedMessage) -> (String, Option<String>) {
     (key.to_owned(), payload.map(str::to_string).to_owned())
 }
 
-fn create_producer(config: Kafka) -> FutureProducer {
+pub(crate) fn create_producer(config: Kafka) -> FutureProducer {
     let mut c = ClientConfig::new();
     c.set("bootstrap.servers", config.brokers)
         .set("security.protocol", config.security_protocol)
@@ -369,7 +867,7 @@ fn create_producer(config: Kafka) -> FutureProducer {
         .expect("Failed to create Kafka producer")
 }
 
-fn set_ssl_config(mut c: ClientConfig, ssl_config: Option<Ssl>) -> ClientConfig {
+pub(crate) fn set_ssl_config(mut c: ClientConfig, ssl_config: Option<Ssl>) -> ClientConfig {
     if let Some(ssl) = ssl_config {
         if let Some(value) = ssl.ca_location {
             c.set("ssl.ca.location", value);
@@ -389,12 +887,17 @@ fn set_ssl_config(mut c: ClientConfig, ssl_config: Option<Ssl>) -> ClientConfig
 
 #[cfg(test)]
 mod tests {
-    use crate::config::{AppConfig, Kafka};
+    use crate::config::{AppConfig, Kafka, OutputTimestamp};
     use crate::fhir::mapper::FhirMapper;
     use crate::fhir::resources::ResourceMap;
-    use crate::processor::{Context, Processor, deserialize_message};
+    use crate::pipeline::{Checks, Pipeline};
+    use crate::processor::{
+        Context, Processor, Watermarks, deserialize_message, exceeds_max_age, lag_records,
+        lag_seconds, output_timestamp, weighted_merge,
+    };
     use crate::test_utils::tests::{get_dummy_resources, get_test_config, read_test_resource};
     use fhir_model::r4b::resources::{Bundle, ResourceType};
+    use futures::{StreamExt, stream};
     use rdkafka::ClientConfig;
     use rdkafka::consumer::{Consumer, StreamConsumer};
     use rdkafka::mocking::MockCluster;
@@ -449,25 +952,44 @@ mod tests {
                 input_topic: INPUT_TOPIC.to_owned(),
                 output_topic: OUTPUT_TOPIC.to_owned(),
                 num_partitions: 1,
+                produce_retries: 3,
                 ssl: None,
+                lanes: vec![],
+                max_message_age: None,
+                max_message_age_action: Default::default(),
+                confirm_replay: false,
+                output_format: Default::default(),
+                output_timestamp: Default::default(),
+                review_topic: None,
             },
             app: Default::default(),
             fhir: get_test_config(),
+            pipeline: Default::default(),
+            canary: None,
+            audit: None,
+            sequence_check: None,
+            demographics: None,
+            quality: None,
         };
         // mapper
         let mapper = Arc::new(FhirMapper {
             config: config.fhir,
             resources: get_dummy_resources(),
+            history: Default::default(),
+            exclusion: Default::default(),
         });
 
         // processor
         let token = CancellationToken::new();
+        let pipeline =
+            Arc::new(Pipeline::new(&config.pipeline, mapper, Checks::default()).unwrap());
         let p = Processor::new(
             config.kafka,
-            mapper,
+            pipeline,
             Context {
                 cancel: token,
                 on_commit: None,
+                readiness: Default::default(),
             },
         );
 
@@ -521,10 +1043,24 @@ mod tests {
                 input_topic: INPUT_TOPIC.to_owned(),
                 output_topic: OUTPUT_TOPIC.to_owned(),
                 num_partitions: 1,
+                produce_retries: 3,
                 ssl: None,
+                lanes: vec![],
+                max_message_age: None,
+                max_message_age_action: Default::default(),
+                confirm_replay: false,
+                output_format: Default::default(),
+                output_timestamp: Default::default(),
+                review_topic: None,
             },
             app: Default::default(),
             fhir: get_test_config(),
+            pipeline: Default::default(),
+            canary: None,
+            audit: None,
+            sequence_check: None,
+            demographics: None,
+            quality: None,
         };
 
         // mapper
@@ -534,7 +1070,10 @@ mod tests {
                 department_map: Default::default(),
                 ward_map: Default::default(),
                 department_id_map: Default::default(),
+                municipality_map: Default::default(),
             },
+            history: Default::default(),
+            exclusion: Default::default(),
         });
 
         // cancellation token
@@ -542,12 +1081,15 @@ mod tests {
         let cloned_token = token.clone();
 
         // processor
+        let pipeline =
+            Arc::new(Pipeline::new(&config.pipeline, mapper, Checks::default()).unwrap());
         let p = Processor::new(
             config.kafka,
-            mapper,
+            pipeline,
             Context {
                 cancel: token.clone(),
                 on_commit: None,
+                readiness: Default::default(),
             },
         );
 
@@ -559,6 +1101,73 @@ mod tests {
         assert!(processor.await.is_ok());
     }
 
+    #[test]
+    fn max_message_age_test() {
+        let day = 24 * 60 * 60 * 1000;
+        let now = 100 * day;
+
+        assert!(!exceeds_max_age(now - day, now, 30));
+        assert!(!exceeds_max_age(now - 30 * day, now, 30));
+        assert!(exceeds_max_age(now - 31 * day, now, 30));
+    }
+
+    #[test]
+    fn output_timestamp_test() {
+        let (input, event, now) = (1_000, 2_000, 3_000);
+
+        assert_eq!(
+            output_timestamp(OutputTimestamp::Input, Some(input), Some(event), now),
+            Some(input)
+        );
+        assert_eq!(
+            output_timestamp(OutputTimestamp::Now, Some(input), Some(event), now),
+            Some(now)
+        );
+        assert_eq!(
+            output_timestamp(OutputTimestamp::Event, Some(input), Some(event), now),
+            Some(event)
+        );
+        // missing EVN-2
+        assert_eq!(
+            output_timestamp(OutputTimestamp::Event, Some(input), None, now),
+            Some(input)
+        );
+    }
+
+    #[test]
+    fn lag_test() {
+        // last record of the partition
+        assert_eq!(lag_records(10, 9), 0);
+        assert_eq!(lag_records(10, 4), 5);
+        // stale high watermark
+        assert_eq!(lag_records(10, 12), 0);
+
+        assert_eq!(lag_seconds(1_000, 61_500), 60);
+        assert_eq!(lag_seconds(2_000, 1_000), 0);
+    }
+
+    #[test]
+    fn watermark_test() {
+        let watermarks = Watermarks::default();
+
+        assert_eq!(watermarks.advance("adt", 0, 200), Some(200));
+        assert_eq!(watermarks.advance("adt", 0, 100), None);
+        assert_eq!(watermarks.advance("adt", 1, 100), Some(100));
+        assert_eq!(watermarks.advance("adt", 0, 300), Some(300));
+    }
+
+    #[tokio::test]
+    async fn weighted_merge_test() {
+        let realtime = stream::iter(vec!["a"; 6]);
+        let backfill = stream::iter(vec!["b"; 3]);
+
+        let merged: Vec<&str> = weighted_merge(vec![(realtime, 3), (backfill, 1)])
+            .collect()
+            .await;
+
+        assert_eq!(merged, vec!["a", "a", "a", "b", "a", "a", "a", "b", "b"]);
+    }
+
     fn init_logging() {
         let _ = env_logger::builder().is_test(true).try_init();
     }
@@ -0,0 +1,231 @@
+use crate::config::QualityConfig;
+use crate::hl7::parser::{MSH_4_1, query};
+use anyhow::anyhow;
+use hl7_parser::Message;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+const UNKNOWN_FACILITY: &str = "unknown";
+
+/// Monitored field, e.g. `PID.7`.
+struct Field {
+    location: String,
+    segment: String,
+}
+
+#[derive(Default)]
+struct FacilityStats {
+    messages: u64,
+    /// messages containing the segment and missing the value, per field
+    fields: HashMap<String, (u64, u64)>,
+    /// unknown code values reported by the mapping
+    unknown_codes: u64,
+    /// failed messages per error type
+    failures: HashMap<String, u64>,
+}
+
+/// Data quality per sending facility (MSH-4.1): missing field rates, unknown code rates and
+/// failures, periodically published as scorecard to give interface owners feedback on the
+/// upstream data quality.
+///
+/// __note:__ the statistics are kept in memory per interval
+pub(crate) struct DataQuality {
+    fields: Vec<Field>,
+    stats: Mutex<HashMap<String, FacilityStats>>,
+    pub(crate) topic: String,
+    pub(crate) interval: Duration,
+}
+
+/// Data quality of a sending facility within the interval.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Scorecard {
+    pub(crate) sending_facility: String,
+    pub(crate) messages: u64,
+    /// share of the messages missing the field, of those containing the segment
+    pub(crate) missing_field_rates: BTreeMap<String, f64>,
+    pub(crate) unknown_codes: u64,
+    /// unknown code values per message
+    pub(crate) unknown_code_rate: f64,
+    /// failed messages per error type (e.g. `Hl7ParseError`)
+    pub(crate) failures: BTreeMap<String, u64>,
+    pub(crate) failure_rate: f64,
+}
+
+impl DataQuality {
+    pub(crate) fn new(config: QualityConfig) -> anyhow::Result<Self> {
+        let fields = config
+            .fields
+            .iter()
+            .map(|location| {
+                let segment = location
+                    .split_once('.')
+                    .filter(|(s, i)| s.len() == 3 && i.parse::<usize>().is_ok_and(|i| i > 0))
+                    .map(|(s, _)| s)
+                    .ok_or(anyhow!("invalid quality field '{location}', e.g. PID.7"))?;
+                Ok(Field {
+                    location: location.clone(),
+                    segment: segment.to_string(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(DataQuality {
+            fields,
+            stats: Mutex::default(),
+            topic: config.topic,
+            interval: Duration::from_secs(config.interval_secs),
+        })
+    }
+
+    /// Records the message and its missing fields, returns the sending facility.
+    pub(crate) fn record(&self, msg: &Message) -> String {
+        let facility = query(msg, MSH_4_1).unwrap_or(UNKNOWN_FACILITY).to_string();
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        let stats = stats.entry(facility.clone()).or_default();
+
+        stats.messages += 1;
+        for field in &self.fields {
+            if msg.segment(&field.segment).is_none() {
+                continue;
+            }
+            let (present, missing) = stats.fields.entry(field.location.clone()).or_default();
+            *present += 1;
+            if query(msg, &field.location).is_none() {
+                *missing += 1;
+            }
+        }
+
+        facility
+    }
+
+    /// Records an unparsable message, its sending facility is unknown.
+    pub(crate) fn record_unparsable(&self, error: &str) {
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        let stats = stats.entry(UNKNOWN_FACILITY.to_string()).or_default();
+
+        stats.messages += 1;
+        *stats.failures.entry(error.to_string()).or_default() += 1;
+    }
+
+    /// Records the unknown code values of a mapped message of the sending facility.
+    pub(crate) fn record_unknown_codes(&self, facility: &str, count: u64) {
+        if count == 0 {
+            return;
+        }
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        stats.entry(facility.to_string()).or_default().unknown_codes += count;
+    }
+
+    /// Records a failed message of the sending facility.
+    pub(crate) fn record_failure(&self, facility: &str, error: &str) {
+        let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+        *stats
+            .entry(facility.to_string())
+            .or_default()
+            .failures
+            .entry(error.to_string())
+            .or_default() += 1;
+    }
+
+    /// Scorecards of the sending facilities since the last call, the statistics are reset.
+    pub(crate) fn scorecards(&self) -> Vec<Scorecard> {
+        let stats = std::mem::take(&mut *self.stats.lock().unwrap_or_else(PoisonError::into_inner));
+        let rate = |count: u64, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                count as f64 / total as f64
+            }
+        };
+
+        let mut scorecards: Vec<Scorecard> = stats
+            .into_iter()
+            .map(|(facility, stats)| {
+                let unknown = stats.unknown_codes;
+                let failed = stats.failures.values().sum();
+                Scorecard {
+                    missing_field_rates: stats
+                        .fields
+                        .into_iter()
+                        .map(|(field, (present, missing))| (field, rate(missing, present)))
+                        .collect(),
+                    unknown_codes: unknown,
+                    unknown_code_rate: rate(unknown, stats.messages),
+                    failures: stats.failures.into_iter().collect(),
+                    failure_rate: rate(failed, stats.messages),
+                    messages: stats.messages,
+                    sending_facility: facility,
+                }
+            })
+            .collect();
+        scorecards.sort_by(|a, b| a.sending_facility.cmp(&b.sending_facility));
+
+        scorecards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(facility: &str, pid_7: &str) -> String {
+        format!(
+            "MSH|^~\\&|ORBIS|{facility}|RECAPP|ORBIS|202111221030||ADT^A08|1234|P|2.5\r\
+            PID|1|1212121|1212121||Sokolovski^Malina||{pid_7}|F"
+        )
+    }
+
+    fn record(quality: &DataQuality, facility: &str, pid_7: &str) -> String {
+        let raw = message(facility, pid_7);
+        quality.record(&Message::parse_with_lenient_newlines(&raw, true).unwrap())
+    }
+
+    fn quality(fields: &[&str]) -> anyhow::Result<DataQuality> {
+        DataQuality::new(QualityConfig {
+            topic: "adt-quality".to_string(),
+            interval_secs: 60,
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn scorecard_per_facility() {
+        let quality = quality(&["PID.7", "PV1.19", "MSH.4"]).unwrap();
+
+        let facility = record(&quality, "QUALITY_A", "19820101");
+        record(&quality, "QUALITY_A", "");
+        record(&quality, "QUALITY_B", "");
+        quality.record_unparsable("Hl7ParseError");
+        quality.record_unknown_codes(&facility, 1);
+        quality.record_failure(&facility, "Hl7ParseError");
+
+        let scorecards = quality.scorecards();
+        assert_eq!(scorecards.len(), 3);
+        let a = &scorecards[0];
+        assert_eq!(a.sending_facility, "QUALITY_A");
+        assert_eq!(a.messages, 2);
+        assert_eq!(a.missing_field_rates["PID.7"], 0.5);
+        assert_eq!(a.missing_field_rates["MSH.4"], 0.0);
+        // no PV1 segment
+        assert!(!a.missing_field_rates.contains_key("PV1.19"));
+        assert_eq!(a.unknown_codes, 1);
+        assert_eq!(a.unknown_code_rate, 0.5);
+        assert_eq!(a.failures["Hl7ParseError"], 1);
+        assert_eq!(a.failure_rate, 0.5);
+        assert_eq!(scorecards[1].missing_field_rates["PID.7"], 1.0);
+        assert_eq!(scorecards[2].sending_facility, "unknown");
+        assert_eq!(scorecards[2].failure_rate, 1.0);
+
+        // reset
+        assert!(quality.scorecards().is_empty());
+    }
+
+    #[test]
+    fn invalid_field() {
+        assert!(quality(&["PID"]).is_err());
+        assert!(quality(&["PID.x"]).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use log::{error, info};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Readiness of the processor, exposed as a marker file for (exec) readiness probes.
+///
+/// The file exists as long as the processor is ready.
+#[derive(Clone, Default)]
+pub(crate) struct Readiness {
+    file: Option<PathBuf>,
+    ready: Arc<AtomicBool>,
+    /// number of paused consumers
+    paused: Arc<AtomicUsize>,
+}
+
+impl Readiness {
+    pub(crate) fn new(file: Option<String>) -> Self {
+        Readiness {
+            file: file.map(PathBuf::from),
+            ready: Default::default(),
+            paused: Default::default(),
+        }
+    }
+
+    /// Marks a consumer as paused. The processor is not ready, until all paused consumers
+    /// are resumed.
+    pub(crate) fn pause(&self) {
+        self.paused.fetch_add(1, Ordering::SeqCst);
+        self.set_ready(false);
+    }
+
+    /// Marks a paused consumer as resumed.
+    pub(crate) fn resume(&self) {
+        if self.paused.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.set_ready(true);
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_ready(&self, ready: bool) {
+        // paused consumers are resumed by `resume`
+        let ready = ready && self.paused.load(Ordering::SeqCst) == 0;
+        if self.ready.swap(ready, Ordering::SeqCst) == ready {
+            return;
+        }
+        info!("[Readiness] ready: {ready}");
+
+        if let Some(file) = &self.file {
+            let result = if ready {
+                fs::write(file, b"ready")
+            } else {
+                fs::remove_file(file)
+            };
+            if let Err(e) = result {
+                error!("Failed to update readiness file {}: {e}", file.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readiness_file_flips() {
+        let file = std::env::temp_dir().join(format!("adt-to-fhir-ready-{}", std::process::id()));
+        let readiness = Readiness::new(Some(file.to_string_lossy().to_string()));
+
+        readiness.set_ready(true);
+        assert!(readiness.is_ready());
+        assert!(file.exists());
+
+        readiness.set_ready(false);
+        assert!(!readiness.is_ready());
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn ready_after_all_consumers_resumed() {
+        let readiness = Readiness::default();
+        readiness.set_ready(true);
+
+        readiness.pause();
+        readiness.pause();
+        readiness.resume();
+        assert!(!readiness.is_ready());
+
+        readiness.resume();
+        assert!(readiness.is_ready());
+    }
+}
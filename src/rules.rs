@@ -0,0 +1,235 @@
+use crate::config::{Operator, RuleAction, RuleConfig};
+use crate::error::MappingError;
+use crate::fhir::mapper::ENTRY_ORDER;
+use crate::hl7::parser::query;
+use crate::limits::{entry_urls, remove_referencing};
+use crate::pipeline::{Next, Outcome, Record, Stage};
+use anyhow::anyhow;
+use hl7_parser::Message;
+use log::debug;
+use serde_json::Value;
+
+/// Site specific skip rules (see [`RuleConfig`]), evaluated on the HL7 payload before mapping.
+///
+/// A matching `skip` rule skips the record, a matching `drop` rule removes the entries of the
+/// given resource types from the mapped bundle, together with the entries referencing them.
+pub(crate) struct RuleStage {
+    rules: Vec<RuleConfig>,
+}
+
+impl RuleStage {
+    pub(crate) fn new(rules: Vec<RuleConfig>) -> anyhow::Result<Self> {
+        for rule in &rules {
+            match rule.action {
+                RuleAction::Drop if rule.resource_types.is_empty() => {
+                    return Err(anyhow!("'drop' rule requires resource_types"));
+                }
+                RuleAction::Skip if !rule.resource_types.is_empty() => {
+                    return Err(anyhow!("'skip' rule doesn't support resource_types"));
+                }
+                _ => {}
+            }
+            // the resource types are matched against the mapped entries
+            if let Some(t) = rule
+                .resource_types
+                .iter()
+                .find(|t| !ENTRY_ORDER.contains(&t.as_str()))
+            {
+                return Err(anyhow!("unknown resource type '{t}' of 'drop' rule"));
+            }
+        }
+
+        Ok(RuleStage { rules })
+    }
+
+    fn matches(rule: &RuleConfig, msg: &Message) -> bool {
+        rule.conditions.iter().all(|c| {
+            let value = query(msg, &c.field);
+            match c.operator {
+                Operator::In => value.is_some_and(|v| c.values.iter().any(|e| e == v)),
+                Operator::NotIn => !value.is_some_and(|v| c.values.iter().any(|e| e == v)),
+                Operator::Present => value.is_some(),
+                Operator::Missing => value.is_none(),
+            }
+        })
+    }
+}
+
+impl Stage for RuleStage {
+    fn handle(&self, record: Record, next: Next<'_>) -> Result<Outcome, MappingError> {
+        // unparsable messages are reported by the mapping
+        let Some(msg) = record
+            .payload
+            .as_deref()
+            .and_then(|p| Message::parse_with_lenient_newlines(p, true).ok())
+        else {
+            return next.run(record);
+        };
+
+        let matched: Vec<&RuleConfig> = self
+            .rules
+            .iter()
+            .filter(|r| Self::matches(r, &msg))
+            .collect();
+        if matched.iter().any(|r| r.action == RuleAction::Skip) {
+            debug!("Skipping record [key={}] by rule", record.key);
+            return Ok(Outcome::Skip);
+        }
+        let dropped: Vec<&str> = matched
+            .iter()
+            .filter(|r| r.action == RuleAction::Drop)
+            .flat_map(|r| r.resource_types.iter().map(String::as_str))
+            .collect();
+
+        match next.run(record)? {
+            Outcome::Produce(mut produced) if !dropped.is_empty() => {
+                let Some(bundle) = produced.payload.as_deref() else {
+                    return Ok(Outcome::Produce(produced));
+                };
+                match drop_entries(&produced.key, bundle, &dropped)? {
+                    Some(bundle) => {
+                        produced.payload = Some(bundle);
+                        Ok(Outcome::Produce(produced))
+                    }
+                    None => Ok(Outcome::Skip),
+                }
+            }
+            outcome => Ok(outcome),
+        }
+    }
+}
+
+/// Removes the entries of the resource types and the entries referencing them from the bundle,
+/// [`None`] if no entry is left.
+fn drop_entries(
+    key: &str,
+    bundle: &str,
+    resource_types: &[&str],
+) -> Result<Option<String>, MappingError> {
+    let mut bundle: Value =
+        serde_json::from_str(bundle).map_err(|e| MappingError::Other(e.into()))?;
+    let Some(entries) = bundle.get_mut("entry").and_then(Value::as_array_mut) else {
+        return Ok(None);
+    };
+
+    let mut dropped_urls = vec![];
+    entries.retain(|e| {
+        // the request url starts with the resource type, also for patch and delete
+        let resource_type = e
+            .pointer("/request/url")
+            .and_then(Value::as_str)
+            .and_then(|url| url.split(['?', '/']).next());
+        let dropped = resource_type.is_some_and(|t| resource_types.contains(&t));
+        if dropped {
+            dropped_urls.extend(entry_urls(e));
+        }
+        !dropped
+    });
+    remove_referencing(entries, dropped_urls, key);
+    if entries.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        serde_json::to_string(&bundle).map_err(|e| MappingError::Other(e.into()))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RuleConditionConfig;
+    use crate::test_utils::tests::read_test_resource;
+
+    fn rule(
+        conditions: Vec<(&str, Operator, Vec<&str>)>,
+        action: RuleAction,
+        resource_types: Vec<&str>,
+    ) -> RuleConfig {
+        RuleConfig {
+            conditions: conditions
+                .into_iter()
+                .map(|(field, operator, values)| RuleConditionConfig {
+                    field: field.to_string(),
+                    operator,
+                    values: values.into_iter().map(String::from).collect(),
+                })
+                .collect(),
+            action,
+            resource_types: resource_types.into_iter().map(String::from).collect(),
+        }
+    }
+
+    #[test]
+    fn rule_matches_conditions() {
+        let hl7 = read_test_resource("a01_test.hl7");
+        let msg = Message::parse_with_lenient_newlines(&hl7, true).unwrap();
+
+        let matching = rule(
+            vec![
+                ("PV1.2", Operator::In, vec!["I"]),
+                ("PV1.3.1", Operator::NotIn, vec!["X", "Y"]),
+                ("PID.2", Operator::Present, vec![]),
+            ],
+            RuleAction::Skip,
+            vec![],
+        );
+        assert!(RuleStage::matches(&matching, &msg));
+
+        let other = rule(
+            vec![
+                ("PV1.2", Operator::In, vec!["O"]),
+                ("PID.2", Operator::Present, vec![]),
+            ],
+            RuleAction::Skip,
+            vec![],
+        );
+        assert!(!RuleStage::matches(&other, &msg));
+    }
+
+    #[test]
+    fn drop_entries_by_resource_type() {
+        let bundle = r#"{"resourceType":"Bundle","entry":[
+            {"request":{"method":"PUT","url":"Patient?identifier=a|1"}},
+            {"request":{"method":"PUT","url":"Encounter?identifier=b|2"}},
+            {"request":{"method":"DELETE","url":"Encounter/3"}}]}"#;
+
+        let result = drop_entries("1", bundle, &["Encounter"]).unwrap().unwrap();
+        let result: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(result["entry"].as_array().unwrap().len(), 1);
+
+        assert_eq!(
+            drop_entries("1", bundle, &["Patient", "Encounter"]).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn drop_referencing_entries() {
+        let bundle = r#"{"resourceType":"Bundle","entry":[
+            {"resource":{"resourceType":"Patient"},
+             "request":{"method":"PUT","url":"Patient?identifier=a|1"}},
+            {"resource":{"resourceType":"Encounter","subject":{"reference":"Patient?identifier=a|1"}},
+             "request":{"method":"PUT","url":"Encounter?identifier=b|2"}},
+            {"resource":{"resourceType":"Condition","encounter":{"reference":"Encounter?identifier=b|2"}},
+             "request":{"method":"PUT","url":"Condition?identifier=c|3"}}]}"#;
+
+        let result = drop_entries("1", bundle, &["Encounter"]).unwrap().unwrap();
+
+        let result: Value = serde_json::from_str(&result).unwrap();
+        let entries = result["entry"].as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["resource"]["resourceType"], "Patient");
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let drop =
+            |resource_types| RuleStage::new(vec![rule(vec![], RuleAction::Drop, resource_types)]);
+
+        assert!(drop(vec!["Encounter"]).is_ok());
+        assert!(drop(vec!["Encountr"]).is_err());
+        assert!(drop(vec![]).is_err());
+        assert!(RuleStage::new(vec![rule(vec![], RuleAction::Skip, vec!["Encounter"])]).is_err());
+    }
+}
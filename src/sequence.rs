@@ -0,0 +1,257 @@
+use crate::config::SequenceCheckConfig;
+use crate::hl7::parser::MessageInfo;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Interval of the eviction of expired visits.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Known admit and discharge time of a visit (unix timestamps in milliseconds).
+#[derive(Debug, Clone, Copy)]
+struct Visit {
+    admit: Option<i64>,
+    discharge: Option<i64>,
+    /// processing time of the last event
+    updated: Instant,
+}
+
+struct Visits {
+    visits: HashMap<String, Visit>,
+    swept: Instant,
+}
+
+/// Detects impossible ADT event sequences per visit number (PV1-19), e.g. a discharge before the
+/// admission or a transfer after the discharge. The events are mapped anyway, the warnings are
+/// produced to the warnings topic.
+///
+/// __note:__ the state is kept in memory and is therefore lost on restart. Visits without events
+/// expire after `visit_ttl`, discharged visits after `discharged_ttl`.
+pub(crate) struct SequenceCheck {
+    visits: Mutex<Visits>,
+    visit_ttl: Duration,
+    discharged_ttl: Duration,
+    pub(crate) topic: String,
+}
+
+/// Impossible event sequence of a visit.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SequenceWarning {
+    pub(crate) r#type: SequenceWarningType,
+    pub(crate) visit_number: String,
+    /// message control id (MSH-10)
+    pub(crate) message_id: Option<String>,
+    pub(crate) event: String,
+    /// event time (EVN-2, PV1-44/PV1-45) in milliseconds
+    pub(crate) event_time: i64,
+    /// known admit or discharge time in milliseconds
+    pub(crate) known_time: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) enum SequenceWarningType {
+    DischargeBeforeAdmit,
+    TransferAfterDischarge,
+}
+
+impl SequenceCheck {
+    pub(crate) fn new(config: SequenceCheckConfig) -> Self {
+        SequenceCheck {
+            visits: Mutex::new(Visits {
+                visits: HashMap::new(),
+                swept: Instant::now(),
+            }),
+            visit_ttl: Duration::from_secs(config.visit_ttl_secs),
+            discharged_ttl: Duration::from_secs(config.discharged_ttl_secs),
+            topic: config.topic,
+        }
+    }
+
+    /// Checks the event against the known state of the visit and updates the state.
+    pub(crate) fn check(&self, info: &MessageInfo) -> Option<SequenceWarning> {
+        self.check_at(info, Instant::now())
+    }
+
+    fn check_at(&self, info: &MessageInfo, now: Instant) -> Option<SequenceWarning> {
+        let visit_number = info.visit_number.as_deref()?;
+        // e.g. ADT^A11_A09
+        let event = info
+            .trigger_event
+            .as_deref()
+            .and_then(|t| t.split('_').next())
+            .or(info.event_type.as_deref())?;
+        let event_time = info.event_time;
+
+        let mut state = self.visits.lock().unwrap_or_else(PoisonError::into_inner);
+        if now.duration_since(state.swept) >= SWEEP_INTERVAL {
+            state.swept = now;
+            state.visits.retain(|_, v| !self.is_expired(v, now));
+        }
+        let visits = &mut state.visits;
+        let visit = visits
+            .entry(visit_number.to_string())
+            .and_modify(|v| {
+                if self.is_expired(v, now) {
+                    v.admit = None;
+                    v.discharge = None;
+                }
+            })
+            .or_insert(Visit {
+                admit: None,
+                discharge: None,
+                updated: now,
+            });
+        visit.updated = now;
+        let warning = |r#type, event_time, known_time| SequenceWarning {
+            r#type,
+            visit_number: visit_number.to_string(),
+            message_id: info.control_id.clone(),
+            event: event.to_string(),
+            event_time,
+            known_time,
+        };
+
+        match event {
+            "A01" | "A04" => {
+                visit.admit = info.admit_time.or(event_time);
+                None
+            }
+            "A03" => {
+                let admit = info.admit_time.or(visit.admit);
+                let discharge = info.discharge_time.or(event_time)?;
+                visit.admit = admit;
+                visit.discharge = Some(discharge);
+
+                admit.filter(|&admit| discharge < admit).map(|admit| {
+                    warning(SequenceWarningType::DischargeBeforeAdmit, discharge, admit)
+                })
+            }
+            "A02" => {
+                let transfer = event_time?;
+                visit
+                    .discharge
+                    .filter(|&discharge| transfer > discharge)
+                    .map(|discharge| {
+                        warning(
+                            SequenceWarningType::TransferAfterDischarge,
+                            transfer,
+                            discharge,
+                        )
+                    })
+            }
+            // cancelled discharge
+            "A13" => {
+                visit.discharge = None;
+                None
+            }
+            // cancelled admission
+            "A11" => {
+                visits.remove(visit_number);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn is_expired(&self, visit: &Visit, now: Instant) -> bool {
+        let ttl = match visit.discharge {
+            Some(_) => self.discharged_ttl,
+            None => self.visit_ttl,
+        };
+
+        now.duration_since(visit.updated) >= ttl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hl7_parser::Message;
+
+    fn message(event: &str, evn_2: &str, pv1_44: &str, pv1_45: &str) -> MessageInfo {
+        let raw = format!(
+            "MSH|^~\\&|ORBIS|KH|RECAPP|ORBIS|202111221030||ADT^{event}|{evn_2}|P|2.5\r\
+            EVN|{event}|{evn_2}\r\
+            PV1|1|I|||||||||||||||||12345678|||||||||||||||||||||||||{pv1_44}|{pv1_45}"
+        );
+
+        MessageInfo::new(&Message::parse_with_lenient_newlines(&raw, true).unwrap())
+    }
+
+    fn check() -> SequenceCheck {
+        SequenceCheck::new(SequenceCheckConfig {
+            topic: "adt-warnings".to_string(),
+            visit_ttl_secs: 3600,
+            discharged_ttl_secs: 600,
+        })
+    }
+
+    #[test]
+    fn discharge_before_admit() {
+        let check = check();
+
+        assert_eq!(
+            check.check(&message("A01", "202111221030", "202111221030", "")),
+            None
+        );
+        let warning = check
+            .check(&message("A03", "202111221130", "", "202111211000"))
+            .expect("expected warning");
+
+        assert_eq!(warning.r#type, SequenceWarningType::DischargeBeforeAdmit);
+        assert_eq!(warning.visit_number, "12345678");
+        assert_eq!(warning.message_id.as_deref(), Some("202111221130"));
+    }
+
+    #[test]
+    fn transfer_after_discharge() {
+        let check = check();
+
+        check.check(&message("A01", "202111221030", "202111221030", ""));
+        assert_eq!(check.check(&message("A02", "202111221100", "", "")), None);
+        check.check(&message("A03", "202111231030", "", "202111231030"));
+
+        let warning = check
+            .check(&message("A02", "202111241030", "", ""))
+            .expect("expected warning");
+        assert_eq!(warning.r#type, SequenceWarningType::TransferAfterDischarge);
+
+        // cancelled discharge
+        check.check(&message("A13", "202111241100", "", ""));
+        assert_eq!(check.check(&message("A02", "202111241200", "", "")), None);
+    }
+
+    #[test]
+    fn expired_visits_are_evicted() {
+        let check = check();
+        let now = Instant::now();
+
+        // open visit expired: admission is unknown
+        check.check_at(&message("A01", "202111221030", "202111221030", ""), now);
+        let now = now + Duration::from_secs(3600);
+        assert_eq!(
+            check.check_at(&message("A03", "202111221130", "", "202111211000"), now),
+            None
+        );
+
+        // discharged visit expired
+        let now = now + Duration::from_secs(600);
+        assert_eq!(
+            check.check_at(&message("A02", "202111241030", "", ""), now),
+            None
+        );
+
+        // swept
+        let now = now + Duration::from_secs(3600);
+        let other = MessageInfo {
+            visit_number: Some("87654321".to_string()),
+            event_type: Some("A08".to_string()),
+            ..Default::default()
+        };
+        check.check_at(&other, now);
+        let visits = &check.visits.lock().unwrap().visits;
+        assert_eq!(visits.keys().collect::<Vec<_>>(), vec!["87654321"]);
+    }
+}
@@ -1,10 +1,12 @@
 #[cfg(test)]
 pub(crate) mod tests {
     use crate::config::{
-        CheckMode, FallConfig, Fhir, LocationConfig, ObservationConfig, OrganizationConfig,
-        PatientConfig, SystemConfig,
+        CancelAdmitMode, CancelPendingAdmitMode, CheckMode, ConditionConfig, ContactConfig,
+        CoverageConfig, FallConfig, Fhir, KontaktebeneConfig, LocationConfig, MessageTypeSource,
+        ObservationConfig, OrganizationConfig, PatientConfig, PatientDeletionMode, ProcedureConfig,
+        SystemConfig, VipConfig,
     };
-    use crate::fhir::resources::{Department, ResourceMap, ValidPeriod, Ward};
+    use crate::fhir::resources::{Department, Municipality, ResourceMap, ValidPeriod, Ward};
     use chrono::NaiveDate;
     use fhir_model::WrongResourceType;
     use fhir_model::r4b::codes::IssueSeverity;
@@ -14,8 +16,8 @@ pub(crate) mod tests {
     use fhir_model::r4b::types::Meta;
     use serde_json::Value;
     use std::collections::HashMap;
-    use std::fs;
     use std::path::PathBuf;
+    use std::{env, fs};
 
     pub fn get_test_config() -> Fhir {
         Fhir {
@@ -27,31 +29,99 @@ pub(crate) mod tests {
                 profile: "https://www.medizininformatik-initiative.de/fhir/core/modul-person/StructureDefinition/Patient|2026.0.1".to_string(),
                 system: "https://fhir.diz.uni-marburg.de/sid/patient-id".to_string(),
                 other_insurance_system: "https://fhir.diz.uni-marburg.de/sid/patient-other-insurance-id".to_string(),
+                delete_person_information: PatientDeletionMode::Delete,
+                exclude: Default::default(),
+                contact: ContactConfig {
+                    related_person: false,
+                    system: "https://fhir.diz.uni-marburg.de/sid/related-person-id".to_string(),
+                },
+                defaults: Default::default(),
+                skip_message_types: vec![],
+                identifier_types: vec![],
+                languages: vec![],
+                marital_statuses: vec![],
+                name_normalization: Default::default(),
+                religion: None,
+                employer: None,
+                municipality_key: None,
+                deceased_rule: Default::default(),
             },
             fall: FallConfig {
                 profile: "https://www.medizininformatik-initiative.de/fhir/core/modul-fall/StructureDefinition/KontaktGesundheitseinrichtung|2026.0.1".to_string(),
                 system: "https://fhir.diz.uni-marburg.de/sid/encounter-id".to_string(),
-                einrichtungskontakt: SystemConfig { system: "https://fhir.diz.uni-marburg.de/sid/encounter-admit-id".to_string() },
-                abteilungskontakt: SystemConfig { system: "https://fhir.diz.uni-marburg.de/sid/departement-id".to_string() },
-                versorgungsstellenkontakt: SystemConfig { system: "https://fhir.diz.uni-marburg.de/sid/ward-id".to_string() },
+                einrichtungskontakt: KontaktebeneConfig { system: "https://fhir.diz.uni-marburg.de/sid/encounter-admit-id".to_string(), ..Default::default() },
+                abteilungskontakt: KontaktebeneConfig { system: "https://fhir.diz.uni-marburg.de/sid/departement-id".to_string(), ..Default::default() },
+                versorgungsstellenkontakt: KontaktebeneConfig { system: "https://fhir.diz.uni-marburg.de/sid/ward-id".to_string(), ..Default::default() },
+                delete_stale_visit_number: false,
+                cancel_admit: CancelAdmitMode::Delete,
+                cancel_pending_admit: CancelPendingAdmitMode::Delete,
+                patient_types: vec![],
+                case_category: None,
+                financial_class: None,
+                wahlleistung: None,
+                admit_source: Default::default(),
+                aufnahmegrund: Default::default(),
+                discharge_reasons: vec![],
+                priorities: vec![],
+                length_unit: Default::default(),
+                service_type_sources: vec![],
+                account_system: None,
+                status_transitions: vec![],
             },
             location: LocationConfig {
                 system_ward: "https://fhir.diz.uni-marburg.de/sid/location-caresite-id".to_string(),
                 system_room: "https://fhir.diz.uni-marburg.de/sid/location-room-id".to_string(),
                 system_bed: "https://fhir.diz.uni-marburg.de/sid/location-bed-id".to_string(),
             },
-            condition: SystemConfig { system: "https://fhir.diz.uni-marburg.de/sid/condition-id".to_string() },
+            condition: ConditionConfig {
+                system: "https://fhir.diz.uni-marburg.de/sid/condition-id".to_string(),
+                profile: "https://www.medizininformatik-initiative.de/fhir/core/modul-diagnose/StructureDefinition/Diagnose|2026.0.0".to_string(),
+            },
+            procedure: ProcedureConfig {
+                system: "https://fhir.diz.uni-marburg.de/sid/procedure-id".to_string(),
+                profile: "https://www.medizininformatik-initiative.de/fhir/core/modul-prozedur/StructureDefinition/Procedure|2026.0.0".to_string(),
+            },
+            practitioner: None,
+            account: None,
+            coverage: CoverageConfig {
+                system: "https://fhir.diz.uni-marburg.de/sid/coverage-id".to_string(),
+                profile_gkv: "http://fhir.de/StructureDefinition/coverage-de-gkv".to_string(),
+                profile_pkv: "http://fhir.de/StructureDefinition/coverage-de-pkv".to_string(),
+                kinds: CoverageConfig::kinds(),
+            },
             observation: ObservationConfig {
                 system: "https://fhir.diz.uni-marburg.de/sid/observation-id".to_string(),
                 profile_weight: "https://www.medizininformatik-initiative.de/fhir/ext/modul-icu/StructureDefinition/koerpergewicht|2025.0.4".to_string(),
                 profile_head_circumference: "https://www.medizininformatik-initiative.de/fhir/ext/modul-icu/StructureDefinition/kopfumfang|2025.0.4".to_string(),
                 profile_vital_status: "https://www.medizininformatik-initiative.de/fhir/core/modul-person/StructureDefinition/Vitalstatus|2026.0.0".to_string(),
                 profile_height: "https://www.medizininformatik-initiative.de/fhir/ext/modul-icu/StructureDefinition/koerpergroesse|2025.0.4".to_string(),
+                obx: vec![],
             },
             organization: OrganizationConfig {
                 department: SystemConfig { system: "https://fhir.diz.uni-marburg.de/sid/department".to_string() },
                 ward: SystemConfig { system: "https://fhir.diz.uni-marburg.de/sid/ward-id".to_string() },
+                managing: None,
+            },
+            unknown_code_samples: false,
+            vip: VipConfig {
+                enabled: true,
+                indicators: vec!["Y".to_string()],
+                system: "http://terminology.hl7.org/CodeSystem/v3-Confidentiality".to_string(),
+                code: "R".to_string(),
+                extension: None,
             },
+            base_url: None,
+            absolute_references: false,
+            message_type_source: MessageTypeSource::Evn,
+            target_preset: None,
+            bundle_type: None,
+            identifier_escaping: None,
+            reference_resolution: None,
+            entry_order: vec![],
+            identifier_selection: Default::default(),
+            protected_units: Default::default(),
+            full_url_namespace: None,
+            role: Default::default(),
         }
     }
     pub fn get_dummy_resources() -> ResourceMap {
@@ -146,6 +216,13 @@ pub(crate) mod tests {
                 ("2200".to_string(), "Urologie".to_string()),
                 ("3700".to_string(), "Sonstige Fachabteilung".to_string()),
             ]),
+            municipality_map: HashMap::from([(
+                "35037".to_string(),
+                Municipality {
+                    gemeindeschluessel: "06534014".to_string(),
+                    district: Some("Marburg-Biedenkopf".to_string()),
+                },
+            )]),
         }
     }
 
@@ -180,6 +257,58 @@ pub(crate) mod tests {
             .unwrap_or_else(|_| panic!("Test resource not found: {}", file_path.display()))
     }
 
+    /// Test resource with fields overridden and segments appended, e.g. `("PV1", 20, "DRG")`
+    /// sets PV1-20 of each PV1 segment. Missing fields are added.
+    pub(crate) fn read_test_message(
+        file_name: &str,
+        fields: &[(&str, usize, &str)],
+        segments: &[&str],
+    ) -> String {
+        read_test_resource(file_name)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut values: Vec<&str> = line.split('|').collect();
+                let name = values[0];
+                for &(_, field, value) in fields.iter().filter(|f| f.0 == name) {
+                    // MSH-1 is the field separator itself
+                    let i = if name == "MSH" { field - 1 } else { field };
+                    values.resize(values.len().max(i + 1), "");
+                    values[i] = value;
+                }
+                values.join("|")
+            })
+            .chain(segments.iter().map(|s| s.to_string()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Compares the value with the expected snapshot `resources/test/expected/{name}.json`.
+    /// Snapshots are (re)written by running the tests with `UPDATE_SNAPSHOTS=1`.
+    pub(crate) fn assert_snapshot(name: &str, actual: &Value) {
+        let mut file_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        file_path.push("resources/test/expected");
+        file_path.push(format!("{name}.json"));
+
+        if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(
+                &file_path,
+                serde_json::to_string_pretty(actual).unwrap() + "\n",
+            )
+            .unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&file_path).unwrap_or_else(|_| {
+            panic!(
+                "Snapshot not found: {}, run the tests with UPDATE_SNAPSHOTS=1",
+                file_path.display()
+            )
+        });
+        let expected: Value = serde_json::from_str(&expected).unwrap();
+        assert_eq!(actual, &expected, "snapshot {}", file_path.display());
+    }
+
     pub(crate) fn send_to_validate(
         request_url: &str,
         serialized_resource: String,